@@ -0,0 +1,103 @@
+#ifndef TSUGUMI_H
+#define TSUGUMI_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Builder of an EPub file, created by [`tsugumi_builder_new`] and freed by
+// [`tsugumi_builder_free`].
+typedef struct TsugumiBuilder TsugumiBuilder;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns the message of the last failure on the calling thread, or null if none has failed.
+//
+// The message is valid until the next failure on the thread.
+const char *tsugumi_last_error(void);
+
+// Creates a builder of the book described by the project file `yaml`, or of an empty book if it
+// is null, whose page sources are relative to the directory `root`.
+//
+// Returns null if `yaml` does not describe a book.
+//
+// # Safety
+//
+// `yaml` must be null or a string, and `root` a string.
+struct TsugumiBuilder *tsugumi_builder_new(const char *yaml, const char *root);
+
+// Sets the metadata `name` of the book to `value`.
+//
+// `name` is one of `title`, `creator`, `language`, `identifier`, `description`, `publisher`,
+// `rights` and `subject`. A title, a creator and a subject are added to the ones already set.
+//
+// # Safety
+//
+// `builder` must be a builder that has not been freed, and `name` and `value` strings.
+int tsugumi_builder_set_metadata(struct TsugumiBuilder *builder,
+                                 const char *name,
+                                 const char *value);
+
+// Adds a page of the image at `path`, relative to the root, to the last chapter of the book.
+//
+// # Safety
+//
+// `builder` must be a builder that has not been freed, and `path` a string.
+int tsugumi_builder_add_page_path(struct TsugumiBuilder *builder, const char *path);
+
+// Adds a page of the image of `len` bytes at `data` to the last chapter of the book, as if it
+// were the file `name` relative to the root, whose extension tells the format of the image.
+//
+// The bytes are copied, so they can be freed once this returns.
+//
+// # Safety
+//
+// `builder` must be a builder that has not been freed, `name` a string, and `data` must point to
+// `len` bytes.
+int tsugumi_builder_add_page_bytes(struct TsugumiBuilder *builder,
+                                   const char *name,
+                                   const uint8_t *data,
+                                   size_t len);
+
+// Builds the book and writes the EPub file to `path`.
+//
+// # Safety
+//
+// `builder` must be a builder that has not been freed, and `path` a string.
+int tsugumi_builder_build_to_path(const struct TsugumiBuilder *builder, const char *path);
+
+// Builds the book and stores the EPub file in a buffer, whose address and length are written to
+// `data` and `len`. The buffer is freed by [`tsugumi_buffer_free`].
+//
+// # Safety
+//
+// `builder` must be a builder that has not been freed, and `data` and `len` must be writable.
+int tsugumi_builder_build_to_buffer(const struct TsugumiBuilder *builder,
+                                    uint8_t **data,
+                                    size_t *len);
+
+// Frees the buffer of `len` bytes at `data` that [`tsugumi_builder_build_to_buffer`] returned.
+//
+// # Safety
+//
+// `data` must be null or a buffer returned with `len` that has not been freed.
+void tsugumi_buffer_free(uint8_t *data, size_t len);
+
+// Frees `builder`.
+//
+// # Safety
+//
+// `builder` must be null or a builder that has not been freed.
+void tsugumi_builder_free(struct TsugumiBuilder *builder);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TSUGUMI_H */
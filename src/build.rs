@@ -0,0 +1,438 @@
+//! Building EPub files from books without a project on the file system.
+//!
+//! ```no_run
+//! use tsugumi::build::EpubBuilder;
+//!
+//! let yaml = std::fs::read_to_string("book/tsugumi.yaml")?;
+//! let book = serde_yaml::from_str(&yaml)?;
+//! let file = std::fs::File::create("book.epub")?;
+//! EpubBuilder::new(book, "book").minify(true).write(file)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The entries of the EPub file can also be taken one by one, to be stored in a container other
+//! than a ZIP file:
+//!
+//! ```no_run
+//! use std::io::Read;
+//! use tsugumi::build::EpubBuilder;
+//!
+//! let yaml = std::fs::read_to_string("book/tsugumi.yaml")?;
+//! let book = serde_yaml::from_str(&yaml)?;
+//! let epub = EpubBuilder::new(book, "book").build()?;
+//! for entry in epub.entries() {
+//!     let mut entry = entry?;
+//!     let mut content = Vec::new();
+//!     entry.reader.read_to_end(&mut content)?;
+//!     println!("{} ({}): {} bytes", entry.name, entry.media_type, content.len());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The sources can be given in memory instead, and the EPub file taken as bytes. Without the
+//! default `fs` feature, such as on `wasm32-unknown-unknown`, they are read only from there, and
+//! the time of the book has to be given by [`modified`](EpubBuilder::modified) or
+//! [`reproducible`](EpubBuilder::reproducible) since the clock is not read on that target:
+//!
+//! ```no_run
+//! use tsugumi::build::EpubBuilder;
+//!
+//! # let (yaml, png) = (String::new(), Vec::<u8>::new());
+//! let book = serde_yaml::from_str(&yaml)?;
+//! let epub = EpubBuilder::new(book, "book")
+//!     .source("a.png", png)
+//!     .reproducible(true)
+//!     .write_to_vec()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::model::Book;
+use crate::task::build::{self, BuildOptions, Context, PageRange, Sources};
+use anyhow::Result;
+use std::io::{Cursor, Seek, Write};
+use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+pub use crate::model::{Format, Profile};
+pub use crate::task::build::{Compression, EmptyElements, LineEnding};
+
+/// Builder of an EPub file from a book, which writes it to any seekable writer.
+///
+/// Unlike the `build` command, it neither caches the images nor records the sources in a lockfile.
+pub struct EpubBuilder {
+    root: PathBuf,
+    book: Book,
+    options: BuildOptions,
+    sources: Sources,
+    reproducible: bool,
+}
+
+impl EpubBuilder {
+    /// Creates a builder of `book`, whose page sources are relative to `root`.
+    pub fn new(book: Book, root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            book,
+            options: BuildOptions {
+                no_cache: true,
+                ..Default::default()
+            },
+            sources: Sources::default(),
+            reproducible: false,
+        }
+    }
+
+    /// Gives `data` as the content of the file at `path` relative to the root, such as a page
+    /// source, which is read instead of the file system.
+    pub fn source(mut self, path: impl AsRef<Path>, data: impl Into<Vec<u8>>) -> Self {
+        self.sources.insert(self.root.join(path), data.into());
+        self
+    }
+
+    /// Packages the book in `format` instead of an EPub file.
+    ///
+    /// A book for Kindle is written as the EPub file the `build` command converts, as the
+    /// converter is a program of its own.
+    pub fn format(mut self, format: Format) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    /// Builds for the target named `name` in the book, whose format, store and image settings
+    /// take the place of those of the book and of this builder.
+    pub fn target(mut self, name: impl Into<String>) -> Self {
+        self.options.target = Some(name.into());
+        self
+    }
+
+    /// Compresses the items of the EPub file with `compression` instead of deflating them.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.options.compression = compression;
+        self
+    }
+
+    /// Makes the book for the reading system of `profile`, such as magnifying panels on Kindle.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.options.profile = profile;
+        self
+    }
+
+    /// Fails the build if it produces any warning.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Prepares the images of at most `jobs` pages at once instead of as many as the CPUs.
+    pub fn jobs(mut self, jobs: NonZeroUsize) -> Self {
+        self.options.jobs = Some(jobs);
+        self
+    }
+
+    /// Builds a preview of only the `chapters`, counting from 1 after the cover chapters, and
+    /// the cover.
+    pub fn chapters(mut self, chapters: impl IntoIterator<Item = usize>) -> Self {
+        self.options.chapter = chapters.into_iter().collect();
+        self
+    }
+
+    /// Builds a preview of only the `pages`, numbered from 1 in the order of the spine, in
+    /// addition to the chapters.
+    pub fn pages(mut self, pages: RangeInclusive<usize>) -> Self {
+        self.options.pages = Some(PageRange::new(*pages.start(), *pages.end()));
+        self
+    }
+
+    /// Packages blank images of the same dimensions instead of the pages.
+    pub fn skip_images(mut self, skip_images: bool) -> Self {
+        self.options.skip_images = skip_images;
+        self
+    }
+
+    /// Writes compact XML and minified styles instead of readable ones.
+    pub fn minify(mut self, minify: bool) -> Self {
+        self.options.minify = minify;
+        self
+    }
+
+    /// Indents XML elements with `indent` spaces instead of 2, or not at all with 0.
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.options.indent = indent;
+        self
+    }
+
+    /// Writes XML elements without content as `empty_elements` says instead of as `<a />`.
+    pub fn empty_elements(mut self, empty_elements: EmptyElements) -> Self {
+        self.options.empty_elements = empty_elements;
+        self
+    }
+
+    /// Ends the lines of the generated XML documents and styles with `line_ending`.
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.options.line_ending = line_ending;
+        self
+    }
+
+    /// Begins the generated XML documents and styles with a byte order mark.
+    pub fn bom(mut self, bom: bool) -> Self {
+        self.options.bom = bom;
+        self
+    }
+
+    /// Writes the styles into the pages instead of packaging them as files.
+    pub fn inline_style(mut self, inline_style: bool) -> Self {
+        self.options.inline_style = inline_style;
+        self
+    }
+
+    /// Also writes an EPUB 2 NCX table of contents for legacy readers.
+    pub fn ncx(mut self, ncx: bool) -> Self {
+        self.options.ncx = ncx;
+        self
+    }
+
+    /// Includes the scripts of the rendition and marks the pages as scripted.
+    pub fn scripted(mut self, scripted: bool) -> Self {
+        self.options.scripted = scripted;
+        self
+    }
+
+    /// Records `modified` as the last modification of the book instead of the time it is written.
+    pub fn modified(mut self, modified: OffsetDateTime) -> Self {
+        self.options.modified = Some(modified);
+        self
+    }
+
+    /// Dates the book and the entries of the EPub file by `SOURCE_DATE_EPOCH`, or by 1980-01-01
+    /// if it is not set, so that builds of the same book are identical.
+    ///
+    /// A time given to [`modified`](Self::modified) takes precedence.
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        self.reproducible = reproducible;
+        self
+    }
+
+    /// Builds the book and writes the EPub file to `writer`, which is returned once complete.
+    pub fn write<W: Write + Seek>(self, writer: W) -> Result<W> {
+        self.build()?.write(writer)
+    }
+
+    /// Builds the book and returns the EPub file as bytes.
+    pub fn write_to_vec(self) -> Result<Vec<u8>> {
+        Ok(self.write(Cursor::new(Vec::new()))?.into_inner())
+    }
+
+    /// Builds the book without packaging it, to take its entries.
+    pub fn build(mut self) -> Result<Epub> {
+        if self.options.modified.is_none() {
+            self.options.modified = build::source_date(self.reproducible)?;
+        }
+        let cx = build::package(&self.root, self.book, self.options, self.sources)?;
+        Ok(Epub { cx })
+    }
+}
+
+pub use crate::task::build::Entry;
+
+/// Book built by [`EpubBuilder`], ready to be packaged.
+pub struct Epub {
+    cx: Context,
+}
+
+impl Epub {
+    /// Yields the entries of the EPub file in the order they are packaged, from `mimetype`, the
+    /// container and the package document to the pages and their images.
+    ///
+    /// The documents are generated and the files opened as the entries are taken.
+    pub fn entries(&self) -> impl Iterator<Item = Result<Entry<'_>>> {
+        self.cx.entries()
+    }
+
+    /// Packages the entries into an EPub file written to `writer`, which is returned once
+    /// complete.
+    pub fn write<W: Write + Seek>(&self, writer: W) -> Result<W> {
+        self.cx.write(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Chapter, Metadata, Page, Title};
+    use std::io::{Cursor, Read as _};
+
+    #[test]
+    fn test_write() {
+        let root = tempfile::tempdir().unwrap();
+        image::RgbImage::new(6, 9)
+            .save(root.path().join("a.png"))
+            .unwrap();
+
+        let book = Book {
+            metadata: Metadata {
+                title: vec![Title {
+                    name: "Title".to_string(),
+                    ..Title::default()
+                }],
+                language: "ja".to_string(),
+                identifier: "id".to_string(),
+                ..Metadata::default()
+            },
+            chapter: vec![Chapter {
+                page: vec![Page {
+                    src: "a.png".into(),
+                    ..Page::default()
+                }],
+                ..Chapter::default()
+            }],
+            ..Book::default()
+        };
+
+        let buf = EpubBuilder::new(book, root.path())
+            .write(Cursor::new(Vec::new()))
+            .unwrap();
+        let mut zip = zip::ZipArchive::new(buf).unwrap();
+        assert_eq!(zip.by_index(0).unwrap().name(), "mimetype");
+        assert!(zip.by_name("item/standard.opf").is_ok());
+    }
+
+    #[test]
+    fn test_source() {
+        let mut png = Cursor::new(Vec::new());
+        image::RgbImage::new(6, 9)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+
+        let book = Book {
+            metadata: Metadata {
+                title: vec![Title {
+                    name: "Title".to_string(),
+                    ..Title::default()
+                }],
+                language: "ja".to_string(),
+                identifier: "id".to_string(),
+                ..Metadata::default()
+            },
+            chapter: vec![Chapter {
+                page: vec![Page {
+                    src: "a.png".into(),
+                    ..Page::default()
+                }],
+                ..Chapter::default()
+            }],
+            ..Book::default()
+        };
+
+        // the root does not exist, so the page is read only from memory
+        let root = tempfile::tempdir().unwrap().path().join("book");
+        let buf = EpubBuilder::new(book, &root)
+            .source("a.png", png.into_inner())
+            .reproducible(true)
+            .write_to_vec()
+            .unwrap();
+        let zip = zip::ZipArchive::new(Cursor::new(buf)).unwrap();
+        assert!(zip.file_names().any(|name| name.ends_with(".png")));
+    }
+
+    #[test]
+    fn test_entries() {
+        let root = tempfile::tempdir().unwrap();
+        image::RgbImage::new(6, 9)
+            .save(root.path().join("a.png"))
+            .unwrap();
+
+        let book = Book {
+            metadata: Metadata {
+                title: vec![Title {
+                    name: "Title".to_string(),
+                    ..Title::default()
+                }],
+                language: "ja".to_string(),
+                identifier: "id".to_string(),
+                ..Metadata::default()
+            },
+            chapter: vec![Chapter {
+                page: vec![Page {
+                    src: "a.png".into(),
+                    ..Page::default()
+                }],
+                ..Chapter::default()
+            }],
+            ..Book::default()
+        };
+
+        let epub = EpubBuilder::new(book, root.path()).build().unwrap();
+        let entries = epub
+            .entries()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut content = Vec::new();
+                entry.reader.read_to_end(&mut content).unwrap();
+                (entry.name, entry.media_type.to_string(), content)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            entries[0],
+            (
+                "mimetype".to_string(),
+                "text/plain".to_string(),
+                b"application/epub+zip".to_vec()
+            )
+        );
+        assert!(entries
+            .iter()
+            .any(|(name, media_type, _)| name.ends_with(".png") && media_type == "image/png"));
+    }
+
+    #[test]
+    fn test_options() {
+        let root = tempfile::tempdir().unwrap();
+        image::RgbImage::new(6, 9)
+            .save(root.path().join("a.png"))
+            .unwrap();
+
+        let book = || Book {
+            metadata: Metadata {
+                title: vec![Title {
+                    name: "Title".to_string(),
+                    ..Title::default()
+                }],
+                language: "ja".to_string(),
+                identifier: "id".to_string(),
+                ..Metadata::default()
+            },
+            chapter: vec![Chapter {
+                page: vec![Page {
+                    src: "a.png".into(),
+                    ..Page::default()
+                }],
+                ..Chapter::default()
+            }],
+            ..Book::default()
+        };
+        let build = || {
+            EpubBuilder::new(book(), root.path())
+                .compression(Compression::Stored)
+                .line_ending(LineEnding::Crlf)
+                .bom(true)
+                .reproducible(true)
+                .write(Cursor::new(Vec::new()))
+                .unwrap()
+                .into_inner()
+        };
+
+        let buf = build();
+        assert_eq!(buf, build());
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(buf)).unwrap();
+        let mut opf = zip.by_name("item/standard.opf").unwrap();
+        assert_eq!(opf.compression(), zip::CompressionMethod::Stored);
+        let mut content = String::new();
+        opf.read_to_string(&mut content).unwrap();
+        assert!(content.starts_with('\u{feff}'));
+        assert!(content.contains("\r\n"));
+    }
+}
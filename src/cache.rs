@@ -0,0 +1,470 @@
+use crate::i18n::tr;
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tracing::{debug, warn};
+
+const VERSION: u32 = 1;
+
+/// Information probed from an image file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Probe {
+    pub width: u32,
+    pub height: u32,
+    pub media_type: String,
+}
+
+impl Probe {
+    /// Reads the dimensions and the format of the image at `path` without decoding it.
+    pub fn read(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Self::read_from(path, BufReader::new(file))
+    }
+
+    /// Reads the dimensions and the format of the image in `reader`, which is known as `path`,
+    /// without decoding it.
+    pub fn read_from<R: BufRead + Seek>(path: &Path, reader: R) -> Result<Self> {
+        let mut reader = image::ImageReader::new(reader)
+            .with_guessed_format()
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        // the content tells the format, which the name only stands in for
+        if reader.format().is_none() {
+            if let Ok(format) = image::ImageFormat::from_path(path) {
+                reader.set_format(format);
+            }
+        }
+        // such as AVIF, whose decoder needs a native library this build goes without
+        if let Some(format) = reader.format().filter(|f| !f.reading_enabled()) {
+            let name = format
+                .extensions_str()
+                .first()
+                .unwrap_or(&"")
+                .to_uppercase();
+            anyhow::bail!(tr!(
+                "`{}` is {name}, which is not supported; convert it to JPEG, PNG or WebP",
+                "`{}` は対応していない {name} です。JPEG、PNG か WebP に変換してください",
+                path.display()
+            ));
+        }
+        let media_type = reader
+            .format()
+            .map(|f| f.to_mime_type())
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let (width, height) = reader
+            .into_dimensions()
+            .with_context(|| format!("failed to read {}", path.display()))?;
+
+        Ok(Self {
+            width,
+            height,
+            media_type,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    size: u64,
+    secs: u64,
+    nanos: u32,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = path.metadata().ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(Self {
+            size: metadata.len(),
+            secs: mtime.as_secs(),
+            nanos: mtime.subsec_nanos(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    stamp: Stamp,
+    probe: Probe,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Contents {
+    version: u32,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+/// Cache of image probes keyed on the path, the modification time and the size of the file.
+#[derive(Default)]
+pub struct ProbeCache {
+    path: Option<PathBuf>,
+    contents: Contents,
+    dirty: bool,
+}
+
+impl ProbeCache {
+    /// Loads the cache persisted at `path`, starting empty if it is missing or unreadable.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        let contents = match File::open(&path) {
+            Ok(file) => match serde_json::from_reader::<_, Contents>(BufReader::new(file)) {
+                Ok(contents) if contents.version == VERSION => contents,
+                Ok(_) => {
+                    debug!("discarding outdated cache {}", path.display());
+                    Contents::default()
+                }
+                Err(e) => {
+                    warn!("discarding broken cache {}: {e}", path.display());
+                    Contents::default()
+                }
+            },
+            Err(_) => Contents::default(),
+        };
+
+        Self {
+            path: Some(path),
+            contents,
+            dirty: false,
+        }
+    }
+
+    /// Creates a cache that neither reads nor persists anything.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Returns the probe of the image at `path`, reading the file only if it has changed.
+    pub fn probe(&mut self, path: &Path) -> Result<Probe> {
+        if self.path.is_none() {
+            return Probe::read(path);
+        }
+
+        let stamp = Stamp::of(path);
+        if let Some(entry) = self.contents.entries.get(path) {
+            if Some(&entry.stamp) == stamp.as_ref() {
+                debug!("using cached probe of {}", path.display());
+                return Ok(entry.probe.clone());
+            }
+        }
+
+        let probe = Probe::read(path)?;
+        if let Some(stamp) = stamp {
+            let entry = Entry {
+                stamp,
+                probe: probe.clone(),
+            };
+            self.contents.entries.insert(path.to_path_buf(), entry);
+            self.dirty = true;
+        }
+
+        Ok(probe)
+    }
+
+    /// Writes the cache back if anything has changed.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(path) = self.path.as_deref().filter(|_| self.dirty) else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+
+        self.contents.version = VERSION;
+
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &self.contents)?;
+        writer.flush()?;
+
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+/// Store of images made from the sources, such as stitched spreads and JPEG fallbacks, named
+/// after the hash of what they are made from, so that unchanged pages are not decoded again.
+///
+/// Nothing is ever evicted; `tsugumi clean --cache` empties the store.
+#[derive(Default)]
+pub struct ImageCache {
+    dir: Option<PathBuf>,
+}
+
+impl ImageCache {
+    /// Uses the store in `dir`, which is created when the first image is stored.
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+        }
+    }
+
+    /// Creates a store that holds nothing.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Returns the key of the image made by `recipe`, which names the operation and the hashes of
+    /// the inputs.
+    pub fn key(recipe: &[&str]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(VERSION.to_le_bytes());
+        for part in recipe {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn path(&self, key: &str, ext: &str) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{key}.{ext}")))
+    }
+
+    /// Returns the path of the stored image of `key`, if any.
+    pub fn get(&self, key: &str, ext: &str) -> Option<PathBuf> {
+        self.path(key, ext).filter(|path| path.is_file())
+    }
+
+    /// Stores a copy of the image at `src` as `key`, and returns the path of the copy.
+    #[cfg(feature = "fs")]
+    pub fn insert(&self, key: &str, ext: &str, src: &Path) -> Result<PathBuf> {
+        let (Some(dir), Some(path)) = (&self.dir, self.path(key, ext)) else {
+            anyhow::bail!("the image cache is disabled");
+        };
+
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+
+        // builds running at the same time may store the same image, so move it in when complete
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        std::io::copy(&mut File::open(src)?, &mut file)?;
+        file.persist(&path)?;
+
+        Ok(path)
+    }
+}
+
+/// Digests of the entries of the files written by the last builds, so that the entries that have
+/// not changed are copied from the last file as they are compressed instead of compressed again.
+#[derive(Default)]
+pub struct EntryCache {
+    path: Option<PathBuf>,
+    contents: EntryContents,
+    dirty: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct EntryContents {
+    version: u32,
+    files: HashMap<PathBuf, WrittenFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WrittenFile {
+    stamp: Stamp,
+
+    /// Digests of the entries keyed on their names.
+    entries: HashMap<String, String>,
+}
+
+impl EntryCache {
+    /// Loads the cache persisted at `path`, starting empty if it is missing or unreadable.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        let contents = match File::open(&path) {
+            Ok(file) => match serde_json::from_reader::<_, EntryContents>(BufReader::new(file)) {
+                Ok(contents) if contents.version == VERSION => contents,
+                Ok(_) => {
+                    debug!("discarding outdated cache {}", path.display());
+                    EntryContents::default()
+                }
+                Err(e) => {
+                    warn!("discarding broken cache {}: {e}", path.display());
+                    EntryContents::default()
+                }
+            },
+            Err(_) => EntryContents::default(),
+        };
+
+        Self {
+            path: Some(path),
+            contents,
+            dirty: false,
+        }
+    }
+
+    /// Creates a cache that neither reads nor persists anything.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Returns the digest of an entry of `content` written with `options`, which describes how it
+    /// is compressed and dated.
+    pub fn digest(content: &[u8], options: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(VERSION.to_le_bytes());
+        hasher.update(options.as_bytes());
+        hasher.update([0]);
+        hasher.update(content);
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Returns the digests of the entries of the file at `path` as it was last written, unless
+    /// it has been changed since.
+    pub fn entries(&self, path: &Path) -> Option<&HashMap<String, String>> {
+        let file = self.contents.files.get(path)?;
+        (Stamp::of(path).as_ref() == Some(&file.stamp)).then_some(&file.entries)
+    }
+
+    /// Records `entries` as the digests of the entries of the file just written at `path`.
+    pub fn insert(&mut self, path: &Path, entries: HashMap<String, String>) {
+        if self.path.is_none() {
+            return;
+        }
+
+        match Stamp::of(path) {
+            Some(stamp) => {
+                let file = WrittenFile { stamp, entries };
+                self.contents.files.insert(path.to_path_buf(), file);
+            }
+            None => {
+                self.contents.files.remove(path);
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Writes the cache back if anything has changed.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(path) = self.path.as_deref().filter(|_| self.dirty) else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+
+        self.contents.version = VERSION;
+
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &self.contents)?;
+        writer.flush()?;
+
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_avif() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.avif");
+        std::fs::write(&path, b"\0\0\0\x1cftypavif\0\0\0\0").unwrap();
+
+        let e = Probe::read(&path).unwrap_err();
+        assert!(
+            e.to_string().contains("is AVIF, which is not supported"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn test_probe_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image.png");
+        image::RgbImage::new(3, 2).save(&image).unwrap();
+
+        let path = dir.path().join("cache.json");
+        let mut cache = ProbeCache::open(&path);
+        let probe = cache.probe(&image).unwrap();
+        assert_eq!(
+            probe,
+            Probe {
+                width: 3,
+                height: 2,
+                media_type: "image/png".to_string(),
+            }
+        );
+        cache.save().unwrap();
+
+        let mut cache = ProbeCache::open(&path);
+        assert!(cache.contents.entries.contains_key(&image));
+
+        image::RgbImage::new(4, 5).save(&image).unwrap();
+        assert_eq!(cache.probe(&image).unwrap().width, 4);
+        assert!(cache.dirty);
+    }
+
+    #[test]
+    fn test_image_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.png");
+        std::fs::write(&src, b"image").unwrap();
+
+        let cache = ImageCache::open(dir.path().join("images"));
+        let key = ImageCache::key(&["join", "a", "b"]);
+        assert_ne!(key, ImageCache::key(&["join", "ab"]));
+        assert_eq!(cache.get(&key, "png"), None);
+
+        let path = cache.insert(&key, "png", &src).unwrap();
+        assert_eq!(cache.get(&key, "png"), Some(path.clone()));
+        assert_eq!(cache.get(&key, "jpg"), None);
+        assert_eq!(std::fs::read(path).unwrap(), b"image");
+
+        assert!(!ImageCache::disabled().is_enabled());
+        assert!(ImageCache::disabled().insert(&key, "png", &src).is_err());
+    }
+
+    #[test]
+    fn test_entry_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let book = dir.path().join("book.epub");
+        std::fs::write(&book, b"book").unwrap();
+
+        let digest = EntryCache::digest(b"content", "stored");
+        assert_ne!(digest, EntryCache::digest(b"content", "deflated"));
+
+        let path = dir.path().join("cache.json");
+        let mut cache = EntryCache::open(&path);
+        assert_eq!(cache.entries(&book), None);
+        cache.insert(&book, HashMap::from([("a".to_string(), digest.clone())]));
+        cache.save().unwrap();
+
+        let cache = EntryCache::open(&path);
+        assert_eq!(cache.entries(&book).unwrap()["a"], digest);
+
+        // a file changed by anything but the build is not reused
+        std::fs::write(&book, b"other book").unwrap();
+        assert_eq!(cache.entries(&book), None);
+    }
+}
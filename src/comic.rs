@@ -0,0 +1,220 @@
+use crate::model::{CollectionType, Direction, Metadata, TitleType};
+use std::io::Write;
+use xml::writer::XmlEvent;
+use xml::EventWriter;
+
+/// Name of the metadata entry of a CBZ file.
+pub const COMIC_INFO: &str = "ComicInfo.xml";
+
+/// Elements of the credits in the order of the schema, with the MARC relators they are made of.
+const CREDITS: &[(&str, &[&str])] = &[
+    ("Writer", &["aut"]),
+    ("Penciller", &["art", "ill"]),
+    ("Inker", &["ink"]),
+    ("Colorist", &["clr"]),
+    ("Letterer", &["ltr"]),
+    ("CoverArtist", &["cov"]),
+    ("Editor", &["edt"]),
+    ("Translator", &["trl"]),
+    ("Publisher", &["pbl"]),
+];
+
+/// Values of `AgeRating` in the schema, each with the age it is given for if it is one.
+const AGE_RATINGS: &[(&str, Option<u32>)] = &[
+    ("Everyone", Some(0)),
+    ("Everyone 10+", Some(10)),
+    ("Teen", Some(13)),
+    ("MA15+", Some(15)),
+    ("Mature 17+", Some(17)),
+    ("Adults Only 18+", Some(18)),
+    ("Early Childhood", None),
+    ("G", None),
+    ("Kids to Adults", None),
+    ("M", None),
+    ("PG", None),
+    ("R18+", None),
+    ("Rating Pending", None),
+    ("Unknown", None),
+    ("X18+", None),
+];
+
+/// Returns the value of `AgeRating` for the age rating of the book: the value of the schema that
+/// it is, or else the one of the highest age that is not above the age it begins with, such as
+/// `MA15+` for `15+`.
+fn age_rating(rating: &str) -> Option<&'static str> {
+    let rating = rating.trim();
+    if let Some((value, _)) = AGE_RATINGS
+        .iter()
+        .find(|(value, _)| value.eq_ignore_ascii_case(rating))
+    {
+        return Some(value);
+    }
+
+    let digits = rating
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rating.len());
+    let age = rating[..digits].parse::<u32>().ok()?;
+    AGE_RATINGS
+        .iter()
+        .filter(|(_, min)| min.is_some_and(|min| min <= age))
+        .max_by_key(|(_, min)| *min)
+        .map(|(value, _)| *value)
+}
+
+/// Writes `ComicInfo.xml`, the metadata comic readers take from a CBZ file.
+///
+/// Creators without a role are credited as writers, and the content warnings are written as tags.
+/// `cover` tells whether the first of the `pages` is the front cover.
+pub fn write_comic_info<W: Write>(
+    w: &mut EventWriter<W>,
+    metadata: &Metadata,
+    direction: Direction,
+    pages: usize,
+    cover: bool,
+) -> xml::writer::Result<()> {
+    let element = |w: &mut EventWriter<W>, name: &str, value: &str| {
+        w.write(XmlEvent::start_element(name))?;
+        w.write(XmlEvent::characters(value))?;
+        w.write(XmlEvent::end_element())
+    };
+
+    w.write(
+        XmlEvent::start_element("ComicInfo")
+            .ns("xsi", "http://www.w3.org/2001/XMLSchema-instance")
+            .ns("xsd", "http://www.w3.org/2001/XMLSchema"),
+    )?;
+
+    let title = metadata
+        .title
+        .iter()
+        .find(|t| t.title_type == TitleType::Main)
+        .or_else(|| metadata.title.first());
+    if let Some(title) = title {
+        element(w, "Title", &title.name)?;
+    }
+
+    let series = metadata
+        .collection
+        .iter()
+        .find(|c| c.collection_type == CollectionType::Series);
+    if let Some(series) = series {
+        element(w, "Series", &series.name)?;
+        if let Some(position) = &series.position {
+            element(w, "Number", position.as_str())?;
+        }
+    }
+
+    if let Some(description) = &metadata.description {
+        element(w, "Summary", description)?;
+    }
+
+    for (name, roles) in CREDITS {
+        let credits = metadata
+            .creator
+            .iter()
+            .chain(&metadata.contributor)
+            .filter(|c| c.relator().is_some_and(|role| roles.contains(&role)))
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        if !credits.is_empty() {
+            element(w, name, &credits.join(", "))?;
+        }
+    }
+
+    if !metadata.content_warning.is_empty() {
+        element(w, "Tags", &metadata.content_warning.join(", "))?;
+    }
+
+    element(w, "PageCount", &pages.to_string())?;
+    element(w, "LanguageISO", &metadata.language)?;
+    if direction == Direction::RightToLeft {
+        element(w, "Manga", "YesAndRightToLeft")?;
+    }
+    if let Some(rating) = metadata.age_rating.as_deref().and_then(age_rating) {
+        element(w, "AgeRating", rating)?;
+    }
+
+    if cover && 0 < pages {
+        w.write(XmlEvent::start_element("Pages"))?;
+        w.write(
+            XmlEvent::start_element("Page")
+                .attr("Image", "0")
+                .attr("Type", "FrontCover"),
+        )?;
+        w.write(XmlEvent::end_element())?; // Page
+        w.write(XmlEvent::end_element())?; // Pages
+    }
+
+    w.write(XmlEvent::end_element())?; // ComicInfo
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Collection, Creator, Title};
+    use xml::EmitterConfig;
+
+    #[test]
+    fn test_write_comic_info() {
+        let creator = |name: &str, role: Option<&str>| Creator {
+            name: name.to_string(),
+            role: role.map(str::to_string),
+            ..Creator::default()
+        };
+        let metadata = Metadata {
+            title: vec![Title {
+                name: "Title".to_string(),
+                ..Title::default()
+            }],
+            creator: vec![
+                creator("A", None),
+                creator("B", Some("ill")),
+                creator("C", Some("aut")),
+                Creator {
+                    scheme: Some("onix:codelist17".to_string()),
+                    ..creator("E", Some("A01"))
+                },
+            ],
+            contributor: vec![creator("D", Some("pbl"))],
+            collection: vec![Collection {
+                name: "Series".to_string(),
+                collection_type: CollectionType::Series,
+                position: "2.5".parse().ok(),
+            }],
+            language: "ja".to_string(),
+            description: Some("Summary".to_string()),
+            age_rating: Some("15+".to_string()),
+            content_warning: vec!["violence".to_string(), "gore".to_string()],
+            ..Metadata::default()
+        };
+
+        let mut w = EventWriter::new_with_config(
+            Vec::new(),
+            EmitterConfig::new().write_document_declaration(false),
+        );
+        write_comic_info(&mut w, &metadata, Direction::RightToLeft, 3, true).unwrap();
+        let xml = String::from_utf8(w.into_inner()).unwrap();
+
+        assert!(xml.contains(
+            "<Title>Title</Title><Series>Series</Series><Number>2.5</Number>\
+             <Summary>Summary</Summary><Writer>"
+        ));
+        assert!(xml.contains("<Writer>A, C</Writer><Penciller>B</Penciller>"));
+        assert!(xml.contains(
+            "<Publisher>D</Publisher><Tags>violence, gore</Tags><PageCount>3</PageCount>"
+        ));
+        assert!(xml.contains("<Manga>YesAndRightToLeft</Manga><AgeRating>MA15+</AgeRating>"));
+        assert!(xml.contains(r#"<Page Image="0" Type="FrontCover" />"#));
+    }
+
+    #[test]
+    fn test_age_rating() {
+        assert_eq!(age_rating("teen"), Some("Teen"));
+        assert_eq!(age_rating("R18+"), Some("R18+"));
+        assert_eq!(age_rating("18+"), Some("Adults Only 18+"));
+        assert_eq!(age_rating("12"), Some("Everyone 10+"));
+        assert_eq!(age_rating("all ages"), None);
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::{Context as _, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::PathBuf;
+
+/// File name of the user configuration in the configuration directory of tsugumi.
+const CONFIG_FILE_NAME: &str = "config.yaml";
+
+/// Settings of the user that apply to every project.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// URLs of the destinations `publish` uploads books to, keyed on their names.
+    pub publish: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Returns the path to the user configuration: `TSUGUMI_CONFIG` if it is set, or
+    /// `tsugumi/config.yaml` in the configuration directory of the user.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("TSUGUMI_CONFIG") {
+            return Some(path.into());
+        }
+
+        let dir = if cfg!(windows) {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+                })
+        };
+        dir.map(|dir| dir.join(env!("CARGO_PKG_NAME")).join(CONFIG_FILE_NAME))
+    }
+
+    /// Reads the user configuration, which is empty if there is none.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to open `{}`", path.display()))
+            }
+        };
+
+        serde_yaml::from_reader(file)
+            .with_context(|| format!("failed to read `{}`", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let config: Config =
+            serde_yaml::from_str("publish:\n  nas: sftp://me@nas/books/\n").unwrap();
+        assert_eq!(config.publish["nas"], "sftp://me@nas/books/");
+
+        assert_eq!(
+            serde_yaml::from_str::<Config>("{}").unwrap(),
+            Config::default()
+        );
+        assert!(serde_yaml::from_str::<Config>("upload: {}").is_err());
+    }
+}
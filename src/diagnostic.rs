@@ -0,0 +1,134 @@
+use crate::i18n::tr;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Renders the line of `source` that `error` points at, with the offending token underlined and a
+/// suggestion for misspelled names.
+pub fn render(source: &str, path: &Path, error: &serde_yaml::Error) -> Option<String> {
+    let location = error.location()?;
+    let line = source.lines().nth(location.line().checked_sub(1)?)?;
+
+    // the column counts characters, while the line is sliced by bytes
+    let column = line
+        .char_indices()
+        .nth(location.column().saturating_sub(1))
+        .map_or(line.len(), |(i, _)| i);
+    let token = line
+        .get(column..)?
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .next()
+        .unwrap_or_default();
+
+    let number = location.line().to_string();
+    let gutter = " ".repeat(number.len());
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{gutter}--> {}:{}:{}",
+        path.display(),
+        location.line(),
+        location.column()
+    );
+    let _ = writeln!(out, "{gutter} |");
+    let _ = writeln!(out, "{number} | {line}");
+    let _ = write!(
+        out,
+        "{gutter} | {}{}",
+        " ".repeat(line[..column].chars().count()),
+        "^".repeat(token.chars().count().max(1))
+    );
+
+    if let Some(suggestion) = suggest(&error.to_string()) {
+        let _ = write!(
+            out,
+            "\n{gutter} = {}",
+            tr!(
+                "help: did you mean `{suggestion}`?",
+                "ヒント: `{suggestion}` の間違いではありませんか？"
+            )
+        );
+    }
+
+    Some(out)
+}
+
+/// Suggests the expected name closest to the unknown one in an `unknown field` or `unknown variant`
+/// error message.
+fn suggest(message: &str) -> Option<&str> {
+    let (_, rest) = message
+        .split_once("unknown field `")
+        .or_else(|| message.split_once("unknown variant `"))?;
+    let (unknown, expected) = rest.split_once('`')?;
+
+    expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|name| (distance(unknown, name), name))
+        .filter(|&(d, name)| d <= (name.chars().count() / 3).max(2))
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, name)| name)
+}
+
+/// Returns the Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let cost = if ca == cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(
+            suggest("unknown field `languag`, expected one of `title`, `language`"),
+            Some("language")
+        );
+        assert_eq!(
+            suggest("unknown variant `portait`, expected `landscape` or `portrait`"),
+            Some("portrait")
+        );
+        assert_eq!(suggest("unknown field `foo`, expected `title`"), None);
+        assert_eq!(suggest("invalid type: map"), None);
+    }
+
+    #[test]
+    fn test_render() {
+        #[derive(Debug, serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        #[allow(dead_code)]
+        struct Doc {
+            title: String,
+        }
+
+        let source = "titel: a\n";
+        let error = serde_yaml::from_str::<Doc>(source).unwrap_err();
+        assert_eq!(
+            render(source, Path::new("a.yaml"), &error).unwrap(),
+            " --> a.yaml:1:1\n  |\n1 | titel: a\n  | ^^^^^\n  = help: did you mean `title`?"
+        );
+
+        let source = "{title: 表題, titel: a}\n";
+        let error = serde_yaml::from_str::<Doc>(source).unwrap_err();
+        assert_eq!(
+            render(source, Path::new("a.yaml"), &error).unwrap(),
+            " --> a.yaml:1:13\n  |\n1 | {title: 表題, titel: a}\n  |             ^^^^^\n  \
+             = help: did you mean `title`?"
+        );
+    }
+}
@@ -0,0 +1,527 @@
+use anyhow::{bail, Context as _, Result};
+use std::fmt;
+
+/// Step of the path to a node of a YAML document.
+#[derive(Debug, Clone, Copy)]
+pub enum Segment<'a> {
+    /// Value of a key of a mapping.
+    Key(&'a str),
+
+    /// Item of a sequence, or the node itself for the first one, as the project file allows a
+    /// single item in place of a list.
+    Index(usize),
+}
+
+/// YAML document edited in place, which keeps the comments, the order of the keys and the
+/// formatting of every line but the edited ones.
+///
+/// Only nodes in block style can be edited; anything in flow style such as `[a, b]` fails, so
+/// that the caller can write the document as a whole instead.
+pub struct Document {
+    lines: Vec<String>,
+    trailing_newline: bool,
+}
+
+/// Lines that make a node of the document.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    start: usize,
+    end: usize,
+
+    /// Column the content of the node starts at.
+    indent: usize,
+
+    /// Whether the content of the first line starts at `indent`, after the `-` of a sequence item
+    /// or a key.
+    item: bool,
+
+    /// Whether the node is a scalar on the line of its key.
+    inline: bool,
+}
+
+impl Document {
+    pub fn new(source: &str) -> Self {
+        Self {
+            lines: source.lines().map(str::to_string).collect(),
+            trailing_newline: source.is_empty() || source.ends_with('\n'),
+        }
+    }
+
+    /// Sets `key` of the mapping at `path` to the YAML scalar `value`, adding the key after the
+    /// others if it is missing, or removes the key if `value` is `None`.
+    pub fn set(&mut self, path: &[Segment], key: &str, value: Option<&str>) -> Result<()> {
+        let node = self.resolve(path)?;
+
+        let Some(line) = self.find_key(node, key) else {
+            let Some(value) = value else {
+                return Ok(());
+            };
+            if !self.is_mapping(node) {
+                bail!("`{key}` cannot be added to a node that is not a mapping");
+            }
+
+            let last = (node.start..node.end)
+                .rev()
+                .find(|&l| self.content(node, l).is_some())
+                .unwrap_or(node.start);
+            let entry = format!("{}{key}: {value}", " ".repeat(node.indent));
+            self.lines.insert(last + 1, entry);
+            return Ok(());
+        };
+
+        let (start, end) = self
+            .inline_value(node, line)
+            .with_context(|| format!("`{key}` has a block value"))?;
+        match value {
+            Some(value) => self.lines[line].replace_range(start..end, value),
+            None if node.item && line == node.start => {
+                bail!("`{key}` cannot be removed from the line of its sequence item")
+            }
+            None => {
+                self.lines.remove(line);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Turns the scalar at `path` into a mapping that has it as the value of `key`, unless it is a
+    /// mapping already.
+    pub fn expand(&mut self, path: &[Segment], key: &str) -> Result<()> {
+        let node = self.resolve(path)?;
+        if self.is_mapping(node) {
+            return Ok(());
+        }
+        if node.inline {
+            bail!("the node is on the line of its key");
+        }
+
+        let mut lines = (node.start..node.end).filter(|&l| self.content(node, l).is_some());
+        let (Some(line), None) = (lines.next(), lines.next()) else {
+            bail!("the node is not a single line");
+        };
+        let (column, text) = self.content(node, line).unwrap_or_default();
+        if text.starts_with(['[', '{', '|', '>', '&', '*', '!']) {
+            bail!("the node is not a plain scalar");
+        }
+
+        self.lines[line].insert_str(column, &format!("{key}: "));
+        Ok(())
+    }
+
+    /// Adds `item`, a node in block style of one or more lines, to the end of the sequence at
+    /// `path`, turning a single scalar on the line of its key into a sequence first.
+    pub fn push(&mut self, path: &[Segment], item: &str) -> Result<()> {
+        let mut node = self.resolve(path)?;
+        if node.inline {
+            node = self.unfold(node)?;
+        }
+
+        let (_, text) = (node.start..node.end)
+            .find_map(|l| self.content(node, l))
+            .context("the node is empty")?;
+        if !is_item(text) {
+            bail!("the node is not a sequence in block style");
+        }
+
+        let last = (node.start..node.end)
+            .rev()
+            .find(|&l| self.content(node, l).is_some())
+            .unwrap_or(node.start);
+        let indent = " ".repeat(node.indent);
+        let lines = item.lines().enumerate().map(|(i, line)| match (i, line) {
+            (0, _) => format!("{indent}- {line}"),
+            (_, "") => String::new(),
+            _ => format!("{indent}  {line}"),
+        });
+        self.lines.splice(last + 1..last + 1, lines);
+        Ok(())
+    }
+
+    /// Moves the scalar of `node` on the line of its key to an item of a sequence on the next line,
+    /// and returns the node of the sequence.
+    fn unfold(&mut self, node: Node) -> Result<Node> {
+        let (line, start) = (node.start, node.indent);
+        let end = start + strip_comment(&self.lines[line][start..]).trim_end().len();
+        let value = self.lines[line][start..end].to_string();
+        if value.starts_with(['[', '{', '|', '>', '&', '*', '!']) {
+            bail!("the node is not a plain scalar");
+        }
+
+        // the sequence goes at the column of the key, after the `-` of an item it is on
+        let head = self.lines[line][..start].trim_end();
+        let column = head.len() - head.trim_start_matches([' ', '-']).len();
+        self.lines[line] = format!("{head}{}", &self.lines[line][end..]);
+        self.lines
+            .insert(line + 1, format!("{}- {value}", " ".repeat(column)));
+
+        Ok(Node {
+            start: line + 1,
+            end: line + 2,
+            indent: column,
+            item: false,
+            inline: false,
+        })
+    }
+
+    fn root(&self) -> Node {
+        let node = Node {
+            start: 0,
+            end: self.lines.len(),
+            indent: 0,
+            item: false,
+            inline: false,
+        };
+        Node {
+            indent: self.first_column(node).unwrap_or(0),
+            ..node
+        }
+    }
+
+    fn resolve(&self, path: &[Segment]) -> Result<Node> {
+        let mut node = self.root();
+        for segment in path {
+            node = match *segment {
+                Segment::Key(key) => {
+                    let line = self
+                        .find_key(node, key)
+                        .with_context(|| format!("`{key}` does not exist"))?;
+                    self.value(node, line)
+                        .with_context(|| format!("`{key}` has no value"))?
+                }
+                Segment::Index(index) => self.item(node, index)?,
+            };
+        }
+        Ok(node)
+    }
+
+    /// Returns the column and the text of `line` of `node`, unless it is blank or a comment.
+    fn content(&self, node: Node, line: usize) -> Option<(usize, &str)> {
+        let text = &self.lines[line];
+        let column = if node.item && line == node.start {
+            node.indent
+        } else {
+            text.len() - text.trim_start_matches(' ').len()
+        };
+
+        let content = text.get(column..)?.trim_end();
+        if content.is_empty()
+            || content.starts_with('#')
+            || (column == 0 && (content == "---" || content == "..."))
+        {
+            None
+        } else {
+            Some((column, content))
+        }
+    }
+
+    fn first_column(&self, node: Node) -> Option<usize> {
+        (node.start..node.end).find_map(|l| self.content(node, l).map(|(column, _)| column))
+    }
+
+    fn is_mapping(&self, node: Node) -> bool {
+        (node.start..node.end)
+            .find_map(|l| self.content(node, l))
+            .is_some_and(|(_, text)| split_key(text).is_some())
+    }
+
+    fn find_key(&self, node: Node, key: &str) -> Option<usize> {
+        (node.start..node.end).find(|&l| {
+            self.content(node, l).is_some_and(|(column, text)| {
+                column == node.indent && split_key(text).is_some_and(|(k, _)| k == key)
+            })
+        })
+    }
+
+    /// Returns the range of the scalar value on the line of a key, without the comment after it.
+    fn inline_value(&self, node: Node, line: usize) -> Option<(usize, usize)> {
+        let (column, text) = self.content(node, line)?;
+        let (_, rest) = split_key(text)?;
+        let offset = column + text.len() - rest.len();
+
+        let value = strip_comment(rest).trim_end();
+        let start = offset + rest.len() - rest.trim_start().len();
+        let end = offset + value.len();
+        (start < end).then_some((start, end))
+    }
+
+    /// Returns the node of the value of the key on `line`.
+    fn value(&self, node: Node, line: usize) -> Option<Node> {
+        if let Some((start, _)) = self.inline_value(node, line) {
+            return Some(Node {
+                start: line,
+                end: line + 1,
+                indent: start,
+                item: true,
+                inline: true,
+            });
+        }
+
+        // a sequence may be at the same column as its key
+        let end = (line + 1..node.end)
+            .find(|&l| {
+                self.content(node, l).is_some_and(|(column, text)| {
+                    column < node.indent || (column == node.indent && !is_item(text))
+                })
+            })
+            .unwrap_or(node.end);
+        let value = Node {
+            start: line + 1,
+            end,
+            indent: 0,
+            item: false,
+            inline: false,
+        };
+        Some(Node {
+            indent: self.first_column(value)?,
+            ..value
+        })
+    }
+
+    fn item(&self, node: Node, index: usize) -> Result<Node> {
+        let (_, text) = (node.start..node.end)
+            .find_map(|l| self.content(node, l))
+            .context("the node is empty")?;
+        if text.starts_with(['[', '{']) {
+            bail!("the node is in flow style");
+        }
+        if !is_item(text) {
+            return match index {
+                0 => Ok(node),
+                _ => bail!("item {index} does not exist"),
+            };
+        }
+
+        let items = (node.start..node.end)
+            .filter(|&l| {
+                self.content(node, l)
+                    .is_some_and(|(column, text)| column == node.indent && is_item(text))
+            })
+            .collect::<Vec<_>>();
+        let &line = items
+            .get(index)
+            .with_context(|| format!("item {index} does not exist"))?;
+        let end = items.get(index + 1).copied().unwrap_or(node.end);
+
+        let (column, text) = self.content(node, line).unwrap_or_default();
+        let rest = &text[1..];
+        if rest.trim().is_empty() {
+            let item = Node {
+                start: line + 1,
+                end,
+                indent: 0,
+                item: false,
+                inline: false,
+            };
+            return Ok(Node {
+                indent: self.first_column(item).context("the item is empty")?,
+                ..item
+            });
+        }
+
+        Ok(Node {
+            start: line,
+            end,
+            indent: column + 1 + rest.len() - rest.trim_start().len(),
+            item: true,
+            inline: false,
+        })
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if 0 < i {
+                f.write_str("\n")?;
+            }
+            f.write_str(line)?;
+        }
+        if self.trailing_newline && !self.lines.is_empty() {
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats `value` as a YAML scalar that fits on a line.
+pub fn scalar(value: &str) -> Result<String> {
+    let yaml = serde_yaml::to_string(value)?;
+    let yaml = yaml.trim_end_matches('\n');
+    if yaml.contains('\n') {
+        bail!("`{value}` does not fit on a line");
+    }
+    Ok(yaml.to_string())
+}
+
+fn is_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// Splits `key: value` into the plain key and the rest after the colon.
+fn split_key(text: &str) -> Option<(&str, &str)> {
+    if text.starts_with(['-', '[', '{', '"', '\'', '#', '?']) {
+        return None;
+    }
+
+    let mut offset = 0;
+    while let Some(i) = text[offset..].find(':') {
+        let colon = offset + i;
+        let rest = &text[colon + 1..];
+        if rest.is_empty() || rest.starts_with([' ', '\t']) {
+            return Some((&text[..colon], rest));
+        }
+        offset = colon + 1;
+    }
+
+    None
+}
+
+/// Removes the comment from the end of an inline value.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &text[..i],
+            None => {}
+        }
+        previous = c;
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Segment::*;
+
+    const SOURCE: &str = "\
+# the book
+metadata:
+  title: Title  # main title
+chapter:
+- name: First
+  page:
+  - a.png  # cover
+  - src: b.png
+    toc: Old
+-
+  page:
+    - c.png
+- page: [d.png, e.png]
+";
+
+    #[test]
+    fn test_set() {
+        let mut document = Document::new(SOURCE);
+        document
+            .set(&[Key("metadata")], "title", Some("New"))
+            .unwrap();
+        document
+            .set(
+                &[Key("chapter"), Index(0), Key("page"), Index(1)],
+                "toc",
+                None,
+            )
+            .unwrap();
+        document
+            .set(&[Key("chapter"), Index(1)], "toc", Some("false"))
+            .unwrap();
+        assert_eq!(
+            document.to_string(),
+            SOURCE
+                .replace("Title  # main", "New  # main")
+                .replace("    toc: Old\n", "")
+                .replace("    - c.png\n", "    - c.png\n  toc: false\n")
+        );
+
+        let page = [Key("chapter"), Index(1), Key("page"), Index(0)];
+        let mut document = Document::new(SOURCE);
+        document.expand(&page, "src").unwrap();
+        document.set(&page, "toc", Some("'#1'")).unwrap();
+        assert!(document
+            .to_string()
+            .contains("    - src: c.png\n      toc: '#1'\n- page"));
+
+        let mut document = Document::new(SOURCE);
+        let page = [Key("chapter"), Index(0), Key("page"), Index(0)];
+        document.expand(&page, "src").unwrap();
+        assert!(document.to_string().contains("  - src: a.png  # cover\n"));
+
+        let mut document = Document::new(SOURCE);
+        let page = [Key("chapter"), Index(2), Key("page"), Index(0)];
+        assert!(document.set(&page, "toc", Some("x")).is_err());
+        assert!(document
+            .set(&[Key("chapter"), Index(3)], "toc", Some("x"))
+            .is_err());
+        assert!(document.set(&[Key("chapter")], "toc", Some("x")).is_err());
+        assert_eq!(document.to_string(), SOURCE);
+
+        let source = "chapter:\n- page: a.png  # cover\n";
+        let mut document = Document::new(source);
+        let page = [Key("chapter"), Index(0), Key("page"), Index(0)];
+        document.set(&page, "toc", None).unwrap();
+        assert!(document.expand(&page, "src").is_err());
+        assert!(document.set(&page, "toc", Some("x")).is_err());
+        assert_eq!(document.to_string(), source);
+    }
+
+    #[test]
+    fn test_push() {
+        let mut document = Document::new(SOURCE);
+        document
+            .push(&[Key("chapter"), Index(0), Key("page")], "f.png")
+            .unwrap();
+        document
+            .push(&[Key("chapter"), Index(1), Key("page")], "g.png")
+            .unwrap();
+        document
+            .push(&[Key("chapter")], "name: Last\npage:\n- h.png")
+            .unwrap();
+        assert_eq!(
+            document.to_string(),
+            SOURCE
+                .replace("    toc: Old\n", "    toc: Old\n  - f.png\n")
+                .replace("    - c.png\n", "    - c.png\n    - g.png\n")
+                + "- name: Last\n  page:\n  - h.png\n"
+        );
+
+        let source = "chapter:\n- page: a.png  # cover\n";
+        let mut document = Document::new(source);
+        let page = [Key("chapter"), Index(0), Key("page")];
+        document.push(&page, "b.png").unwrap();
+        assert_eq!(
+            document.to_string(),
+            "chapter:\n- page:  # cover\n  - a.png\n  - b.png\n"
+        );
+
+        let mut document = Document::new(SOURCE);
+        let page = [Key("chapter"), Index(2), Key("page")];
+        assert!(document.push(&page, "f.png").is_err());
+        assert!(document.push(&[Key("metadata")], "f.png").is_err());
+        assert_eq!(document.to_string(), SOURCE);
+    }
+
+    #[test]
+    fn test_scalar() {
+        assert_eq!(scalar("caption").unwrap(), "caption");
+        assert_eq!(scalar("a: b").unwrap(), "'a: b'");
+        assert_eq!(scalar("true").unwrap(), "'true'");
+        assert!(scalar("a\nb").is_err());
+    }
+
+    #[test]
+    fn test_strip_comment() {
+        assert_eq!(strip_comment(" a # b"), " a ");
+        assert_eq!(strip_comment(" a#b"), " a#b");
+        assert_eq!(strip_comment(r#" "a # \" # b" # c"#), r#" "a # \" # b" "#);
+        assert_eq!(strip_comment(" 'a # b'"), " 'a # b'");
+    }
+}
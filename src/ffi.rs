@@ -0,0 +1,339 @@
+//! C interface of the EPub builder, for programs not written in Rust to build books without running
+//! the `tsugumi` binary. Its header is `include/tsugumi.h`, generated by `cbindgen`.
+//!
+//! ```c
+//! TsugumiBuilder *builder = tsugumi_builder_new(NULL, "book");
+//! tsugumi_builder_set_metadata(builder, "title", "Title");
+//! tsugumi_builder_set_metadata(builder, "language", "ja");
+//! tsugumi_builder_add_page_path(builder, "001.png");
+//! tsugumi_builder_add_page_bytes(builder, "002.png", png, png_len);
+//! if (tsugumi_builder_build_to_path(builder, "book.epub") != 0) {
+//!     fprintf(stderr, "%s\n", tsugumi_last_error());
+//! }
+//! tsugumi_builder_free(builder);
+//! ```
+//!
+//! The functions that return `int` return 0 on success, or -1 on failure, whose message is then
+//! taken by [`tsugumi_last_error`]. The strings are UTF-8 and terminated by NUL.
+
+use crate::build::EpubBuilder;
+use crate::model::{Book, Chapter, Creator, Page, Title};
+use anyhow::{Context as _, Result};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::path::PathBuf;
+
+thread_local! {
+    /// Message of the last failure on the thread.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Builder of an EPub file, created by [`tsugumi_builder_new`] and freed by
+/// [`tsugumi_builder_free`].
+pub struct TsugumiBuilder {
+    root: PathBuf,
+    book: Book,
+    sources: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl TsugumiBuilder {
+    fn builder(&self) -> EpubBuilder {
+        let builder = EpubBuilder::new(self.book.clone(), &self.root);
+        self.sources.iter().fold(builder, |builder, (path, data)| {
+            builder.source(path, data.clone())
+        })
+    }
+
+    /// Adds a page of the image at `src` to the last chapter, which is created if there is none.
+    fn add_page(&mut self, src: PathBuf) {
+        if self.book.chapter.is_empty() {
+            self.book.chapter.push(Chapter::default());
+        }
+        let chapter = self.book.chapter.last_mut().unwrap();
+        chapter.page.push(Page {
+            src,
+            ..Page::default()
+        });
+    }
+}
+
+/// Records the error of `result` as the last one, and returns the status for it.
+fn status(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            let message = format!("{e:#}").replace('\0', " ");
+            let message = CString::new(message).unwrap_or_default();
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+            -1
+        }
+    }
+}
+
+/// Reads the string at `s` as UTF-8, naming it `name` in the error.
+unsafe fn string<'a>(s: *const c_char, name: &str) -> Result<&'a str> {
+    anyhow::ensure!(!s.is_null(), "`{name}` is null");
+    CStr::from_ptr(s)
+        .to_str()
+        .with_context(|| format!("`{name}` is not UTF-8"))
+}
+
+/// Returns the message of the last failure on the calling thread, or null if none has failed.
+///
+/// The message is valid until the next failure on the thread.
+#[no_mangle]
+pub extern "C" fn tsugumi_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Creates a builder of the book described by the project file `yaml`, or of an empty book if it
+/// is null, whose page sources are relative to the directory `root`.
+///
+/// Returns null if `yaml` does not describe a book.
+///
+/// # Safety
+///
+/// `yaml` must be null or a string, and `root` a string.
+#[no_mangle]
+pub unsafe extern "C" fn tsugumi_builder_new(
+    yaml: *const c_char,
+    root: *const c_char,
+) -> *mut TsugumiBuilder {
+    let mut builder = None;
+    let result = (|| {
+        let root = string(root, "root")?;
+        let book = match yaml.is_null() {
+            true => Book::default(),
+            false => serde_yaml::from_str(string(yaml, "yaml")?)?,
+        };
+        builder = Some(TsugumiBuilder {
+            root: root.into(),
+            book,
+            sources: Vec::new(),
+        });
+        Ok(())
+    })();
+
+    match status(result) {
+        0 => builder.map_or(std::ptr::null_mut(), |builder| {
+            Box::into_raw(Box::new(builder))
+        }),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Sets the metadata `name` of the book to `value`.
+///
+/// `name` is one of `title`, `creator`, `language`, `identifier`, `description`, `publisher`,
+/// `rights` and `subject`. A title, a creator and a subject are added to the ones already set.
+///
+/// # Safety
+///
+/// `builder` must be a builder that has not been freed, and `name` and `value` strings.
+#[no_mangle]
+pub unsafe extern "C" fn tsugumi_builder_set_metadata(
+    builder: *mut TsugumiBuilder,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    let metadata = &mut (*builder).book.metadata;
+    status((|| {
+        let value = string(value, "value")?.to_string();
+        match string(name, "name")? {
+            "title" => metadata.title.push(Title {
+                name: value,
+                ..Title::default()
+            }),
+            "creator" => metadata.creator.push(Creator {
+                name: value,
+                ..Creator::default()
+            }),
+            "language" => metadata.language = value,
+            "identifier" => metadata.identifier = value,
+            "description" => metadata.description = Some(value),
+            "publisher" => metadata.publisher = Some(value),
+            "rights" => metadata.rights = Some(value),
+            "subject" => metadata.subject.push(value),
+            name => anyhow::bail!("unknown metadata `{name}`"),
+        }
+        Ok(())
+    })())
+}
+
+/// Adds a page of the image at `path`, relative to the root, to the last chapter of the book.
+///
+/// # Safety
+///
+/// `builder` must be a builder that has not been freed, and `path` a string.
+#[no_mangle]
+pub unsafe extern "C" fn tsugumi_builder_add_page_path(
+    builder: *mut TsugumiBuilder,
+    path: *const c_char,
+) -> c_int {
+    let builder = &mut *builder;
+    status((|| {
+        builder.add_page(string(path, "path")?.into());
+        Ok(())
+    })())
+}
+
+/// Adds a page of the image of `len` bytes at `data` to the last chapter of the book, as if it
+/// were the file `name` relative to the root, whose extension tells the format of the image.
+///
+/// The bytes are copied, so they can be freed once this returns.
+///
+/// # Safety
+///
+/// `builder` must be a builder that has not been freed, `name` a string, and `data` must point to
+/// `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn tsugumi_builder_add_page_bytes(
+    builder: *mut TsugumiBuilder,
+    name: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let builder = &mut *builder;
+    status((|| {
+        let name = PathBuf::from(string(name, "name")?);
+        anyhow::ensure!(!data.is_null() || len == 0, "`data` is null");
+        let data = match len {
+            0 => Vec::new(),
+            _ => std::slice::from_raw_parts(data, len).to_vec(),
+        };
+        builder.sources.push((name.clone(), data));
+        builder.add_page(name);
+        Ok(())
+    })())
+}
+
+/// Builds the book and writes the EPub file to `path`.
+///
+/// # Safety
+///
+/// `builder` must be a builder that has not been freed, and `path` a string.
+#[no_mangle]
+pub unsafe extern "C" fn tsugumi_builder_build_to_path(
+    builder: *const TsugumiBuilder,
+    path: *const c_char,
+) -> c_int {
+    let builder = &*builder;
+    status((|| {
+        let path = string(path, "path")?;
+        let file =
+            std::fs::File::create(path).with_context(|| format!("failed to create `{path}`"))?;
+        builder.builder().write(file)?;
+        Ok(())
+    })())
+}
+
+/// Builds the book and stores the EPub file in a buffer, whose address and length are written to
+/// `data` and `len`. The buffer is freed by [`tsugumi_buffer_free`].
+///
+/// # Safety
+///
+/// `builder` must be a builder that has not been freed, and `data` and `len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn tsugumi_builder_build_to_buffer(
+    builder: *const TsugumiBuilder,
+    data: *mut *mut u8,
+    len: *mut usize,
+) -> c_int {
+    let builder = &*builder;
+    status((|| {
+        let buf = builder.builder().write_to_vec()?.into_boxed_slice();
+        *len = buf.len();
+        *data = Box::into_raw(buf).cast();
+        Ok(())
+    })())
+}
+
+/// Frees the buffer of `len` bytes at `data` that [`tsugumi_builder_build_to_buffer`] returned.
+///
+/// # Safety
+///
+/// `data` must be null or a buffer returned with `len` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn tsugumi_buffer_free(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// Frees `builder`.
+///
+/// # Safety
+///
+/// `builder` must be null or a builder that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn tsugumi_builder_free(builder: *mut TsugumiBuilder) {
+    if !builder.is_null() {
+        drop(Box::from_raw(builder));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_build_to_buffer() {
+        let mut png = Cursor::new(Vec::new());
+        image::RgbImage::new(6, 9)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+
+        let root = tempfile::tempdir().unwrap();
+        let root = CString::new(root.path().to_str().unwrap()).unwrap();
+        unsafe {
+            let builder = tsugumi_builder_new(std::ptr::null(), root.as_ptr());
+            assert!(!builder.is_null());
+            for (name, value) in [("title", "Title"), ("language", "ja"), ("identifier", "id")] {
+                let name = CString::new(name).unwrap();
+                let value = CString::new(value).unwrap();
+                let status = tsugumi_builder_set_metadata(builder, name.as_ptr(), value.as_ptr());
+                assert_eq!(status, 0);
+            }
+            let name = c"a.png";
+            let status =
+                tsugumi_builder_add_page_bytes(builder, name.as_ptr(), png.as_ptr(), png.len());
+            assert_eq!(status, 0);
+
+            let (mut data, mut len) = (std::ptr::null_mut(), 0);
+            assert_eq!(
+                tsugumi_builder_build_to_buffer(builder, &mut data, &mut len),
+                0
+            );
+            let epub = std::slice::from_raw_parts(data, len).to_vec();
+            tsugumi_buffer_free(data, len);
+            tsugumi_builder_free(builder);
+
+            let zip = zip::ZipArchive::new(Cursor::new(epub)).unwrap();
+            assert_eq!(
+                zip.file_names()
+                    .filter(|name| name.ends_with(".png"))
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_metadata_unknown() {
+        let root = c"book";
+        unsafe {
+            let builder = tsugumi_builder_new(std::ptr::null(), root.as_ptr());
+            let status = tsugumi_builder_set_metadata(builder, c"color".as_ptr(), c"red".as_ptr());
+            assert_eq!(status, -1);
+            let message = CStr::from_ptr(tsugumi_last_error()).to_str().unwrap();
+            assert_eq!(message, "unknown metadata `color`");
+            tsugumi_builder_free(builder);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::OnceLock;
+
+/// Language of the messages shown to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Lang {
+    En,
+    Ja,
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+impl Lang {
+    /// Determines the language from the locale environment variables.
+    pub fn detect() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .map_or(Self::En, |value| Self::from_locale(&value))
+    }
+
+    fn from_locale(locale: &str) -> Self {
+        if locale.starts_with("ja") {
+            Self::Ja
+        } else {
+            Self::En
+        }
+    }
+
+    /// Returns the language selected for the process, English unless [`init`] says otherwise.
+    pub fn current() -> Self {
+        LANG.get().copied().unwrap_or(Self::En)
+    }
+}
+
+/// Selects the language of the messages, detecting it from the locale if `lang` is `None`.
+pub fn init(lang: Option<Lang>) {
+    let _ = LANG.set(lang.unwrap_or_else(Lang::detect));
+}
+
+/// Formats the message in the current language.
+///
+/// ```ignore
+/// tr!("`{}` does not exist", "`{}` が存在しません", path.display())
+/// ```
+macro_rules! tr {
+    ($en:literal, $ja:literal $(, $args:expr)* $(,)?) => {
+        match $crate::i18n::Lang::current() {
+            $crate::i18n::Lang::En => format!($en $(, $args)*),
+            $crate::i18n::Lang::Ja => format!($ja $(, $args)*),
+        }
+    };
+}
+
+pub(crate) use tr;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_locale() {
+        assert_eq!(Lang::from_locale("ja_JP.UTF-8"), Lang::Ja);
+        assert_eq!(Lang::from_locale("en_US.UTF-8"), Lang::En);
+        assert_eq!(Lang::from_locale("C"), Lang::En);
+    }
+
+    #[test]
+    fn test_tr() {
+        let n = 1;
+        assert_eq!(tr!("{n} page", "{n} ページ"), "1 page");
+    }
+}
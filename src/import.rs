@@ -0,0 +1,654 @@
+use crate::model::{
+    Book, Chapter, Collection, CollectionType, Creator, Direction, Metadata, Orientation, Page,
+    Rendition, Title, TitleType, Viewport,
+};
+use crate::task::build::has_property;
+use crate::url::percent_decode;
+use anyhow::{Context as _, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+use xml::reader::{EventReader, XmlEvent};
+use zip::ZipArchive;
+
+/// Directory the images of an imported book are extracted to.
+pub const IMAGE_DIR: &str = "image";
+
+/// Book reconstructed from an EPub file.
+pub struct Import {
+    pub book: Book,
+
+    /// Constructs of the EPub file that the project file cannot represent.
+    pub findings: Vec<String>,
+}
+
+/// Element of an XML document, without its children.
+pub(crate) struct Element {
+    pub name: String,
+    attributes: Vec<(String, String)>,
+    pub text: String,
+    depth: usize,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct Item {
+    href: String,
+    media_type: String,
+    properties: String,
+}
+
+/// Document in the spine of an EPub file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpineItem {
+    /// Name of the entry of the document.
+    pub href: String,
+
+    /// Whether the document takes both sides of a spread.
+    pub center: bool,
+}
+
+/// Reads the fixed-layout EPub file at `path` and extracts its page images under `root`.
+pub fn import(path: &Path, root: &Path) -> Result<Import> {
+    let file = File::open(path).with_context(|| format!("failed to open `{}`", path.display()))?;
+    let mut zip = ZipArchive::new(BufReader::new(file))
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+
+    let (opf_path, package) = read_package(&mut zip)?;
+    let items = read_items(&opf_path, &package);
+
+    let mut findings = Vec::new();
+
+    let cover_image = items
+        .values()
+        .find(|item| has_property(&item.properties, "cover-image"))
+        .map(|item| item.href.clone());
+
+    let toc = match items
+        .values()
+        .find(|item| has_property(&item.properties, "nav"))
+    {
+        Some(nav) => read_nav(&mut zip, &nav.href, &mut findings)?,
+        None => {
+            findings.push("no navigation document; chapters are not restored".to_string());
+            HashMap::new()
+        }
+    };
+
+    let mut images = 0;
+    let mut chapter = Vec::<Chapter>::new();
+    for itemref in package.iter().filter(|e| e.name == "itemref") {
+        let Some(item) = itemref.attr("idref").and_then(|id| items.get(id)) else {
+            continue;
+        };
+
+        if itemref.attr("linear") == Some("no") {
+            findings.push(format!(
+                "`{}` is not linear, but is imported as a page",
+                item.href
+            ));
+        }
+
+        let image = if item.media_type.starts_with("image/") {
+            item.href.clone()
+        } else {
+            let document = read_xml(&mut zip, &item.href)?;
+            let mut refs = document
+                .iter()
+                .filter(|e| e.name == "img" || e.name == "image")
+                .filter_map(|e| e.attr("src").or(e.attr("xlink:href")).or(e.attr("href")));
+
+            let Some(image) = refs.next() else {
+                findings.push(format!("`{}` has no image and is skipped", item.href));
+                continue;
+            };
+            if refs.next().is_some() {
+                findings.push(format!("only the first image of `{}` is used", item.href));
+            }
+
+            resolve(&item.href, image)
+        };
+
+        images += 1;
+        let ext = Path::new(&image)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("jpg");
+        let src = Path::new(IMAGE_DIR).join(format!("{images:04}.{ext}"));
+        extract(&mut zip, &image, &root.join(&src))?;
+
+        let page = Page {
+            src,
+            ..Page::default()
+        };
+        let caption = toc.get(&item.href).cloned();
+
+        if cover_image.as_deref() == Some(image.as_str()) {
+            chapter.push(Chapter {
+                name: caption.or_else(|| Some("表紙".to_string())),
+                page: vec![page],
+                cover: true,
+                ..Chapter::default()
+            });
+        } else if caption.is_some() || chapter.last().is_none_or(|c| c.cover) {
+            chapter.push(Chapter {
+                name: caption,
+                page: vec![page],
+                ..Chapter::default()
+            });
+        } else {
+            chapter.last_mut().unwrap().page.push(page);
+        }
+    }
+
+    if chapter.is_empty() {
+        anyhow::bail!("`{}` has no pages", path.display());
+    }
+
+    let mut metadata = read_metadata(&package, &mut findings);
+    if metadata.title.is_empty() {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        findings.push(format!(
+            "no title; the book is titled `{name}` after the file"
+        ));
+        metadata.title.push(Title {
+            name: name.to_string(),
+            title_type: TitleType::Main,
+            ..Title::default()
+        });
+    }
+
+    let book = Book {
+        metadata,
+        rendition: read_rendition(&package),
+        chapter,
+        ..Book::default()
+    };
+
+    Ok(Import { book, findings })
+}
+
+/// Reads the documents of the spine of the EPub file in `zip` in reading order, and the direction
+/// the pages progress in.
+pub fn read_spine<R: Read + Seek>(zip: &mut ZipArchive<R>) -> Result<(Vec<SpineItem>, Direction)> {
+    let (opf_path, package) = read_package(zip)?;
+    let items = read_items(&opf_path, &package);
+
+    let spine = package
+        .iter()
+        .filter(|e| e.name == "itemref")
+        .filter_map(|itemref| {
+            let item = items.get(itemref.attr("idref")?)?;
+            let properties = itemref.attr("properties").unwrap_or_default();
+            Some(SpineItem {
+                href: item.href.clone(),
+                center: has_property(properties, "rendition:page-spread-center")
+                    || has_property(properties, "rendition:spread-none"),
+            })
+        })
+        .collect();
+
+    Ok((spine, read_rendition(&package).direction))
+}
+
+/// Metadata and cover of an EPub file, which catalogs list it with.
+pub struct Summary {
+    pub metadata: Metadata,
+
+    /// Entry name and media type of the cover image, if the book has one.
+    pub cover: Option<(String, String)>,
+}
+
+/// Reads the metadata and the cover image of the EPub file in `zip`.
+pub fn read_summary<R: Read + Seek>(zip: &mut ZipArchive<R>) -> Result<Summary> {
+    let (opf_path, package) = read_package(zip)?;
+    let cover = read_items(&opf_path, &package)
+        .into_values()
+        .find(|item| has_property(&item.properties, "cover-image"))
+        .map(|item| (item.href, item.media_type));
+
+    Ok(Summary {
+        metadata: read_metadata(&package, &mut Vec::new()),
+        cover,
+    })
+}
+
+/// Reads the size of the page from the `viewport` meta element of the XHTML document `name`.
+pub fn read_viewport<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Option<Viewport>> {
+    let viewport = read_xml(zip, name)?
+        .iter()
+        .find(|e| e.name == "meta" && e.attr("name") == Some("viewport"))
+        .and_then(|e| e.attr("content"))
+        .and_then(|content| {
+            let mut width = None;
+            let mut height = None;
+            for property in content.split(',') {
+                match property.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                    Some(("width", v)) => width = v.parse().ok(),
+                    Some(("height", v)) => height = v.parse().ok(),
+                    _ => {}
+                }
+            }
+            Some(Viewport {
+                width: width?,
+                height: height?,
+            })
+        });
+
+    Ok(viewport)
+}
+
+/// Reads the package document of the EPub file in `zip`, and returns its entry name and elements.
+fn read_package<R: Read + Seek>(zip: &mut ZipArchive<R>) -> Result<(String, Vec<Element>)> {
+    let container = read_xml(zip, "META-INF/container.xml")?;
+    let opf_path = container
+        .iter()
+        .find(|e| e.name == "rootfile")
+        .and_then(|e| e.attr("full-path"))
+        .context("no package document in the container")?
+        .to_string();
+    let package = read_xml(zip, &opf_path)?;
+
+    Ok((opf_path, package))
+}
+
+/// Reads the items of the manifest keyed on their ids.
+fn read_items(opf_path: &str, package: &[Element]) -> HashMap<String, Item> {
+    package
+        .iter()
+        .filter(|e| e.name == "item")
+        .filter_map(|e| {
+            let item = Item {
+                href: resolve(opf_path, e.attr("href")?),
+                media_type: e.attr("media-type").unwrap_or_default().to_string(),
+                properties: e.attr("properties").unwrap_or_default().to_string(),
+            };
+            Some((e.attr("id")?.to_string(), item))
+        })
+        .collect()
+}
+
+fn read_metadata(package: &[Element], findings: &mut Vec<String>) -> Metadata {
+    let text = |name: &str| {
+        package
+            .iter()
+            .filter(|e| e.name == name)
+            .map(|e| e.text.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+    };
+
+    let unique_id = package
+        .iter()
+        .find(|e| e.name == "package")
+        .and_then(|e| e.attr("unique-identifier"));
+    let identifier = package
+        .iter()
+        .filter(|e| e.name == "identifier")
+        .find(|e| unique_id.is_some() && e.attr("id") == unique_id)
+        .map(|e| e.text.trim().to_string())
+        .or_else(|| text("identifier").into_iter().next())
+        .unwrap_or_else(|| format!("urn:uuid:{}", uuid::Uuid::new_v4()));
+
+    let mut title = text("title");
+    if 1 < title.len() {
+        findings.push("only the first title is imported".to_string());
+    }
+    title.truncate(1);
+
+    Metadata {
+        title: title
+            .into_iter()
+            .map(|name| Title {
+                name,
+                title_type: TitleType::Main,
+                ..Title::default()
+            })
+            .collect(),
+        creator: text("creator")
+            .into_iter()
+            .map(|name| Creator {
+                name,
+                ..Creator::default()
+            })
+            .collect(),
+        contributor: text("contributor")
+            .into_iter()
+            .map(|name| Creator {
+                name,
+                ..Creator::default()
+            })
+            .collect(),
+        language: text("language")
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| "ja".to_string()),
+        identifier,
+        description: text("description").into_iter().next(),
+        publisher: text("publisher").into_iter().next(),
+        publication_date: text("date")
+            .into_iter()
+            .next()
+            .and_then(|s| s.get(..10).unwrap_or(&s).parse().ok()),
+        rights: text("rights").into_iter().next(),
+        subject: text("subject"),
+        collection: read_collections(package),
+        ..Metadata::default()
+    }
+}
+
+/// Reads the collections the book belongs to, or else the series calibre records.
+fn read_collections(package: &[Element]) -> Vec<Collection> {
+    let refined = |id: Option<&str>, property: &str| {
+        let refines = format!("#{}", id?);
+        package
+            .iter()
+            .find(|e| {
+                e.name == "meta"
+                    && e.attr("refines") == Some(refines.as_str())
+                    && e.attr("property") == Some(property)
+            })
+            .map(|e| e.text.trim().to_string())
+    };
+
+    let collections = package
+        .iter()
+        .filter(|e| e.name == "meta" && e.attr("property") == Some("belongs-to-collection"))
+        .filter(|e| !e.text.trim().is_empty())
+        .map(|e| Collection {
+            name: e.text.trim().to_string(),
+            collection_type: refined(e.attr("id"), "collection-type")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(CollectionType::Series),
+            position: refined(e.attr("id"), "group-position").and_then(|s| s.parse().ok()),
+        })
+        .collect::<Vec<_>>();
+    if !collections.is_empty() {
+        return collections;
+    }
+
+    let calibre = |name: &str| {
+        package
+            .iter()
+            .find(|e| e.name == "meta" && e.attr("name") == Some(name))
+            .and_then(|e| e.attr("content"))
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+    };
+    calibre("calibre:series")
+        .map(|name| Collection {
+            name: name.to_string(),
+            collection_type: CollectionType::Series,
+            position: calibre("calibre:series_index").and_then(|s| s.parse().ok()),
+        })
+        .into_iter()
+        .collect()
+}
+
+fn read_rendition(package: &[Element]) -> Rendition {
+    let meta = |property: &str| {
+        package
+            .iter()
+            .find(|e| e.name == "meta" && e.attr("property") == Some(property))
+            .map(|e| e.text.trim())
+    };
+
+    let direction = package
+        .iter()
+        .find(|e| e.name == "spine")
+        .and_then(|e| e.attr("page-progression-direction"))
+        .and_then(|s| s.parse::<Direction>().ok())
+        .unwrap_or_default();
+
+    Rendition {
+        direction,
+        layout: meta("rendition:layout")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_default(),
+        orientation: meta("rendition:orientation")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(Orientation::Portrait),
+        spread: meta("rendition:spread")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_default(),
+        ..Rendition::default()
+    }
+}
+
+/// Reads the captions of the table of contents keyed on the path of the document they point to.
+fn read_nav<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    href: &str,
+    findings: &mut Vec<String>,
+) -> Result<HashMap<String, String>> {
+    let nav = read_xml(zip, href)?;
+
+    let Some(toc) = nav
+        .iter()
+        .position(|e| e.name == "nav" && e.attr("epub:type") == Some("toc"))
+    else {
+        findings.push("no table of contents; chapters are not restored".to_string());
+        return Ok(HashMap::new());
+    };
+
+    let depth = nav[toc].depth;
+    let entries = nav[toc + 1..]
+        .iter()
+        .take_while(|e| depth < e.depth)
+        .filter(|e| e.name == "a")
+        .collect::<Vec<_>>();
+
+    let top = entries.iter().map(|e| e.depth).min().unwrap_or_default();
+    if entries.iter().any(|e| top < e.depth) {
+        findings.push("nested entries of the table of contents are flattened".to_string());
+    }
+
+    let mut captions = HashMap::new();
+    for entry in entries {
+        let Some(target) = entry.attr("href") else {
+            continue;
+        };
+        if target.contains('#') {
+            findings.push(format!(
+                "`{target}` points into a page; its fragment is dropped"
+            ));
+        }
+
+        captions
+            .entry(resolve(href, target))
+            .or_insert_with(|| entry.text.trim().to_string());
+    }
+
+    Ok(captions)
+}
+
+/// Reads the elements of the XML document `name` in document order.
+pub(crate) fn read_xml<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Vec<Element>> {
+    let entry = zip
+        .by_name(name)
+        .with_context(|| format!("failed to open `{name}`"))?;
+
+    let mut elements = Vec::<Element>::new();
+    let mut open = Vec::new();
+    for event in EventReader::new(BufReader::new(entry)) {
+        match event.with_context(|| format!("failed to read `{name}`"))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let attributes = attributes
+                    .into_iter()
+                    .map(|a| {
+                        let name = match a.name.prefix {
+                            Some(prefix) => format!("{prefix}:{}", a.name.local_name),
+                            None => a.name.local_name,
+                        };
+                        (name, a.value)
+                    })
+                    .collect();
+
+                open.push(elements.len());
+                elements.push(Element {
+                    name: name.local_name,
+                    attributes,
+                    text: String::new(),
+                    depth: open.len(),
+                });
+            }
+            XmlEvent::EndElement { .. } => {
+                open.pop();
+            }
+            XmlEvent::Characters(text) => {
+                for &i in &open {
+                    elements[i].text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(elements)
+}
+
+fn extract<R: Read + Seek>(zip: &mut ZipArchive<R>, name: &str, path: &Path) -> Result<()> {
+    let mut entry = zip
+        .by_name(name)
+        .with_context(|| format!("failed to open `{name}`"))?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create `{}`", parent.display()))?;
+    }
+
+    let mut file =
+        File::create(path).with_context(|| format!("failed to create `{}`", path.display()))?;
+    std::io::copy(&mut entry, &mut file).with_context(|| format!("failed to extract `{name}`"))?;
+
+    Ok(())
+}
+
+/// Resolves `href` relative to the entry `base` into an entry name.
+///
+/// The escapes of `href`, which is a URL, are decoded, as the entry names are not escaped.
+pub(crate) fn resolve(base: &str, href: &str) -> String {
+    let href = percent_decode(href.split('#').next().unwrap_or_default());
+
+    let mut path = PathBuf::from(base);
+    path.pop();
+
+    let mut parts = Vec::new();
+    let path = path.join(&href);
+    for part in path.iter() {
+        match part.to_str() {
+            Some("..") => {
+                parts.pop();
+            }
+            Some(".") => {}
+            Some(part) => parts.push(part),
+            None => {}
+        }
+    }
+
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    #[test]
+    fn test_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Series 3.epub");
+
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        let options = SimpleFileOptions::default();
+        zip.start_file("META-INF/container.xml", options).unwrap();
+        zip.write_all(
+            br#"<container><rootfiles><rootfile full-path="item/a.opf"/></rootfiles></container>"#,
+        )
+        .unwrap();
+        zip.start_file("item/a.opf", options).unwrap();
+        zip.write_all(
+            br##"<package unique-identifier="id"><metadata>
+<identifier id="id">urn:uuid:1</identifier><language>ja</language>
+<meta property="belongs-to-collection" id="c">Series</meta>
+<meta refines="#c" property="collection-type">series</meta>
+<meta refines="#c" property="group-position">3</meta></metadata>
+<manifest><item id="c" href="image/cover%20page.png" media-type="image/png" properties="cover-image"/>
+<item id="p" href="xhtml/p%201.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c"/><itemref idref="p"/></spine></package>"##,
+        )
+        .unwrap();
+        zip.start_file("item/image/cover page.png", options)
+            .unwrap();
+        zip.write_all(b"cover").unwrap();
+        zip.start_file("item/xhtml/p 1.xhtml", options).unwrap();
+        zip.write_all(br#"<html><body><img src="../image/page%201.png"/></body></html>"#)
+            .unwrap();
+        zip.start_file("item/image/page 1.png", options).unwrap();
+        zip.write_all(b"page").unwrap();
+        zip.finish().unwrap();
+
+        let root = dir.path().join("project");
+        let Import { book, findings } = import(&path, &root).unwrap();
+        assert_eq!(
+            findings,
+            [
+                "no navigation document; chapters are not restored",
+                "no title; the book is titled `Series 3` after the file",
+            ]
+        );
+
+        let metadata = &book.metadata;
+        assert_eq!(metadata.title[0].name, "Series 3");
+        assert_eq!(metadata.collection.len(), 1);
+        assert_eq!(metadata.collection[0].name, "Series");
+        assert_eq!(
+            metadata.collection[0].collection_type,
+            CollectionType::Series
+        );
+        assert_eq!(
+            metadata.collection[0].position.as_ref().map(|p| p.as_str()),
+            Some("3")
+        );
+
+        assert_eq!(book.chapter.len(), 2);
+        assert!(book.chapter[0].cover);
+        let src = &book.chapter[1].page[0].src;
+        assert_eq!(std::fs::read(root.join(src)).unwrap(), b"page");
+
+        // the book can be written as a project file
+        assert!(serde_yaml::to_string(&book).unwrap().contains("Series 3"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(
+            resolve("item/standard.opf", "image/a.jpg"),
+            "item/image/a.jpg"
+        );
+        assert_eq!(
+            resolve("item/xhtml/p-0001.xhtml", "../image/a.jpg"),
+            "item/image/a.jpg"
+        );
+        assert_eq!(resolve("nav.xhtml", "p.xhtml#top"), "p.xhtml");
+        assert_eq!(resolve("nav.xhtml", "a%20b.xhtml#c%20d"), "a b.xhtml");
+    }
+}
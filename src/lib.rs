@@ -0,0 +1,48 @@
+//! Types of `tsugumi.yaml`, the project file of tsugumi, for programs that read or write it, and
+//! the builder of EPub files for programs that embed tsugumi.
+//!
+//! ```no_run
+//! let yaml = std::fs::read_to_string("tsugumi.yaml")?;
+//! let book: tsugumi::model::Book = serde_yaml::from_str(&yaml)?;
+//! println!("{}", serde_yaml::to_string(&book)?);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+// the caches, the messages and the checks serve the command line interface, which needs `fs`
+#![cfg_attr(not(feature = "fs"), allow(dead_code))]
+
+pub mod build;
+mod cache;
+mod comic;
+#[cfg(feature = "fs")]
+mod config;
+#[cfg(feature = "fs")]
+mod diagnostic;
+#[cfg(feature = "fs")]
+mod edit;
+#[cfg(feature = "fs")]
+pub mod ffi;
+mod i18n;
+#[cfg(feature = "fs")]
+mod import;
+#[cfg(feature = "fs")]
+mod lock;
+mod message;
+pub mod model;
+mod panel;
+mod pdf;
+mod profile;
+#[cfg(feature = "fs")]
+mod project;
+#[cfg(feature = "fs")]
+mod report;
+mod status;
+mod task;
+mod url;
+#[cfg(feature = "fs")]
+mod validate;
+
+/// Runs the command line interface, which is the `tsugumi` binary.
+#[cfg(feature = "fs")]
+#[doc(hidden)]
+pub use task::main;
@@ -0,0 +1,168 @@
+use crate::i18n::tr;
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write as _};
+use std::path::{Path, PathBuf};
+
+const VERSION: u32 = 1;
+
+/// Hashes of the sources used for a build.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub version: u32,
+
+    /// Hash of the project file.
+    pub manifest: String,
+
+    pub source: Vec<Source>,
+}
+
+/// Source file packaged into the book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Source {
+    /// Path relative to the project root.
+    pub path: PathBuf,
+
+    /// Hash of the file contents.
+    pub sha256: String,
+
+    /// Name of the entry in the EPub file the source is packaged as.
+    pub entry: String,
+}
+
+impl Lockfile {
+    pub fn new(manifest: String, source: Vec<Source>) -> Self {
+        Self {
+            version: VERSION,
+            manifest,
+            source,
+        }
+    }
+
+    /// Reads the lockfile at `path`, if any.
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to open `{}`", path.display()))
+            }
+        };
+
+        let lock: Self = serde_yaml::from_reader(file)
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        if lock.version != VERSION {
+            anyhow::bail!(
+                "unsupported version {} of `{}`",
+                lock.version,
+                path.display()
+            );
+        }
+
+        Ok(Some(lock))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("failed to create `{}`", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_yaml::to_writer(&mut writer, self)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Describes how the sources recorded in `self` differ from `other`.
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        if self.manifest != other.manifest {
+            changes.push(tr!(
+                "project file has changed",
+                "プロジェクトファイルが変更されています"
+            ));
+        }
+
+        for source in &self.source {
+            match other.source.iter().find(|s| s.path == source.path) {
+                Some(s) if s.sha256 != source.sha256 => changes.push(tr!(
+                    "`{}` has changed",
+                    "`{}` が変更されています",
+                    source.path.display()
+                )),
+                Some(_) => {}
+                None => changes.push(tr!(
+                    "`{}` is no longer used",
+                    "`{}` は使われなくなりました",
+                    source.path.display()
+                )),
+            }
+        }
+
+        for source in &other.source {
+            if !self.source.iter().any(|s| s.path == source.path) {
+                changes.push(tr!(
+                    "`{}` is not locked",
+                    "`{}` はロックされていません",
+                    source.path.display()
+                ));
+            }
+        }
+
+        changes
+    }
+}
+
+/// Returns the SHA-256 hash of the contents of `reader` in hex.
+pub fn hash<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Returns the SHA-256 hash of the file at `path` in hex.
+pub fn hash_file(path: &Path) -> Result<String> {
+    File::open(path)
+        .and_then(hash)
+        .with_context(|| format!("failed to read `{}`", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(path: &str, sha256: &str) -> Source {
+        Source {
+            path: path.into(),
+            sha256: sha256.to_string(),
+            entry: format!("item/{path}"),
+        }
+    }
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(
+            hash(b"abc".as_slice()).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Lockfile::new("m".to_string(), vec![source("a", "1"), source("b", "2")]);
+        assert!(old.diff(&old).is_empty());
+
+        let new = Lockfile::new("n".to_string(), vec![source("a", "3"), source("c", "4")]);
+        assert_eq!(
+            old.diff(&new),
+            [
+                "project file has changed",
+                "`a` has changed",
+                "`b` is no longer used",
+                "`c` is not locked",
+            ]
+        );
+    }
+}
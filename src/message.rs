@@ -0,0 +1,336 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+
+/// Format of the findings of a validation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    #[default]
+    Text,
+    Sarif,
+    Github,
+}
+
+/// Validation that produces findings, identified by a stable id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    MissingSource,
+    DuplicateSource,
+    UnreadableImage,
+    ExifRotation,
+    CmykImage,
+    OversizedImage,
+    MissingFileAs,
+    EmptyChapter,
+    MissingCover,
+    SmallCover,
+    SvgLink,
+    UnsupportedCss,
+}
+
+impl Rule {
+    const ALL: [Self; 12] = [
+        Self::MissingSource,
+        Self::DuplicateSource,
+        Self::UnreadableImage,
+        Self::ExifRotation,
+        Self::CmykImage,
+        Self::OversizedImage,
+        Self::MissingFileAs,
+        Self::EmptyChapter,
+        Self::MissingCover,
+        Self::SmallCover,
+        Self::SvgLink,
+        Self::UnsupportedCss,
+    ];
+
+    /// Returns the id of the rule, which never changes once published.
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::MissingSource => "TSG001",
+            Self::DuplicateSource => "TSG002",
+            Self::UnreadableImage => "TSG003",
+            Self::ExifRotation => "TSG004",
+            Self::CmykImage => "TSG005",
+            Self::OversizedImage => "TSG006",
+            Self::MissingFileAs => "TSG007",
+            Self::EmptyChapter => "TSG008",
+            Self::MissingCover => "TSG009",
+            Self::SmallCover => "TSG010",
+            Self::SvgLink => "TSG011",
+            Self::UnsupportedCss => "TSG012",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::MissingSource => "missing-source",
+            Self::DuplicateSource => "duplicate-source",
+            Self::UnreadableImage => "unreadable-image",
+            Self::ExifRotation => "exif-rotation",
+            Self::CmykImage => "cmyk-image",
+            Self::OversizedImage => "oversized-image",
+            Self::MissingFileAs => "missing-file-as",
+            Self::EmptyChapter => "empty-chapter",
+            Self::MissingCover => "missing-cover",
+            Self::SmallCover => "small-cover",
+            Self::SvgLink => "svg-link",
+            Self::UnsupportedCss => "unsupported-css",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::MissingSource => "A source file of a page does not exist.",
+            Self::DuplicateSource => "A source file is used by more than one page.",
+            Self::UnreadableImage => "An image cannot be read.",
+            Self::ExifRotation => "An image is rotated by its EXIF orientation.",
+            Self::CmykImage => "A JPEG image is in the CMYK color space.",
+            Self::OversizedImage => "An image has more pixels than the store accepts.",
+            Self::MissingFileAs => "A creator has no name to sort by.",
+            Self::EmptyChapter => "A chapter has no pages.",
+            Self::MissingCover => "The store requires a cover the book does not have.",
+            Self::SmallCover => "The cover is smaller than the store requires.",
+            Self::SvgLink => "A page places links on its image the store does not follow.",
+            Self::UnsupportedCss => "A style uses CSS the store ignores.",
+        }
+    }
+
+    fn is_error(self) -> bool {
+        matches!(self, Self::MissingSource | Self::UnreadableImage)
+    }
+
+    /// Returns the severity of the findings as named by SARIF and GitHub Actions.
+    pub fn level(self) -> &'static str {
+        if self.is_error() {
+            "error"
+        } else {
+            "warning"
+        }
+    }
+}
+
+/// Finding of a validation in a file relative to the project directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub rule: Rule,
+    pub text: String,
+    pub path: PathBuf,
+}
+
+impl Message {
+    /// Formats the message as a workflow command of GitHub Actions, which annotates the file.
+    fn to_github(&self) -> String {
+        let escape = |s: &str| {
+            s.replace('%', "%25")
+                .replace('\r', "%0D")
+                .replace('\n', "%0A")
+        };
+        let property = |s: &str| escape(s).replace(':', "%3A").replace(',', "%2C");
+
+        format!(
+            "::{} file={},title={}::{}",
+            self.rule.level(),
+            property(&self.path.to_string_lossy()),
+            property(&format!("{} {}", self.rule.id(), self.rule.name())),
+            escape(&self.text)
+        )
+    }
+}
+
+/// Logs findings as they are reported, or collects them to write them in a machine readable
+/// format at the end.
+pub struct Reporter {
+    format: MessageFormat,
+
+    /// Project directory the paths of the findings are relative to.
+    root: PathBuf,
+    messages: Vec<Message>,
+}
+
+impl Reporter {
+    pub fn new(format: MessageFormat, root: &Path) -> Self {
+        Self {
+            format,
+            root: root.to_path_buf(),
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn report(&mut self, rule: Rule, text: impl Into<String>, path: &Path) {
+        let message = Message {
+            rule,
+            text: text.into(),
+            path: path.to_path_buf(),
+        };
+
+        match self.format {
+            MessageFormat::Text if rule.is_error() => error!("{}", message.text),
+            MessageFormat::Text => warn!("{}", message.text),
+            _ => self.messages.push(message),
+        }
+    }
+
+    /// Writes the collected findings to the standard output.
+    ///
+    /// Their paths are made relative to the directory the tools that read them resolve paths
+    /// against, rather than to the project directory.
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(base) = base_dir(&self.root) {
+            for message in &mut self.messages {
+                message.path = relocate(&self.root, &message.path, &base);
+            }
+        }
+
+        match self.format {
+            MessageFormat::Text => {}
+            MessageFormat::Sarif => {
+                serde_json::to_writer_pretty(std::io::stdout().lock(), &sarif(&self.messages))?;
+                println!();
+            }
+            MessageFormat::Github => {
+                for message in &self.messages {
+                    println!("{}", message.to_github());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the directory the paths of the findings are resolved against: the workspace of GitHub
+/// Actions, or else the root of the git repository, whichever contains the project directory
+/// `root`.
+fn base_dir(root: &Path) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let workspace = std::env::var_os("GITHUB_WORKSPACE")
+        .and_then(|dir| PathBuf::from(dir).canonicalize().ok())
+        .filter(|dir| root.starts_with(dir));
+    workspace.or_else(|| {
+        root.ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .map(Path::to_path_buf)
+    })
+}
+
+/// Makes `path`, relative to the project directory `root`, relative to `base` instead.
+fn relocate(root: &Path, path: &Path, base: &Path) -> PathBuf {
+    root.canonicalize()
+        .ok()
+        .and_then(|root| {
+            root.join(path)
+                .strip_prefix(base)
+                .ok()
+                .map(Path::to_path_buf)
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Builds a SARIF 2.1.0 log of `messages`.
+fn sarif(messages: &[Message]) -> Value {
+    let rules = Rule::ALL
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id(),
+                "name": rule.name(),
+                "shortDescription": { "text": rule.description() },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let results = messages
+        .iter()
+        .map(|message| {
+            json!({
+                "ruleId": message.rule.id(),
+                "level": message.rule.level(),
+                "message": { "text": message.text },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": {
+                            "uri": message.path.to_string_lossy().replace('\\', "/"),
+                        },
+                    },
+                }],
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/dacci/tsugumi-rs",
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_github() {
+        let message = Message {
+            rule: Rule::MissingSource,
+            text: "`a.png` does not exist\n(100%)".to_string(),
+            path: "dir,1/tsugumi.yaml".into(),
+        };
+        assert_eq!(
+            message.to_github(),
+            "::error file=dir%2C1/tsugumi.yaml,title=TSG001 missing-source\
+             ::`a.png` does not exist%0A(100%25)"
+        );
+    }
+
+    #[test]
+    fn test_relocate() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("books/one");
+        std::fs::create_dir_all(&root).unwrap();
+        assert_eq!(base_dir(&root), None);
+
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        let base = base_dir(&root).unwrap();
+        assert_eq!(base, dir.path().canonicalize().unwrap());
+        assert_eq!(
+            relocate(&root, Path::new("pages/a.png"), &base),
+            Path::new("books/one/pages/a.png")
+        );
+    }
+
+    #[test]
+    fn test_sarif() {
+        let sarif = sarif(&[Message {
+            rule: Rule::CmykImage,
+            text: "text".to_string(),
+            path: "a.jpg".into(),
+        }]);
+
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "TSG005");
+        assert_eq!(result["level"], "warning");
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "a.jpg"
+        );
+        assert_eq!(
+            sarif["runs"][0]["tool"]["driver"]["rules"]
+                .as_array()
+                .unwrap()
+                .len(),
+            Rule::ALL.len()
+        );
+    }
+}
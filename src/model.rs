@@ -1,15 +1,110 @@
+pub use crate::profile::Profile;
 use serde::de::{self, value::Error as ValueError};
 use serde::ser::{self, SerializeMap};
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-#[derive(Debug, Default)]
-#[cfg_attr(test, derive(PartialEq))]
+/// Latest version of the project file, which is the one written.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Book {
+    /// Version of the project file, which is the first one if missing.
+    pub version: Option<u32>,
+
     pub metadata: Metadata,
     pub rendition: Rendition,
     pub chapter: Vec<Chapter>,
+
+    /// Whether build warnings are treated as errors.
+    pub strict: bool,
+
+    /// Caption of unnamed chapters in the table of contents, with `{n}` replaced by the number of
+    /// the chapter.
+    pub chapter_format: Option<String>,
+
+    /// How the cover page is chosen.
+    pub cover: Cover,
+
+    /// High-resolution image of the cover kept in the book besides the one displayed.
+    pub archival_cover: Option<PathBuf>,
+
+    /// Directory that the sources of the pages are relative to, itself relative to the project
+    /// file.
+    pub assets_dir: Option<PathBuf>,
+
+    /// How the images are packaged.
+    pub images: Images,
+
+    /// Named output profiles the book can be built for, in the order they are defined.
+    pub targets: Vec<BuildTarget>,
+}
+
+impl Book {
+    /// Returns the path to the page source `src` relative to the project file.
+    pub fn source_path(&self, src: &Path) -> PathBuf {
+        match &self.assets_dir {
+            Some(dir) => dir.join(src),
+            None => src.to_path_buf(),
+        }
+    }
+
+    /// Moves the sub-chapters of every chapter into the list of chapters right after it, in the
+    /// order of the pages of the book, recording their depth in the hierarchy.
+    pub fn flatten_chapters(&mut self) {
+        fn flatten(chapters: Vec<Chapter>, depth: usize, flat: &mut Vec<Chapter>) {
+            for mut chapter in chapters {
+                let children = std::mem::take(&mut chapter.children);
+                chapter.depth += depth;
+                flat.push(chapter);
+                flatten(children, depth + 1, flat);
+            }
+        }
+
+        let chapters = std::mem::take(&mut self.chapter);
+        flatten(chapters, 0, &mut self.chapter);
+    }
+
+    /// Returns the output profile named `name`.
+    pub fn target(&self, name: &str) -> Option<&BuildTarget> {
+        self.targets.iter().find(|target| target.name == name)
+    }
+
+    /// Whether the page at `page` of the chapter at `chapter` is a cover page.
+    pub fn is_cover(&self, chapter: usize, page: usize) -> bool {
+        // the first chapter may be a part without pages of its own
+        let first = || self.chapter[..chapter].iter().all(|c| c.page.is_empty());
+        self.chapter[chapter].cover || (self.cover == Cover::Auto && page == 0 && first())
+    }
+
+    /// Returns the captions generated for the unnamed chapters, which are numbered among the
+    /// chapters of the body matter.
+    pub fn numbered_names(&self) -> Vec<Option<String>> {
+        let format =
+            self.chapter_format
+                .as_deref()
+                .unwrap_or(if self.metadata.language.starts_with("ja") {
+                    "第{n}話"
+                } else {
+                    "Chapter {n}"
+                });
+
+        let mut number = 0;
+        self.chapter
+            .iter()
+            .map(|chapter| {
+                if !matches!(chapter.kind(), None | Some(Kind::Bodymatter)) {
+                    return None;
+                }
+                number += 1;
+                chapter
+                    .name
+                    .is_none()
+                    .then(|| format.replace("{n}", &number.to_string()))
+            })
+            .collect()
+    }
 }
 
 impl<'de> de::Deserialize<'de> for Book {
@@ -25,9 +120,17 @@ impl<'de> de::Deserialize<'de> for Book {
 
             fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
                 enum Field {
+                    Version,
                     Metadata,
                     Rendition,
                     Chapter,
+                    Strict,
+                    ChapterFormat,
+                    Cover,
+                    ArchivalCover,
+                    AssetsDir,
+                    Images,
+                    Targets,
                 }
 
                 impl<'de> de::Deserialize<'de> for Field {
@@ -45,12 +148,32 @@ impl<'de> de::Deserialize<'de> for Book {
 
                             fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
                                 match v {
+                                    "version" => Ok(Field::Version),
                                     "metadata" => Ok(Field::Metadata),
                                     "rendition" => Ok(Field::Rendition),
                                     "chapter" => Ok(Field::Chapter),
+                                    "strict" => Ok(Field::Strict),
+                                    "chapterFormat" => Ok(Field::ChapterFormat),
+                                    "cover" => Ok(Field::Cover),
+                                    "archivalCover" => Ok(Field::ArchivalCover),
+                                    "assetsDir" => Ok(Field::AssetsDir),
+                                    "images" => Ok(Field::Images),
+                                    "targets" => Ok(Field::Targets),
                                     field => Err(de::Error::unknown_field(
                                         field,
-                                        &["metadata", "rendition", "chapter"],
+                                        &[
+                                            "version",
+                                            "metadata",
+                                            "rendition",
+                                            "chapter",
+                                            "strict",
+                                            "chapterFormat",
+                                            "cover",
+                                            "archivalCover",
+                                            "assetsDir",
+                                            "images",
+                                            "targets",
+                                        ],
                                     )),
                                 }
                             }
@@ -60,12 +183,39 @@ impl<'de> de::Deserialize<'de> for Book {
                     }
                 }
 
+                let mut version = None;
                 let mut metadata = None;
                 let mut rendition = None;
                 let mut chapter = None;
+                let mut strict = None;
+                let mut chapter_format = None;
+                let mut cover = None;
+                let mut archival_cover = None;
+                let mut assets_dir = None;
+                let mut images = None;
+                let mut targets = None;
 
                 while let Some(field) = map.next_key()? {
                     match field {
+                        Field::Version => {
+                            if version.is_some() {
+                                return Err(de::Error::duplicate_field("version"));
+                            }
+                            version = map
+                                .next_value::<u32>()
+                                .and_then(|v| match v {
+                                    0 => Err(de::Error::invalid_value(
+                                        de::Unexpected::Unsigned(0),
+                                        &"a version from 1",
+                                    )),
+                                    v if VERSION < v => Err(de::Error::custom(format!(
+                                        "the project file requires a newer tsugumi, as it is of \
+                                         version {v} and this one reads up to version {VERSION}"
+                                    ))),
+                                    v => Ok(v),
+                                })
+                                .map(Some)?;
+                        }
                         Field::Metadata => {
                             if metadata.is_some() {
                                 return Err(de::Error::duplicate_field("metadata"));
@@ -94,17 +244,78 @@ impl<'de> de::Deserialize<'de> for Book {
                                 })
                                 .map(Some)?;
                         }
+                        Field::Strict => {
+                            if strict.is_some() {
+                                return Err(de::Error::duplicate_field("strict"));
+                            }
+                            strict = map.next_value().map(Some)?;
+                        }
+                        Field::ChapterFormat => {
+                            if chapter_format.is_some() {
+                                return Err(de::Error::duplicate_field("chapterFormat"));
+                            }
+                            chapter_format = map.next_value().map(Some)?;
+                        }
+                        Field::Cover => {
+                            if cover.is_some() {
+                                return Err(de::Error::duplicate_field("cover"));
+                            }
+                            cover = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::ArchivalCover => {
+                            if archival_cover.is_some() {
+                                return Err(de::Error::duplicate_field("archivalCover"));
+                            }
+                            archival_cover = map.next_value().map(Some)?;
+                        }
+                        Field::AssetsDir => {
+                            if assets_dir.is_some() {
+                                return Err(de::Error::duplicate_field("assetsDir"));
+                            }
+                            assets_dir = map.next_value().map(Some)?;
+                        }
+                        Field::Images => {
+                            if images.is_some() {
+                                return Err(de::Error::duplicate_field("images"));
+                            }
+                            images = map.next_value().map(Some)?;
+                        }
+                        Field::Targets => {
+                            if targets.is_some() {
+                                return Err(de::Error::duplicate_field("targets"));
+                            }
+                            targets = map
+                                .next_value::<target_map::Deserialize>()
+                                .map(|d| d.0)
+                                .map(Some)?;
+                        }
                     }
                 }
 
                 let metadata = metadata.ok_or_else(|| de::Error::missing_field("metadata"))?;
                 let rendition = rendition.unwrap_or_default();
                 let chapter = chapter.ok_or_else(|| de::Error::missing_field("chapter"))?;
+                let strict = strict.unwrap_or_default();
+                let cover = cover.unwrap_or_default();
+                let images = images.unwrap_or_default();
+                let targets = targets.unwrap_or_default();
 
+                // version 1 is the only one so far, so older project files need no migration yet
                 Ok(Book {
+                    version,
                     metadata,
                     rendition,
                     chapter,
+                    strict,
+                    chapter_format,
+                    cover,
+                    archival_cover,
+                    assets_dir,
+                    images,
+                    targets,
                 })
             }
         }
@@ -117,6 +328,10 @@ impl ser::Serialize for Book {
     fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         let mut map = serializer.serialize_map(None)?;
 
+        if let Some(version) = self.version {
+            map.serialize_entry("version", &version)?;
+        }
+
         map.serialize_entry("metadata", &self.metadata)?;
         map.serialize_entry("rendition", &self.rendition)?;
 
@@ -126,12 +341,39 @@ impl ser::Serialize for Book {
             map.serialize_entry("chapter", &invariable::wrap(&self.chapter))?;
         }
 
+        if self.strict {
+            map.serialize_entry("strict", &self.strict)?;
+        }
+
+        if let Some(chapter_format) = &self.chapter_format {
+            map.serialize_entry("chapterFormat", chapter_format)?;
+        }
+
+        if self.cover != Cover::default() {
+            map.serialize_entry("cover", &serde_enum::wrap(&self.cover))?;
+        }
+
+        if let Some(archival_cover) = &self.archival_cover {
+            map.serialize_entry("archivalCover", archival_cover)?;
+        }
+
+        if let Some(assets_dir) = &self.assets_dir {
+            map.serialize_entry("assetsDir", assets_dir)?;
+        }
+
+        if self.images != Images::default() {
+            map.serialize_entry("images", &self.images)?;
+        }
+
+        if !self.targets.is_empty() {
+            map.serialize_entry("targets", &target_map::Serialize(&self.targets))?;
+        }
+
         map.end()
     }
 }
 
-#[derive(Debug, Default)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Metadata {
     pub title: Vec<Title>,
     pub creator: Vec<Creator>,
@@ -139,6 +381,26 @@ pub struct Metadata {
     pub collection: Vec<Collection>,
     pub language: String,
     pub identifier: String,
+    pub description: Option<String>,
+    pub publisher: Option<String>,
+    pub publication_date: Option<Date>,
+    pub rights: Option<String>,
+    pub subject: Vec<String>,
+    pub book_type: Vec<String>,
+    pub schema_type: Option<String>,
+    pub age_rating: Option<String>,
+    pub content_warning: Vec<String>,
+}
+
+impl Metadata {
+    /// Returns the main title of the book, or the first title if none is marked as main.
+    pub fn main_title(&self) -> Option<&str> {
+        self.title
+            .iter()
+            .find(|t| t.title_type == TitleType::Main)
+            .or_else(|| self.title.first())
+            .map(|t| t.name.as_str())
+    }
 }
 
 impl<'de> de::Deserialize<'de> for Metadata {
@@ -160,6 +422,15 @@ impl<'de> de::Deserialize<'de> for Metadata {
                     Collection,
                     Language,
                     Identifier,
+                    Description,
+                    Publisher,
+                    PublicationDate,
+                    Rights,
+                    Subject,
+                    Type,
+                    SchemaType,
+                    AgeRating,
+                    ContentWarning,
                 }
 
                 impl<'de> de::Deserialize<'de> for Field {
@@ -183,6 +454,15 @@ impl<'de> de::Deserialize<'de> for Metadata {
                                     "collection" => Ok(Field::Collection),
                                     "language" => Ok(Field::Language),
                                     "identifier" => Ok(Field::Identifier),
+                                    "description" => Ok(Field::Description),
+                                    "publisher" => Ok(Field::Publisher),
+                                    "publicationDate" => Ok(Field::PublicationDate),
+                                    "rights" => Ok(Field::Rights),
+                                    "subject" => Ok(Field::Subject),
+                                    "type" => Ok(Field::Type),
+                                    "schemaType" => Ok(Field::SchemaType),
+                                    "ageRating" => Ok(Field::AgeRating),
+                                    "contentWarning" => Ok(Field::ContentWarning),
                                     field => Err(de::Error::unknown_field(
                                         field,
                                         &[
@@ -190,7 +470,17 @@ impl<'de> de::Deserialize<'de> for Metadata {
                                             "creator",
                                             "contributor",
                                             "collection",
+                                            "language",
                                             "identifier",
+                                            "description",
+                                            "publisher",
+                                            "publicationDate",
+                                            "rights",
+                                            "subject",
+                                            "type",
+                                            "schemaType",
+                                            "ageRating",
+                                            "contentWarning",
                                         ],
                                     )),
                                 }
@@ -207,6 +497,15 @@ impl<'de> de::Deserialize<'de> for Metadata {
                 let mut collection = None;
                 let mut language = None;
                 let mut identifier = None;
+                let mut description = None;
+                let mut publisher = None;
+                let mut publication_date = None;
+                let mut rights = None;
+                let mut subject = None;
+                let mut book_type = None;
+                let mut schema_type = None;
+                let mut age_rating = None;
+                let mut content_warning = None;
 
                 while let Some(field) = map.next_key()? {
                     match field {
@@ -283,6 +582,69 @@ impl<'de> de::Deserialize<'de> for Metadata {
                                 })
                                 .map(Some)?;
                         }
+                        Field::Description => {
+                            if description.is_some() {
+                                return Err(de::Error::duplicate_field("description"));
+                            }
+                            description = map.next_value().map(Some)?;
+                        }
+                        Field::Publisher => {
+                            if publisher.is_some() {
+                                return Err(de::Error::duplicate_field("publisher"));
+                            }
+                            publisher = map.next_value().map(Some)?;
+                        }
+                        Field::PublicationDate => {
+                            if publication_date.is_some() {
+                                return Err(de::Error::duplicate_field("publicationDate"));
+                            }
+                            publication_date = map.next_value().map(Some)?;
+                        }
+                        Field::Rights => {
+                            if rights.is_some() {
+                                return Err(de::Error::duplicate_field("rights"));
+                            }
+                            rights = map.next_value().map(Some)?;
+                        }
+                        Field::Subject => {
+                            if subject.is_some() {
+                                return Err(de::Error::duplicate_field("subject"));
+                            }
+                            subject = map
+                                .next_value::<invariable::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Type => {
+                            if book_type.is_some() {
+                                return Err(de::Error::duplicate_field("type"));
+                            }
+                            book_type = map
+                                .next_value::<invariable::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::SchemaType => {
+                            if schema_type.is_some() {
+                                return Err(de::Error::duplicate_field("schemaType"));
+                            }
+                            schema_type = map.next_value().map(Some)?;
+                        }
+                        Field::AgeRating => {
+                            if age_rating.is_some() {
+                                return Err(de::Error::duplicate_field("ageRating"));
+                            }
+                            age_rating = map.next_value().map(Some)?;
+                        }
+                        Field::ContentWarning => {
+                            if content_warning.is_some() {
+                                return Err(de::Error::duplicate_field("contentWarning"));
+                            }
+                            content_warning = map
+                                .next_value::<invariable::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
                     }
                 }
 
@@ -293,6 +655,9 @@ impl<'de> de::Deserialize<'de> for Metadata {
                 let language = language.ok_or_else(|| de::Error::missing_field("language"))?;
                 let identifier =
                     identifier.ok_or_else(|| de::Error::missing_field("identifier"))?;
+                let subject = subject.unwrap_or_default();
+                let book_type = book_type.unwrap_or_default();
+                let content_warning = content_warning.unwrap_or_default();
 
                 Ok(Metadata {
                     title,
@@ -301,6 +666,15 @@ impl<'de> de::Deserialize<'de> for Metadata {
                     collection,
                     language,
                     identifier,
+                    description,
+                    publisher,
+                    publication_date,
+                    rights,
+                    subject,
+                    book_type,
+                    schema_type,
+                    age_rating,
+                    content_warning,
                 })
             }
         }
@@ -343,17 +717,133 @@ impl ser::Serialize for Metadata {
             map.serialize_entry("identifier", &self.identifier)?;
         }
 
+        if let Some(description) = &self.description {
+            map.serialize_entry("description", description)?;
+        }
+
+        if let Some(publisher) = &self.publisher {
+            map.serialize_entry("publisher", publisher)?;
+        }
+
+        if let Some(publication_date) = &self.publication_date {
+            map.serialize_entry("publicationDate", publication_date)?;
+        }
+
+        if let Some(rights) = &self.rights {
+            map.serialize_entry("rights", rights)?;
+        }
+
+        if !self.subject.is_empty() {
+            map.serialize_entry("subject", &invariable::wrap(&self.subject))?;
+        }
+
+        if !self.book_type.is_empty() {
+            map.serialize_entry("type", &invariable::wrap(&self.book_type))?;
+        }
+
+        if let Some(schema_type) = &self.schema_type {
+            map.serialize_entry("schemaType", schema_type)?;
+        }
+
+        if let Some(age_rating) = &self.age_rating {
+            map.serialize_entry("ageRating", age_rating)?;
+        }
+
+        if !self.content_warning.is_empty() {
+            map.serialize_entry("contentWarning", &invariable::wrap(&self.content_warning))?;
+        }
+
         map.end()
     }
 }
 
-#[derive(Debug, Default)]
-#[cfg_attr(test, derive(PartialEq))]
+/// Date of publication in the form of `YYYY`, `YYYY-MM` or `YYYY-MM-DD`, which is kept as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Date(String);
+
+impl Date {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Date {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |s: &str, len: usize, max: u32| {
+            s.len() == len
+                && s.bytes().all(|b| b.is_ascii_digit())
+                && s.parse().is_ok_and(|v: u32| 0 < v && v <= max)
+        };
+        let valid = match s.split('-').collect::<Vec<_>>()[..] {
+            [year] => number(year, 4, 9999),
+            [year, month] => number(year, 4, 9999) && number(month, 2, 12),
+            [year, month, day] => {
+                number(year, 4, 9999) && number(month, 2, 12) && number(day, 2, 31)
+            }
+            _ => false,
+        };
+        if valid {
+            Ok(Self(s.to_string()))
+        } else {
+            Err(de::Error::invalid_value(
+                de::Unexpected::Str(s),
+                &"a date like `2024`, `2024-05` or `2024-05-31`",
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Date {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = Date;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a year or a string")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                v.to_string().parse().map_err(de::Error::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                v.to_string().parse().map_err(de::Error::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl ser::Serialize for Date {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Title {
     pub name: String,
     pub title_type: TitleType,
     pub alternate_script: Option<String>,
     pub file_as: Option<String>,
+
+    /// Position in which the title is displayed, if it differs from the order of the titles.
+    pub display_seq: Option<u32>,
 }
 
 impl<'de> de::Deserialize<'de> for Title {
@@ -384,6 +874,7 @@ impl<'de> de::Deserialize<'de> for Title {
                     TitleType,
                     AlternateScript,
                     FileAs,
+                    DisplaySeq,
                 }
 
                 impl<'de> de::Deserialize<'de> for Field {
@@ -405,9 +896,16 @@ impl<'de> de::Deserialize<'de> for Title {
                                     "type" => Ok(Field::TitleType),
                                     "alternateScript" => Ok(Field::AlternateScript),
                                     "fileAs" => Ok(Field::FileAs),
+                                    "displaySeq" => Ok(Field::DisplaySeq),
                                     field => Err(de::Error::unknown_field(
                                         field,
-                                        &["name", "type", "alternateScript", "fileAs"],
+                                        &[
+                                            "name",
+                                            "type",
+                                            "alternateScript",
+                                            "fileAs",
+                                            "displaySeq",
+                                        ],
                                     )),
                                 }
                             }
@@ -421,6 +919,7 @@ impl<'de> de::Deserialize<'de> for Title {
                 let mut title_type = None;
                 let mut alternate_script = None;
                 let mut file_as = None;
+                let mut display_seq = None;
 
                 while let Some(field) = map.next_key()? {
                     match field {
@@ -460,6 +959,24 @@ impl<'de> de::Deserialize<'de> for Title {
                             }
                             file_as = map.next_value().map(Some)?;
                         }
+                        Field::DisplaySeq => {
+                            if display_seq.is_some() {
+                                return Err(de::Error::duplicate_field("displaySeq"));
+                            }
+                            display_seq = map
+                                .next_value()
+                                .and_then(|v: u32| {
+                                    if v == 0 {
+                                        Err(de::Error::invalid_value(
+                                            de::Unexpected::Unsigned(0),
+                                            &"a position from 1",
+                                        ))
+                                    } else {
+                                        Ok(v)
+                                    }
+                                })
+                                .map(Some)?;
+                        }
                     }
                 }
 
@@ -471,6 +988,7 @@ impl<'de> de::Deserialize<'de> for Title {
                     title_type,
                     alternate_script,
                     file_as,
+                    display_seq,
                 })
             }
         }
@@ -485,7 +1003,10 @@ impl ser::Serialize for Title {
             return Err(ser::Error::custom("name must not be empty"));
         }
 
-        if self.title_type.is_default() && self.alternate_script.is_none() && self.file_as.is_none()
+        if self.title_type.is_default()
+            && self.alternate_script.is_none()
+            && self.file_as.is_none()
+            && self.display_seq.is_none()
         {
             serializer.serialize_str(&self.name)
         } else {
@@ -505,6 +1026,10 @@ impl ser::Serialize for Title {
                 map.serialize_entry("fileAs", file_as)?;
             }
 
+            if let Some(display_seq) = &self.display_seq {
+                map.serialize_entry("displaySeq", display_seq)?;
+            }
+
             map.end()
         }
     }
@@ -560,13 +1085,34 @@ impl AsRef<str> for TitleType {
     }
 }
 
-#[derive(Debug, Default)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Creator {
     pub name: String,
     pub role: Option<String>,
+
+    /// Scheme of `role`, which is [`MARC_RELATORS`] if missing.
+    pub scheme: Option<String>,
+
     pub alternate_script: Option<String>,
     pub file_as: Option<String>,
+
+    /// Position in which the creator is displayed, if it differs from the order of the creators.
+    pub display_seq: Option<u32>,
+}
+
+/// Scheme of the MARC relator codes, such as `aut` and `ill`.
+pub const MARC_RELATORS: &str = "marc:relators";
+
+impl Creator {
+    /// Returns the MARC relator code of the role, which is `aut` if missing, or `None` if the role
+    /// is in another scheme.
+    pub fn relator(&self) -> Option<&str> {
+        match (&self.role, self.scheme.as_deref()) {
+            (None, _) => Some("aut"),
+            (Some(role), None | Some(MARC_RELATORS)) => Some(role),
+            (Some(_), Some(_)) => None,
+        }
+    }
 }
 
 impl<'de> de::Deserialize<'de> for Creator {
@@ -595,8 +1141,10 @@ impl<'de> de::Deserialize<'de> for Creator {
                 enum Field {
                     Name,
                     Role,
+                    Scheme,
                     AlternateScript,
                     FileAs,
+                    DisplaySeq,
                 }
 
                 impl<'de> de::Deserialize<'de> for Field {
@@ -616,8 +1164,10 @@ impl<'de> de::Deserialize<'de> for Creator {
                                 match v {
                                     "name" => Ok(Field::Name),
                                     "role" => Ok(Field::Role),
+                                    "scheme" => Ok(Field::Scheme),
                                     "alternateScript" => Ok(Field::AlternateScript),
                                     "fileAs" => Ok(Field::FileAs),
+                                    "displaySeq" => Ok(Field::DisplaySeq),
                                     field => Err(de::Error::unknown_field(field, &[])),
                                 }
                             }
@@ -629,8 +1179,10 @@ impl<'de> de::Deserialize<'de> for Creator {
 
                 let mut name = None;
                 let mut role = None;
+                let mut scheme = None;
                 let mut alternate_script = None;
                 let mut file_as = None;
+                let mut display_seq = None;
 
                 while let Some(field) = map.next_key()? {
                     match field {
@@ -655,6 +1207,12 @@ impl<'de> de::Deserialize<'de> for Creator {
                             }
                             role = map.next_value().map(Some)?;
                         }
+                        Field::Scheme => {
+                            if scheme.is_some() {
+                                return Err(de::Error::duplicate_field("scheme"));
+                            }
+                            scheme = map.next_value().map(Some)?;
+                        }
                         Field::AlternateScript => {
                             if alternate_script.is_some() {
                                 return Err(de::Error::duplicate_field("alternate_script"));
@@ -667,16 +1225,39 @@ impl<'de> de::Deserialize<'de> for Creator {
                             }
                             file_as = map.next_value().map(Some)?;
                         }
+                        Field::DisplaySeq => {
+                            if display_seq.is_some() {
+                                return Err(de::Error::duplicate_field("displaySeq"));
+                            }
+                            display_seq = map
+                                .next_value()
+                                .and_then(|v: u32| {
+                                    if v == 0 {
+                                        Err(de::Error::invalid_value(
+                                            de::Unexpected::Unsigned(0),
+                                            &"a position from 1",
+                                        ))
+                                    } else {
+                                        Ok(v)
+                                    }
+                                })
+                                .map(Some)?;
+                        }
                     }
                 }
 
                 let name = name.unwrap_or_default();
+                if scheme.is_some() && role.is_none() {
+                    return Err(de::Error::missing_field("role"));
+                }
 
                 Ok(Creator {
                     name,
                     role,
+                    scheme,
                     alternate_script,
                     file_as,
+                    display_seq,
                 })
             }
         }
@@ -691,7 +1272,12 @@ impl ser::Serialize for Creator {
             return Err(ser::Error::custom("name must not be empty"));
         }
 
-        if self.role.is_none() && self.alternate_script.is_none() && self.file_as.is_none() {
+        if self.role.is_none()
+            && self.scheme.is_none()
+            && self.alternate_script.is_none()
+            && self.file_as.is_none()
+            && self.display_seq.is_none()
+        {
             serializer.serialize_str(&self.name)
         } else {
             let mut map = serializer.serialize_map(None)?;
@@ -702,6 +1288,10 @@ impl ser::Serialize for Creator {
                 map.serialize_entry("role", role)?;
             }
 
+            if let Some(scheme) = &self.scheme {
+                map.serialize_entry("scheme", scheme)?;
+            }
+
             if let Some(alternate_script) = &self.alternate_script {
                 map.serialize_entry("alternateScript", alternate_script)?;
             }
@@ -710,17 +1300,20 @@ impl ser::Serialize for Creator {
                 map.serialize_entry("fileAs", file_as)?;
             }
 
+            if let Some(display_seq) = &self.display_seq {
+                map.serialize_entry("displaySeq", display_seq)?;
+            }
+
             map.end()
         }
     }
 }
 
-#[derive(Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Collection {
     pub name: String,
     pub collection_type: CollectionType,
-    pub position: Option<u32>,
+    pub position: Option<Position>,
 }
 
 impl<'de> de::Deserialize<'de> for Collection {
@@ -845,6 +1438,95 @@ impl ser::Serialize for Collection {
     }
 }
 
+/// Position of a book in a collection, such as `3` or `3.5` for an extra volume, which is kept as
+/// written.
+///
+/// A number is kept in its shortest form, so a position with trailing zeros such as `3.10` must be
+/// quoted not to be read as `3.1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position(String);
+
+impl Position {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<u32> for Position {
+    fn from(value: u32) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl FromStr for Position {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (integer, fraction) = s.split_once('.').unwrap_or((s, "0"));
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if digits(integer) && digits(fraction) {
+            Ok(Self(s.to_string()))
+        } else {
+            Err(de::Error::invalid_value(
+                de::Unexpected::Str(s),
+                &"a number like `3` or `3.5`",
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Position {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = Position;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a number or a string")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(Position(v.to_string()))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map(|v| Position(v.to_string()))
+                    .map_err(|_| de::Error::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                v.to_string()
+                    .parse()
+                    .map_err(|_| de::Error::invalid_value(de::Unexpected::Float(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl ser::Serialize for Position {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // keep whole numbers plain, and the others as written
+        match self.0.parse::<u64>() {
+            Ok(value) => serializer.serialize_u64(value),
+            Err(_) => serializer.serialize_str(&self.0),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CollectionType {
     Series,
@@ -872,14 +1554,23 @@ impl AsRef<str> for CollectionType {
     }
 }
 
-#[derive(Debug, Default)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Rendition {
     pub direction: Direction,
     pub layout: Layout,
     pub orientation: Orientation,
     pub spread: Spread,
     pub style: Vec<Style>,
+    pub viewport: Option<Viewport>,
+
+    /// JavaScript files loaded by every page when the build allows scripts.
+    pub script: Vec<PathBuf>,
+
+    /// Whether pages are split into two when they are scans of double pages.
+    pub split: Split,
+
+    /// Whether to write an EPUB 2 NCX table of contents for legacy readers as well.
+    pub ncx: bool,
 }
 
 impl<'de> de::Deserialize<'de> for Rendition {
@@ -900,6 +1591,10 @@ impl<'de> de::Deserialize<'de> for Rendition {
                     Orientation,
                     Spread,
                     Style,
+                    Viewport,
+                    Script,
+                    Split,
+                    Ncx,
                 }
 
                 impl<'de> de::Deserialize<'de> for Field {
@@ -922,9 +1617,23 @@ impl<'de> de::Deserialize<'de> for Rendition {
                                     "orientation" => Ok(Field::Orientation),
                                     "spread" => Ok(Field::Spread),
                                     "style" => Ok(Field::Style),
+                                    "viewport" => Ok(Field::Viewport),
+                                    "script" => Ok(Field::Script),
+                                    "split" => Ok(Field::Split),
+                                    "ncx" => Ok(Field::Ncx),
                                     field => Err(de::Error::unknown_field(
                                         field,
-                                        &["direction", "layout", "orientation", "spread", "style"],
+                                        &[
+                                            "direction",
+                                            "layout",
+                                            "orientation",
+                                            "spread",
+                                            "style",
+                                            "viewport",
+                                            "script",
+                                            "split",
+                                            "ncx",
+                                        ],
                                     )),
                                 }
                             }
@@ -939,6 +1648,10 @@ impl<'de> de::Deserialize<'de> for Rendition {
                 let mut orientation = None;
                 let mut spread = None;
                 let mut style = None;
+                let mut viewport = None;
+                let mut script = None;
+                let mut split = None;
+                let mut ncx = None;
 
                 while let Some(field) = map.next_key()? {
                     match field {
@@ -987,14 +1700,47 @@ impl<'de> de::Deserialize<'de> for Rendition {
                                 .map(|d| d.unwrap())
                                 .map(Some)?;
                         }
-                    }
-                }
-
-                let direction = direction.unwrap_or_default();
-                let layout = layout.unwrap_or_default();
+                        Field::Viewport => {
+                            if viewport.is_some() {
+                                return Err(de::Error::duplicate_field("viewport"));
+                            }
+                            viewport = map.next_value().map(Some)?;
+                        }
+                        Field::Script => {
+                            if script.is_some() {
+                                return Err(de::Error::duplicate_field("script"));
+                            }
+                            script = map
+                                .next_value::<invariable::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Split => {
+                            if split.is_some() {
+                                return Err(de::Error::duplicate_field("split"));
+                            }
+                            split = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Ncx => {
+                            if ncx.is_some() {
+                                return Err(de::Error::duplicate_field("ncx"));
+                            }
+                            ncx = map.next_value().map(Some)?;
+                        }
+                    }
+                }
+
+                let direction = direction.unwrap_or_default();
+                let layout = layout.unwrap_or_default();
                 let orientation = orientation.unwrap_or_default();
                 let spread = spread.unwrap_or_default();
                 let style = style.unwrap_or_default();
+                let script = script.unwrap_or_default();
+                let split = split.unwrap_or_default();
+                let ncx = ncx.unwrap_or_default();
 
                 Ok(Rendition {
                     direction,
@@ -1002,6 +1748,10 @@ impl<'de> de::Deserialize<'de> for Rendition {
                     orientation,
                     spread,
                     style,
+                    viewport,
+                    script,
+                    split,
+                    ncx,
                 })
             }
         }
@@ -1034,6 +1784,22 @@ impl ser::Serialize for Rendition {
             map.serialize_entry("style", &invariable::wrap(&self.style))?;
         }
 
+        if let Some(viewport) = &self.viewport {
+            map.serialize_entry("viewport", viewport)?;
+        }
+
+        if !self.script.is_empty() {
+            map.serialize_entry("script", &invariable::wrap(&self.script))?;
+        }
+
+        if !self.split.is_default() {
+            map.serialize_entry("split", &serde_enum::wrap(&self.split))?;
+        }
+
+        if self.ncx {
+            map.serialize_entry("ncx", &self.ncx)?;
+        }
+
         map.end()
     }
 }
@@ -1168,12 +1934,168 @@ impl AsRef<str> for Spread {
     }
 }
 
-#[derive(Debug, Default)]
-#[cfg_attr(test, derive(PartialEq))]
+/// Whether a page is split into its halves.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    #[default]
+    None,
+
+    /// Split landscape images in a book that is not landscape, taking them for double pages.
+    Auto,
+}
+
+impl FromStr for Split {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "auto" => Ok(Self::Auto),
+            variant => Err(de::Error::unknown_variant(variant, &["none", "auto"])),
+        }
+    }
+}
+
+impl AsRef<str> for Split {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::None => "none",
+            Self::Auto => "auto",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl<'de> de::Deserialize<'de> for Viewport {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Viewport;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                enum Field {
+                    Width,
+                    Height,
+                }
+
+                impl<'de> de::Deserialize<'de> for Field {
+                    fn deserialize<D: de::Deserializer<'de>>(
+                        deserializer: D,
+                    ) -> Result<Self, D::Error> {
+                        struct Visitor;
+
+                        impl de::Visitor<'_> for Visitor {
+                            type Value = Field;
+
+                            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                                formatter.write_str("an identifier")
+                            }
+
+                            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                                match v {
+                                    "width" => Ok(Field::Width),
+                                    "height" => Ok(Field::Height),
+                                    field => {
+                                        Err(de::Error::unknown_field(field, &["width", "height"]))
+                                    }
+                                }
+                            }
+                        }
+
+                        deserializer.deserialize_identifier(Visitor)
+                    }
+                }
+
+                let mut width = None;
+                let mut height = None;
+
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        Field::Width => {
+                            if width.is_some() {
+                                return Err(de::Error::duplicate_field("width"));
+                            }
+                            width = map
+                                .next_value()
+                                .and_then(|v: u32| {
+                                    if v == 0 {
+                                        Err(de::Error::invalid_value(
+                                            de::Unexpected::Unsigned(0),
+                                            &"a length from 1",
+                                        ))
+                                    } else {
+                                        Ok(v)
+                                    }
+                                })
+                                .map(Some)?;
+                        }
+                        Field::Height => {
+                            if height.is_some() {
+                                return Err(de::Error::duplicate_field("height"));
+                            }
+                            height = map
+                                .next_value()
+                                .and_then(|v: u32| {
+                                    if v == 0 {
+                                        Err(de::Error::invalid_value(
+                                            de::Unexpected::Unsigned(0),
+                                            &"a length from 1",
+                                        ))
+                                    } else {
+                                        Ok(v)
+                                    }
+                                })
+                                .map(Some)?;
+                        }
+                    }
+                }
+
+                let width = width.ok_or_else(|| de::Error::missing_field("width"))?;
+                let height = height.ok_or_else(|| de::Error::missing_field("height"))?;
+
+                Ok(Viewport { width, height })
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+impl ser::Serialize for Viewport {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+
+        map.serialize_entry("width", &self.width)?;
+        map.serialize_entry("height", &self.height)?;
+
+        map.end()
+    }
+}
+
+impl fmt::Display for Viewport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "width={}, height={}", self.width, self.height)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Style {
     pub link: bool,
     pub href: String,
     pub src: String,
+
+    /// Media query the style applies to, such as `(max-width: 1200px)`.
+    pub media: Option<String>,
 }
 
 impl<'de> de::Deserialize<'de> for Style {
@@ -1192,6 +2114,7 @@ impl<'de> de::Deserialize<'de> for Style {
                     Link,
                     Href,
                     Src,
+                    Media,
                 }
 
                 impl<'de> de::Deserialize<'de> for Field {
@@ -1212,9 +2135,10 @@ impl<'de> de::Deserialize<'de> for Style {
                                     "link" => Ok(Field::Link),
                                     "href" => Ok(Field::Href),
                                     "src" => Ok(Field::Src),
+                                    "media" => Ok(Field::Media),
                                     field => Err(de::Error::unknown_field(
                                         field,
-                                        &["link", "href", "src"],
+                                        &["link", "href", "src", "media"],
                                     )),
                                 }
                             }
@@ -1227,6 +2151,7 @@ impl<'de> de::Deserialize<'de> for Style {
                 let mut link = None;
                 let mut href = None;
                 let mut src = None;
+                let mut media = None;
 
                 while let Some(field) = map.next_key()? {
                     match field {
@@ -1266,6 +2191,21 @@ impl<'de> de::Deserialize<'de> for Style {
                                 })
                                 .map(Some)?;
                         }
+                        Field::Media => {
+                            if media.is_some() {
+                                return Err(de::Error::duplicate_field("media"));
+                            }
+                            media = map
+                                .next_value()
+                                .and_then(|s: String| {
+                                    if s.is_empty() {
+                                        Err(de::Error::invalid_length(0, &"at least 1"))
+                                    } else {
+                                        Ok(s)
+                                    }
+                                })
+                                .map(Some)?;
+                        }
                     }
                 }
 
@@ -1273,7 +2213,12 @@ impl<'de> de::Deserialize<'de> for Style {
                 let href = href.ok_or_else(|| de::Error::missing_field("href"))?;
                 let src = src.ok_or_else(|| de::Error::missing_field("src"))?;
 
-                Ok(Style { link, href, src })
+                Ok(Style {
+                    link,
+                    href,
+                    src,
+                    media,
+                })
             }
         }
 
@@ -1292,16 +2237,79 @@ impl ser::Serialize for Style {
         map.serialize_entry("href", &self.href)?;
         map.serialize_entry("src", &self.src)?;
 
+        if let Some(media) = &self.media {
+            map.serialize_entry("media", media)?;
+        }
+
         map.end()
     }
 }
 
-#[derive(Debug, Default)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Chapter {
     pub name: Option<String>,
     pub page: Vec<Page>,
     pub cover: bool,
+
+    /// Whether the name of the chapter is listed in the table of contents.
+    pub toc: bool,
+
+    /// CSS classes added to the body of every page of the chapter.
+    pub class: Vec<String>,
+
+    /// Hrefs of the styles linked from the pages of the chapter in place of the linked ones.
+    pub style: Vec<String>,
+
+    /// Structural semantics of the chapter.
+    pub kind: Option<Kind>,
+
+    /// Panels of the pages of the chapter that do not specify their own.
+    pub panel: Option<Panel>,
+
+    /// Sub-chapters, which follow the pages of the chapter and are listed under it in the table of
+    /// contents.
+    pub children: Vec<Chapter>,
+
+    /// Depth of the chapter in the hierarchy once the sub-chapters are flattened into the list of
+    /// the book, which is not read from the project file.
+    pub depth: usize,
+}
+
+impl Default for Chapter {
+    fn default() -> Self {
+        Self {
+            name: None,
+            page: Vec::new(),
+            cover: false,
+            toc: true,
+            class: Vec::new(),
+            style: Vec::new(),
+            kind: None,
+            panel: None,
+            children: Vec::new(),
+            depth: 0,
+        }
+    }
+}
+
+impl Chapter {
+    /// Returns the caption of the entry of the page at `index` in the table of contents, if any.
+    ///
+    /// An unnamed chapter is listed as `name`.
+    pub fn caption<'a>(&'a self, index: usize, name: Option<&'a str>) -> Option<&'a str> {
+        // a caption of the page takes precedence over the name of the chapter
+        let page = self.page.get(index).filter(|page| !page.hidden)?;
+        page.toc.as_deref().or(self
+            .name
+            .as_deref()
+            .or(name)
+            .filter(|_| index == 0 && self.toc))
+    }
+
+    /// Returns the structural semantics of the chapter, which is implied by `cover`.
+    pub fn kind(&self) -> Option<Kind> {
+        self.kind.or(self.cover.then_some(Kind::Cover))
+    }
 }
 
 impl<'de> de::Deserialize<'de> for Chapter {
@@ -1320,6 +2328,12 @@ impl<'de> de::Deserialize<'de> for Chapter {
                     Name,
                     Page,
                     Cover,
+                    Toc,
+                    Class,
+                    Style,
+                    Kind,
+                    Panel,
+                    Children,
                 }
 
                 impl<'de> de::Deserialize<'de> for Field {
@@ -1340,9 +2354,18 @@ impl<'de> de::Deserialize<'de> for Chapter {
                                     "name" => Ok(Field::Name),
                                     "page" => Ok(Field::Page),
                                     "cover" => Ok(Field::Cover),
+                                    "toc" => Ok(Field::Toc),
+                                    "class" => Ok(Field::Class),
+                                    "style" => Ok(Field::Style),
+                                    "kind" => Ok(Field::Kind),
+                                    "panel" => Ok(Field::Panel),
+                                    "children" => Ok(Field::Children),
                                     field => Err(de::Error::unknown_field(
                                         field,
-                                        &["name", "page", "cover"],
+                                        &[
+                                            "name", "page", "cover", "toc", "class", "style",
+                                            "kind", "panel", "children",
+                                        ],
                                     )),
                                 }
                             }
@@ -1355,6 +2378,12 @@ impl<'de> de::Deserialize<'de> for Chapter {
                 let mut name = None;
                 let mut page = None;
                 let mut cover = None;
+                let mut toc = None;
+                let mut class = None;
+                let mut style = None;
+                let mut kind = None;
+                let mut panel = None;
+                let mut children = None;
 
                 while let Some(field) = map.next_key()? {
                     match field {
@@ -1386,13 +2415,81 @@ impl<'de> de::Deserialize<'de> for Chapter {
                             }
                             cover = map.next_value().map(Some)?;
                         }
+                        Field::Toc => {
+                            if toc.is_some() {
+                                return Err(de::Error::duplicate_field("toc"));
+                            }
+                            toc = map.next_value().map(Some)?;
+                        }
+                        Field::Class => {
+                            if class.is_some() {
+                                return Err(de::Error::duplicate_field("class"));
+                            }
+                            class = map
+                                .next_value::<invariable::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Style => {
+                            if style.is_some() {
+                                return Err(de::Error::duplicate_field("style"));
+                            }
+                            style = map
+                                .next_value::<invariable::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Kind => {
+                            if kind.is_some() {
+                                return Err(de::Error::duplicate_field("kind"));
+                            }
+                            kind = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Panel => {
+                            if panel.is_some() {
+                                return Err(de::Error::duplicate_field("panel"));
+                            }
+                            panel = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Children => {
+                            if children.is_some() {
+                                return Err(de::Error::duplicate_field("children"));
+                            }
+                            children = map.next_value().map(Some)?;
+                        }
                     }
                 }
 
-                let page = page.ok_or_else(|| de::Error::missing_field("page"))?;
+                // a part may consist of its sub-chapters alone
+                let children: Vec<Chapter> = children.unwrap_or_default();
+                let page = match page {
+                    Some(page) => page,
+                    None if !children.is_empty() => Vec::new(),
+                    None => return Err(de::Error::missing_field("page")),
+                };
                 let cover = cover.unwrap_or_default();
+                let toc = toc.unwrap_or(true);
+                let class = class.unwrap_or_default();
+                let style = style.unwrap_or_default();
 
-                Ok(Chapter { name, page, cover })
+                Ok(Chapter {
+                    name,
+                    page,
+                    cover,
+                    toc,
+                    class,
+                    style,
+                    kind,
+                    panel,
+                    children,
+                    depth: 0,
+                })
             }
         }
 
@@ -1416,62 +2513,1374 @@ impl ser::Serialize for Chapter {
             map.serialize_entry("cover", &self.cover)?;
         }
 
-        map.end()
-    }
-}
+        if !self.toc {
+            map.serialize_entry("toc", &self.toc)?;
+        }
 
-#[derive(Debug, Default)]
-#[cfg_attr(test, derive(PartialEq))]
-pub struct Page {
-    pub src: PathBuf,
-}
+        if !self.class.is_empty() {
+            map.serialize_entry("class", &invariable::wrap(&self.class))?;
+        }
 
-impl<'de> de::Deserialize<'de> for Page {
-    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        struct Visitor;
+        if !self.style.is_empty() {
+            map.serialize_entry("style", &invariable::wrap(&self.style))?;
+        }
 
-        impl de::Visitor<'_> for Visitor {
-            type Value = Page;
+        if let Some(kind) = &self.kind {
+            map.serialize_entry("kind", &serde_enum::wrap(kind))?;
+        }
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a string")
-            }
+        if let Some(panel) = &self.panel {
+            map.serialize_entry("panel", panel)?;
+        }
 
-            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
-                if v.is_empty() {
-                    Err(de::Error::invalid_length(0, &"at least 1"))
-                } else {
-                    Ok(Page { src: v.into() })
-                }
-            }
+        if !self.children.is_empty() {
+            map.serialize_entry("children", &self.children)?;
         }
 
-        deserializer.deserialize_str(Visitor)
+        map.end()
     }
 }
 
-impl ser::Serialize for Page {
-    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        if self.src.is_default() {
-            Err(ser::Error::custom("page must not be empty"))
-        } else {
-            ser::Serialize::serialize(&self.src, serializer)
-        }
-    }
+/// How the cover page of a book is chosen.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Cover {
+    /// The pages of the chapters marked as `cover`.
+    #[default]
+    Chapter,
+
+    /// The first page of the book, even if its chapter is not marked as `cover`.
+    Auto,
 }
 
-trait IsDefault {
-    fn is_default(&self) -> bool;
+impl FromStr for Cover {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chapter" => Ok(Self::Chapter),
+            "auto" => Ok(Self::Auto),
+            variant => Err(de::Error::unknown_variant(variant, &["chapter", "auto"])),
+        }
+    }
 }
 
-impl<T: PartialEq + Default> IsDefault for T {
-    fn is_default(&self) -> bool {
-        T::default().eq(self)
+impl AsRef<str> for Cover {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Chapter => "chapter",
+            Self::Auto => "auto",
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
+/// How the images of a book are packaged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Images {
+    pub name_by: NameBy,
+
+    /// Width in pixels pages are scaled down to fit in.
+    pub max_width: Option<u32>,
+
+    /// Height in pixels pages are scaled down to fit in.
+    pub max_height: Option<u32>,
+
+    /// Quality from 1 to 100 of the JPEG images encoded for pages.
+    pub quality: Option<u8>,
+
+    /// Format pages are converted to, instead of keeping the format of their sources.
+    pub format: Option<PageFormat>,
+
+    /// Format pages in formats that reading systems need not support, such as WebP, are converted
+    /// to, instead of being given a JPEG fallback.
+    pub transcode: Option<PageFormat>,
+}
+
+impl<'de> de::Deserialize<'de> for Images {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Images;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                enum Field {
+                    NameBy,
+                    MaxWidth,
+                    MaxHeight,
+                    Quality,
+                    Format,
+                    Transcode,
+                }
+
+                impl<'de> de::Deserialize<'de> for Field {
+                    fn deserialize<D: de::Deserializer<'de>>(
+                        deserializer: D,
+                    ) -> Result<Self, D::Error> {
+                        struct Visitor;
+
+                        impl de::Visitor<'_> for Visitor {
+                            type Value = Field;
+
+                            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                                formatter.write_str("an identifier")
+                            }
+
+                            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                                match v {
+                                    "nameBy" => Ok(Field::NameBy),
+                                    "maxWidth" => Ok(Field::MaxWidth),
+                                    "maxHeight" => Ok(Field::MaxHeight),
+                                    "quality" => Ok(Field::Quality),
+                                    "format" => Ok(Field::Format),
+                                    "transcode" => Ok(Field::Transcode),
+                                    field => Err(de::Error::unknown_field(
+                                        field,
+                                        &[
+                                            "nameBy",
+                                            "maxWidth",
+                                            "maxHeight",
+                                            "quality",
+                                            "format",
+                                            "transcode",
+                                        ],
+                                    )),
+                                }
+                            }
+                        }
+
+                        deserializer.deserialize_identifier(Visitor)
+                    }
+                }
+
+                let mut name_by = None;
+                let mut max_width = None;
+                let mut max_height = None;
+                let mut quality = None;
+                let mut format = None;
+                let mut transcode = None;
+
+                let positive = |v: u32| {
+                    if v == 0 {
+                        Err(de::Error::invalid_value(
+                            de::Unexpected::Unsigned(0),
+                            &"a positive size",
+                        ))
+                    } else {
+                        Ok(v)
+                    }
+                };
+
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        Field::NameBy => {
+                            if name_by.is_some() {
+                                return Err(de::Error::duplicate_field("nameBy"));
+                            }
+                            name_by = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::MaxWidth => {
+                            if max_width.is_some() {
+                                return Err(de::Error::duplicate_field("maxWidth"));
+                            }
+                            max_width = map.next_value().and_then(positive).map(Some)?;
+                        }
+                        Field::MaxHeight => {
+                            if max_height.is_some() {
+                                return Err(de::Error::duplicate_field("maxHeight"));
+                            }
+                            max_height = map.next_value().and_then(positive).map(Some)?;
+                        }
+                        Field::Quality => {
+                            if quality.is_some() {
+                                return Err(de::Error::duplicate_field("quality"));
+                            }
+                            quality = map
+                                .next_value()
+                                .and_then(|v: u8| {
+                                    if (1..=100).contains(&v) {
+                                        Ok(v)
+                                    } else {
+                                        Err(de::Error::invalid_value(
+                                            de::Unexpected::Unsigned(v.into()),
+                                            &"a quality from 1 to 100",
+                                        ))
+                                    }
+                                })
+                                .map(Some)?;
+                        }
+                        Field::Format => {
+                            if format.is_some() {
+                                return Err(de::Error::duplicate_field("format"));
+                            }
+                            format = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Transcode => {
+                            if transcode.is_some() {
+                                return Err(de::Error::duplicate_field("transcode"));
+                            }
+                            transcode = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                    }
+                }
+
+                let name_by = name_by.unwrap_or_default();
+
+                Ok(Images {
+                    name_by,
+                    max_width,
+                    max_height,
+                    quality,
+                    format,
+                    transcode,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+impl ser::Serialize for Images {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+
+        if self.name_by != NameBy::default() {
+            map.serialize_entry("nameBy", &serde_enum::wrap(&self.name_by))?;
+        }
+
+        if let Some(max_width) = &self.max_width {
+            map.serialize_entry("maxWidth", max_width)?;
+        }
+
+        if let Some(max_height) = &self.max_height {
+            map.serialize_entry("maxHeight", max_height)?;
+        }
+
+        if let Some(quality) = &self.quality {
+            map.serialize_entry("quality", quality)?;
+        }
+
+        if let Some(format) = &self.format {
+            map.serialize_entry("format", &serde_enum::wrap(format))?;
+        }
+
+        if let Some(transcode) = &self.transcode {
+            map.serialize_entry("transcode", &serde_enum::wrap(transcode))?;
+        }
+
+        map.end()
+    }
+}
+
+impl Images {
+    /// Returns these settings with those set in `other` taking their place.
+    pub fn overridden_by(self, other: &Images) -> Self {
+        Self {
+            name_by: if other.name_by == NameBy::default() {
+                self.name_by
+            } else {
+                other.name_by
+            },
+            max_width: other.max_width.or(self.max_width),
+            max_height: other.max_height.or(self.max_height),
+            quality: other.quality.or(self.quality),
+            format: other.format.or(self.format),
+            transcode: other.transcode.or(self.transcode),
+        }
+    }
+}
+
+/// Container a book is packaged in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Epub,
+    Cbz,
+    Kindle,
+    Pdf,
+}
+
+impl Format {
+    /// Returns the file extension of the container.
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Epub | Self::Kindle => "epub",
+            Self::Cbz => "cbz",
+            Self::Pdf => "pdf",
+        }
+    }
+
+    /// Whether the book is packaged as an EPub file, even if it is converted afterwards.
+    pub(crate) fn is_epub(self) -> bool {
+        matches!(self, Self::Epub | Self::Kindle)
+    }
+}
+
+impl FromStr for Format {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "epub" => Ok(Self::Epub),
+            "cbz" => Ok(Self::Cbz),
+            "kindle" => Ok(Self::Kindle),
+            "pdf" => Ok(Self::Pdf),
+            variant => Err(de::Error::unknown_variant(
+                variant,
+                &["epub", "cbz", "kindle", "pdf"],
+            )),
+        }
+    }
+}
+
+impl AsRef<str> for Format {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Epub => "epub",
+            Self::Cbz => "cbz",
+            Self::Kindle => "kindle",
+            Self::Pdf => "pdf",
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "generic" => Ok(Self::Generic),
+            "kindle" => Ok(Self::Kindle),
+            "kobo" => Ok(Self::Kobo),
+            "apple" => Ok(Self::Apple),
+            variant => Err(de::Error::unknown_variant(
+                variant,
+                &["generic", "kindle", "kobo", "apple"],
+            )),
+        }
+    }
+}
+
+impl AsRef<str> for Profile {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Named output profile of a book, which builds it with settings of its own.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BuildTarget {
+    /// Name of the target, which is also appended to the name of the file built for it.
+    pub name: String,
+
+    /// Container the book is packaged in.
+    pub format: Option<Format>,
+
+    /// Store the book is checked against.
+    pub profile: Option<Profile>,
+
+    /// Settings of the images that take the place of those of the book.
+    pub images: Images,
+}
+
+impl<'de> de::Deserialize<'de> for BuildTarget {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = BuildTarget;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                enum Field {
+                    Format,
+                    Profile,
+                    Images,
+                }
+
+                impl<'de> de::Deserialize<'de> for Field {
+                    fn deserialize<D: de::Deserializer<'de>>(
+                        deserializer: D,
+                    ) -> Result<Self, D::Error> {
+                        struct Visitor;
+
+                        impl de::Visitor<'_> for Visitor {
+                            type Value = Field;
+
+                            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                                formatter.write_str("an identifier")
+                            }
+
+                            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                                match v {
+                                    "format" => Ok(Field::Format),
+                                    "profile" => Ok(Field::Profile),
+                                    "images" => Ok(Field::Images),
+                                    field => Err(de::Error::unknown_field(
+                                        field,
+                                        &["format", "profile", "images"],
+                                    )),
+                                }
+                            }
+                        }
+
+                        deserializer.deserialize_identifier(Visitor)
+                    }
+                }
+
+                let mut format = None;
+                let mut profile = None;
+                let mut images = None;
+
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        Field::Format => {
+                            if format.is_some() {
+                                return Err(de::Error::duplicate_field("format"));
+                            }
+                            format = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Profile => {
+                            if profile.is_some() {
+                                return Err(de::Error::duplicate_field("profile"));
+                            }
+                            profile = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Images => {
+                            if images.is_some() {
+                                return Err(de::Error::duplicate_field("images"));
+                            }
+                            images = map.next_value().map(Some)?;
+                        }
+                    }
+                }
+
+                let images = images.unwrap_or_default();
+
+                Ok(BuildTarget {
+                    name: String::new(),
+                    format,
+                    profile,
+                    images,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+impl ser::Serialize for BuildTarget {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+
+        if let Some(format) = &self.format {
+            map.serialize_entry("format", &serde_enum::wrap(format))?;
+        }
+
+        if let Some(profile) = &self.profile {
+            map.serialize_entry("profile", &serde_enum::wrap(profile))?;
+        }
+
+        if self.images != Images::default() {
+            map.serialize_entry("images", &self.images)?;
+        }
+
+        map.end()
+    }
+}
+
+/// Format of the images of pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFormat {
+    Jpeg,
+    Png,
+}
+
+impl PageFormat {
+    /// Returns the file extension of the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+        }
+    }
+}
+
+impl FromStr for PageFormat {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jpeg" => Ok(Self::Jpeg),
+            "png" => Ok(Self::Png),
+            variant => Err(de::Error::unknown_variant(variant, &["jpeg", "png"])),
+        }
+    }
+}
+
+impl AsRef<str> for PageFormat {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Jpeg => "jpeg",
+            Self::Png => "png",
+        }
+    }
+}
+
+/// How the packaged images are named.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NameBy {
+    /// Numbers in the order of the spine.
+    #[default]
+    Sequence,
+
+    /// Digests of the contents, which stay the same across rebuilds and are shared by identical
+    /// images.
+    Hash,
+}
+
+impl FromStr for NameBy {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequence" => Ok(Self::Sequence),
+            "hash" => Ok(Self::Hash),
+            variant => Err(de::Error::unknown_variant(variant, &["sequence", "hash"])),
+        }
+    }
+}
+
+impl AsRef<str> for NameBy {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Sequence => "sequence",
+            Self::Hash => "hash",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Cover,
+    Frontmatter,
+    Bodymatter,
+    Backmatter,
+}
+
+impl FromStr for Kind {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cover" => Ok(Self::Cover),
+            "frontmatter" => Ok(Self::Frontmatter),
+            "bodymatter" => Ok(Self::Bodymatter),
+            "backmatter" => Ok(Self::Backmatter),
+            variant => Err(de::Error::unknown_variant(
+                variant,
+                &["cover", "frontmatter", "bodymatter", "backmatter"],
+            )),
+        }
+    }
+}
+
+impl AsRef<str> for Kind {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Cover => "cover",
+            Self::Frontmatter => "frontmatter",
+            Self::Bodymatter => "bodymatter",
+            Self::Backmatter => "backmatter",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Page {
+    /// Source image, or a directory or a glob pattern standing for the images of as many pages.
+    pub src: PathBuf,
+
+    /// Pair of pages stitched into a single spread image instead of `src`.
+    pub join: Vec<PathBuf>,
+
+    /// Caption of the entry of the page in the table of contents.
+    pub toc: Option<String>,
+
+    /// Whether the page intentionally uses the same source as another page.
+    pub reuse: bool,
+
+    /// CSS classes added to the body of the page.
+    pub class: Vec<String>,
+
+    /// Whether the page is left out of the table of contents.
+    pub hidden: bool,
+
+    /// Panels of the page, magnified one by one by readers that support it.
+    pub panel: Option<Panel>,
+
+    /// Regions of the page that link to another page or an external resource.
+    pub link: Vec<Link>,
+
+    /// Narration played while the page is shown.
+    pub audio: Option<Audio>,
+
+    /// Side of the spread the page is placed on, alternating from the previous page if missing.
+    pub spread: Option<Placement>,
+
+    /// Whether the page is split into two, as set for the rendition if missing.
+    pub split: Option<Split>,
+}
+
+impl Page {
+    /// Returns the source files of the page.
+    pub fn sources(&self) -> Vec<&Path> {
+        if self.join.is_empty() {
+            vec![self.src.as_path()]
+        } else {
+            self.join.iter().map(PathBuf::as_path).collect()
+        }
+    }
+}
+
+impl fmt::Display for Page {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sources = self.sources();
+        for (i, src) in sources.iter().enumerate() {
+            if 0 < i {
+                f.write_str(" + ")?;
+            }
+            write!(f, "{}", src.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Page {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Page;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map or a string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                if v.is_empty() {
+                    Err(de::Error::invalid_length(0, &"at least 1"))
+                } else {
+                    Ok(Page {
+                        src: v.into(),
+                        ..Page::default()
+                    })
+                }
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                enum Field {
+                    Src,
+                    Join,
+                    Toc,
+                    Reuse,
+                    Class,
+                    Hidden,
+                    Panel,
+                    Link,
+                    Audio,
+                    Spread,
+                    Split,
+                }
+
+                impl<'de> de::Deserialize<'de> for Field {
+                    fn deserialize<D: de::Deserializer<'de>>(
+                        deserializer: D,
+                    ) -> Result<Self, D::Error> {
+                        struct Visitor;
+
+                        impl de::Visitor<'_> for Visitor {
+                            type Value = Field;
+
+                            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                                formatter.write_str("an identifier")
+                            }
+
+                            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                                match v {
+                                    "src" => Ok(Field::Src),
+                                    "join" => Ok(Field::Join),
+                                    "toc" => Ok(Field::Toc),
+                                    "reuse" => Ok(Field::Reuse),
+                                    "class" => Ok(Field::Class),
+                                    "hidden" => Ok(Field::Hidden),
+                                    "panel" => Ok(Field::Panel),
+                                    "link" => Ok(Field::Link),
+                                    "audio" => Ok(Field::Audio),
+                                    "spread" => Ok(Field::Spread),
+                                    "split" => Ok(Field::Split),
+                                    field => Err(de::Error::unknown_field(
+                                        field,
+                                        &[
+                                            "src", "join", "toc", "reuse", "class", "hidden",
+                                            "panel", "link", "audio", "spread", "split",
+                                        ],
+                                    )),
+                                }
+                            }
+                        }
+
+                        deserializer.deserialize_identifier(Visitor)
+                    }
+                }
+
+                let mut src = None;
+                let mut join = None;
+                let mut toc = None;
+                let mut reuse = None;
+                let mut class = None;
+                let mut hidden = None;
+                let mut panel = None;
+                let mut link = None;
+                let mut audio = None;
+                let mut spread = None;
+                let mut split = None;
+
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        Field::Src => {
+                            if src.is_some() {
+                                return Err(de::Error::duplicate_field("src"));
+                            }
+                            src = map
+                                .next_value()
+                                .and_then(|s: PathBuf| {
+                                    if s.as_os_str().is_empty() {
+                                        Err(de::Error::invalid_length(0, &"at least 1"))
+                                    } else {
+                                        Ok(s)
+                                    }
+                                })
+                                .map(Some)?;
+                        }
+                        Field::Join => {
+                            if join.is_some() {
+                                return Err(de::Error::duplicate_field("join"));
+                            }
+                            join = map
+                                .next_value()
+                                .and_then(|v: Vec<PathBuf>| {
+                                    if v.len() != 2 {
+                                        Err(de::Error::invalid_length(v.len(), &"2"))
+                                    } else {
+                                        Ok(v)
+                                    }
+                                })
+                                .map(Some)?;
+                        }
+                        Field::Toc => {
+                            if toc.is_some() {
+                                return Err(de::Error::duplicate_field("toc"));
+                            }
+                            toc = map.next_value().map(Some)?;
+                        }
+                        Field::Reuse => {
+                            if reuse.is_some() {
+                                return Err(de::Error::duplicate_field("reuse"));
+                            }
+                            reuse = map.next_value().map(Some)?;
+                        }
+                        Field::Class => {
+                            if class.is_some() {
+                                return Err(de::Error::duplicate_field("class"));
+                            }
+                            class = map
+                                .next_value::<invariable::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Hidden => {
+                            if hidden.is_some() {
+                                return Err(de::Error::duplicate_field("hidden"));
+                            }
+                            hidden = map.next_value().map(Some)?;
+                        }
+                        Field::Panel => {
+                            if panel.is_some() {
+                                return Err(de::Error::duplicate_field("panel"));
+                            }
+                            panel = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Link => {
+                            if link.is_some() {
+                                return Err(de::Error::duplicate_field("link"));
+                            }
+                            link = map
+                                .next_value::<invariable::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Audio => {
+                            if audio.is_some() {
+                                return Err(de::Error::duplicate_field("audio"));
+                            }
+                            audio = map.next_value().map(Some)?;
+                        }
+                        Field::Spread => {
+                            if spread.is_some() {
+                                return Err(de::Error::duplicate_field("spread"));
+                            }
+                            spread = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                        Field::Split => {
+                            if split.is_some() {
+                                return Err(de::Error::duplicate_field("split"));
+                            }
+                            split = map
+                                .next_value::<serde_enum::Deserialize<_>>()
+                                .map(|d| d.unwrap())
+                                .map(Some)?;
+                        }
+                    }
+                }
+
+                let reuse = reuse.unwrap_or_default();
+                let class = class.unwrap_or_default();
+                let hidden = hidden.unwrap_or_default();
+                let link = link.unwrap_or_default();
+                let (src, join) = match (src, join) {
+                    (Some(src), None) => (src, Vec::new()),
+                    (None, Some(join)) => (PathBuf::new(), join),
+                    (None, None) => return Err(de::Error::missing_field("src")),
+                    (Some(_), Some(_)) => {
+                        return Err(de::Error::custom("`src` and `join` are exclusive"))
+                    }
+                };
+
+                Ok(Page {
+                    src,
+                    join,
+                    toc,
+                    reuse,
+                    class,
+                    hidden,
+                    panel,
+                    link,
+                    audio,
+                    spread,
+                    split,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl ser::Serialize for Page {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.join.is_empty() && self.src.is_default() {
+            return Err(ser::Error::custom("page must not be empty"));
+        }
+
+        if self.join.is_empty()
+            && self.toc.is_none()
+            && !self.reuse
+            && self.class.is_empty()
+            && !self.hidden
+            && self.panel.is_none()
+            && self.link.is_empty()
+            && self.audio.is_none()
+            && self.spread.is_none()
+            && self.split.is_none()
+        {
+            ser::Serialize::serialize(&self.src, serializer)
+        } else {
+            let mut map = serializer.serialize_map(None)?;
+
+            if self.join.is_empty() {
+                map.serialize_entry("src", &self.src)?;
+            } else {
+                map.serialize_entry("join", &self.join)?;
+            }
+
+            if let Some(toc) = &self.toc {
+                map.serialize_entry("toc", toc)?;
+            }
+
+            if self.reuse {
+                map.serialize_entry("reuse", &self.reuse)?;
+            }
+
+            if !self.class.is_empty() {
+                map.serialize_entry("class", &invariable::wrap(&self.class))?;
+            }
+
+            if self.hidden {
+                map.serialize_entry("hidden", &self.hidden)?;
+            }
+
+            if let Some(panel) = &self.panel {
+                map.serialize_entry("panel", panel)?;
+            }
+
+            if !self.link.is_empty() {
+                map.serialize_entry("link", &invariable::wrap(&self.link))?;
+            }
+
+            if let Some(audio) = &self.audio {
+                map.serialize_entry("audio", audio)?;
+            }
+
+            if let Some(spread) = &self.spread {
+                map.serialize_entry("spread", &serde_enum::wrap(spread))?;
+            }
+
+            if let Some(split) = &self.split {
+                map.serialize_entry("split", &serde_enum::wrap(split))?;
+            }
+
+            map.end()
+        }
+    }
+}
+
+/// Side of a spread a page is placed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    Left,
+    Right,
+
+    /// Across both sides, alone.
+    Center,
+}
+
+impl Placement {
+    /// Returns the placement of the page following a page placed on `self`, or the first page of
+    /// the book if `None`, in a book read in `direction`.
+    pub fn next(this: Option<Self>, direction: Direction) -> Self {
+        match (this, direction) {
+            (Some(Self::Left), _) => Self::Right,
+            (Some(Self::Right), _) => Self::Left,
+            (_, Direction::RightToLeft) => Self::Right,
+            (_, Direction::LeftToRight) => Self::Left,
+        }
+    }
+}
+
+impl FromStr for Placement {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "left" => Ok(Self::Left),
+            "right" => Ok(Self::Right),
+            "center" => Ok(Self::Center),
+            variant => Err(de::Error::unknown_variant(
+                variant,
+                &["left", "right", "center"],
+            )),
+        }
+    }
+}
+
+impl AsRef<str> for Placement {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Left => "left",
+            Self::Right => "right",
+            Self::Center => "center",
+        }
+    }
+}
+
+/// Rectangular region of a page in pixels that links to `target`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Position of a page in the spine, starting at 1.
+    Page(usize),
+
+    /// URL of an external resource.
+    Href(String),
+}
+
+impl<'de> de::Deserialize<'de> for Link {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Link;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                enum Field {
+                    X,
+                    Y,
+                    Width,
+                    Height,
+                    Page,
+                    Href,
+                }
+
+                impl<'de> de::Deserialize<'de> for Field {
+                    fn deserialize<D: de::Deserializer<'de>>(
+                        deserializer: D,
+                    ) -> Result<Self, D::Error> {
+                        struct Visitor;
+
+                        impl de::Visitor<'_> for Visitor {
+                            type Value = Field;
+
+                            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                                formatter.write_str("an identifier")
+                            }
+
+                            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                                match v {
+                                    "x" => Ok(Field::X),
+                                    "y" => Ok(Field::Y),
+                                    "width" => Ok(Field::Width),
+                                    "height" => Ok(Field::Height),
+                                    "page" => Ok(Field::Page),
+                                    "href" => Ok(Field::Href),
+                                    field => Err(de::Error::unknown_field(
+                                        field,
+                                        &["x", "y", "width", "height", "page", "href"],
+                                    )),
+                                }
+                            }
+                        }
+
+                        deserializer.deserialize_identifier(Visitor)
+                    }
+                }
+
+                let mut x = None;
+                let mut y = None;
+                let mut width = None;
+                let mut height = None;
+                let mut page = None;
+                let mut href = None;
+
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        Field::X => {
+                            if x.is_some() {
+                                return Err(de::Error::duplicate_field("x"));
+                            }
+                            x = map.next_value().map(Some)?;
+                        }
+                        Field::Y => {
+                            if y.is_some() {
+                                return Err(de::Error::duplicate_field("y"));
+                            }
+                            y = map.next_value().map(Some)?;
+                        }
+                        Field::Width => {
+                            if width.is_some() {
+                                return Err(de::Error::duplicate_field("width"));
+                            }
+                            width = map.next_value().map(Some)?;
+                        }
+                        Field::Height => {
+                            if height.is_some() {
+                                return Err(de::Error::duplicate_field("height"));
+                            }
+                            height = map.next_value().map(Some)?;
+                        }
+                        Field::Page => {
+                            if page.is_some() {
+                                return Err(de::Error::duplicate_field("page"));
+                            }
+                            page = map
+                                .next_value()
+                                .and_then(|v: usize| {
+                                    if v == 0 {
+                                        Err(de::Error::invalid_value(
+                                            de::Unexpected::Unsigned(0),
+                                            &"at least 1",
+                                        ))
+                                    } else {
+                                        Ok(v)
+                                    }
+                                })
+                                .map(Some)?;
+                        }
+                        Field::Href => {
+                            if href.is_some() {
+                                return Err(de::Error::duplicate_field("href"));
+                            }
+                            href = map.next_value().map(Some)?;
+                        }
+                    }
+                }
+
+                let x = x.ok_or_else(|| de::Error::missing_field("x"))?;
+                let y = y.ok_or_else(|| de::Error::missing_field("y"))?;
+                let width = width.ok_or_else(|| de::Error::missing_field("width"))?;
+                let height = height.ok_or_else(|| de::Error::missing_field("height"))?;
+                let target = match (page, href) {
+                    (Some(page), None) => Target::Page(page),
+                    (None, Some(href)) => Target::Href(href),
+                    (None, None) => return Err(de::Error::missing_field("page")),
+                    (Some(_), Some(_)) => {
+                        return Err(de::Error::custom("`page` and `href` are exclusive"))
+                    }
+                };
+
+                Ok(Link {
+                    x,
+                    y,
+                    width,
+                    height,
+                    target,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+impl ser::Serialize for Link {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+
+        map.serialize_entry("x", &self.x)?;
+        map.serialize_entry("y", &self.y)?;
+        map.serialize_entry("width", &self.width)?;
+        map.serialize_entry("height", &self.height)?;
+
+        match &self.target {
+            Target::Page(page) => map.serialize_entry("page", page)?,
+            Target::Href(href) => map.serialize_entry("href", href)?,
+        }
+
+        map.end()
+    }
+}
+
+/// Clip of an audio file that narrates a page, in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    pub src: PathBuf,
+    pub begin: f64,
+    pub end: f64,
+}
+
+impl Audio {
+    /// Returns the length of the clip in seconds.
+    pub fn duration(&self) -> f64 {
+        self.end - self.begin
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Audio {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Audio;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                enum Field {
+                    Src,
+                    Begin,
+                    End,
+                }
+
+                impl<'de> de::Deserialize<'de> for Field {
+                    fn deserialize<D: de::Deserializer<'de>>(
+                        deserializer: D,
+                    ) -> Result<Self, D::Error> {
+                        struct Visitor;
+
+                        impl de::Visitor<'_> for Visitor {
+                            type Value = Field;
+
+                            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                                formatter.write_str("an identifier")
+                            }
+
+                            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                                match v {
+                                    "src" => Ok(Field::Src),
+                                    "begin" => Ok(Field::Begin),
+                                    "end" => Ok(Field::End),
+                                    field => Err(de::Error::unknown_field(
+                                        field,
+                                        &["src", "begin", "end"],
+                                    )),
+                                }
+                            }
+                        }
+
+                        deserializer.deserialize_identifier(Visitor)
+                    }
+                }
+
+                let mut src = None;
+                let mut begin = None;
+                let mut end = None;
+
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        Field::Src => {
+                            if src.is_some() {
+                                return Err(de::Error::duplicate_field("src"));
+                            }
+                            src = map.next_value().map(Some)?;
+                        }
+                        Field::Begin => {
+                            if begin.is_some() {
+                                return Err(de::Error::duplicate_field("begin"));
+                            }
+                            begin = map.next_value().map(Some)?;
+                        }
+                        Field::End => {
+                            if end.is_some() {
+                                return Err(de::Error::duplicate_field("end"));
+                            }
+                            end = map.next_value().map(Some)?;
+                        }
+                    }
+                }
+
+                let src = src.ok_or_else(|| de::Error::missing_field("src"))?;
+                let begin = begin.unwrap_or_default();
+                let end = end.ok_or_else(|| de::Error::missing_field("end"))?;
+                if !(0.0 <= begin && begin < end) {
+                    return Err(de::Error::custom("`begin` must be before `end`"));
+                }
+
+                Ok(Audio { src, begin, end })
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+impl ser::Serialize for Audio {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+
+        map.serialize_entry("src", &self.src)?;
+
+        if self.begin != 0.0 {
+            map.serialize_entry("begin", &self.begin)?;
+        }
+
+        map.serialize_entry("end", &self.end)?;
+
+        map.end()
+    }
+}
+
+/// Layout of the panels of a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Panel {
+    /// Grid of equally sized panels, such as `2x3` for 2 columns and 3 rows.
+    Grid { columns: u32, rows: u32 },
+
+    /// JSON file that lists the regions of the panels in pixels.
+    Sidecar(PathBuf),
+}
+
+impl FromStr for Panel {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.ends_with(".json") {
+            return Ok(Self::Sidecar(s.into()));
+        }
+
+        s.split_once('x')
+            .and_then(|(columns, rows)| Some((columns.parse().ok()?, rows.parse().ok()?)))
+            .filter(|&(columns, rows)| 0 < columns && 0 < rows)
+            .map(|(columns, rows)| Self::Grid { columns, rows })
+            .ok_or_else(|| {
+                de::Error::invalid_value(
+                    de::Unexpected::Str(s),
+                    &"a grid such as `2x3` or a JSON file",
+                )
+            })
+    }
+}
+
+impl fmt::Display for Panel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Grid { columns, rows } => write!(f, "{columns}x{rows}"),
+            Self::Sidecar(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl ser::Serialize for Panel {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+trait IsDefault {
+    fn is_default(&self) -> bool;
+}
+
+impl<T: PartialEq + Default> IsDefault for T {
+    fn is_default(&self) -> bool {
+        T::default().eq(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
     use serde_test::*;
 
@@ -1491,6 +3900,7 @@ mod tests {
                 chapter: vec![Chapter {
                     page: vec![Page {
                         src: "cover.jpg".into(),
+                        ..Page::default()
                     }],
                     ..Chapter::default()
                 }],
@@ -1520,6 +3930,219 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_de_book_version() {
+        assert_de_tokens_error::<Book>(
+            &[
+                Token::Map { len: None },
+                Token::Str("version"),
+                Token::U32(2),
+            ],
+            "the project file requires a newer tsugumi, as it is of version 2 and this one reads \
+             up to version 1",
+        );
+        assert_de_tokens_error::<Book>(
+            &[
+                Token::Map { len: None },
+                Token::Str("version"),
+                Token::U32(0),
+            ],
+            "invalid value: integer `0`, expected a version from 1",
+        );
+    }
+
+    #[test]
+    fn test_serde_images() {
+        assert_tokens(
+            &Images {
+                name_by: NameBy::Hash,
+                ..Images::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("nameBy"),
+                Token::Str("hash"),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_tokens(
+            &Images {
+                max_height: Some(2048),
+                quality: Some(85),
+                format: Some(PageFormat::Jpeg),
+                transcode: Some(PageFormat::Png),
+                ..Images::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("maxHeight"),
+                Token::U32(2048),
+                Token::Str("quality"),
+                Token::U8(85),
+                Token::Str("format"),
+                Token::Str("jpeg"),
+                Token::Str("transcode"),
+                Token::Str("png"),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_de_tokens_error::<Images>(
+            &[
+                Token::Map { len: None },
+                Token::Str("quality"),
+                Token::U8(0),
+            ],
+            "invalid value: integer `0`, expected a quality from 1 to 100",
+        );
+
+        assert_de_tokens_error::<Images>(
+            &[
+                Token::Map { len: None },
+                Token::Str("nameBy"),
+                Token::Str("md5"),
+            ],
+            "unknown variant `md5`, expected `sequence` or `hash`",
+        );
+    }
+
+    #[test]
+    fn test_images_overridden_by() {
+        let images = Images {
+            max_width: Some(1600),
+            quality: Some(90),
+            ..Images::default()
+        };
+        let other = Images {
+            quality: Some(75),
+            format: Some(PageFormat::Jpeg),
+            ..Images::default()
+        };
+        assert_eq!(
+            images.overridden_by(&other),
+            Images {
+                max_width: Some(1600),
+                quality: Some(75),
+                format: Some(PageFormat::Jpeg),
+                ..Images::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_serde_targets() {
+        let targets = vec![
+            BuildTarget {
+                name: "store".to_string(),
+                profile: Some(Profile::Kobo),
+                images: Images {
+                    max_height: Some(2048),
+                    ..Images::default()
+                },
+                ..BuildTarget::default()
+            },
+            BuildTarget {
+                name: "kindle".to_string(),
+                format: Some(Format::Kindle),
+                ..BuildTarget::default()
+            },
+        ];
+        assert_ser_tokens(
+            &target_map::Serialize(&targets),
+            &[
+                Token::Map { len: Some(2) },
+                Token::Str("store"),
+                Token::Map { len: None },
+                Token::Str("profile"),
+                Token::Str("kobo"),
+                Token::Str("images"),
+                Token::Map { len: None },
+                Token::Str("maxHeight"),
+                Token::U32(2048),
+                Token::MapEnd,
+                Token::MapEnd,
+                Token::Str("kindle"),
+                Token::Map { len: None },
+                Token::Str("format"),
+                Token::Str("kindle"),
+                Token::MapEnd,
+                Token::MapEnd,
+            ],
+        );
+
+        let book: Book = serde_yaml::from_str(
+            "metadata: {title: T, language: ja, identifier: id}\n\
+             chapter: {page: a.png}\n\
+             targets:\n  \
+               store: {profile: kobo, images: {maxHeight: 2048}}\n  \
+               kindle: {format: kindle}\n",
+        )
+        .unwrap();
+        assert_eq!(book.targets, targets);
+        assert_eq!(book.target("kindle"), Some(&targets[1]));
+        assert_eq!(book.target("archive"), None);
+
+        assert_de_tokens_error::<BuildTarget>(
+            &[
+                Token::Map { len: None },
+                Token::Str("format"),
+                Token::Str("mobi"),
+            ],
+            "unknown variant `mobi`, expected one of `epub`, `cbz`, `kindle`, `pdf`",
+        );
+        assert_de_tokens_error::<BuildTarget>(
+            &[
+                Token::Map { len: None },
+                Token::Str("profile"),
+                Token::Str("google"),
+            ],
+            "unknown variant `google`, expected one of `generic`, `kindle`, `kobo`, `apple`",
+        );
+
+        assert_de_tokens_error::<target_map::Deserialize>(
+            &[
+                Token::Map { len: None },
+                Token::Str("store"),
+                Token::Map { len: None },
+                Token::MapEnd,
+                Token::Str("store"),
+            ],
+            "duplicate target `store`",
+        );
+    }
+
+    #[test]
+    fn test_is_cover() {
+        let chapter = |cover| Chapter {
+            page: vec![Page::default(), Page::default()],
+            cover,
+            ..Chapter::default()
+        };
+        let mut book = Book {
+            chapter: vec![chapter(false), chapter(true)],
+            ..Book::default()
+        };
+        assert!(!book.is_cover(0, 0));
+        assert!(book.is_cover(1, 1));
+
+        book.cover = Cover::Auto;
+        assert!(book.is_cover(0, 0));
+        assert!(!book.is_cover(0, 1));
+    }
+
+    #[test]
+    fn test_source_path() {
+        let mut book = Book::default();
+        assert_eq!(book.source_path(Path::new("a.png")), Path::new("a.png"));
+
+        book.assets_dir = Some("pages".into());
+        assert_eq!(
+            book.source_path(Path::new("a.png")),
+            Path::new("pages/a.png")
+        );
+    }
+
     #[test]
     fn test_serde_metadata() {
         assert_ser_tokens_error(
@@ -1532,6 +4155,52 @@ mod tests {
             &[Token::Map { len: Some(0) }, Token::MapEnd],
             "missing field `title`",
         );
+
+        assert_tokens(
+            &Metadata {
+                title: vec![Title {
+                    name: "Title".to_string(),
+                    ..Title::default()
+                }],
+                language: "ja".to_string(),
+                identifier: "id".to_string(),
+                publisher: Some("Publisher".to_string()),
+                publication_date: "2024-05".parse().ok(),
+                subject: vec!["Fiction".to_string()],
+                book_type: vec!["comic".to_string(), "manga".to_string()],
+                schema_type: Some("ComicStory".to_string()),
+                age_rating: Some("15+".to_string()),
+                content_warning: vec!["violence".to_string()],
+                ..Metadata::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("title"),
+                Token::Str("Title"),
+                Token::Str("language"),
+                Token::Str("ja"),
+                Token::Str("identifier"),
+                Token::Str("id"),
+                Token::Str("publisher"),
+                Token::Str("Publisher"),
+                Token::Str("publicationDate"),
+                Token::Str("2024-05"),
+                Token::Str("subject"),
+                Token::Str("Fiction"),
+                Token::Str("type"),
+                Token::Seq { len: Some(2) },
+                Token::Str("comic"),
+                Token::Str("manga"),
+                Token::SeqEnd,
+                Token::Str("schemaType"),
+                Token::Str("ComicStory"),
+                Token::Str("ageRating"),
+                Token::Str("15+"),
+                Token::Str("contentWarning"),
+                Token::Str("violence"),
+                Token::MapEnd,
+            ],
+        );
     }
 
     #[test]
@@ -1563,20 +4232,68 @@ mod tests {
             &[Token::Str("Name")],
         );
 
-        assert_tokens(
-            &Creator {
-                name: "Name".to_string(),
-                role: Some("aut".to_string()),
-                ..Creator::default()
-            },
+        assert_tokens(
+            &Creator {
+                name: "Name".to_string(),
+                role: Some("aut".to_string()),
+                ..Creator::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("name"),
+                Token::Str("Name"),
+                Token::Str("role"),
+                Token::Str("aut"),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_tokens(
+            &Creator {
+                name: "Name".to_string(),
+                display_seq: Some(2),
+                ..Creator::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("name"),
+                Token::Str("Name"),
+                Token::Str("displaySeq"),
+                Token::U32(2),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_de_tokens_error::<Creator>(
             &[
                 Token::Map { len: None },
                 Token::Str("name"),
                 Token::Str("Name"),
-                Token::Str("role"),
-                Token::Str("aut"),
+                Token::Str("scheme"),
+                Token::Str("onix:codelist17"),
                 Token::MapEnd,
             ],
+            "missing field `role`",
+        );
+    }
+
+    #[test]
+    fn test_creator_relator() {
+        let creator = |role: Option<&str>, scheme: Option<&str>| Creator {
+            name: "Name".to_string(),
+            role: role.map(str::to_string),
+            scheme: scheme.map(str::to_string),
+            ..Creator::default()
+        };
+        assert_eq!(creator(None, None).relator(), Some("aut"));
+        assert_eq!(creator(Some("ill"), None).relator(), Some("ill"));
+        assert_eq!(
+            creator(Some("ill"), Some(MARC_RELATORS)).relator(),
+            Some("ill")
+        );
+        assert_eq!(
+            creator(Some("A01"), Some("onix:codelist17")).relator(),
+            None
         );
     }
 
@@ -1599,6 +4316,55 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_serde_position() {
+        assert_tokens(&Position::from(3), &[Token::U64(3)]);
+        assert_tokens(&"3.50".parse::<Position>().unwrap(), &[Token::Str("3.50")]);
+        assert_de_tokens(&Position::from(3), &[Token::Str("3")]);
+
+        assert_de_tokens(&"3.5".parse::<Position>().unwrap(), &[Token::F64(3.5)]);
+
+        // a float keeps only its shortest form, so `3.10` must be quoted
+        assert_eq!(
+            serde_yaml::from_str::<Position>("3.10").unwrap().as_str(),
+            "3.1"
+        );
+        assert_eq!(
+            serde_yaml::from_str::<Position>("\"3.10\"")
+                .unwrap()
+                .as_str(),
+            "3.10"
+        );
+        assert_de_tokens_error::<Position>(
+            &[Token::Str("3a")],
+            "invalid value: string \"3a\", expected a number like `3` or `3.5`",
+        );
+        assert_de_tokens_error::<Position>(
+            &[Token::I64(-1)],
+            "invalid value: integer `-1`, expected a number or a string",
+        );
+        assert!(".5".parse::<Position>().is_err());
+        assert!("3.".parse::<Position>().is_err());
+    }
+
+    #[test]
+    fn test_serde_date() {
+        assert_tokens(&"2024".parse::<Date>().unwrap(), &[Token::Str("2024")]);
+        assert_tokens(
+            &"2024-05-31".parse::<Date>().unwrap(),
+            &[Token::Str("2024-05-31")],
+        );
+        assert_de_tokens(&"2024".parse::<Date>().unwrap(), &[Token::U64(2024)]);
+
+        assert_de_tokens_error::<Date>(
+            &[Token::Str("2024/05")],
+            "invalid value: string \"2024/05\", expected a date like `2024`, `2024-05` or `2024-05-31`",
+        );
+        assert!("2024-13".parse::<Date>().is_err());
+        assert!("2024-5-1".parse::<Date>().is_err());
+        assert!("24".parse::<Date>().is_err());
+    }
+
     #[test]
     fn test_serde_rendition() {
         assert_tokens(
@@ -1611,7 +4377,9 @@ mod tests {
                     link: false,
                     href: "Href".to_string(),
                     src: "Src".to_string(),
+                    media: Some("(orientation: landscape)".to_string()),
                 }],
+                ncx: true,
                 ..Rendition::default()
             },
             &[
@@ -1622,9 +4390,70 @@ mod tests {
                 Token::Str("Href"),
                 Token::Str("src"),
                 Token::Str("Src"),
+                Token::Str("media"),
+                Token::Str("(orientation: landscape)"),
+                Token::MapEnd,
+                Token::Str("ncx"),
+                Token::Bool(true),
+                Token::MapEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_serde_viewport() {
+        assert_tokens(
+            &Viewport {
+                width: 1600,
+                height: 2560,
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("width"),
+                Token::U32(1600),
+                Token::Str("height"),
+                Token::U32(2560),
                 Token::MapEnd,
+            ],
+        );
+
+        assert_de_tokens_error::<Viewport>(
+            &[
+                Token::Map { len: None },
+                Token::Str("width"),
+                Token::U32(1600),
                 Token::MapEnd,
             ],
+            "missing field `height`",
+        );
+
+        assert_de_tokens_error::<Viewport>(
+            &[Token::Map { len: None }, Token::Str("width"), Token::U32(0)],
+            "invalid value: integer `0`, expected a length from 1",
+        );
+
+        assert_de_tokens_error::<Viewport>(
+            &[
+                Token::Map { len: None },
+                Token::Str("width"),
+                Token::U32(1600),
+                Token::Str("width"),
+            ],
+            "duplicate field `width`",
+        );
+
+        assert_de_tokens_error::<Viewport>(
+            &[Token::Map { len: None }, Token::Str("depth")],
+            "unknown field `depth`, expected `width` or `height`",
+        );
+
+        assert_de_tokens_error::<Viewport>(
+            &[
+                Token::Map { len: None },
+                Token::Str("height"),
+                Token::Str("2560"),
+            ],
+            "invalid type: string \"2560\", expected u32",
         );
     }
 
@@ -1640,23 +4469,477 @@ mod tests {
     fn test_serde_chapter() {
         assert_tokens(
             &Chapter {
-                page: vec![Page { src: "page".into() }],
+                page: vec![Page {
+                    src: "page".into(),
+                    ..Page::default()
+                }],
+                ..Chapter::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("page"),
+                Token::Str("page"),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_tokens(
+            &Chapter {
+                page: vec![Page {
+                    src: "page".into(),
+                    ..Page::default()
+                }],
+                toc: false,
+                ..Chapter::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("page"),
+                Token::Str("page"),
+                Token::Str("toc"),
+                Token::Bool(false),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_tokens(
+            &Chapter {
+                page: vec![Page {
+                    src: "page".into(),
+                    ..Page::default()
+                }],
+                kind: Some(Kind::Backmatter),
                 ..Chapter::default()
             },
             &[
                 Token::Map { len: None },
                 Token::Str("page"),
                 Token::Str("page"),
+                Token::Str("kind"),
+                Token::Str("backmatter"),
+                Token::MapEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_serde_chapter_children() {
+        let chapter = |name: &str, children| Chapter {
+            name: Some(name.to_string()),
+            page: vec![Page {
+                src: "page".into(),
+                ..Page::default()
+            }],
+            children,
+            ..Chapter::default()
+        };
+        assert_tokens(
+            &Chapter {
+                page: Vec::new(),
+                ..chapter("Part", vec![chapter("Chapter", Vec::new())])
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("name"),
+                Token::Str("Part"),
+                Token::Str("children"),
+                Token::Seq { len: Some(1) },
+                Token::Map { len: None },
+                Token::Str("name"),
+                Token::Str("Chapter"),
+                Token::Str("page"),
+                Token::Str("page"),
+                Token::MapEnd,
+                Token::SeqEnd,
+                Token::MapEnd,
+            ],
+        );
+        assert_de_tokens_error::<Chapter>(
+            &[
+                Token::Map { len: None },
+                Token::Str("children"),
+                Token::Seq { len: Some(0) },
+                Token::SeqEnd,
+                Token::MapEnd,
+            ],
+            "missing field `page`",
+        );
+
+        let mut book = Book {
+            chapter: vec![
+                chapter("A", vec![chapter("B", vec![chapter("C", Vec::new())])]),
+                chapter("D", Vec::new()),
+            ],
+            ..Book::default()
+        };
+        book.flatten_chapters();
+        book.flatten_chapters();
+        let chapters = book
+            .chapter
+            .iter()
+            .map(|c| (c.name.as_deref().unwrap(), c.depth, c.children.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chapters,
+            [("A", 0, 0), ("B", 1, 0), ("C", 2, 0), ("D", 0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_numbered_names() {
+        let chapter = |name: Option<&str>, kind| Chapter {
+            name: name.map(str::to_string),
+            kind,
+            ..Chapter::default()
+        };
+        let mut book = Book {
+            metadata: Metadata {
+                language: "ja".to_string(),
+                ..Metadata::default()
+            },
+            chapter: vec![
+                chapter(None, Some(Kind::Cover)),
+                chapter(None, None),
+                chapter(Some("Extra"), None),
+                chapter(None, Some(Kind::Bodymatter)),
+                chapter(None, Some(Kind::Backmatter)),
+            ],
+            ..Book::default()
+        };
+        assert_eq!(
+            book.numbered_names(),
+            [
+                None,
+                Some("第1話".to_string()),
+                None,
+                Some("第3話".to_string()),
+                None
+            ]
+        );
+
+        book.chapter_format = Some("Episode {n}".to_string());
+        assert_eq!(book.numbered_names()[1].as_deref(), Some("Episode 1"));
+    }
+
+    #[test]
+    fn test_panel() {
+        assert_eq!(
+            "2x3".parse::<Panel>().unwrap(),
+            Panel::Grid {
+                columns: 2,
+                rows: 3
+            }
+        );
+        assert_eq!(
+            "p01.json".parse::<Panel>().unwrap(),
+            Panel::Sidecar("p01.json".into())
+        );
+        assert!("0x2".parse::<Panel>().is_err());
+        assert!("grid".parse::<Panel>().is_err());
+
+        assert_tokens(
+            &Page {
+                src: "path".into(),
+                panel: Some(Panel::Grid {
+                    columns: 1,
+                    rows: 4,
+                }),
+                ..Page::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("src"),
+                Token::Str("path"),
+                Token::Str("panel"),
+                Token::Str("1x4"),
+                Token::MapEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_serde_link() {
+        assert_tokens(
+            &Link {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 4,
+                target: Target::Page(5),
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("x"),
+                Token::U32(1),
+                Token::Str("y"),
+                Token::U32(2),
+                Token::Str("width"),
+                Token::U32(3),
+                Token::Str("height"),
+                Token::U32(4),
+                Token::Str("page"),
+                Token::U64(5),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_de_tokens_error::<Link>(
+            &[
+                Token::Map { len: None },
+                Token::Str("x"),
+                Token::U32(1),
+                Token::Str("y"),
+                Token::U32(2),
+                Token::Str("width"),
+                Token::U32(3),
+                Token::Str("height"),
+                Token::U32(4),
+                Token::Str("page"),
+                Token::U64(5),
+                Token::Str("href"),
+                Token::Str("https://example.com/"),
+                Token::MapEnd,
+            ],
+            "`page` and `href` are exclusive",
+        );
+    }
+
+    #[test]
+    fn test_serde_audio() {
+        assert_tokens(
+            &Audio {
+                src: "a.mp3".into(),
+                begin: 0.0,
+                end: 2.5,
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("src"),
+                Token::Str("a.mp3"),
+                Token::Str("end"),
+                Token::F64(2.5),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_de_tokens_error::<Audio>(
+            &[
+                Token::Map { len: None },
+                Token::Str("src"),
+                Token::Str("a.mp3"),
+                Token::Str("begin"),
+                Token::F64(3.0),
+                Token::Str("end"),
+                Token::F64(2.5),
                 Token::MapEnd,
             ],
+            "`begin` must be before `end`",
         );
     }
 
+    #[test]
+    fn test_chapter_caption() {
+        let chapter = Chapter {
+            name: Some("Chapter".to_string()),
+            page: vec![
+                Page {
+                    src: "a".into(),
+                    ..Page::default()
+                },
+                Page {
+                    src: "b".into(),
+                    toc: Some("Caption".to_string()),
+                    ..Page::default()
+                },
+                Page {
+                    src: "c".into(),
+                    toc: Some("Ad".to_string()),
+                    hidden: true,
+                    ..Page::default()
+                },
+            ],
+            ..Chapter::default()
+        };
+        assert_eq!(chapter.caption(0, None), Some("Chapter"));
+        assert_eq!(chapter.caption(1, None), Some("Caption"));
+        assert_eq!(chapter.caption(2, None), None);
+
+        let chapter = Chapter {
+            toc: false,
+            ..chapter
+        };
+        assert_eq!(chapter.caption(0, None), None);
+        assert_eq!(chapter.caption(1, None), Some("Caption"));
+    }
+
+    #[test]
+    fn test_placement_next() {
+        use Direction::*;
+        use Placement::*;
+
+        assert_eq!(Placement::next(None, RightToLeft), Right);
+        assert_eq!(Placement::next(None, LeftToRight), Left);
+        assert_eq!(Placement::next(Some(Right), RightToLeft), Left);
+        assert_eq!(Placement::next(Some(Left), RightToLeft), Right);
+        assert_eq!(Placement::next(Some(Center), RightToLeft), Right);
+        assert_eq!(Placement::next(Some(Center), LeftToRight), Left);
+    }
+
     #[test]
     fn test_serde_page() {
-        assert_tokens(&Page { src: "path".into() }, &[Token::Str("path")]);
+        assert_tokens(
+            &Page {
+                src: "path".into(),
+                ..Page::default()
+            },
+            &[Token::Str("path")],
+        );
+
+        assert_tokens(
+            &Page {
+                join: vec!["a".into(), "b".into()],
+                ..Page::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("join"),
+                Token::Seq { len: Some(2) },
+                Token::Str("a"),
+                Token::Str("b"),
+                Token::SeqEnd,
+                Token::MapEnd,
+            ],
+        );
+
+        assert_de_tokens_error::<Page>(
+            &[
+                Token::Map { len: None },
+                Token::Str("join"),
+                Token::Seq { len: Some(1) },
+                Token::Str("a"),
+                Token::SeqEnd,
+            ],
+            "invalid length 1, expected 2",
+        );
+
+        assert_tokens(
+            &Page {
+                src: "path".into(),
+                toc: Some("Caption".to_string()),
+                ..Page::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("src"),
+                Token::Str("path"),
+                Token::Str("toc"),
+                Token::Str("Caption"),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_tokens(
+            &Page {
+                src: "path".into(),
+                reuse: true,
+                ..Page::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("src"),
+                Token::Str("path"),
+                Token::Str("reuse"),
+                Token::Bool(true),
+                Token::MapEnd,
+            ],
+        );
+
+        assert_tokens(
+            &Page {
+                src: "path".into(),
+                class: vec!["art".to_string()],
+                ..Page::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("src"),
+                Token::Str("path"),
+                Token::Str("class"),
+                Token::Str("art"),
+                Token::MapEnd,
+            ],
+        );
 
         assert_ser_tokens_error(&Page::default(), &[], "page must not be empty");
+
+        assert_tokens(
+            &Page {
+                src: "path".into(),
+                spread: Some(Placement::Left),
+                split: Some(Split::Auto),
+                ..Page::default()
+            },
+            &[
+                Token::Map { len: None },
+                Token::Str("src"),
+                Token::Str("path"),
+                Token::Str("spread"),
+                Token::Str("left"),
+                Token::Str("split"),
+                Token::Str("auto"),
+                Token::MapEnd,
+            ],
+        );
+    }
+}
+
+/// Build targets written as a map from their names to their settings.
+mod target_map {
+    use super::*;
+
+    pub struct Deserialize(pub Vec<BuildTarget>);
+
+    impl<'de> de::Deserialize<'de> for Deserialize {
+        fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct Visitor;
+
+            impl<'de> de::Visitor<'de> for Visitor {
+                type Value = Deserialize;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("a map of targets")
+                }
+
+                fn visit_map<A: de::MapAccess<'de>>(
+                    self,
+                    mut map: A,
+                ) -> Result<Self::Value, A::Error> {
+                    let mut targets = Vec::<BuildTarget>::new();
+                    while let Some(name) = map.next_key::<String>()? {
+                        if targets.iter().any(|target| target.name == name) {
+                            return Err(de::Error::custom(format!("duplicate target `{name}`")));
+                        }
+                        let target = map.next_value()?;
+                        targets.push(BuildTarget { name, ..target });
+                    }
+                    Ok(Deserialize(targets))
+                }
+            }
+
+            deserializer.deserialize_map(Visitor)
+        }
+    }
+
+    pub struct Serialize<'a>(pub &'a [BuildTarget]);
+
+    impl ser::Serialize for Serialize<'_> {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(Some(self.0.len()))?;
+            for target in self.0 {
+                map.serialize_entry(&target.name, target)?;
+            }
+            map.end()
+        }
     }
 }
 
@@ -1728,7 +5011,7 @@ mod serde_enum {
         }
     }
 
-    pub fn wrap<T>(inner: &T) -> Serialize<T> {
+    pub fn wrap<T>(inner: &T) -> Serialize<'_, T> {
         Serialize(inner)
     }
 
@@ -1917,7 +5200,7 @@ mod invariable {
         }
     }
 
-    pub fn wrap<T>(inner: &[T]) -> Serialize<T> {
+    pub fn wrap<T>(inner: &[T]) -> Serialize<'_, T> {
         Serialize(inner)
     }
 
@@ -0,0 +1,209 @@
+use crate::i18n::tr;
+use crate::model::Direction;
+use anyhow::{Context as _, Result};
+use serde::Deserialize;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use xml::writer::XmlEvent;
+use xml::EventWriter;
+
+/// Rectangle of a panel in pixels of the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// Whether the region lies within a page of `width`×`height`.
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        0 < self.width
+            && 0 < self.height
+            && self.x.saturating_add(self.width) <= width
+            && self.y.saturating_add(self.height) <= height
+    }
+}
+
+/// Splits a page of `width`×`height` into `columns`×`rows` panels in reading order.
+///
+/// Fails if the grid has more columns or rows than the page has pixels, which would leave some
+/// panels empty.
+pub fn grid(
+    columns: u32,
+    rows: u32,
+    width: u32,
+    height: u32,
+    direction: Direction,
+) -> Result<Vec<Region>> {
+    if columns == 0 || rows == 0 || width < columns || height < rows {
+        anyhow::bail!(tr!(
+            "a grid of {columns}x{rows} panels does not fit in a page of {width}x{height} pixels",
+            "{columns}x{rows} のコマの格子は {width}x{height} ピクセルのページに収まりません"
+        ));
+    }
+
+    // the products of the edges and the indices may not fit in 32 bits
+    let split = |length: u32, count: u32, index: u32| {
+        (u64::from(length) * u64::from(index) / u64::from(count)) as u32
+    };
+
+    let mut regions = Vec::with_capacity(columns as usize * rows as usize);
+    for row in 0..rows {
+        for i in 0..columns {
+            let column = match direction {
+                Direction::RightToLeft => columns - 1 - i,
+                Direction::LeftToRight => i,
+            };
+
+            let x = split(width, columns, column);
+            let y = split(height, rows, row);
+            regions.push(Region {
+                x,
+                y,
+                width: split(width, columns, column + 1) - x,
+                height: split(height, rows, row + 1) - y,
+            });
+        }
+    }
+
+    Ok(regions)
+}
+
+/// Reads the regions of the panels, in reading order, from `reader` of the JSON file at `path`.
+pub fn read<R: Read>(path: &Path, reader: R) -> Result<Vec<Region>> {
+    serde_json::from_reader(BufReader::new(reader))
+        .with_context(|| format!("failed to read `{}`", path.display()))
+}
+
+/// Writes the region magnification markup of Kindle for `regions` of a page.
+///
+/// Each panel gets a tap target over its region and a hidden view of the image enlarged to fit
+/// the page, which the reader shows in the panel view.
+pub fn write_magnification<W: Write>(
+    w: &mut EventWriter<W>,
+    image: &str,
+    width: u32,
+    height: u32,
+    regions: &[Region],
+) -> xml::writer::Result<()> {
+    let percent = |value: u32, total: u32| f64::from(value) * 100.0 / f64::from(total);
+
+    w.write(XmlEvent::start_element("div").attr("class", "panels").attr(
+        "style",
+        "position:absolute;left:0;top:0;width:100%;height:100%",
+    ))?;
+
+    for (i, region) in regions.iter().enumerate() {
+        let ordinal = i + 1;
+        let target = format!("panel-{ordinal}");
+
+        let style = format!(
+            "position:absolute;left:{:.4}%;top:{:.4}%;width:{:.4}%;height:{:.4}%",
+            percent(region.x, width),
+            percent(region.y, height),
+            percent(region.width, width),
+            percent(region.height, height)
+        );
+        w.write(XmlEvent::start_element("div").attr("style", &style))?;
+        w.write(
+            XmlEvent::start_element("a")
+                .attr("class", "app-amzn-magnify")
+                .attr("style", "display:block;width:100%;height:100%")
+                .attr(
+                    "data-app-amzn-magnify",
+                    &format!(r#"{{"targetId":"{target}","ordinal":{ordinal}}}"#),
+                ),
+        )?;
+        w.write(XmlEvent::end_element())?; // a
+        w.write(XmlEvent::end_element())?; // div
+
+        // enlarge the region as much as it fits in the page
+        let scale = (f64::from(width) / f64::from(region.width))
+            .min(f64::from(height) / f64::from(region.height));
+        w.write(
+            XmlEvent::start_element("div")
+                .attr("id", &target)
+                .attr("class", "target-mag")
+                .attr(
+                    "style",
+                    "display:none;position:absolute;left:0;top:0;width:100%;height:100%;overflow:hidden",
+                ),
+        )?;
+        let style = format!(
+            "position:absolute;width:{:.4}%;left:{:.4}%;top:{:.4}%",
+            100.0 * scale,
+            // avoid writing `-0`
+            0.0 - percent(region.x, width) * scale,
+            0.0 - percent(region.y, height) * scale
+        );
+        w.write(
+            XmlEvent::start_element("img")
+                .attr("src", image)
+                .attr("alt", "")
+                .attr("style", &style),
+        )?;
+        w.write(XmlEvent::end_element())?; // img
+        w.write(XmlEvent::end_element())?; // div
+    }
+
+    w.write(XmlEvent::end_element())?; // div
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid() {
+        let region = |x, y| Region {
+            x,
+            y,
+            width: 50,
+            height: 100,
+        };
+
+        assert_eq!(
+            grid(2, 2, 100, 200, Direction::RightToLeft).unwrap(),
+            [region(50, 0), region(0, 0), region(50, 100), region(0, 100)]
+        );
+        assert_eq!(
+            grid(2, 1, 100, 100, Direction::LeftToRight).unwrap(),
+            [
+                Region {
+                    x: 0,
+                    y: 0,
+                    width: 50,
+                    height: 100
+                },
+                Region {
+                    x: 50,
+                    y: 0,
+                    width: 50,
+                    height: 100
+                }
+            ]
+        );
+
+        // the edges times the indices overflow 32 bits
+        let regions = grid(3, 1, u32::MAX, 10, Direction::LeftToRight).unwrap();
+        assert_eq!(regions[2].x + regions[2].width, u32::MAX);
+        assert!(grid(101, 1, 100, 100, Direction::LeftToRight).is_err());
+        assert!(grid(1, 101, 100, 100, Direction::LeftToRight).is_err());
+    }
+
+    #[test]
+    fn test_fits() {
+        let region = Region {
+            x: 10,
+            y: 10,
+            width: 90,
+            height: 40,
+        };
+        assert!(region.fits(100, 50));
+        assert!(!region.fits(99, 50));
+    }
+}
@@ -0,0 +1,354 @@
+use crate::model::Direction;
+use anyhow::{Context as _, Result};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{ColorType, ImageDecoder, ImageFormat, ImageReader};
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, TextStr};
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+/// Points, the unit of PDF, per pixel, which lays the pages out at 96 pixels per inch as CSS does.
+const POINTS_PER_PIXEL: f32 = 0.75;
+
+/// Image of a page of a PDF file, which fills a page of its own size.
+pub struct Page<'a> {
+    /// Path to the image, which it is named after if it is in memory.
+    pub src: &'a Path,
+    /// Reads the content of the image as it is embedded, so that one page is held at a time.
+    pub read: Box<dyn Fn() -> Result<Vec<u8>> + 'a>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Entry of the outline of a PDF file.
+pub struct Bookmark<'a> {
+    /// Position of the page the entry points at, starting at 0.
+    pub page: usize,
+    pub title: &'a str,
+    /// Nesting level of the entry, at most one deeper than the entry before it.
+    pub level: usize,
+}
+
+/// Document information of a PDF file.
+pub struct Info<'a> {
+    pub title: &'a str,
+    pub author: &'a str,
+    pub language: &'a str,
+    pub direction: Direction,
+    /// Whether the pages are shown in spreads rather than one at a time.
+    pub spread: bool,
+}
+
+/// Writes a PDF file of `pages` into `w`, with `bookmarks` as its outline.
+///
+/// JPEG images are embedded as they are, while those of the other formats are decoded and
+/// compressed again.
+pub fn write_pdf<W: Write>(
+    w: &mut W,
+    info: &Info,
+    pages: &[Page],
+    bookmarks: &[Bookmark],
+) -> Result<()> {
+    let mut ids = (1..).map(Ref::new);
+    let mut next = || ids.next().unwrap();
+    let catalog_id = next();
+    let tree_id = next();
+    let info_id = next();
+    let outline_id = next();
+    let page_ids = pages.iter().map(|_| next()).collect::<Vec<_>>();
+    let bookmark_ids = bookmarks.iter().map(|_| next()).collect::<Vec<_>>();
+
+    let mut pdf = Pdf::new();
+
+    let mut catalog = pdf.catalog(catalog_id);
+    catalog.pages(tree_id);
+    if !bookmarks.is_empty() {
+        catalog.outlines(outline_id);
+    }
+    if !info.language.is_empty() {
+        catalog.lang(TextStr(info.language));
+    }
+    catalog.page_layout(if info.spread {
+        pdf_writer::types::PageLayout::TwoPageRight
+    } else {
+        pdf_writer::types::PageLayout::SinglePage
+    });
+    let mut preferences = catalog.viewer_preferences();
+    preferences.direction(match info.direction {
+        Direction::RightToLeft => pdf_writer::types::Direction::R2L,
+        Direction::LeftToRight => pdf_writer::types::Direction::L2R,
+    });
+    preferences.pair(Name(b"DisplayDocTitle"), true);
+    preferences.finish();
+    catalog.finish();
+
+    let mut document_info = pdf.document_info(info_id);
+    document_info.title(TextStr(info.title));
+    if !info.author.is_empty() {
+        document_info.author(TextStr(info.author));
+    }
+    document_info.creator(TextStr(env!("CARGO_PKG_NAME")));
+    document_info.finish();
+
+    pdf.pages(tree_id)
+        .kids(page_ids.iter().copied())
+        .count(pages.len() as i32);
+
+    let image_name = Name(b"Im1");
+    for (page, &page_id) in pages.iter().zip(&page_ids) {
+        let (image_id, mask_id, content_id) = (next(), next(), next());
+        let (width, height) = (
+            page.width as f32 * POINTS_PER_PIXEL,
+            page.height as f32 * POINTS_PER_PIXEL,
+        );
+
+        let mut pdf_page = pdf.page(page_id);
+        pdf_page.parent(tree_id);
+        pdf_page.media_box(Rect::new(0.0, 0.0, width, height));
+        pdf_page.contents(content_id);
+        pdf_page.resources().x_objects().pair(image_name, image_id);
+        pdf_page.finish();
+
+        let image = read_image(page.src, (page.read)()?)?;
+        let mut xobject = pdf.image_xobject(image_id, &image.samples);
+        xobject.filter(image.filter);
+        xobject.width(image.width as i32);
+        xobject.height(image.height as i32);
+        if image.gray {
+            xobject.color_space().device_gray();
+        } else {
+            xobject.color_space().device_rgb();
+        }
+        xobject.bits_per_component(8);
+        if image.mask.is_some() {
+            xobject.s_mask(mask_id);
+        }
+        xobject.finish();
+
+        if let Some(mask) = &image.mask {
+            let mut xobject = pdf.image_xobject(mask_id, mask);
+            xobject.filter(Filter::FlateDecode);
+            xobject.width(image.width as i32);
+            xobject.height(image.height as i32);
+            xobject.color_space().device_gray();
+            xobject.bits_per_component(8);
+        }
+
+        let mut content = Content::new();
+        content.save_state();
+        content.transform([width, 0.0, 0.0, height, 0.0, 0.0]);
+        content.x_object(image_name);
+        content.restore_state();
+        pdf.stream(content_id, &content.finish());
+    }
+
+    write_outline(&mut pdf, outline_id, &bookmark_ids, &page_ids, bookmarks);
+
+    w.write_all(&pdf.finish())?;
+    Ok(())
+}
+
+/// Writes the outline `outline_id` of `bookmarks`, which is a tree of linked lists of the entries
+/// nested at each level.
+fn write_outline(
+    pdf: &mut Pdf,
+    outline_id: Ref,
+    ids: &[Ref],
+    page_ids: &[Ref],
+    bookmarks: &[Bookmark],
+) {
+    if bookmarks.is_empty() {
+        return;
+    }
+
+    let mut parents = Vec::with_capacity(bookmarks.len());
+    let mut ancestors = Vec::new();
+    for (i, bookmark) in bookmarks.iter().enumerate() {
+        ancestors.truncate(bookmark.level);
+        parents.push(ancestors.last().copied());
+        ancestors.push(i);
+    }
+    let children = |parent: Option<usize>| {
+        (0..bookmarks.len())
+            .filter(|&i| parents[i] == parent)
+            .collect::<Vec<_>>()
+    };
+    // every entry is open, so the descendants of an entry are all visible
+    let descendants = |i: usize| {
+        bookmarks[i + 1..]
+            .iter()
+            .take_while(|bookmark| bookmarks[i].level < bookmark.level)
+            .count()
+    };
+
+    let roots = children(None);
+    pdf.outline(outline_id)
+        .first(ids[roots[0]])
+        .last(ids[roots[roots.len() - 1]])
+        .count(bookmarks.len() as i32);
+
+    for (i, bookmark) in bookmarks.iter().enumerate() {
+        let siblings = children(parents[i]);
+        let position = siblings.iter().position(|&j| j == i).unwrap();
+
+        let mut item = pdf.outline_item(ids[i]);
+        item.title(TextStr(bookmark.title));
+        item.parent(parents[i].map_or(outline_id, |parent| ids[parent]));
+        if let Some(&prev) = position.checked_sub(1).and_then(|p| siblings.get(p)) {
+            item.prev(ids[prev]);
+        }
+        if let Some(&next) = siblings.get(position + 1) {
+            item.next(ids[next]);
+        }
+        let kids = children(Some(i));
+        if let (Some(&first), Some(&last)) = (kids.first(), kids.last()) {
+            item.first(ids[first]);
+            item.last(ids[last]);
+            item.count(descendants(i) as i32);
+        }
+        item.dest().page(page_ids[bookmark.page]).fit();
+    }
+}
+
+/// Image encoded as a PDF image stream.
+struct Image {
+    width: u32,
+    height: u32,
+    gray: bool,
+    filter: Filter,
+    samples: Vec<u8>,
+    /// Compressed alpha channel, if the image has one.
+    mask: Option<Vec<u8>>,
+}
+
+/// Reads the image `data` at `path`, taking a JPEG image as it is if a PDF reader can show it so.
+fn read_image(path: &Path, data: Vec<u8>) -> Result<Image> {
+    let jpeg = {
+        let reader = open_image(path, &data)?;
+        match reader.format() {
+            Some(ImageFormat::Jpeg) => {
+                let decoder = reader.into_decoder()?;
+                let (width, height) = decoder.dimensions();
+                match decoder.color_type() {
+                    ColorType::L8 => Some((width, height, true)),
+                    ColorType::Rgb8 => Some((width, height, false)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    };
+    if let Some((width, height, gray)) = jpeg {
+        return Ok(Image {
+            width,
+            height,
+            gray,
+            filter: Filter::DctDecode,
+            samples: data,
+            mask: None,
+        });
+    }
+
+    let image = open_image(path, &data)?
+        .decode()
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    let color = image.color();
+    let gray = matches!(color.channel_count(), 1 | 2);
+    let samples = if gray {
+        deflate(image.to_luma8().as_raw())?
+    } else {
+        deflate(image.to_rgb8().as_raw())?
+    };
+    let mask = if color.has_alpha() {
+        let alpha = image.to_luma_alpha8();
+        let alpha = alpha.pixels().map(|p| p.0[1]).collect::<Vec<_>>();
+        Some(deflate(&alpha)?)
+    } else {
+        None
+    };
+
+    Ok(Image {
+        width: image.width(),
+        height: image.height(),
+        gray,
+        filter: Filter::FlateDecode,
+        samples,
+        mask,
+    })
+}
+
+/// Opens the image `data` at `path` in the format of its content, or else of its name.
+fn open_image<'a>(path: &Path, data: &'a [u8]) -> Result<ImageReader<Cursor<&'a [u8]>>> {
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    if reader.format().is_none() {
+        if let Ok(format) = ImageFormat::from_path(path) {
+            reader.set_format(format);
+        }
+    }
+    Ok(reader)
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_pdf() {
+        let dir = tempfile::tempdir().unwrap();
+        let jpeg = dir.path().join("a.jpg");
+        let png = dir.path().join("b.png");
+        image::RgbImage::new(40, 60).save(&jpeg).unwrap();
+        image::RgbaImage::new(80, 60).save(&png).unwrap();
+
+        let info = Info {
+            title: "Title",
+            author: "Author",
+            language: "ja",
+            direction: Direction::RightToLeft,
+            spread: true,
+        };
+        fn page(src: &Path, width: u32, height: u32) -> Page<'_> {
+            let read = Box::new(move || Ok(std::fs::read(src)?));
+            Page {
+                src,
+                read,
+                width,
+                height,
+            }
+        }
+        let bookmark = |page, title, level| Bookmark { page, title, level };
+        let mut buf = Vec::new();
+        write_pdf(
+            &mut buf,
+            &info,
+            &[page(&jpeg, 40, 60), page(&png, 80, 60)],
+            &[
+                bookmark(0, "Part", 0),
+                bookmark(0, "One", 1),
+                bookmark(1, "Two", 1),
+            ],
+        )
+        .unwrap();
+
+        let pdf = String::from_utf8_lossy(&buf);
+        assert!(pdf.starts_with("%PDF-"));
+        assert!(pdf.contains("/Direction /R2L"));
+        assert!(pdf.contains("/PageLayout /TwoPageRight"));
+        assert!(pdf.contains("/MediaBox [0 0 30 45]"));
+        assert!(pdf.contains("/MediaBox [0 0 60 45]"));
+        assert!(pdf.contains("/Filter /DCTDecode"));
+        assert!(pdf.contains("/SMask"));
+        assert!(pdf.contains("/Title (Part)"));
+        // the outline of three entries, and the two pages and the two entries in the first
+        assert!(pdf.contains("/Count 3"));
+        assert_eq!(pdf.matches("/Count 2").count(), 2);
+    }
+}
@@ -0,0 +1,268 @@
+use clap::ValueEnum;
+use std::sync::OnceLock;
+
+/// Target store or reading system of a build.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Profile {
+    #[default]
+    Generic,
+    Kindle,
+    Kobo,
+    Apple,
+}
+
+/// Limits a store imposes on the books it accepts.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Maximum size of the EPub file in bytes.
+    pub file_size: Option<u64>,
+
+    /// Maximum number of pixels of a single image.
+    pub image_pixels: Option<u64>,
+
+    /// Length in pixels the long edge of the cover should reach.
+    pub cover_edge: Option<u32>,
+
+    /// Range of the aspect ratio, height over width, the cover should be in.
+    pub cover_ratio: Option<(f64, f64)>,
+}
+
+/// Known quirks of the reading system of a store.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// Whether the store rejects books without a cover image.
+    pub cover_required: bool,
+
+    /// Minimum width and height of the cover image.
+    pub cover_size: Option<(u32, u32)>,
+
+    /// Whether the links placed on the image of a page in inline SVG can be followed.
+    pub svg_links: bool,
+
+    /// CSS declarations the reading system ignores.
+    pub unsupported_css: &'static [&'static str],
+
+    /// Whether the reading system magnifies panels of comics one by one.
+    pub panel_view: bool,
+}
+
+const MIB: u64 = 1024 * 1024;
+
+impl Profile {
+    /// Returns the ingestion limits of the store, as published in its guidelines.
+    pub fn limits(self) -> Limits {
+        match self {
+            Self::Generic => Limits::default(),
+            Self::Kindle => Limits {
+                file_size: Some(650 * MIB),
+                image_pixels: Some(5_000_000),
+                cover_edge: Some(2560),
+                cover_ratio: Some((1.5, 1.7)),
+            },
+            Self::Kobo => Limits {
+                file_size: Some(500 * MIB),
+                image_pixels: Some(3200 * 3200),
+                cover_edge: Some(1600),
+                cover_ratio: Some((1.25, 1.8)),
+            },
+            Self::Apple => Limits {
+                file_size: Some(2048 * MIB),
+                image_pixels: Some(5_600_000),
+                cover_edge: Some(1600),
+                cover_ratio: Some((1.25, 1.8)),
+            },
+        }
+    }
+
+    /// Returns the quirks of the reading system of the store.
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Self::Generic => Quirks {
+                svg_links: true,
+                ..Default::default()
+            },
+            Self::Kindle => Quirks {
+                cover_required: true,
+                cover_size: Some((625, 1000)),
+                svg_links: false,
+                unsupported_css: &["position: fixed", "column-count"],
+                panel_view: true,
+            },
+            Self::Kobo => Quirks {
+                cover_required: true,
+                cover_size: None,
+                svg_links: true,
+                unsupported_css: &["position: fixed"],
+                panel_view: false,
+            },
+            Self::Apple => Quirks {
+                cover_required: true,
+                cover_size: Some((1400, 1400)),
+                svg_links: true,
+                unsupported_css: &[],
+                panel_view: false,
+            },
+        }
+    }
+
+    /// Returns the name of the profile as given on the command line.
+    pub fn as_str(self) -> &'static str {
+        // the names are those clap derives, so that they cannot drift apart
+        static NAMES: OnceLock<Vec<String>> = OnceLock::new();
+        let names = NAMES.get_or_init(|| {
+            Self::value_variants()
+                .iter()
+                .filter_map(ValueEnum::to_possible_value)
+                .map(|value| value.get_name().to_string())
+                .collect()
+        });
+        &names[self as usize]
+    }
+
+    /// Human readable name of the store.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Generic => "any store",
+            Self::Kindle => "Kindle",
+            Self::Kobo => "Kobo",
+            Self::Apple => "Apple Books",
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Limits {
+    /// Describes how a book of `file_size` bytes with images of `dimensions` exceeds the limits.
+    pub fn check(&self, file_size: u64, dimensions: &[(String, u32, u32)]) -> Vec<String> {
+        let mut findings = Vec::new();
+
+        if let Some(limit) = self.file_size {
+            if limit < file_size {
+                findings.push(format!(
+                    "the book is {} but the limit is {}",
+                    crate::project::format_size(file_size),
+                    crate::project::format_size(limit)
+                ));
+            }
+        }
+
+        if let Some(limit) = self.image_pixels {
+            for (name, width, height) in dimensions {
+                let pixels = *width as u64 * *height as u64;
+                if limit < pixels {
+                    findings.push(format!(
+                        "`{name}` has {pixels} pixels ({width}x{height}) but the limit is {limit}"
+                    ));
+                }
+            }
+        }
+
+        findings
+    }
+
+    /// Describes how a cover of `width` by `height` falls short of what the store expects.
+    pub fn check_cover(&self, width: u32, height: u32) -> Vec<String> {
+        let mut findings = Vec::new();
+
+        if let Some(limit) = self.cover_edge {
+            if width.max(height) < limit {
+                findings.push(format!(
+                    "the cover is {width}x{height} but its long edge should be at least {limit} pixels"
+                ));
+            }
+        }
+
+        if let Some((min, max)) = self.cover_ratio {
+            let ratio = height as f64 / width.max(1) as f64;
+            if !(min..=max).contains(&ratio) {
+                findings.push(format!(
+                    "the cover is {width}x{height} but its height should be {min} to {max} times its width"
+                ));
+            }
+        }
+
+        findings
+    }
+}
+
+impl Quirks {
+    /// Returns the unsupported declarations that `css` contains.
+    pub fn find_unsupported_css(&self, css: &str) -> Vec<&'static str> {
+        let css = normalize_css(css);
+        self.unsupported_css
+            .iter()
+            .copied()
+            .filter(|decl| css.contains(&normalize_css(decl)))
+            .collect()
+    }
+}
+
+fn normalize_css(css: &str) -> String {
+    css.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_str() {
+        assert_eq!(Profile::Generic.as_str(), "generic");
+        assert_eq!(Profile::Apple.as_str(), "apple");
+        for &profile in Profile::value_variants() {
+            assert_eq!(Profile::from_str(profile.as_str(), false), Ok(profile));
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let limits = Limits {
+            file_size: Some(100),
+            image_pixels: Some(100),
+            ..Limits::default()
+        };
+        let images = [("a".to_string(), 10, 10), ("b".to_string(), 10, 11)];
+
+        assert!(limits.check(100, &images[..1]).is_empty());
+        assert_eq!(
+            limits.check(101, &images),
+            [
+                "the book is 101 B but the limit is 100 B",
+                "`b` has 110 pixels (10x11) but the limit is 100",
+            ]
+        );
+        assert!(Profile::Generic
+            .limits()
+            .check(u64::MAX, &images)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_check_cover() {
+        let limits = Profile::Kindle.limits();
+        assert!(limits.check_cover(1600, 2560).is_empty());
+        assert_eq!(
+            limits.check_cover(1000, 1000),
+            [
+                "the cover is 1000x1000 but its long edge should be at least 2560 pixels",
+                "the cover is 1000x1000 but its height should be 1.5 to 1.7 times its width",
+            ]
+        );
+        assert!(Profile::Generic.limits().check_cover(1, 1).is_empty());
+    }
+
+    #[test]
+    fn test_find_unsupported_css() {
+        let quirks = Profile::Kindle.quirks();
+        assert!(quirks
+            .find_unsupported_css("p { position: relative; }")
+            .is_empty());
+        assert_eq!(
+            quirks.find_unsupported_css("div {\n  POSITION:fixed;\n  column-count: 2;\n}"),
+            ["position: fixed", "column-count"]
+        );
+    }
+}
@@ -0,0 +1,665 @@
+use crate::diagnostic;
+use crate::edit::Document;
+use crate::i18n::tr;
+use crate::model::{Book, Page, VERSION};
+use crate::status::{Failure, Status};
+use anyhow::{Context as _, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// File name of the project file.
+pub const FILE_NAME: &str = "tsugumi.yaml";
+
+/// File name of the lockfile.
+pub const LOCK_FILE_NAME: &str = "tsugumi.lock";
+
+/// Name of the directory that holds the data generated for a project.
+const DIR_NAME: &str = ".tsugumi";
+
+/// File name of the list of files, in the syntax of `.gitignore`, that directories and glob
+/// patterns of page sources leave out.
+pub const IGNORE_FILE_NAME: &str = ".tsugumiignore";
+
+/// Location of a book project on the file system.
+pub struct Project {
+    path: PathBuf,
+    root: PathBuf,
+}
+
+impl Project {
+    /// Finds the project file in the current directory or any parent directory.
+    pub fn find() -> Result<Self> {
+        let start = std::env::current_dir().context("failed to get current directory")?;
+
+        let mut current = start.as_path();
+        loop {
+            let path = current.join(FILE_NAME);
+            if path.exists() {
+                break Ok(Self::new(path));
+            }
+
+            if let Some(parent) = current.parent() {
+                current = parent;
+            } else {
+                break Err(Failure::new(
+                    Status::Manifest,
+                    tr!(
+                        "could not find `{FILE_NAME}` in `{}` or any parent directory",
+                        "`{}` とその親ディレクトリに `{FILE_NAME}` が見つかりません",
+                        start.display()
+                    ),
+                )
+                .into());
+            }
+        }
+    }
+
+    /// Opens the project at `path`, which is either the project file or the directory containing it.
+    pub fn open(path: &Path) -> Result<Self> {
+        let path = if path.is_dir() {
+            path.join(FILE_NAME)
+        } else {
+            path.to_path_buf()
+        };
+
+        if !path.is_file() {
+            return Err(Failure::new(
+                Status::Manifest,
+                tr!(
+                    "could not find `{}`",
+                    "`{}` が見つかりません",
+                    path.display()
+                ),
+            )
+            .into());
+        }
+
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("failed to open `{}`", path.display()))?;
+        Ok(Self::new(path))
+    }
+
+    /// Opens the project at `path` if given, or finds it from the current directory otherwise.
+    pub fn open_or_find(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::open(path),
+            None => Self::find(),
+        }
+    }
+
+    fn new(path: PathBuf) -> Self {
+        let root = path.parent().unwrap().to_path_buf();
+        Self { path, root }
+    }
+
+    /// Path to the project file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path to the lockfile that records the sources of the last locked build.
+    ///
+    /// It is kept next to the project file to be committed with it, out of the directory that
+    /// `clean` removes.
+    pub fn lock_path(&self) -> PathBuf {
+        self.root.join(LOCK_FILE_NAME)
+    }
+
+    /// Directory that page sources are relative to.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Reads the book from the project file.
+    pub fn load(&self) -> Result<Book> {
+        let path = &self.path;
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to open `{}`", path.display()))?;
+
+        // check the version first, as keys of a newer version would be reported as unknown
+        let version = serde_yaml::from_str::<serde_yaml::Value>(&source)
+            .ok()
+            .and_then(|value| value.get("version")?.as_u64());
+        if let Some(version) = version.filter(|v| u64::from(VERSION) < *v) {
+            return Err(Failure::new(
+                Status::Manifest,
+                tr!(
+                    "`{}` requires a newer tsugumi, as it is of version {version} and this one reads up to version {VERSION}",
+                    "`{}` はバージョン {version} で、バージョン {VERSION} までしか読めないため、新しい tsugumi が必要です",
+                    path.display()
+                ),
+            )
+            .into());
+        }
+
+        serde_yaml::from_str(&source).map_err(|e| {
+            let context = match diagnostic::render(&source, path, &e) {
+                Some(snippet) => format!("failed to read `{}`\n{snippet}", path.display()),
+                None => format!("failed to read `{}`", path.display()),
+            };
+            anyhow::Error::new(e).context(context)
+        })
+    }
+
+    /// Writes `book` back to the project file.
+    pub fn save(&self, book: &Book) -> Result<()> {
+        let path = &self.path;
+        let file =
+            File::create(path).with_context(|| format!("failed to create `{}`", path.display()))?;
+        serde_yaml::to_writer(file, book)
+            .with_context(|| format!("failed to write `{}`", path.display()))
+    }
+
+    /// Writes `book` back to the project file by applying `edit` to its text, which keeps the
+    /// comments and the formatting of the rest, or as a whole if the edit cannot be made in place.
+    pub fn edit(&self, book: &Book, edit: impl FnOnce(&mut Document) -> Result<()>) -> Result<()> {
+        let path = &self.path;
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to open `{}`", path.display()))?;
+
+        let mut document = Document::new(&source);
+        let edited = edit(&mut document).and_then(|()| {
+            // the edit must describe the same book, or the file would say something else
+            let text = document.to_string();
+            match serde_yaml::from_str::<Book>(&text) {
+                Ok(edited) if edited == *book => Ok(text),
+                Ok(_) => anyhow::bail!("the edited file describes another book"),
+                Err(e) => Err(e.into()),
+            }
+        });
+
+        match edited {
+            Ok(text) => std::fs::write(path, text)
+                .with_context(|| format!("failed to write `{}`", path.display())),
+            Err(e) => {
+                debug!("cannot edit `{}` in place: {e:#}", path.display());
+                warn!(
+                    "{}",
+                    tr!(
+                        "rewriting `{}` as a whole, which drops its comments",
+                        "`{}` を全体で書き直します。コメントは失われます",
+                        path.display()
+                    )
+                );
+                self.save(book)
+            }
+        }
+    }
+
+    /// Directory that holds the data generated for the project.
+    pub fn dir(&self) -> PathBuf {
+        self.root.join(DIR_NAME)
+    }
+
+    /// Directory that holds the build cache.
+    pub fn cache_dir(&self) -> PathBuf {
+        self.dir().join("cache")
+    }
+
+    /// Creates the project data directory if it does not exist yet.
+    pub fn create_dir(&self) -> Result<()> {
+        let dir = self.dir();
+        if dir.is_dir() {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create `{}`", dir.display()))?;
+
+        // the directory only contains generated data
+        std::fs::write(dir.join(".gitignore"), "*\n")
+            .with_context(|| format!("failed to initialize `{}`", dir.display()))?;
+
+        Ok(())
+    }
+}
+
+/// Flattens the sub-chapters of `book` into its list of chapters, and replaces each page whose
+/// source is a directory or a glob pattern such as `scans/*.jpg` rather than a file with a page for
+/// each of the images it stands for, in natural order of their paths.
+///
+/// The pages share the classes and the switches of the page they replace, while its caption, links
+/// and the like go to the first one. A pattern that matches nothing is left as is to be reported
+/// missing.
+pub fn expand_book(root: &Path, book: &mut Book) {
+    book.flatten_chapters();
+
+    let ignore = Ignore::load(root);
+    let base = root.join(book.source_path(Path::new("")));
+    for chapter in &mut book.chapter {
+        let pages = std::mem::take(&mut chapter.page);
+        for page in pages {
+            let sources = match page.join.is_empty() {
+                true => expand_source(&base, &page.src, &ignore),
+                false => Vec::new(),
+            };
+            if sources.is_empty() {
+                chapter.page.push(page);
+                continue;
+            }
+
+            let shared = Page {
+                reuse: page.reuse,
+                class: page.class.clone(),
+                hidden: page.hidden,
+                split: page.split,
+                ..Page::default()
+            };
+            let mut sources = sources.into_iter();
+            chapter.page.push(Page {
+                src: sources.next().unwrap_or_default(),
+                ..page
+            });
+            chapter.page.extend(sources.map(|src| Page {
+                src,
+                ..shared.clone()
+            }));
+        }
+    }
+}
+
+/// Returns the images that `src` relative to `base` stands for, or none if it is a file.
+fn expand_source(base: &Path, src: &Path, ignore: &Ignore) -> Vec<PathBuf> {
+    let path = base.join(src);
+    if path.is_file() {
+        return Vec::new();
+    }
+
+    let mut sources = if path.is_dir() {
+        let Ok(entries) = std::fs::read_dir(&path) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && is_image(path) && !ignore.is_ignored(path))
+            .collect::<Vec<_>>()
+    } else {
+        let text = src.to_string_lossy();
+        if !text.contains(['*', '?', '[']) {
+            return Vec::new();
+        }
+        // the directory of the project may contain the special characters itself
+        let pattern = format!("{}/{text}", glob::Pattern::escape(&base.to_string_lossy()));
+        let Ok(paths) = glob::glob(&pattern) else {
+            return Vec::new();
+        };
+        paths
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file() && is_image(path) && !ignore.is_ignored(path))
+            .collect()
+    };
+
+    sources.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    sources
+        .into_iter()
+        .filter_map(|path| Some(path.strip_prefix(base).ok()?.to_path_buf()))
+        .collect()
+}
+
+/// Files of a project that directories and glob patterns of page sources leave out, as listed in
+/// its ignore file.
+pub struct Ignore {
+    root: PathBuf,
+    canonical_root: Option<PathBuf>,
+    matcher: Gitignore,
+}
+
+impl Ignore {
+    /// Reads the ignore file of the project in `root`, which ignores nothing if it does not exist.
+    pub fn load(root: &Path) -> Self {
+        let path = root.join(IGNORE_FILE_NAME);
+        let mut builder = GitignoreBuilder::new(root);
+        if path.is_file() {
+            // the valid lines are used even if the others are not
+            if let Some(e) = builder.add(&path) {
+                warn!("failed to read `{}`: {e}", path.display());
+            }
+        }
+        let matcher = builder.build().unwrap_or_else(|e| {
+            warn!("failed to read `{}`: {e}", path.display());
+            Gitignore::empty()
+        });
+
+        Self {
+            root: root.to_path_buf(),
+            canonical_root: root.canonicalize().ok(),
+            matcher,
+        }
+    }
+
+    /// Whether the file at `path` is ignored, by itself or by one of its directories. A file out
+    /// of the project is never ignored.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let relative = path
+            .strip_prefix(&self.root)
+            .ok()
+            .or_else(|| path.strip_prefix(self.canonical_root.as_ref()?).ok());
+        relative.is_some_and(|relative| {
+            self.matcher
+                .matched_path_or_any_parents(relative, false)
+                .is_ignore()
+        })
+    }
+}
+
+/// Whether `path` is named as an image, which leaves out files such as `.DS_Store` and `Thumbs.db`.
+pub(crate) fn is_image(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    !hidden
+        && mime_guess::from_path(path).first_or_octet_stream().type_() == mime_guess::mime::IMAGE
+}
+
+/// Compares `a` and `b` with the runs of digits in them as numbers, so that `2.png` comes before
+/// `10.png`.
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let i = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let j = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let (m, n) = (
+                a[..i].trim_start_matches('0'),
+                b[..j].trim_start_matches('0'),
+            );
+            let ordering = m.len().cmp(&n.len()).then_with(|| m.cmp(n));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            (a, b) = (&a[i..], &b[j..]);
+        } else if x != y {
+            return x.cmp(&y);
+        } else {
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+/// Returns the total size of the files under `path`, or zero if it does not exist.
+pub fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Formats `size` in bytes with a binary unit prefix.
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = size as f64;
+    let mut unit = 0;
+    while 1024.0 <= value && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{size} {}", UNITS[unit])
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Parses a size such as `300MB`, `1.5GiB` or `4096`, with a decimal or binary unit prefix.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| format!("`{s}` is not a size"))?;
+    let unit = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000 * 1000,
+        "gb" => 1000 * 1000 * 1000,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        unit => return Err(format!("`{unit}` is not a unit of size")),
+    };
+
+    Ok((value * unit as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edit::Segment;
+
+    #[test]
+    fn test_create_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let project = Project::new(root.path().join(FILE_NAME));
+
+        project.create_dir().unwrap();
+        assert!(project.dir().join(".gitignore").is_file());
+
+        std::fs::create_dir_all(project.cache_dir()).unwrap();
+        std::fs::write(project.cache_dir().join("a"), [0; 10]).unwrap();
+        assert_eq!(dir_size(&project.dir()).unwrap(), 12);
+        assert_eq!(dir_size(&root.path().join("missing")).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_open() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(Project::open(root.path()).is_err());
+
+        std::fs::write(root.path().join(FILE_NAME), "").unwrap();
+        let root = root.path().canonicalize().unwrap();
+        assert_eq!(Project::open(&root).unwrap().root(), root);
+        assert_eq!(
+            Project::open(&root.join(FILE_NAME)).unwrap().path(),
+            root.join(FILE_NAME)
+        );
+    }
+
+    #[test]
+    fn test_load_version() {
+        let root = tempfile::tempdir().unwrap();
+        let project = Project::new(root.path().join(FILE_NAME));
+        let metadata =
+            "metadata: {title: A, language: ja, identifier: id}\nchapter: [{page: [a.png]}]\n";
+
+        std::fs::write(project.path(), format!("version: 1\n{metadata}")).unwrap();
+        assert_eq!(project.load().unwrap().version, Some(1));
+
+        std::fs::write(project.path(), format!("version: 2\n{metadata}newKey: 1\n")).unwrap();
+        let e = project.load().unwrap_err();
+        assert_eq!(Status::of(&e), Status::Manifest);
+        assert!(e.to_string().contains("newer tsugumi"));
+    }
+
+    #[test]
+    fn test_edit() {
+        let root = tempfile::tempdir().unwrap();
+        let project = Project::new(root.path().join(FILE_NAME));
+        let source = "# comment\nmetadata: {title: A, language: ja, identifier: id}\nchapter:\n- page: a.png\n";
+        std::fs::write(project.path(), source).unwrap();
+
+        let mut book = project.load().unwrap();
+        book.chapter[0].toc = false;
+        project
+            .edit(&book, |document| {
+                document.set(
+                    &[Segment::Key("chapter"), Segment::Index(0)],
+                    "toc",
+                    Some("false"),
+                )
+            })
+            .unwrap();
+        let text = std::fs::read_to_string(project.path()).unwrap();
+        assert_eq!(text, format!("{source}  toc: false\n"));
+
+        // an edit that does not match the book is not applied
+        book.metadata.title[0].name = "B".to_string();
+        project.edit(&book, |_| Ok(())).unwrap();
+        assert_eq!(project.load().unwrap(), book);
+        assert!(!std::fs::read_to_string(project.path())
+            .unwrap()
+            .contains("# comment"));
+    }
+
+    #[test]
+    fn test_expand_book() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("scans");
+        std::fs::create_dir(&dir).unwrap();
+        for name in ["10.png", "2.png", "1.jpg", ".DS_Store", "notes.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let page = |src: &str| Page {
+            src: src.into(),
+            ..Page::default()
+        };
+        let mut book = Book {
+            chapter: vec![crate::model::Chapter {
+                page: vec![
+                    Page {
+                        toc: Some("Scans".to_string()),
+                        hidden: true,
+                        ..page("scans")
+                    },
+                    page("scans/*.png"),
+                    page("scans/*.gif"),
+                    page("scans/1.jpg"),
+                    page("scans/*"),
+                ],
+                ..Default::default()
+            }],
+            ..Book::default()
+        };
+        expand_book(root.path(), &mut book);
+
+        let pages = &book.chapter[0].page;
+        let sources = pages
+            .iter()
+            .map(|page| page.src.clone())
+            .collect::<Vec<_>>();
+        let scan = |name: &str| Path::new("scans").join(name);
+        assert_eq!(
+            sources,
+            [
+                scan("1.jpg"),
+                scan("2.png"),
+                scan("10.png"),
+                scan("2.png"),
+                scan("10.png"),
+                "scans/*.gif".into(),
+                "scans/1.jpg".into(),
+                scan("1.jpg"),
+                scan("2.png"),
+                scan("10.png"),
+            ]
+        );
+        assert_eq!(pages[0].toc.as_deref(), Some("Scans"));
+        assert_eq!(pages[1].toc, None);
+        assert!(pages[..3].iter().all(|page| page.hidden));
+    }
+
+    #[test]
+    fn test_expand_book_ignore() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("scans");
+        std::fs::create_dir_all(dir.join("thumbs")).unwrap();
+        for name in ["1.png", "1-work.png", "2.png", "thumbs/1.png"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        std::fs::write(root.path().join(IGNORE_FILE_NAME), "*-work.png\nthumbs/\n").unwrap();
+
+        let page = |src: &str| Page {
+            src: src.into(),
+            ..Page::default()
+        };
+        let mut book = Book {
+            chapter: vec![crate::model::Chapter {
+                page: vec![
+                    page("scans"),
+                    page("scans/**/*.png"),
+                    page("scans/1-work.png"),
+                ],
+                ..Default::default()
+            }],
+            ..Book::default()
+        };
+        expand_book(root.path(), &mut book);
+
+        let sources = book.chapter[0]
+            .page
+            .iter()
+            .map(|page| page.src.clone())
+            .collect::<Vec<_>>();
+        let scan = |name: &str| Path::new("scans").join(name);
+        assert_eq!(
+            sources,
+            [
+                scan("1.png"),
+                scan("2.png"),
+                scan("1.png"),
+                scan("2.png"),
+                scan("1-work.png"),
+            ]
+        );
+
+        let ignore = Ignore::load(root.path());
+        assert!(ignore.is_ignored(&dir.join("thumbs/1.png")));
+        assert!(!ignore.is_ignored(&dir.join("1.png")));
+        assert!(!ignore.is_ignored(Path::new("/elsewhere/1-work.png")));
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        assert_eq!(natural_cmp("p2.png", "p10.png"), Ordering::Less);
+        assert_eq!(natural_cmp("p010.png", "p9.png"), Ordering::Greater);
+        assert_eq!(natural_cmp("a1b2", "a1b2"), Ordering::Equal);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+        assert_eq!(natural_cmp("b", "a10"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(300 * 1024 * 1024), "300.0 MiB");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("300MB"), Ok(300_000_000));
+        assert_eq!(parse_size("1.5 GiB"), Ok(3 << 29));
+        assert_eq!(parse_size("2k"), Ok(2048));
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("3 pages").is_err());
+    }
+}
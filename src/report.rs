@@ -0,0 +1,257 @@
+use crate::project::format_size;
+use anyhow::{Context as _, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Number of pages listed in the text report.
+const TOP_PAGES: usize = 10;
+
+/// Kind of an entry in the EPub file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Image,
+    Xhtml,
+    Style,
+    Other,
+}
+
+impl Category {
+    fn of(name: &str) -> Self {
+        let dir = name
+            .strip_prefix("item/")
+            .and_then(|name| name.split_once('/'))
+            .map(|(dir, _)| dir);
+
+        match dir {
+            Some("image") => Self::Image,
+            Some("xhtml") => Self::Xhtml,
+            Some("style") => Self::Style,
+            _ => Self::Other,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Image => "images",
+            Self::Xhtml => "XHTML",
+            Self::Style => "styles",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Uncompressed and compressed size of one or more entries.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Size {
+    pub size: u64,
+    pub compressed: u64,
+}
+
+impl Size {
+    /// Returns the compressed size relative to the uncompressed one.
+    pub fn ratio(&self) -> f64 {
+        if self.size == 0 {
+            1.0
+        } else {
+            self.compressed as f64 / self.size as f64
+        }
+    }
+}
+
+impl std::ops::AddAssign for Size {
+    fn add_assign(&mut self, other: Self) {
+        self.size += other.size;
+        self.compressed += other.compressed;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub category: Category,
+    #[serde(flatten)]
+    pub size: Size,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageSize {
+    pub page: String,
+    #[serde(flatten)]
+    pub size: Size,
+}
+
+/// Breakdown of the size of an EPub file.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub total: Size,
+    pub categories: BTreeMap<Category, Size>,
+    /// Pages from the largest to the smallest.
+    pub pages: Vec<PageSize>,
+    pub entries: Vec<Entry>,
+}
+
+impl Report {
+    /// Reads the sizes of the entries of the EPub file at `path`.
+    ///
+    /// `pages` lists the names of the entries that make up each page.
+    pub fn read(path: &Path, pages: &[(String, Vec<String>)]) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("failed to open `{}`", path.display()))?;
+        let mut zip = ZipArchive::new(file)?;
+
+        let mut entries = Vec::with_capacity(zip.len());
+        for i in 0..zip.len() {
+            let file = zip.by_index_raw(i)?;
+            entries.push(Entry {
+                name: file.name().to_string(),
+                category: Category::of(file.name()),
+                size: Size {
+                    size: file.size(),
+                    compressed: file.compressed_size(),
+                },
+            });
+        }
+
+        Ok(Self::new(entries, pages))
+    }
+
+    fn new(entries: Vec<Entry>, pages: &[(String, Vec<String>)]) -> Self {
+        let mut total = Size::default();
+        let mut categories = BTreeMap::new();
+        for entry in &entries {
+            total += entry.size;
+            *categories.entry(entry.category).or_default() += entry.size;
+        }
+
+        let sizes = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.size))
+            .collect::<HashMap<_, _>>();
+        let mut pages = pages
+            .iter()
+            .map(|(page, names)| {
+                let mut size = Size::default();
+                for name in names {
+                    size += sizes.get(name.as_str()).copied().unwrap_or_default();
+                }
+                PageSize {
+                    page: page.clone(),
+                    size,
+                }
+            })
+            .collect::<Vec<_>>();
+        pages.sort_by_key(|page| std::cmp::Reverse(page.size.compressed));
+
+        Self {
+            total,
+            categories,
+            pages,
+            entries,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = |f: &mut fmt::Formatter<'_>, name: &str, size: &Size| {
+            writeln!(
+                f,
+                "{name:<8}  {:>10}  {:>10}  {:>5.1}%",
+                format_size(size.size),
+                format_size(size.compressed),
+                size.ratio() * 100.0
+            )
+        };
+
+        writeln!(
+            f,
+            "{:<8}  {:>10}  {:>10}  {:>6}",
+            "", "size", "compressed", "ratio"
+        )?;
+        for (category, size) in &self.categories {
+            row(f, category.name(), size)?;
+        }
+        row(f, "total", &self.total)?;
+
+        if !self.pages.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "largest pages:")?;
+            for page in self.pages.iter().take(TOP_PAGES) {
+                writeln!(
+                    f,
+                    "  {:>10}  {}",
+                    format_size(page.size.compressed),
+                    page.page
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, size: u64, compressed: u64) -> Entry {
+        Entry {
+            name: name.to_string(),
+            category: Category::of(name),
+            size: Size { size, compressed },
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        let report = Report::new(
+            vec![
+                entry("mimetype", 20, 20),
+                entry("item/image/i-0001.png", 1000, 900),
+                entry("item/image/i-0002.png", 3000, 2900),
+                entry("item/xhtml/p-0001.xhtml", 400, 100),
+                entry("item/xhtml/p-0002.xhtml", 400, 100),
+                entry("item/style/default.css", 200, 50),
+            ],
+            &[
+                (
+                    "a.png".to_string(),
+                    vec![
+                        "item/xhtml/p-0001.xhtml".to_string(),
+                        "item/image/i-0001.png".to_string(),
+                    ],
+                ),
+                (
+                    "b.png".to_string(),
+                    vec![
+                        "item/xhtml/p-0002.xhtml".to_string(),
+                        "item/image/i-0002.png".to_string(),
+                    ],
+                ),
+            ],
+        );
+
+        assert_eq!(
+            report.total,
+            Size {
+                size: 5020,
+                compressed: 4070
+            }
+        );
+        assert_eq!(
+            report.categories[&Category::Image],
+            Size {
+                size: 4000,
+                compressed: 3800
+            }
+        );
+        assert_eq!(report.categories[&Category::Other].size, 20);
+        assert_eq!(report.pages[0].page, "b.png");
+        assert_eq!(report.pages[0].size.compressed, 3000);
+    }
+}
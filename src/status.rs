@@ -0,0 +1,108 @@
+use std::fmt;
+use std::process::ExitCode;
+
+/// Exit status of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success = 0,
+    Failure = 1,
+    // 2 is used by clap for invalid command lines
+    Manifest = 3,
+    MissingSources = 4,
+    Image = 5,
+    Io = 6,
+    Locked = 7,
+    Strict = 8,
+    Invalid = 9,
+}
+
+impl Status {
+    /// Description of every exit status, shown in `--help`.
+    pub const HELP: &'static str = "\
+Exit status:
+  0  Success
+  1  Unclassified failure
+  2  Invalid command line
+  3  Invalid project file
+  4  Missing source files
+  5  Unreadable image
+  6  I/O error
+  7  Sources differ from the lockfile
+  8  Warnings in strict mode
+  9  Invalid EPub file";
+
+    /// Determines the exit status for `error` from the first recognized error in its chain.
+    pub fn of(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(|e| {
+                if let Some(e) = e.downcast_ref::<Failure>() {
+                    Some(e.status)
+                } else if e.is::<serde_yaml::Error>() {
+                    Some(Self::Manifest)
+                } else if e.is::<image::ImageError>() {
+                    Some(Self::Image)
+                } else if e.is::<std::io::Error>() || e.is::<zip::result::ZipError>() {
+                    Some(Self::Io)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(Self::Failure)
+    }
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        Self::from(status as u8)
+    }
+}
+
+/// Error that carries the exit status explicitly.
+#[derive(Debug)]
+pub struct Failure {
+    status: Status,
+    message: String,
+}
+
+impl Failure {
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context as _;
+
+    #[test]
+    fn test_status_of() {
+        let e = anyhow::anyhow!("error");
+        assert_eq!(Status::of(&e), Status::Failure);
+
+        let e = anyhow::Error::new(Failure::new(Status::MissingSources, "missing"));
+        assert_eq!(Status::of(&e), Status::MissingSources);
+
+        let e = serde_yaml::from_str::<u32>("a")
+            .context("failed to read")
+            .unwrap_err();
+        assert_eq!(Status::of(&e), Status::Manifest);
+
+        let e = std::fs::File::open("/nonexistent")
+            .context("failed to open")
+            .unwrap_err();
+        assert_eq!(Status::of(&e), Status::Io);
+    }
+}
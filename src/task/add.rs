@@ -0,0 +1,250 @@
+use crate::edit::{scalar, Segment};
+use crate::i18n::tr;
+use crate::model::{Book, Chapter, Page};
+use crate::project::{Ignore, Project};
+use anyhow::{Context as _, Result};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, warn};
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    /// Image files to add as pages, or glob patterns such as `scans/*.png`.
+    #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    files: Vec<String>,
+
+    /// Name or position, starting at 1, of the chapter to add the pages to [default: the last one]
+    #[arg(long, value_name = "CHAPTER", conflicts_with = "new_chapter")]
+    chapter: Option<String>,
+
+    /// Add the pages to a new chapter of this name at the end of the book.
+    #[arg(long, value_name = "NAME")]
+    new_chapter: Option<String>,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::find()?;
+    let mut book = project.load()?;
+
+    let base = project.root().join(book.source_path(Path::new("")));
+    let base = base
+        .canonicalize()
+        .with_context(|| format!("failed to open `{}`", base.display()))?;
+
+    // the same image may be added again with `reuse`, but not by a glob that matches it again
+    let mut used = book
+        .chapter
+        .iter()
+        .flat_map(|chapter| &chapter.page)
+        .flat_map(|page| std::iter::once(&page.src).chain(&page.join))
+        .cloned()
+        .collect::<HashSet<_>>();
+    let mut pages = Vec::new();
+    let ignore = Ignore::load(project.root());
+    for file in expand(&args.files, &ignore)? {
+        let src = source_path(&base, &file)?;
+        if used.insert(PathBuf::from(&src)) {
+            pages.push(src);
+        } else {
+            warn!(
+                "{}",
+                tr!(
+                    "skipping `{src}`, which is a page already",
+                    "`{src}` はすでにページなのでスキップします"
+                )
+            );
+        }
+    }
+    if pages.is_empty() {
+        info!("{}", tr!("no pages to add", "追加するページがありません"));
+        return Ok(());
+    }
+
+    let items = pages
+        .iter()
+        .map(|src| scalar(src))
+        .collect::<Result<Vec<_>>>()?;
+    let added = pages.iter().map(|src| Page {
+        src: src.into(),
+        ..Page::default()
+    });
+
+    let chapter = match (&args.new_chapter, &args.chapter) {
+        (Some(_), _) => None,
+        (None, Some(chapter)) => Some(find_chapter(&book, chapter)?),
+        (None, None) => book.chapter.len().checked_sub(1),
+    };
+    match chapter {
+        Some(c) => {
+            book.chapter[c].page.extend(added);
+            project.edit(&book, |document| {
+                let path = [
+                    Segment::Key("chapter"),
+                    Segment::Index(c),
+                    Segment::Key("page"),
+                ];
+                items.iter().try_for_each(|item| document.push(&path, item))
+            })?;
+            info!(
+                "{}",
+                tr!(
+                    "added {} page(s) to chapter {}",
+                    "{} ページを第 {} 章に追加しました",
+                    pages.len(),
+                    c + 1
+                )
+            );
+        }
+        None => {
+            let mut item = String::new();
+            if let Some(name) = &args.new_chapter {
+                item.push_str(&format!("name: {}\n", scalar(name)?));
+            }
+            item.push_str("page:\n");
+            for page in &items {
+                item.push_str(&format!("- {page}\n"));
+            }
+
+            book.chapter.push(Chapter {
+                name: args.new_chapter,
+                page: added.collect(),
+                ..Chapter::default()
+            });
+            project.edit(&book, |document| {
+                document.push(&[Segment::Key("chapter")], &item)
+            })?;
+            info!(
+                "{}",
+                tr!(
+                    "added {} page(s) to a new chapter",
+                    "{} ページを新しい章に追加しました",
+                    pages.len()
+                )
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the files of `patterns`, each of which is a path or a glob pattern whose matches are
+/// taken in the order of their names, except for those `ignore` leaves out.
+fn expand(patterns: &[String], ignore: &Ignore) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            let file = PathBuf::from(pattern);
+            if !file.is_file() {
+                anyhow::bail!(tr!(
+                    "`{pattern}` does not exist",
+                    "`{pattern}` が存在しません"
+                ));
+            }
+            files.push(file);
+            continue;
+        }
+
+        let matches = glob::glob(pattern)
+            .with_context(|| format!("`{pattern}` is not a valid pattern"))?
+            .filter_map(|entry| entry.ok())
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                !path
+                    .canonicalize()
+                    .is_ok_and(|path| ignore.is_ignored(&path))
+            })
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            anyhow::bail!(tr!(
+                "`{pattern}` matches no file",
+                "`{pattern}` に一致するファイルがありません"
+            ));
+        }
+        files.extend(matches);
+    }
+
+    Ok(files)
+}
+
+/// Returns the path to `file` relative to `base`, the directory of the page sources, with `/` as
+/// the separator on any platform.
+fn source_path(base: &Path, file: &Path) -> Result<String> {
+    let path = file
+        .canonicalize()
+        .with_context(|| format!("failed to open `{}`", file.display()))?;
+    let relative = path.strip_prefix(base).map_err(|_| {
+        anyhow::anyhow!(tr!(
+            "`{}` is not in `{}`",
+            "`{}` は `{}` の中にありません",
+            file.display(),
+            base.display()
+        ))
+    })?;
+
+    let segments = relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(segment) => segment.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .with_context(|| format!("`{}` is not a valid page source", file.display()))?;
+    Ok(segments.join("/"))
+}
+
+/// Returns the position of the chapter named `chapter`, or at `chapter` starting at 1.
+fn find_chapter(book: &Book, chapter: &str) -> Result<usize> {
+    let position = chapter
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .filter(|&c| c < book.chapter.len());
+    position
+        .or_else(|| {
+            let name = Some(chapter);
+            book.chapter.iter().position(|c| c.name.as_deref() == name)
+        })
+        .with_context(|| {
+            tr!(
+                "chapter `{chapter}` does not exist",
+                "章 `{chapter}` が存在しません"
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_chapter() {
+        let chapter = |name: &str| Chapter {
+            name: Some(name.to_string()),
+            ..Chapter::default()
+        };
+        let book = Book {
+            chapter: vec![chapter("Cover"), chapter("3"), chapter("Body")],
+            ..Book::default()
+        };
+
+        assert_eq!(find_chapter(&book, "1").unwrap(), 0);
+        assert_eq!(find_chapter(&book, "3").unwrap(), 2);
+        assert_eq!(find_chapter(&book, "Body").unwrap(), 2);
+        assert!(find_chapter(&book, "0").is_err());
+        assert!(find_chapter(&book, "Back").is_err());
+    }
+
+    #[test]
+    fn test_source_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(base.join("scans")).unwrap();
+        std::fs::write(base.join("scans/a.png"), "").unwrap();
+        std::fs::write(dir.path().join("b.png"), "").unwrap();
+
+        let file = dir.path().join("scans").join("a.png");
+        assert_eq!(source_path(&base, &file).unwrap(), "scans/a.png");
+        assert!(source_path(&base.join("scans"), &dir.path().join("b.png")).is_err());
+        assert!(source_path(&base, &dir.path().join("c.png")).is_err());
+    }
+}
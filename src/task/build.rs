@@ -1,813 +1,5791 @@
-use crate::model::{Book, Chapter, Orientation, Page, TitleType};
-use anyhow::{anyhow, Context as _, Result};
+#[cfg(feature = "fs")]
+use super::validate;
+use crate::cache::{EntryCache, ImageCache, Probe, ProbeCache};
+use crate::comic::{self, COMIC_INFO};
+use crate::i18n::tr;
+#[cfg(feature = "fs")]
+use crate::lock::{hash_file, Lockfile, Source};
+#[cfg(feature = "fs")]
+use crate::message::Reporter;
+use crate::message::{MessageFormat, Rule};
+use crate::model::{
+    Audio, Book, Chapter, Collection, CollectionType, Direction, Format, Kind, NameBy, Orientation,
+    Page, PageFormat, Panel, Placement, Position, Split, Spread, Target, TitleType, Viewport,
+    MARC_RELATORS,
+};
+use crate::panel::{self, Region};
+use crate::pdf;
+use crate::profile::Profile;
+#[cfg(feature = "fs")]
+use crate::project::{expand_book, format_size, parse_size, Project};
+#[cfg(feature = "fs")]
+use crate::report::Report;
+use crate::status::{Failure, Status};
+use anyhow::{Context as _, Result};
 use indexmap::IndexMap as Map;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufRead, Cursor, Read, Seek, Write};
+#[cfg(feature = "fs")]
+use std::io::{BufReader, BufWriter};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
-use tempfile::{NamedTempFile, TempPath};
-use time::{format_description::well_known::Iso8601, OffsetDateTime};
-use tracing::{debug, info, warn};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "fs")]
+use tempfile::TempPath;
+use time::{format_description::well_known::Iso8601, OffsetDateTime, UtcOffset};
+use tracing::{debug, error, info, instrument, warn, Span};
 use xml::writer::XmlEvent;
 use xml::{EmitterConfig, EventWriter};
 use zip::write::SimpleFileOptions;
-use zip::{CompressionMethod, ZipWriter};
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
 
+/// Size of the buffers used while writing the EPub file.
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// File name of the probe cache in the cache directory.
+pub(super) const PROBE_CACHE: &str = "probe.json";
+
+/// Name of the directory of the image cache in the cache directory.
+const IMAGE_CACHE: &str = "images";
+
+/// File name of the digests of the entries of the files written, in the cache directory.
+const ENTRY_CACHE: &str = "cache.json";
+
+/// Quality of the JPEG images encoded for pages unless the project sets one.
+pub(super) const JPEG_QUALITY: u8 = 90;
+
+/// Manifest id of the navigation document.
+const NAV_ID: &str = "toc";
+
+/// Manifest id of the image of the cover.
+const COVER_ID: &str = "cover";
+
+/// Manifest id of the high-resolution image of the cover.
+const ARCHIVAL_COVER_ID: &str = "cover-archival";
+
+/// Manifest id of the NCX table of contents.
+const NCX_ID: &str = "ncx";
+
+/// Href of the NCX table of contents.
+const NCX_HREF: &str = "toc.ncx";
+
+/// Manifest ids of the items written outside the manifest map.
+const RESERVED_IDS: &[&str] = &[NAV_ID, NCX_ID];
+
+/// Hrefs of the files written outside the manifest map.
+const RESERVED_HREFS: &[&str] = &["standard.opf", "navigation-documents.xhtml", NCX_HREF];
+
+/// Media types of the items generated as XML documents.
+const GENERATED_MEDIA_TYPES: &[&str] = &["application/xhtml+xml", "application/smil+xml"];
+
+/// Media types that are stored without compression.
+const COMPRESSED_MEDIA_TYPES: &[&str] = &[
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "audio/mpeg",
+    "audio/mp4",
+];
+
+/// Class the reading system gives to the element being narrated.
+const MEDIA_ACTIVE_CLASS: &str = "-epub-media-overlay-active";
+
+/// Fragment identifier of the narrated element of a page.
+const OVERLAY_TARGET: &str = "page";
+
+/// Number of hexadecimal digits of the hash that names an image.
+const IMAGE_HASH_LEN: usize = 16;
+
+/// Image media types every reading system is required to render.
+const CORE_IMAGE_TYPES: &[&str] = &["image/gif", "image/jpeg", "image/png", "image/svg+xml"];
+
+#[cfg(feature = "fs")]
 #[derive(clap::Args)]
 pub(super) struct Args {
+    /// Project directory or project file to build instead of finding it from the current directory.
+    #[arg(value_name = "PROJECT", value_hint = clap::ValueHint::AnyPath)]
+    pub project: Option<PathBuf>,
+
     /// Output EPub file in PATH.
-    #[arg(short, long, value_name = "PATH", value_hint = clap::ValueHint::DirPath)]
+    #[arg(short, long, value_name = "PATH", value_hint = clap::ValueHint::DirPath, env = "TSUGUMI_OUTPUT")]
     output: Option<PathBuf>,
-}
 
-pub(super) fn main(args: Args) -> Result<()> {
-    let path = find_project()?;
-
-    let cx = Builder::new(&path)?.build()?;
-
-    let output = args
-        .output
-        .as_deref()
-        .or_else(|| path.parent())
-        .unwrap_or_else(|| Path::new(""));
-    cx.write_to(output)
+    /// Output EPub file in DIR as `{name}.{profile}.epub`, so that builds for several stores can
+    /// share the directory.
+    #[arg(
+        long,
+        value_name = "DIR",
+        value_hint = clap::ValueHint::DirPath,
+        env = "TSUGUMI_OUT_DIR",
+        conflicts_with = "output"
+    )]
+    out_dir: Option<PathBuf>,
+
+    /// Name of the EPub file without the extension [default: main title of the book]
+    #[arg(long, value_name = "NAME", value_hint = clap::ValueHint::Other)]
+    name: Option<String>,
+
+    /// Container to package the book in: an EPub file, a CBZ file of the page images for comic
+    /// readers, an EPub file for Kindle converted to AZW3 if Calibre or KindleGen is installed, or a
+    /// PDF file for printing and proofing.
+    #[arg(long, value_name = "FORMAT", default_value = "epub")]
+    format: Format,
+
+    /// Build for the targets named NAME in the project file, each into a file of its own, with
+    /// the format, store and image settings of the target.
+    #[arg(long, value_name = "NAME", value_delimiter = ',')]
+    target: Vec<String>,
+
+    /// Build for every target in the project file.
+    #[arg(long, conflicts_with = "target")]
+    all_targets: bool,
+
+    /// Compression method of the items in the EPub file.
+    #[arg(
+        long,
+        value_name = "METHOD",
+        default_value = "deflated",
+        env = "TSUGUMI_COMPRESSION"
+    )]
+    compression: Compression,
+
+    /// Do not use the cache of probed and converted images.
+    #[arg(long, env = "TSUGUMI_NO_CACHE")]
+    no_cache: bool,
+
+    /// Number of images to prepare at once [default: number of CPUs]
+    #[arg(short, long, value_name = "N", env = "TSUGUMI_JOBS")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Record the sources in `tsugumi.lock` next to the project file to verify the book against
+    /// later.
+    #[arg(long, env = "TSUGUMI_LOCK")]
+    lock: bool,
+
+    /// Fail if the sources differ from `tsugumi.lock` instead of updating it.
+    #[arg(long, env = "TSUGUMI_LOCKED")]
+    locked: bool,
+
+    /// Also write an EPUB 2 NCX table of contents for legacy readers.
+    #[arg(long)]
+    ncx: bool,
+
+    /// Warn about limits of the store the book is made for.
+    #[arg(
+        long,
+        value_name = "STORE",
+        default_value = "generic",
+        env = "TSUGUMI_PROFILE"
+    )]
+    profile: Profile,
+
+    /// Warn if the long edge of the cover is shorter than this many pixels [default: by the store]
+    #[arg(long, value_name = "PX", env = "TSUGUMI_MIN_COVER_EDGE")]
+    min_cover_edge: Option<u32>,
+
+    /// Report features of the book the reading system of a store is known to mishandle.
+    #[arg(long, value_name = "STORE", env = "TSUGUMI_LINT")]
+    lint: Option<Profile>,
+
+    /// Format of the findings of --lint: log messages, a SARIF log or GitHub Actions annotations,
+    /// the latter two on the standard output instead of the summary.
+    #[arg(long, value_name = "FORMAT", default_value = "text", requires = "lint")]
+    message_format: MessageFormat,
+
+    /// Fail if the build produces any warning.
+    #[arg(long, env = "TSUGUMI_STRICT")]
+    strict: bool,
+
+    /// Check the structure of the EPub file once it is written, as `validate` does.
+    #[arg(long, env = "TSUGUMI_VALIDATE")]
+    validate: bool,
+
+    /// Date the book and the entries of the EPub file by SOURCE_DATE_EPOCH, or by 1980-01-01 if it
+    /// is not set, so that builds of the same sources are identical.
+    #[arg(long, env = "TSUGUMI_REPRODUCIBLE")]
+    reproducible: bool,
+
+    /// Write XML without indentation and minify the styles.
+    #[arg(long, env = "TSUGUMI_MINIFY")]
+    minify: bool,
+
+    /// Write the styles into the head of every page instead of linking them.
+    #[arg(long, env = "TSUGUMI_INLINE_STYLE")]
+    inline_style: bool,
+
+    /// Number of spaces to indent XML elements with, or 0 not to indent them.
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    indent: usize,
+
+    /// Write XML elements without content as `<a />`, `<a/>` or `<a></a>`.
+    #[arg(long, value_name = "STYLE", default_value = "padded")]
+    empty_elements: EmptyElements,
+
+    /// Line ending of the generated XML documents and styles.
+    #[arg(long, value_name = "EOL", default_value = "lf")]
+    line_ending: LineEnding,
+
+    /// Begin the generated XML documents and styles with a byte order mark.
+    #[arg(long)]
+    bom: bool,
+
+    /// Include the scripts of the rendition and mark the pages as scripted.
+    #[arg(long, env = "TSUGUMI_SCRIPTED")]
+    scripted: bool,
+
+    /// Write the size breakdown of the EPub file in PATH as JSON instead of printing a summary.
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    report: Option<PathBuf>,
+
+    /// Split the book between chapters into EPub files of at most SIZE each, such as `300MB`,
+    /// estimated from the sizes of the page sources.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        conflicts_with_all = ["chapter", "pages", "lock", "locked"]
+    )]
+    split_size: Option<u64>,
+
+    /// Build a preview with blank images of the same dimensions instead of the pages.
+    #[arg(long, conflicts_with_all = ["lock", "locked"])]
+    skip_images: bool,
+
+    /// Build a preview of only the Nth chapters, counting from 1 after the cover chapters, and the cover.
+    #[arg(
+        long,
+        value_name = "N",
+        value_delimiter = ',',
+        conflicts_with_all = ["lock", "locked"]
+    )]
+    chapter: Vec<usize>,
+
+    /// Build a preview of only the pages in RANGE, such as `10..50`, numbered as by `list`.
+    #[arg(long, value_name = "RANGE", conflicts_with_all = ["lock", "locked"])]
+    pages: Option<PageRange>,
 }
 
-fn find_project() -> Result<PathBuf> {
-    let start = std::env::current_dir().context("failed to get current directory")?;
-
-    let mut current = start.as_path();
-    loop {
-        let path = current.join("tsugumi.yaml");
-        if path.exists() {
-            break Ok(path);
+#[cfg(feature = "fs")]
+impl Args {
+    /// Returns the names of the targets to build for, which is empty to build without one.
+    pub(super) fn targets(&self, project: &Project) -> Result<Vec<String>> {
+        if !self.all_targets {
+            return Ok(self.target.clone());
         }
 
-        if let Some(parent) = current.parent() {
-            current = parent;
-        } else {
-            break Err(anyhow!(
-                "could not find `tsugumi.yaml` in `{}` or any parent directory",
-                start.display()
+        let book = project.load()?;
+        if book.targets.is_empty() {
+            anyhow::bail!(tr!(
+                "`{}` defines no targets",
+                "`{}` にはターゲットが定義されていません",
+                project.path().display()
             ));
         }
+        Ok(book.targets.into_iter().map(|target| target.name).collect())
     }
 }
 
-struct Builder {
-    root: PathBuf,
-    book: Rc<Book>,
-}
-
-impl Builder {
-    fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref();
-        let file =
-            File::open(path).with_context(|| format!("failed to open `{}`", path.display()))?;
-        let book: Book = serde_yaml::from_reader(file)
-            .with_context(|| format!("failed to read `{}`", path.display()))?;
-
-        Ok(Self {
-            root: path.parent().unwrap().to_path_buf(),
-            book: Rc::new(book),
-        })
-    }
-
-    fn build(&self) -> Result<Context> {
-        let mut cx = Context {
-            book: Rc::clone(&self.book),
-            title: self
-                .book
-                .metadata
-                .title
-                .iter()
-                .find(|t| t.title_type == TitleType::Main)
-                .or_else(|| self.book.metadata.title.first())
-                .map(|t| t.name.as_str().to_string())
-                .unwrap_or_default(),
+#[cfg(feature = "fs")]
+impl From<Args> for BuildOptions {
+    fn from(args: Args) -> Self {
+        Self {
+            output: args.output,
+            out_dir: args.out_dir,
+            name: args.name,
+            format: args.format,
+            compression: args.compression,
+            no_cache: args.no_cache,
+            jobs: args.jobs,
+            lock: args.lock,
+            locked: args.locked,
+            ncx: args.ncx,
+            profile: args.profile,
+            min_cover_edge: args.min_cover_edge,
+            lint: args.lint,
+            message_format: args.message_format,
+            strict: args.strict,
+            validate: args.validate,
+            report: args.report,
+            scripted: args.scripted,
+            minify: args.minify,
+            inline_style: args.inline_style,
+            indent: args.indent,
+            empty_elements: args.empty_elements,
+            line_ending: args.line_ending,
+            bom: args.bom,
+            chapter: args.chapter,
+            pages: args.pages,
+            skip_images: args.skip_images,
+            split_size: args.split_size,
             ..Default::default()
-        };
-
-        if self.book.rendition.style.is_empty() {
-            self.build_default_style(&mut cx)?;
-        } else {
-            self.build_style(&mut cx)?;
         }
-
-        for chapter in &self.book.chapter {
-            self.build_chapter(&mut cx, chapter)?;
-        }
-
-        Ok(cx)
     }
+}
 
-    fn build_default_style(&self, cx: &mut Context) -> Result<()> {
-        info!("building default style");
-
-        let mut file = NamedTempFile::new()?;
-        file.write_all(include_bytes!("../default-style.css"))?;
+/// Options that control how a book is built and packaged.
+#[derive(Debug, Clone)]
+pub(crate) struct BuildOptions {
+    /// Directory to write the EPub file in.
+    ///
+    /// Defaults to the directory that contains the project file.
+    pub output: Option<PathBuf>,
 
-        let item = Item {
-            media_type: "text/css".to_string(),
-            href: "style/default.css".to_string(),
-            properties: None,
-            src: file.into_temp_path().into(),
-        };
+    /// Directory to write the EPub file in, named after the profile as well as the book.
+    ///
+    /// Takes precedence over `output`.
+    pub out_dir: Option<PathBuf>,
 
-        let id = "s-default".to_string();
-        cx.manifest.insert(id.clone(), item);
-        cx.styles.push(id);
+    /// Name of the EPub file without the extension.
+    ///
+    /// Defaults to the main title of the book.
+    pub name: Option<String>,
 
-        Ok(())
-    }
+    /// Container the book is packaged in.
+    pub format: Format,
 
-    fn build_style(&self, cx: &mut Context) -> Result<()> {
-        info!("building style");
+    /// Compression method of the items in the EPub file.
+    ///
+    /// The `mimetype` entry is always stored uncompressed.
+    pub compression: Compression,
 
-        for (style, seq) in self.book.rendition.style.iter().zip(1..) {
-            let mut file = NamedTempFile::new()?;
-            file.write_all(style.src.as_bytes())?;
-            let src = file.into_temp_path();
+    /// Timestamp recorded as `dcterms:modified`.
+    ///
+    /// Defaults to the time the package is written.
+    pub modified: Option<OffsetDateTime>,
 
-            let item = Item {
-                media_type: "text/css".to_string(),
-                href: format!("style/{}", style.href),
-                properties: None,
-                src: src.into(),
-            };
+    /// Whether to probe and convert every image instead of using the cache in the project
+    /// directory.
+    pub no_cache: bool,
 
-            let id = format!("s-{seq:04}");
-            cx.manifest.insert(id.clone(), item);
+    /// Number of threads that prepare the images of the pages.
+    ///
+    /// Defaults to the available parallelism.
+    pub jobs: Option<NonZeroUsize>,
 
-            if style.link {
-                cx.styles.push(id);
-            }
-        }
+    /// Whether to record the sources in the lockfile.
+    pub lock: bool,
 
-        Ok(())
-    }
+    /// Whether to require the sources to match the lockfile instead of updating it.
+    pub locked: bool,
 
-    fn build_chapter(&self, cx: &mut Context, chapter: &Chapter) -> Result<()> {
-        info!(
-            "building chapter {}",
-            chapter.name.as_deref().unwrap_or("(untitled)")
-        );
+    /// Whether to write an EPUB 2 NCX table of contents in addition to the navigation document.
+    ///
+    /// Also enabled by `ncx: true` in the rendition of the project file.
+    pub ncx: bool,
 
-        let mut first = true;
-        for page in &chapter.page {
-            let id = self.build_page(cx, chapter, page)?;
-            if first {
-                first = false;
+    /// Store whose limits the book is checked against.
+    pub profile: Profile,
 
-                if let Some(name) = &chapter.name {
-                    cx.toc.insert(id, name.clone());
-                }
-            }
-        }
+    /// Length in pixels the long edge of the cover should reach instead of what the store expects.
+    pub min_cover_edge: Option<u32>,
 
-        Ok(())
-    }
+    /// Store whose reading system the book is linted against.
+    pub lint: Option<Profile>,
 
-    fn build_page(&self, cx: &mut Context, chapter: &Chapter, page: &Page) -> Result<String> {
-        debug!("building page from {}", page.src.display());
+    /// Format the findings of the lint are reported in.
+    pub message_format: MessageFormat,
 
-        let src = self.root.join(&page.src);
+    /// Whether warnings about the book fail the build.
+    ///
+    /// Also enabled by `strict: true` in the project file.
+    pub strict: bool,
 
-        let (width, height) = {
-            let img =
-                image::open(&src).with_context(|| format!("failed to read {}", src.display()))?;
-            (img.width(), img.height())
-        };
+    /// Whether to check the structure of the EPub file once it is written.
+    pub validate: bool,
 
-        match self.book.rendition.orientation {
-            Orientation::Landscape if width < height => {
-                warn!("`{}` is a portrait page", page.src.display())
-            }
-            Orientation::Portrait if height < width => {
-                warn!("`{}` is a landscape page", page.src.display())
-            }
-            _ => {}
-        }
+    /// File to write the size breakdown of the EPub file in.
+    ///
+    /// A summary is printed instead if not set.
+    pub report: Option<PathBuf>,
 
-        let id = cx.add_image(src.as_path(), chapter.cover);
-        let image = cx.manifest.get(&id).unwrap();
+    /// Whether to write compact XML and minified styles instead of readable ones.
+    pub minify: bool,
 
-        let mut file = NamedTempFile::new()?;
+    /// Whether to write the styles into the pages instead of packaging them as files.
+    pub inline_style: bool,
 
-        writeln!(file, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
-        writeln!(file, r#"<!DOCTYPE html>"#)?;
+    /// Number of spaces to indent XML elements with.
+    ///
+    /// Ignored if `minify` is set.
+    pub indent: usize,
 
-        let mut writer = EventWriter::new_with_config(
-            file,
-            EmitterConfig::new()
-                .perform_indent(true)
-                .write_document_declaration(false),
-        );
+    /// How to write XML elements without content.
+    pub empty_elements: EmptyElements,
 
-        writer.write(
-            XmlEvent::start_element("html")
-                .default_ns("http://www.w3.org/1999/xhtml")
-                .ns("epub", "http://www.idpf.org/2007/ops")
-                .attr("xml:lang", &self.book.metadata.language),
-        )?;
+    /// Line ending of the generated XML documents and styles.
+    pub line_ending: LineEnding,
 
-        writer.write(XmlEvent::start_element("head"))?;
+    /// Whether to begin the generated XML documents and styles with a byte order mark, which
+    /// reading systems do not need and some stores reject.
+    pub bom: bool,
 
-        writer.write(XmlEvent::start_element("meta").attr("charset", "UTF-8"))?;
-        writer.write(XmlEvent::end_element())?; // meta
+    /// Whether to include the scripts of the rendition.
+    ///
+    /// Books are script-free by default.
+    pub scripted: bool,
 
-        writer.write(XmlEvent::start_element("title"))?;
-        writer.write(XmlEvent::characters(&cx.title))?;
-        writer.write(XmlEvent::end_element())?; // title
+    /// Chapters to build a preview of, counting from 1.
+    pub chapter: Vec<usize>,
 
-        for id in &cx.styles {
-            let item = cx.manifest.get(id).unwrap();
-            writer.write(
-                XmlEvent::start_element("link")
-                    .attr("rel", "stylesheet")
-                    .attr("type", item.media_type.as_str())
-                    .attr("href", &format!("../{}", item.href)),
-            )?;
-            writer.write(XmlEvent::end_element())?; // link
-        }
+    /// Pages to build a preview of, in addition to `chapter`.
+    pub pages: Option<PageRange>,
 
-        writer.write(
-            XmlEvent::start_element("meta")
-                .attr("name", "viewport")
-                .attr("content", &format!("width={width}, height={height}")),
-        )?;
-        writer.write(XmlEvent::end_element())?; // meta
+    /// Whether to package blank placeholders instead of the images.
+    pub skip_images: bool,
 
-        writer.write(XmlEvent::end_element())?; // head
+    /// Maximum size of each EPub file the book is split into.
+    ///
+    /// Only `run_split` splits the book.
+    pub split_size: Option<u64>,
 
-        let mut event = XmlEvent::start_element("body");
-        if chapter.cover {
-            event = event.attr("epub:type", "cover");
-        }
-        writer.write(event)?;
+    /// Part of the book to build as one of several volumes.
+    pub volume: Option<Volume>,
 
-        writer.write(XmlEvent::start_element("div").attr("class", "main"))?;
+    /// Name of the target of the project file to build for, whose settings take the place of
+    /// those of the book and of these options.
+    pub target: Option<String>,
+}
 
-        writer.write(
-            XmlEvent::start_element("svg")
-                .default_ns("http://www.w3.org/2000/svg")
-                .ns("xlink", "http://www.w3.org/1999/xlink")
-                .attr("version", "1.1")
-                .attr("width", "100%")
-                .attr("height", "100%")
-                .attr("viewBox", &format!("0 0 {width} {height}")),
-        )?;
-        writer.write(
-            XmlEvent::start_element("image")
-                .attr("width", &width.to_string())
-                .attr("height", &height.to_string())
-                .attr("xlink:href", &format!("../{}", image.href)),
-        )?;
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            output: None,
+            out_dir: None,
+            name: None,
+            format: Format::default(),
+            compression: Compression::default(),
+            modified: None,
+            no_cache: false,
+            jobs: None,
+            lock: false,
+            locked: false,
+            ncx: false,
+            profile: Profile::default(),
+            min_cover_edge: None,
+            lint: None,
+            message_format: MessageFormat::Text,
+            strict: false,
+            validate: false,
+            report: None,
+            minify: false,
+            inline_style: false,
+            indent: 2,
+            empty_elements: EmptyElements::default(),
+            line_ending: LineEnding::default(),
+            bom: false,
+            scripted: false,
+            chapter: Vec::new(),
+            pages: None,
+            skip_images: false,
+            split_size: None,
+            volume: None,
+            target: None,
+        }
+    }
+}
 
-        writer.write(XmlEvent::end_element())?; // image
-        writer.write(XmlEvent::end_element())?; // svg
-        writer.write(XmlEvent::end_element())?; // div
-        writer.write(XmlEvent::end_element())?; // body
-        writer.write(XmlEvent::end_element())?; // html
+impl BuildOptions {
+    /// Whether only a part of the book is built.
+    fn is_partial(&self) -> bool {
+        !self.chapter.is_empty() || self.pages.is_some()
+    }
 
-        let id = cx.add_page(writer.into_inner().into_temp_path(), chapter.cover);
+    /// Whether only a part of the book or only its structure is built.
+    fn is_preview(&self) -> bool {
+        self.is_partial() || self.skip_images
+    }
 
-        let props = if chapter.cover {
-            Some("rendition:page-spread-center".to_string())
+    /// Returns the byte order mark the generated text files begin with, if any.
+    fn bom(&self) -> &'static [u8] {
+        if self.bom {
+            "\u{feff}".as_bytes()
         } else {
-            None
-        };
-        cx.add_spine(id.clone(), props);
-
-        Ok(id)
+            b""
+        }
     }
-}
 
-struct Item {
-    media_type: String,
-    href: String,
-    properties: Option<String>,
-    src: Resource,
-}
+    /// Returns the CSS of a style as it is packaged: minified or with the line ending of the
+    /// generated files, and without the byte order mark of its source.
+    fn style_text(&self, css: &str) -> String {
+        let css = css.strip_prefix('\u{feff}').unwrap_or(css);
+        if self.minify {
+            return minify_css(css);
+        }
 
-enum Resource {
-    PathBuf(PathBuf),
-    TempPath(TempPath),
-}
+        let css = css.replace("\r\n", "\n");
+        match self.line_ending {
+            LineEnding::Lf => css,
+            LineEnding::Crlf => css.replace('\n', "\r\n"),
+        }
+    }
 
-impl From<&Path> for Resource {
-    fn from(path: &Path) -> Self {
-        Self::PathBuf(path.to_path_buf())
+    fn emitter_config(&self) -> EmitterConfig {
+        EmitterConfig::new()
+            .perform_indent(!self.minify && 0 < self.indent)
+            .indent_string(" ".repeat(self.indent))
+            .normalize_empty_elements(self.empty_elements != EmptyElements::Expanded)
+            .pad_self_closing(self.empty_elements == EmptyElements::Padded)
+            .line_separator(self.line_ending.as_str())
     }
 }
 
-impl From<PathBuf> for Resource {
-    fn from(path: PathBuf) -> Self {
-        Self::PathBuf(path)
-    }
+/// Serialization of XML elements without content.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EmptyElements {
+    #[default]
+    Padded,
+    Compact,
+    Expanded,
 }
 
-impl From<TempPath> for Resource {
-    fn from(path: TempPath) -> Self {
-        Self::TempPath(path)
-    }
+/// Line ending of the generated XML documents and styles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
 }
 
-impl AsRef<Path> for Resource {
-    fn as_ref(&self) -> &Path {
+impl LineEnding {
+    fn as_str(self) -> &'static str {
         match self {
-            Self::PathBuf(path) => path.as_path(),
-            Self::TempPath(path) => path.as_ref(),
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
         }
     }
 }
 
-#[derive(Default)]
-struct ItemRef {
-    id_ref: String,
-    linear: bool,
-    properties: Option<String>,
+/// Compression method of the items in the EPub file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    Stored,
+    #[default]
+    Deflated,
 }
 
-#[derive(Default)]
-struct Context {
-    book: Rc<Book>,
-    title: String,
-    manifest: Map<String, Item>,
-    spine: Vec<ItemRef>,
-    styles: Vec<String>,
-    image_index: usize,
-    page_index: usize,
-    toc: Map<String, String>,
+impl From<Compression> for CompressionMethod {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Stored => Self::Stored,
+            Compression::Deflated => Self::Deflated,
+        }
+    }
 }
 
-impl Context {
-    fn add_image(&mut self, src: impl Into<Resource>, cover: bool) -> String {
-        let src = src.into();
-        let mime = mime_guess::from_path(&src).first_or_octet_stream();
-        let ext = src
-            .as_ref()
-            .extension()
-            .and_then(|e| e.to_str())
-            .map(|e| format!(".{e}"))
-            .unwrap_or_default();
-
-        let (id, properties) = if cover {
-            ("cover".to_string(), Some("cover-image".to_string()))
-        } else {
-            self.image_index += 1;
-            (format!("i-{:04}", self.image_index), None)
-        };
+#[cfg(feature = "fs")]
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::open_or_find(args.project.as_deref())?;
+    let modified = source_date(args.reproducible)?;
+
+    let targets = args.targets(&project)?;
+
+    let options = BuildOptions {
+        modified,
+        ..BuildOptions::from(args)
+    };
+    // the findings in a machine readable format take the standard output to themselves
+    let quiet = options.message_format != MessageFormat::Text;
+    let summary = move |report: &Report| {
+        if !quiet {
+            print!("{report}");
+        }
+    };
+    if targets.is_empty() {
+        return run_options(&project, options, summary);
+    }
 
-        let item = Item {
-            media_type: mime.to_string(),
-            href: format!("image/{id}{ext}"),
-            properties,
-            src,
+    for target in targets {
+        info!("building for target `{target}`");
+        let options = BuildOptions {
+            target: Some(target),
+            ..options.clone()
         };
-
-        self.manifest.insert(id.clone(), item);
-
-        id
+        run_options(&project, options, summary)?;
     }
 
-    fn add_page(&mut self, src: impl Into<Resource>, cover: bool) -> String {
-        let id = if cover {
-            "p-cover".to_string()
-        } else {
-            self.page_index += 1;
-            format!("p-{:04}", self.page_index)
-        };
+    Ok(())
+}
 
-        let item = Item {
-            media_type: "application/xhtml+xml".to_string(),
-            href: format!("xhtml/{id}.xhtml"),
-            properties: Some("svg".to_string()),
-            src: src.into(),
-        };
+/// Builds the book of `project` into one EPub file, or into several if `options` has it split.
+#[cfg(feature = "fs")]
+fn run_options(
+    project: &Project,
+    options: BuildOptions,
+    summary: impl FnMut(&Report) + Copy,
+) -> Result<()> {
+    match options.split_size {
+        Some(max_size) => {
+            run_split(project, options, max_size, summary)?;
+        }
+        None => {
+            run(project, options, summary)?;
+        }
+    }
 
-        self.manifest.insert(id.clone(), item);
+    Ok(())
+}
 
-        id
+/// Date of reproducible builds without `SOURCE_DATE_EPOCH`: 1980-01-01T00:00:00Z, the earliest a
+/// ZIP file can record.
+const REPRODUCIBLE_EPOCH: i64 = 315_532_800;
+
+/// Returns the time to record as the last modification of the book: `SOURCE_DATE_EPOCH` if it is
+/// set, or a fixed date if the build is to be reproducible.
+///
+/// The modification time of the project file is not used, since checking it out or copying it
+/// changes that but not the book.
+pub(crate) fn source_date(reproducible: bool) -> Result<Option<OffsetDateTime>> {
+    if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+        return epoch
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|epoch| OffsetDateTime::from_unix_timestamp(epoch).ok())
+            .map(Some)
+            .with_context(|| format!("SOURCE_DATE_EPOCH `{epoch}` is not a UNIX timestamp"));
     }
 
-    fn add_spine(&mut self, id_ref: String, properties: Option<String>) {
-        self.spine.push(ItemRef {
-            id_ref,
-            linear: true,
-            properties,
-        })
+    if !reproducible {
+        return Ok(None);
     }
 
-    fn write_to(&self, path: impl AsRef<Path>) -> Result<()> {
-        let path = path.as_ref().join(format!("{}.epub", self.title));
-        let file = File::create(path)?;
-        let mut zip = ZipWriter::new(file);
+    Ok(OffsetDateTime::from_unix_timestamp(REPRODUCIBLE_EPOCH).ok())
+}
+
+/// Builds the book of `project` into as many EPub files as it takes to keep each of them within
+/// `max_size`, and returns their paths.
+///
+/// The book is split between chapters, and every volume has the cover of the book.
+#[cfg(feature = "fs")]
+pub(super) fn run_split(
+    project: &Project,
+    options: BuildOptions,
+    max_size: u64,
+    mut summary: impl FnMut(&Report),
+) -> Result<Vec<PathBuf>> {
+    let mut book = project.load()?;
+    expand_book(project.root(), &mut book);
+    let volumes = plan_volumes(project.root(), &book, max_size);
+    if volumes.len() < 2 {
+        return Ok(vec![run(project, options, summary)?]);
+    }
 
-        self.write_mimetype(&mut zip)?;
-        self.write_container(&mut zip)?;
-        self.write_package(&mut zip)?;
-        self.write_navigation(&mut zip)?;
+    info!("splitting the book into {} volumes", volumes.len());
+
+    let count = volumes.len();
+    volumes
+        .into_iter()
+        .zip(1..)
+        .map(|((chapters, findings), number)| {
+            let options = BuildOptions {
+                volume: Some(Volume {
+                    number,
+                    count,
+                    chapters,
+                    findings,
+                }),
+                ..options.clone()
+            };
+            run(project, options, &mut summary)
+        })
+        .collect()
+}
 
-        info!("writing items");
-        for (_, item) in &self.manifest {
-            zip.start_file(format!("item/{}", item.href), SimpleFileOptions::default())?;
-            let mut file = File::open(&item.src)?;
-            std::io::copy(&mut file, &mut zip)?;
+/// Groups the chapters of `book` into volumes whose page sources, which make up most of an EPub
+/// file, add up to at most `max_size` together with the cover.
+///
+/// Returns the chapters of each volume, numbered as by `--chapter`, and the findings about the
+/// volume, which its build warns about.
+#[cfg(feature = "fs")]
+fn plan_volumes(root: &Path, book: &Book, max_size: u64) -> Vec<(Vec<usize>, Vec<String>)> {
+    let size = |page: &Page| {
+        page.sources()
+            .into_iter()
+            .filter_map(|src| root.join(book.source_path(src)).metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum::<u64>()
+    };
+
+    // the cover is in every volume
+    let mut cover = 0;
+    let mut chapters = Vec::new();
+    let numbers = chapter_numbers(book);
+    for (i, chapter) in book.chapter.iter().enumerate() {
+        let mut body = None;
+        for (j, page) in chapter.page.iter().enumerate() {
+            if book.is_cover(i, j) {
+                cover += size(page);
+            } else {
+                *body.get_or_insert(0) += size(page);
+            }
+        }
+        if let (Some(number), Some(size)) = (numbers[i], body) {
+            chapters.push((number, size));
         }
+    }
 
-        Ok(())
+    let mut volumes = Vec::<(Vec<usize>, Vec<String>)>::new();
+    let mut current = 0;
+    for (number, size) in chapters {
+        match volumes.last_mut() {
+            Some((volume, _)) if cover + current + size <= max_size => {
+                volume.push(number);
+                current += size;
+            }
+            _ => {
+                let mut findings = Vec::new();
+                if max_size < cover + size {
+                    findings.push(tr!(
+                        "chapter {number} alone exceeds {}, which is left as a volume of its own",
+                        "第 {number} 章だけで {} を超えるため、単独の巻にします",
+                        format_size(max_size)
+                    ));
+                }
+                volumes.push((vec![number], findings));
+                current = size;
+            }
+        }
     }
 
-    fn write_mimetype(&self, zip: &mut ZipWriter<File>) -> Result<()> {
-        info!("writing mimetype");
+    volumes
+}
 
-        zip.start_file(
-            "mimetype",
-            SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
-        )?;
+/// Part of a book built into one of several EPub files.
+#[derive(Debug, Clone)]
+pub(crate) struct Volume {
+    /// Position of the volume, counting from 1.
+    pub number: usize,
 
-        zip.write_all(b"application/epub+zip")?;
+    /// Number of the volumes of the book.
+    pub count: usize,
 
-        Ok(())
-    }
+    /// Chapters of the volume, numbered as by `--chapter`.
+    pub chapters: Vec<usize>,
 
-    fn write_container(&self, zip: &mut ZipWriter<File>) -> Result<()> {
-        info!("writing container");
+    /// Findings made while grouping the chapters into the volume.
+    pub findings: Vec<String>,
+}
 
-        zip.start_file("META-INF/container.xml", SimpleFileOptions::default())?;
-        let mut w = EventWriter::new_with_config(zip, EmitterConfig::new().perform_indent(true));
+impl Volume {
+    /// Leaves only the chapters of the volume in `book`, numbering its title and identifier and
+    /// putting it in a set with the other volumes.
+    fn apply(&self, book: Book) -> Result<Book> {
+        let mut book = select(book, &self.chapters, None)?;
+        let metadata = &mut book.metadata;
+
+        let main = metadata
+            .title
+            .iter()
+            .position(|t| t.title_type == TitleType::Main)
+            .unwrap_or_default();
+        if let Some(title) = metadata.title.get_mut(main) {
+            metadata.collection.push(Collection {
+                name: title.name.clone(),
+                collection_type: CollectionType::Set,
+                position: u32::try_from(self.number).ok().map(Position::from),
+            });
+            title.name = format!("{} {}", title.name, self.number);
+        }
+        metadata.identifier = volume_identifier(&metadata.identifier, self.number);
 
-        w.write(
-            XmlEvent::start_element("container")
-                .default_ns("urn:oasis:names:tc:opendocument:xmlns:container")
-                .attr("version", "1.0"),
-        )?;
+        Ok(book)
+    }
+}
 
-        w.write(XmlEvent::start_element("rootfiles"))?;
+/// Derives the identifier of a volume from that of the whole book, keeping it a UUID if it is one.
+fn volume_identifier(identifier: &str, number: usize) -> String {
+    if identifier.starts_with("urn:uuid:") {
+        let digest = Sha256::digest(format!("{identifier}#{number}"));
+        let bytes = digest[..16].try_into().unwrap();
+        format!(
+            "urn:uuid:{}",
+            uuid::Builder::from_random_bytes(bytes).into_uuid()
+        )
+    } else {
+        format!("{identifier}-{number}")
+    }
+}
 
-        w.write(
-            XmlEvent::start_element("rootfile")
-                .attr("full-path", "item/standard.opf")
-                .attr("media-type", "application/oebps-package+xml"),
-        )?;
+/// Builds the book of `project` into an EPub file and returns its path.
+///
+/// The size breakdown of the file is passed to `summary` unless it is written to a file.
+#[cfg(feature = "fs")]
+pub(super) fn run(
+    project: &Project,
+    options: BuildOptions,
+    summary: impl FnOnce(&Report),
+) -> Result<PathBuf> {
+    let cx = Builder::new(project, options)?.build()?;
+    cx.check_file_name();
+
+    if let Some(profile) = cx.options.lint {
+        // the findings are about the book, which the project file describes
+        let file_name = Path::new(project.path().file_name().unwrap_or_default());
+        let mut reporter = Reporter::new(cx.options.message_format, project.root());
+        for (rule, finding) in cx.lint(profile) {
+            reporter.report(rule, finding, file_name);
+            cx.warnings.fetch_add(1, Ordering::Relaxed);
+        }
+        reporter.finish()?;
+    }
+    cx.check_strict()?;
+
+    // neither a preview nor a volume records the sources of the whole book
+    let lock = if !(cx.options.lock || cx.options.locked)
+        || cx.options.is_preview()
+        || cx.options.volume.is_some()
+    {
+        None
+    } else {
+        Some(cx.lockfile(project)?)
+    };
+    let lock_path = project.lock_path();
+    if let (true, Some(lock)) = (cx.options.locked, &lock) {
+        check_locked(&lock_path, lock)?;
+    }
 
-        w.write(XmlEvent::end_element())?; // rootfile
-        w.write(XmlEvent::end_element())?; // rootfiles
-        w.write(XmlEvent::end_element())?; // container
+    let mut cache = if cx.options.no_cache {
+        EntryCache::disabled()
+    } else {
+        EntryCache::open(project.cache_dir().join(ENTRY_CACHE))
+    };
+    let path = match &cx.options.out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create `{}`", dir.display()))?;
+            cx.write_to(dir, &mut cache)?
+        }
+        None => cx.write_to(
+            cx.options.output.as_deref().unwrap_or(project.root()),
+            &mut cache,
+        )?,
+    };
+    if let Err(e) = cache.save() {
+        warn!("failed to save cache: {e:#}");
+    }
+    cx.check_profile(&path)?;
+    if cx.options.validate && cx.options.format.is_epub() {
+        validate::validate_file(&path)?;
+    }
 
-        Ok(())
+    // a PDF file has no entries to break the size down by, as warned while building
+    if cx.options.format != Format::Pdf {
+        let report = cx.report(&path)?;
+        match &cx.options.report {
+            Some(report_path) => {
+                let file = File::create(report_path)
+                    .with_context(|| format!("failed to create `{}`", report_path.display()))?;
+                serde_json::to_writer_pretty(BufWriter::new(file), &report)?;
+            }
+            None => summary(&report),
+        }
     }
 
-    fn write_package(&self, zip: &mut ZipWriter<File>) -> Result<()> {
-        info!("writing package");
+    cx.check_strict()?;
 
-        zip.start_file("item/standard.opf", SimpleFileOptions::default())?;
-        let mut w = EventWriter::new_with_config(zip, EmitterConfig::new().perform_indent(true));
+    let path = match cx.options.format {
+        Format::Kindle => convert_kindle(&path)?.unwrap_or(path),
+        _ => path,
+    };
 
-        w.write(
-            XmlEvent::start_element("package")
-                .default_ns("http://www.idpf.org/2007/opf")
-                .attr("version", "3.0")
-                .attr("xml:lang", &self.book.metadata.language)
-                .attr("unique-identifier", "unique-id")
-                .attr("prefix", "ebpaj: http://www.ebpaj.jp/"),
-        )?;
+    if let (false, Some(lock)) = (cx.options.locked, &lock) {
+        lock.write(&lock_path)?;
+    }
 
-        self.write_package_metadata(&mut w)?;
-        self.write_package_manifest(&mut w)?;
-        self.write_package_spine(&mut w)?;
+    Ok(path)
+}
 
-        w.write(XmlEvent::end_element())?;
+/// Converts the EPub file for Kindle at `path` with Calibre or KindleGen, whichever is installed,
+/// and returns the path to the Kindle book written next to it, or none if neither is installed.
+#[cfg(feature = "fs")]
+fn convert_kindle(path: &Path) -> Result<Option<PathBuf>> {
+    let (mut command, output) = if let Some(program) = find_program("ebook-convert") {
+        let output = path.with_extension("azw3");
+        let mut command = std::process::Command::new(program);
+        command.arg(path).arg(&output);
+        (command, output)
+    } else if let Some(program) = find_program("kindlegen") {
+        let mut command = std::process::Command::new(program);
+        command.arg(path);
+        (command, path.with_extension("mobi"))
+    } else {
+        info!(
+            "{}",
+            tr!(
+                "neither Calibre nor KindleGen is installed; `{}` can be sent to Kindle as is",
+                "Calibre も KindleGen もインストールされていません。`{}` はそのまま Kindle に送れます",
+                path.display()
+            )
+        );
+        return Ok(None);
+    };
 
-        Ok(())
+    // a file left by an earlier conversion would pass for the output of a failed one
+    match std::fs::remove_file(&output) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("failed to remove `{}`", output.display()));
+        }
+        _ => {}
     }
 
-    fn write_package_metadata<W: Write>(&self, w: &mut EventWriter<W>) -> Result<()> {
-        w.write(XmlEvent::start_element("metadata").ns("dc", "http://purl.org/dc/elements/1.1/"))?;
+    info!(
+        "{}",
+        tr!(
+            "converting to `{}` with {}",
+            "{1} で `{0}` に変換しています",
+            output.display(),
+            command.get_program().to_string_lossy()
+        )
+    );
+    let result = command.output().with_context(|| {
+        format!(
+            "failed to run `{}`",
+            command.get_program().to_string_lossy()
+        )
+    })?;
+    // KindleGen exits with 1 when it only has warnings
+    let converted = matches!(result.status.code(), Some(0 | 1)) && output.is_file();
+    if !result.status.success() && !converted {
+        anyhow::bail!(
+            "{}\n{}",
+            tr!(
+                "failed to convert `{}` for Kindle",
+                "`{}` を Kindle 向けに変換できませんでした",
+                path.display()
+            ),
+            String::from_utf8_lossy(&result.stderr).trim()
+        );
+    }
 
-        for (title, seq) in self.book.metadata.title.iter().zip(1..) {
-            let refines = format!("#title{seq}");
+    Ok(Some(output))
+}
 
-            w.write(XmlEvent::start_element("dc:title").attr("id", &refines[1..]))?;
-            w.write(XmlEvent::characters(&title.name))?;
-            w.write(XmlEvent::end_element())?;
+/// Returns the path to the program `name` in one of the directories of `PATH`, if any.
+#[cfg(feature = "fs")]
+pub(super) fn find_program(name: &str) -> Option<PathBuf> {
+    let file_name = format!("{name}{}", std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.is_file())
+}
 
-            w.write(
-                XmlEvent::start_element("meta")
-                    .attr("refines", &refines)
-                    .attr("property", "title-type"),
-            )?;
-            w.write(XmlEvent::characters(title.title_type.as_ref()))?;
-            w.write(XmlEvent::end_element())?;
+/// Builds `book`, whose page sources are relative to `root`, into `writer` without a project, so
+/// neither the cache nor the lockfile is used.
+pub(crate) fn package(
+    root: &Path,
+    book: Book,
+    options: BuildOptions,
+    sources: Sources,
+) -> Result<Context> {
+    let cx = Builder::with_book(
+        root,
+        book,
+        options,
+        sources,
+        ProbeCache::disabled(),
+        ImageCache::disabled(),
+    )?
+    .build()?;
+    cx.check_strict()?;
+    cx.validate()?;
+    Ok(cx)
+}
 
-            if let Some(value) = &title.alternate_script {
-                w.write(
-                    XmlEvent::start_element("meta")
-                        .attr("refines", &refines)
-                        .attr("property", "alternate-script"),
-                )?;
-                w.write(XmlEvent::characters(value))?;
-                w.write(XmlEvent::end_element())?;
+#[cfg(feature = "fs")]
+fn check_locked(path: &Path, lock: &Lockfile) -> Result<()> {
+    let Some(locked) = Lockfile::read(path)? else {
+        return Err(Failure::new(
+            Status::Locked,
+            tr!("`{}` does not exist", "`{}` が存在しません", path.display()),
+        )
+        .into());
+    };
+
+    let changes = locked.diff(lock);
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    for change in &changes {
+        error!("{change}");
+    }
+
+    Err(Failure::new(
+        Status::Locked,
+        tr!(
+            "sources differ from `{}`",
+            "ソースが `{}` と異なります",
+            path.display()
+        ),
+    )
+    .into())
+}
+
+struct Builder {
+    root: PathBuf,
+    book: Arc<Book>,
+    options: Arc<BuildOptions>,
+    sources: Sources,
+    cache: Mutex<ProbeCache>,
+    images: ImageCache,
+    /// Manifest ids of the pages in the order of the spine.
+    page_ids: Vec<String>,
+}
+
+impl Builder {
+    #[cfg(feature = "fs")]
+    fn new(project: &Project, options: BuildOptions) -> Result<Self> {
+        let book = project.load()?;
+        let (cache, images) = if options.no_cache {
+            (ProbeCache::disabled(), ImageCache::disabled())
+        } else {
+            project.create_dir()?;
+            let dir = project.cache_dir();
+            (
+                ProbeCache::open(dir.join(PROBE_CACHE)),
+                ImageCache::open(dir.join(IMAGE_CACHE)),
+            )
+        };
+
+        let sources = Sources::default();
+        Self::with_book(project.root(), book, options, sources, cache, images)
+    }
+
+    /// Creates a builder of `book`, whose page sources are relative to `root` and read from
+    /// `sources` if given there.
+    fn with_book(
+        root: &Path,
+        mut book: Book,
+        mut options: BuildOptions,
+        sources: Sources,
+        cache: ProbeCache,
+        images: ImageCache,
+    ) -> Result<Self> {
+        #[cfg(feature = "fs")]
+        expand_book(root, &mut book);
+        #[cfg(not(feature = "fs"))]
+        book.flatten_chapters();
+        if let Some(name) = &options.target {
+            let Some(target) = book.target(name) else {
+                anyhow::bail!(tr!(
+                    "the project file has no target named `{name}`",
+                    "プロジェクトファイルに `{name}` という名前のターゲットがありません"
+                ));
+            };
+
+            options.format = target.format.unwrap_or(options.format);
+            options.profile = target.profile.unwrap_or(options.profile);
+            book.images = book.images.overridden_by(&target.images);
+        }
+        options.ncx |= book.rendition.ncx;
+        if options.is_partial() {
+            book = select(book, &options.chapter, options.pages)?;
+        }
+        if let Some(volume) = &options.volume {
+            info!("building volume {} of {}", volume.number, volume.count);
+            book = volume.apply(book)?;
+        }
+
+        let (mut index, mut covers) = (0, 0);
+        let page_ids = book
+            .chapter
+            .iter()
+            .enumerate()
+            .flat_map(|(i, chapter)| (0..chapter.page.len()).map(move |j| (i, j)))
+            .map(|(i, j)| book.is_cover(i, j))
+            .map(|cover| {
+                if cover {
+                    covers += 1;
+                    page_id(cover, covers)
+                } else {
+                    index += 1;
+                    page_id(cover, index)
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            book: Arc::new(book),
+            options: Arc::new(options),
+            sources,
+            cache: Mutex::new(cache),
+            images,
+            page_ids,
+        })
+    }
+
+    #[instrument(name = "build", skip_all)]
+    fn build(&self) -> Result<Context> {
+        let mut cx = Context {
+            book: Arc::clone(&self.book),
+            options: Arc::clone(&self.options),
+            title: self
+                .book
+                .metadata
+                .main_title()
+                .unwrap_or_default()
+                .to_string(),
+            package: Package {
+                name_by: self.book.images.name_by,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        self.validate(&cx)?;
+        if let Some(volume) = &self.options.volume {
+            for finding in &volume.findings {
+                cx.warn(finding);
             }
+        }
+        if self.options.format == Format::Pdf && self.options.report.is_some() {
+            cx.warn(tr!(
+                "no report is written for a PDF file",
+                "PDF ファイルのレポートは書き出しません"
+            ));
+        }
 
-            if let Some(value) = &title.file_as {
-                w.write(
-                    XmlEvent::start_element("meta")
-                        .attr("refines", &refines)
-                        .attr("property", "file-as"),
-                )?;
-                w.write(XmlEvent::characters(value))?;
-                w.write(XmlEvent::end_element())?;
+        if self.book.rendition.style.is_empty() {
+            self.build_default_style(&mut cx)?;
+        } else {
+            self.build_style(&mut cx)?;
+        }
+
+        if !self.book.rendition.script.is_empty() {
+            if self.options.scripted {
+                self.build_script(&mut cx)?;
+            } else {
+                cx.warn(tr!(
+                    "leaving out the scripts, which are included only with --scripted",
+                    "スクリプトは --scripted を指定した場合のみ含めるため省きます"
+                ));
             }
+        }
 
-            w.write(
-                XmlEvent::start_element("meta")
-                    .attr("refines", &refines)
-                    .attr("property", "display-seq"),
-            )?;
-            w.write(XmlEvent::characters(&seq.to_string()))?;
-            w.write(XmlEvent::end_element())?;
+        if let Some(src) = &self.book.archival_cover {
+            let src = self.sources.get(self.root.join(src))?;
+            if self.options.skip_images {
+                let Probe { width, height, .. } = self.probe(&src)?;
+                cx.package.add_archival_cover(placeholder(width, height)?)?;
+            } else {
+                cx.package.add_archival_cover(src)?;
+            }
         }
 
-        for (creator, seq) in self.book.metadata.creator.iter().zip(1..) {
-            let refines = format!("#creator{seq}");
+        let mut images = self.prepare_images(&cx)?.into_iter();
+        let names = self.book.numbered_names();
+        for (index, (chapter, name)) in self.book.chapter.iter().zip(&names).enumerate() {
+            let images = images.by_ref().take(chapter.page.len()).collect();
+            self.build_chapter(&mut cx, index, chapter, name.as_deref(), images)?;
+        }
+        cx.check_cover();
 
-            w.write(XmlEvent::start_element("dc:creator").attr("id", &refines[1..]))?;
-            w.write(XmlEvent::characters(&creator.name))?;
-            w.write(XmlEvent::end_element())?;
+        if let Err(e) = self.cache.lock().unwrap().save() {
+            warn!("failed to save cache: {e:#}");
+        }
 
-            if let Some(value) = &creator.role {
-                w.write(
-                    XmlEvent::start_element("meta")
-                        .attr("refines", &refines)
-                        .attr("property", "role")
-                        .attr("scheme", "marc:relators"),
-                )?;
-                w.write(XmlEvent::characters(value))?;
-                w.write(XmlEvent::end_element())?;
-            }
+        Ok(cx)
+    }
 
-            if let Some(value) = &creator.alternate_script {
-                w.write(
-                    XmlEvent::start_element("meta")
-                        .attr("refines", &refines)
-                        .attr("property", "alternate-script"),
-                )?;
-                w.write(XmlEvent::characters(value))?;
-                w.write(XmlEvent::end_element())?;
+    fn validate(&self, cx: &Context) -> Result<()> {
+        info!("validating sources");
+
+        for finding in find_duplicates(&self.book) {
+            cx.warn(finding);
+        }
+        for finding in find_empty_chapters(&self.book) {
+            cx.warn(finding);
+        }
+
+        let styles = &self.book.rendition.style;
+        let mut selected = self.book.chapter.iter().flat_map(|c| &c.style);
+        if let Some(href) = selected.find(|href| !styles.iter().any(|style| &style.href == *href)) {
+            return Err(Failure::new(
+                Status::Manifest,
+                tr!(
+                    "style `{href}` selected by a chapter is not in the rendition",
+                    "章が選択したスタイル `{href}` がレンディションにありません"
+                ),
+            )
+            .into());
+        }
+
+        report_missing(&find_missing(&self.root, &self.book, &self.sources))
+    }
+
+    fn build_default_style(&self, cx: &mut Context) -> Result<()> {
+        info!("building default style");
+
+        let id = "s-default".to_string();
+        if self.options.inline_style {
+            let css = include_str!("../default-style.css").to_string();
+            cx.package.inline_styles.insert(id.clone(), css);
+            cx.package.styles.push(id);
+            return Ok(());
+        }
+
+        let item = Item {
+            media_type: "text/css".to_string(),
+            href: "style/default.css".to_string(),
+            properties: None,
+            fallback: None,
+            media_overlay: None,
+            src: Resource::generated("css", include_bytes!("../default-style.css"))?,
+        };
+
+        let id = cx.package.insert(id, item);
+        cx.package.styles.push(id);
+
+        Ok(())
+    }
+
+    fn build_style(&self, cx: &mut Context) -> Result<()> {
+        info!("building style");
+
+        for (style, seq) in self.book.rendition.style.iter().zip(1..) {
+            let mut id = format!("s-{seq:04}");
+            if self.options.inline_style {
+                cx.package
+                    .inline_styles
+                    .insert(id.clone(), style.src.clone());
+            } else {
+                let item = Item {
+                    media_type: "text/css".to_string(),
+                    href: format!("style/{}", href_path(&style.href)?),
+                    properties: None,
+                    fallback: None,
+                    media_overlay: None,
+                    src: Resource::generated("css", style.src.as_bytes())?,
+                };
+
+                id = cx.package.insert(id, item);
             }
 
-            if let Some(value) = &creator.file_as {
-                w.write(
-                    XmlEvent::start_element("meta")
-                        .attr("refines", &refines)
-                        .attr("property", "file-as"),
-                )?;
-                w.write(XmlEvent::characters(value))?;
-                w.write(XmlEvent::end_element())?;
+            cx.package.style_ids.insert(style.href.clone(), id.clone());
+            if let Some(media) = &style.media {
+                cx.package.style_media.insert(id.clone(), media.clone());
+            }
+            if style.link {
+                cx.package.styles.push(id);
             }
+        }
 
-            w.write(
-                XmlEvent::start_element("meta")
-                    .attr("refines", &refines)
-                    .attr("property", "display-seq"),
-            )?;
-            w.write(XmlEvent::characters(&format!("{}", seq)))?;
-            w.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+    fn build_script(&self, cx: &mut Context) -> Result<()> {
+        info!("building script");
+
+        for (script, seq) in self.book.rendition.script.iter().zip(1..) {
+            // split at either separator, as the project file may have been written on Windows
+            let path = script.to_string_lossy();
+            let name = path
+                .rsplit(['/', '\\'])
+                .next()
+                .filter(|name| !name.is_empty() && *name != "..")
+                .with_context(|| format!("`{}` is not a file", script.display()))?;
+
+            let item = Item {
+                media_type: "application/javascript".to_string(),
+                href: format!("script/{}", href_path(name)?),
+                properties: None,
+                fallback: None,
+                media_overlay: None,
+                src: self.sources.get(self.root.join(script))?,
+            };
+
+            let id = cx.package.insert(format!("j-{seq:04}"), item);
+            cx.package.scripts.push(id);
         }
 
-        for (contributor, seq) in self.book.metadata.contributor.iter().zip(1..) {
-            let refines = format!("#creator{seq}");
+        Ok(())
+    }
 
-            w.write(XmlEvent::start_element("dc:creator").attr("id", &refines[1..]))?;
-            w.write(XmlEvent::characters(&contributor.name))?;
-            w.write(XmlEvent::end_element())?;
+    /// Builds the pages of `chapter` at `index`, listing it as `name` in the table of contents if
+    /// unnamed.
+    #[instrument(
+        name = "chapter",
+        skip_all,
+        fields(chapter = chapter.name.as_deref().unwrap_or("(untitled)"))
+    )]
+    fn build_chapter(
+        &self,
+        cx: &mut Context,
+        index: usize,
+        chapter: &Chapter,
+        name: Option<&str>,
+        images: Vec<Vec<PageImage>>,
+    ) -> Result<()> {
+        info!(
+            "building chapter {}",
+            chapter.name.as_deref().unwrap_or("(untitled)")
+        );
 
-            if let Some(value) = &contributor.role {
-                w.write(
-                    XmlEvent::start_element("meta")
-                        .attr("refines", &refines)
-                        .attr("property", "role")
-                        .attr("scheme", "marc:relators"),
-                )?;
-                w.write(XmlEvent::characters(value))?;
-                w.write(XmlEvent::end_element())?;
+        // a part without pages of its own is listed at the first page of its sub-chapters
+        if chapter.page.is_empty() {
+            if let Some(caption) = chapter.name.as_deref().or(name).filter(|_| chapter.toc) {
+                let entry = (caption.to_string(), chapter.depth);
+                cx.package.pending_toc.push(entry);
             }
+        }
 
-            if let Some(value) = &contributor.alternate_script {
-                w.write(
-                    XmlEvent::start_element("meta")
-                        .attr("refines", &refines)
-                        .attr("property", "alternate-script"),
-                )?;
-                w.write(XmlEvent::characters(value))?;
-                w.write(XmlEvent::end_element())?;
+        for (i, (page, images)) in chapter.page.iter().zip(images).enumerate() {
+            let cover = self.book.is_cover(index, i);
+            let split = 1 < images.len();
+            if split && (!page.link.is_empty() || matches!(page.panel, Some(Panel::Sidecar(_)))) {
+                cx.warn(tr!(
+                    "`{page}` is split into two, which leaves out its links and panels",
+                    "`{page}` は二つに分割されるため、そのリンクとコマは省かれます"
+                ));
             }
 
-            if let Some(value) = &contributor.file_as {
-                w.write(
-                    XmlEvent::start_element("meta")
-                        .attr("refines", &refines)
-                        .attr("property", "file-as"),
-                )?;
-                w.write(XmlEvent::characters(value))?;
-                w.write(XmlEvent::end_element())?;
+            let mut ids = Vec::new();
+            for (part, image) in images.into_iter().enumerate() {
+                let part = split.then_some(part);
+                ids.push(self.build_page(cx, chapter, page, image, cover, part)?);
+            }
+            // the first half stands for a split page
+            let id = ids.swap_remove(0);
+            if cover && !chapter.cover {
+                cx.package
+                    .landmarks
+                    .entry(Kind::Cover)
+                    .or_insert_with(|| (id.clone(), landmark_label(Kind::Cover).to_string()));
+            }
+            if let (0, Some(kind)) = (i, chapter.kind()) {
+                let label = chapter.name.as_deref().unwrap_or(landmark_label(kind));
+                cx.package
+                    .landmarks
+                    .entry(kind)
+                    .or_insert_with(|| (id.clone(), label.to_string()));
             }
+            for (caption, depth) in std::mem::take(&mut cx.package.pending_toc) {
+                cx.package.toc.push((id.clone(), caption, depth));
+            }
+            if let Some(caption) = chapter.caption(i, name) {
+                cx.package
+                    .toc
+                    .push((id, caption.to_string(), chapter.depth));
+            }
+        }
 
-            w.write(
-                XmlEvent::start_element("meta")
-                    .attr("refines", &refines)
-                    .attr("property", "display-seq"),
-            )?;
-            w.write(XmlEvent::characters(&format!("{}", seq)))?;
-            w.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+    /// Reads, stitches, splits and converts the images of every page on `jobs` threads, which
+    /// takes most of the time of a build, and returns them in the order of the spine.
+    fn prepare_images(&self, cx: &Context) -> Result<Vec<Vec<PageImage>>> {
+        let pages = self
+            .book
+            .chapter
+            .iter()
+            .enumerate()
+            .flat_map(|(i, chapter)| {
+                let pages = chapter.page.iter().enumerate();
+                pages.map(move |(j, page)| (page, self.book.is_cover(i, j)))
+            })
+            .collect::<Vec<_>>();
+        let jobs = self
+            .options
+            .jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get);
+        let chunk_size = pages.len().div_ceil(jobs).max(1);
+
+        info!("preparing {} image(s) on {jobs} thread(s)", pages.len());
+
+        // prepare them on this thread if there is one job, as on targets without threads
+        if jobs == 1 {
+            return pages
+                .iter()
+                .map(|(page, cover)| self.prepare_image(cx, page, *cover))
+                .collect();
+        }
+
+        let span = Span::current();
+        std::thread::scope(|scope| {
+            let handles = pages
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let span = span.clone();
+                    scope.spawn(move || {
+                        let _enter = span.enter();
+                        chunk
+                            .iter()
+                            .map(|(page, cover)| self.prepare_image(cx, page, *cover))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            // keep the order of the pages, and report the error of the first one that failed
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    /// Prepares the image of `page`, or the images of its halves in the reading order if it is
+    /// split into two.
+    #[instrument(name = "image", level = "debug", skip_all, fields(page = %page))]
+    fn prepare_image(&self, cx: &Context, page: &Page, cover: bool) -> Result<Vec<PageImage>> {
+        let (src, width, height) = if page.join.is_empty() {
+            let src = self
+                .sources
+                .get(self.root.join(self.book.source_path(&page.src)))?;
+            let Probe {
+                width,
+                height,
+                media_type,
+            } = self.probe(&src)?;
+
+            // packaged by its content, but readers that trust the name would fail on the original
+            let named = mime_guess::from_path(src.path()).first_or_octet_stream();
+            if media_type != named.essence_str() {
+                cx.warn(tr!(
+                    "`{page}` is `{media_type}` but named as `{named}`",
+                    "`{page}` は `{media_type}` ですが `{named}` の名前が付いています"
+                ));
+            }
+
+            let orientation = self.book.rendition.orientation;
+            let split = page.split.unwrap_or(self.book.rendition.split);
+            if split == Split::Auto
+                && !cover
+                && orientation != Orientation::Landscape
+                && height < width
+            {
+                debug!("splitting {page} into two");
+                return self
+                    .split_page(&src, width, height)?
+                    .into_iter()
+                    .map(|(src, width, height)| self.finish_image(page, src, width, height))
+                    .collect();
+            }
+
+            match orientation {
+                Orientation::Landscape if width < height => cx.warn(tr!(
+                    "`{page}` is a portrait page",
+                    "`{page}` は縦長のページです"
+                )),
+                Orientation::Portrait if height < width => cx.warn(tr!(
+                    "`{page}` is a landscape page",
+                    "`{page}` は横長のページです"
+                )),
+                _ => {}
+            }
+
+            (src, width, height)
+        } else {
+            self.join_pages(&page.join)?
+        };
+
+        Ok(vec![self.finish_image(page, src, width, height)?])
+    }
+
+    /// Returns the image of `page` at `src` of `width` by `height`, scaled and converted as the
+    /// project asks, with the JPEG fallback it needs, or a blank image of the same dimensions when
+    /// the build skips images.
+    fn finish_image(
+        &self,
+        page: &Page,
+        src: Resource,
+        width: u32,
+        height: u32,
+    ) -> Result<PageImage> {
+        let images = &self.book.images;
+        let (scaled_width, scaled_height) = fit(width, height, images.max_width, images.max_height);
+        let src = if self.options.skip_images {
+            placeholder(scaled_width, scaled_height)?
+        } else {
+            // formats that reading systems need not support are converted if the project asks
+            let (media_type, _) = image_type(&src);
+            let format = if CORE_IMAGE_TYPES.contains(&media_type.as_str()) {
+                images.format
+            } else {
+                images.format.or(images.transcode)
+            };
+            let size = (width, height);
+            self.process_image(page, src, size, (scaled_width, scaled_height), format)?
+        };
+        let (width, height) = (scaled_width, scaled_height);
+
+        let (media_type, _) = image_type(&src);
+        let fallback = if CORE_IMAGE_TYPES.contains(&media_type.as_str()) {
+            None
+        } else {
+            let recipe = || Ok(vec!["jpeg".to_string(), src.hash()?]);
+            Some(self.derive(recipe, "jpg", || {
+                debug!("encoding a JPEG fallback for {page}");
+                encode_jpeg(&src)
+            })?)
+        };
+
+        Ok(PageImage {
+            src,
+            width,
+            height,
+            fallback,
+        })
+    }
+
+    #[instrument(name = "page", level = "debug", skip_all, fields(page = %page))]
+    fn build_page(
+        &self,
+        cx: &mut Context,
+        chapter: &Chapter,
+        page: &Page,
+        image: PageImage,
+        cover: bool,
+        part: Option<usize>,
+    ) -> Result<String> {
+        debug!("building page from {page}");
+
+        let PageImage {
+            src,
+            width,
+            height,
+            fallback,
+        } = image;
+
+        let image_id = cx.package.add_image(src, cover)?;
+        let fallback_id = match (&cx.package.manifest[&image_id].fallback, fallback) {
+            // an identical image shared with an earlier page
+            (Some(id), _) => Some(id.clone()),
+            (None, Some(fallback)) => Some(cx.package.add_fallback(&image_id, fallback)),
+            (None, None) => None,
+        };
+        let image = cx.package.manifest.get(&image_id).unwrap();
+
+        let mut file = Vec::new();
+
+        let eol = self.options.line_ending.as_str();
+        write!(file, r#"<?xml version="1.0" encoding="utf-8"?>{eol}"#)?;
+        write!(file, r#"<!DOCTYPE html>{eol}"#)?;
+
+        let mut writer = EventWriter::new_with_config(
+            file,
+            self.options
+                .emitter_config()
+                .write_document_declaration(false),
+        );
+
+        writer.write(
+            XmlEvent::start_element("html")
+                .default_ns("http://www.w3.org/1999/xhtml")
+                .ns("epub", "http://www.idpf.org/2007/ops")
+                .attr("xml:lang", &self.book.metadata.language),
+        )?;
+
+        writer.write(XmlEvent::start_element("head"))?;
+
+        writer.write(XmlEvent::start_element("meta").attr("charset", "UTF-8"))?;
+        writer.write(XmlEvent::end_element())?; // meta
+
+        writer.write(XmlEvent::start_element("title"))?;
+        writer.write(XmlEvent::characters(&cx.title))?;
+        writer.write(XmlEvent::end_element())?; // title
+
+        let styles = if chapter.style.is_empty() {
+            cx.package.styles.clone()
+        } else {
+            chapter
+                .style
+                .iter()
+                .map(|href| cx.package.style_ids[href].clone())
+                .collect()
+        };
+        for id in &styles {
+            if let Some(css) = cx.package.inline_styles.get(id) {
+                let css = self.options.style_text(css);
+                let mut style = XmlEvent::start_element("style").attr("type", "text/css");
+                if let Some(media) = cx.package.style_media.get(id) {
+                    style = style.attr("media", media);
+                }
+                writer.write(style)?;
+                writer.write(XmlEvent::characters(&css))?;
+                writer.write(XmlEvent::end_element())?; // style
+                continue;
+            }
+
+            let item = cx.package.manifest.get(id).unwrap();
+            let href = format!("../{}", item.href);
+            let mut link = XmlEvent::start_element("link")
+                .attr("rel", "stylesheet")
+                .attr("type", item.media_type.as_str())
+                .attr("href", &href);
+            if let Some(media) = cx.package.style_media.get(id) {
+                link = link.attr("media", media);
+            }
+            writer.write(link)?;
+            writer.write(XmlEvent::end_element())?; // link
+        }
+
+        for id in &cx.package.scripts {
+            let item = cx.package.manifest.get(id).unwrap();
+            writer.write(
+                XmlEvent::start_element("script")
+                    .attr("type", item.media_type.as_str())
+                    .attr("src", &format!("../{}", item.href)),
+            )?;
+            // an empty script element must not be self-closing in HTML
+            writer.write(XmlEvent::characters(""))?;
+            writer.write(XmlEvent::end_element())?; // script
+        }
+
+        writer.write(
+            XmlEvent::start_element("meta")
+                .attr("name", "viewport")
+                .attr("content", &Viewport { width, height }.to_string()),
+        )?;
+        writer.write(XmlEvent::end_element())?; // meta
+
+        writer.write(XmlEvent::end_element())?; // head
+
+        let class = chapter
+            .class
+            .iter()
+            .chain(&page.class)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        // a page promoted to the cover is a cover whatever its chapter is
+        let kind = if cover && !chapter.cover {
+            Some(Kind::Cover)
+        } else {
+            chapter.kind()
+        };
+
+        let mut event = XmlEvent::start_element("body");
+        if let Some(kind) = &kind {
+            event = event.attr("epub:type", kind.as_ref());
+        }
+        if !class.is_empty() {
+            event = event.attr("class", &class);
+        }
+        writer.write(event)?;
+
+        let mut event = XmlEvent::start_element("div").attr("class", "main");
+        if page.audio.is_some() {
+            event = event.attr("id", OVERLAY_TARGET);
+        }
+        writer.write(event)?;
+
+        writer.write(
+            XmlEvent::start_element("svg")
+                .default_ns("http://www.w3.org/2000/svg")
+                .ns("xlink", "http://www.w3.org/1999/xlink")
+                .attr("version", "1.1")
+                .attr("width", "100%")
+                .attr("height", "100%")
+                .attr("viewBox", &format!("0 0 {width} {height}")),
+        )?;
+        writer.write(
+            XmlEvent::start_element("image")
+                .attr("width", &width.to_string())
+                .attr("height", &height.to_string())
+                .attr("xlink:href", &format!("../{}", image.href)),
+        )?;
+        writer.write(XmlEvent::end_element())?; // image
+
+        // the links and panels of a split page are placed on the whole of it
+        let links = if part.is_none() { &page.link[..] } else { &[] };
+        for link in links {
+            let href = match &link.target {
+                Target::Page(index) => match self.page_ids.get(index - 1) {
+                    Some(id) => format!("{id}.xhtml"),
+                    None => {
+                        cx.warn(tr!(
+                            "`{page}` links to page {index}, which does not exist",
+                            "`{page}` が存在しないページ {index} にリンクしています"
+                        ));
+                        continue;
+                    }
+                },
+                Target::Href(href) => href.clone(),
+            };
+
+            writer.write(XmlEvent::start_element("a").attr("xlink:href", &href))?;
+            writer.write(
+                XmlEvent::start_element("rect")
+                    .attr("x", &link.x.to_string())
+                    .attr("y", &link.y.to_string())
+                    .attr("width", &link.width.to_string())
+                    .attr("height", &link.height.to_string())
+                    .attr("fill", "#000")
+                    .attr("fill-opacity", "0"),
+            )?;
+            writer.write(XmlEvent::end_element())?; // rect
+            writer.write(XmlEvent::end_element())?; // a
+        }
+
+        writer.write(XmlEvent::end_element())?; // svg
+        writer.write(XmlEvent::end_element())?; // div
+
+        let panel = page.panel.as_ref().or(chapter.panel.as_ref());
+        if let Some(panel) =
+            panel.filter(|panel| part.is_none() || !matches!(panel, Panel::Sidecar(_)))
+        {
+            let profile = self.options.profile;
+            if profile.quirks().panel_view {
+                let regions = self.build_panels(cx, page, panel, width, height)?;
+                if !regions.is_empty() {
+                    let href = format!("../{}", image.href);
+                    panel::write_magnification(&mut writer, &href, width, height, &regions)?;
+                    cx.package.panel_view = true;
+                }
+            } else {
+                debug!("ignoring the panels of {page} for {}", profile.name());
+            }
+        }
+
+        writer.write(XmlEvent::end_element())?; // body
+        writer.write(XmlEvent::end_element())?; // html
+
+        let src = Resource::generated("xhtml", &writer.into_inner())?;
+        let id = cx.package.add_page(src, cover, part);
+
+        let direction = self.book.rendition.direction;
+        let placement = match (part, page.spread) {
+            // the halves of a double page make a spread of their own
+            (Some(0), _) => Placement::next(None, direction),
+            (Some(_), _) => Placement::next(cx.package.placement, direction),
+            (None, Some(placement)) => placement,
+            (None, None) if cover || !page.join.is_empty() => Placement::Center,
+            (None, None) => Placement::next(cx.package.placement, direction),
+        };
+        cx.package.placement = Some(placement);
+        let props = format!("rendition:page-spread-{}", placement.as_ref());
+        cx.package.add_spine(id.clone(), Some(props));
+        cx.package
+            .viewports
+            .insert(id.clone(), Viewport { width, height });
+        cx.package.images.push((page.to_string(), width, height));
+
+        // the narration of a split page starts with its first half
+        let overlay_id = match &page.audio {
+            Some(audio) if matches!(part, None | Some(0)) => {
+                Some(self.build_overlay(cx, &id, audio)?)
+            }
+            _ => None,
+        };
+
+        cx.package.page_images.push(image_id.clone());
+        cx.package.pages.push((
+            page.to_string(),
+            [Some(id.clone()), Some(image_id), fallback_id, overlay_id]
+                .into_iter()
+                .flatten()
+                .collect(),
+        ));
+        if cover {
+            cx.package.cover = Some(Viewport { width, height });
+        }
+
+        Ok(id)
+    }
+
+    /// Builds the media overlay that narrates the page `id` with `audio`.
+    fn build_overlay(&self, cx: &mut Context, id: &str, audio: &Audio) -> Result<String> {
+        let src = self.root.join(&audio.src);
+        if !self.sources.is_file(&src) {
+            anyhow::bail!("`{}` does not exist", audio.src.display());
+        }
+
+        let audio_id = cx.package.add_audio(self.sources.get(src)?);
+        let audio_href = &cx.package.manifest.get(&audio_id).unwrap().href;
+        let page_href = &cx.package.manifest.get(id).unwrap().href;
+
+        let mut writer = EventWriter::new_with_config(Vec::new(), self.options.emitter_config());
+
+        writer.write(
+            XmlEvent::start_element("smil")
+                .default_ns("http://www.w3.org/ns/SMIL")
+                .ns("epub", "http://www.idpf.org/2007/ops")
+                .attr("version", "3.0"),
+        )?;
+        writer.write(XmlEvent::start_element("body"))?;
+        writer.write(XmlEvent::start_element("par").attr("id", "par1"))?;
+        writer.write(
+            XmlEvent::start_element("text")
+                .attr("src", &format!("../{page_href}#{OVERLAY_TARGET}")),
+        )?;
+        writer.write(XmlEvent::end_element())?; // text
+        writer.write(
+            XmlEvent::start_element("audio")
+                .attr("src", &format!("../{audio_href}"))
+                .attr("clipBegin", &format!("{}s", audio.begin))
+                .attr("clipEnd", &format!("{}s", audio.end)),
+        )?;
+        writer.write(XmlEvent::end_element())?; // audio
+        writer.write(XmlEvent::end_element())?; // par
+        writer.write(XmlEvent::end_element())?; // body
+        writer.write(XmlEvent::end_element())?; // smil
+
+        let src = Resource::generated("smil", &writer.into_inner())?;
+        Ok(cx.package.add_overlay(id, src, audio.duration()))
+    }
+
+    /// Returns the regions of the panels of `page` that lie within the page.
+    fn build_panels(
+        &self,
+        cx: &Context,
+        page: &Page,
+        panel: &Panel,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<Region>> {
+        let regions = match panel {
+            Panel::Grid { columns, rows } => {
+                let direction = self.book.rendition.direction;
+                panel::grid(*columns, *rows, width, height, direction)?
+            }
+            Panel::Sidecar(path) => {
+                let src = self.sources.get(self.root.join(path))?;
+                let regions = panel::read(src.path(), src.open()?)?;
+                regions
+            }
+        };
+
+        Ok(regions
+            .into_iter()
+            .enumerate()
+            .filter(|(i, region)| {
+                let fits = region.fits(width, height);
+                if !fits {
+                    cx.warn(tr!(
+                        "panel {} of `{page}` lies outside the page",
+                        "`{page}` のコマ {} がページの外にあります",
+                        i + 1
+                    ));
+                }
+                fits
+            })
+            .map(|(_, region)| region)
+            .collect())
+    }
+
+    /// Stitches `pages` side by side into a single spread image, in the reading direction.
+    fn join_pages(&self, pages: &[PathBuf]) -> Result<(Resource, u32, u32)> {
+        let mut sources = pages
+            .iter()
+            .map(|src| self.sources.get(self.root.join(self.book.source_path(src))))
+            .collect::<Result<Vec<_>>>()?;
+        if self.book.rendition.direction == Direction::RightToLeft {
+            sources.reverse();
+        }
+
+        // keep the format of the sources, so that photos are not blown up as PNG
+        let ext = pages[0]
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("png");
+
+        let quality = self.book.images.quality.unwrap_or(JPEG_QUALITY);
+        let recipe = || {
+            let mut recipe = vec!["join".to_string()];
+            for source in &sources {
+                recipe.push(source.hash()?);
+            }
+            recipe.push(quality.to_string());
+            Ok(recipe)
+        };
+        let src = self.derive(recipe, ext, || stitch(&sources, ext, quality))?;
+
+        let Probe { width, height, .. } = Probe::read_from(src.path(), src.open()?)?;
+        Ok((src, width, height))
+    }
+
+    /// Scales the image of `page` at `src` from `size` down to `scaled`, and converts it to
+    /// `format`, unless it is already so.
+    fn process_image(
+        &self,
+        page: &Page,
+        src: Resource,
+        size: (u32, u32),
+        scaled: (u32, u32),
+        format: Option<PageFormat>,
+    ) -> Result<Resource> {
+        let images = &self.book.images;
+        let (source_format, format, ext) = target_format(&src, format);
+        if size == scaled && source_format == Some(format) {
+            return Ok(src);
+        }
+
+        let quality = images.quality.unwrap_or(JPEG_QUALITY);
+        let recipe = || {
+            Ok(vec![
+                "scale".to_string(),
+                src.hash()?,
+                format!("{}x{}", scaled.0, scaled.1),
+                ext.to_string(),
+                quality.to_string(),
+            ])
+        };
+        self.derive(recipe, ext, || {
+            debug!("scaling {page} to {}x{} as {ext}", scaled.0, scaled.1);
+            scale(&src, scaled, format, quality)
+        })
+    }
+
+    /// Cuts the double page at `src` of `width` by `height` down the middle, and returns the halves
+    /// in the reading direction.
+    fn split_page(
+        &self,
+        src: &Resource,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<(Resource, u32, u32)>> {
+        let half = width / 2;
+        let mut halves = [("left", 0, half), ("right", half, width - half)];
+        if self.book.rendition.direction == Direction::RightToLeft {
+            halves.reverse();
+        }
+
+        let ext = src
+            .path()
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("png");
+        let quality = self.book.images.quality.unwrap_or(JPEG_QUALITY);
+        halves
+            .into_iter()
+            .map(|(side, x, width)| {
+                // the halves are blanked anyway
+                if self.options.skip_images {
+                    let src = self.sources.get(src.path().to_path_buf())?;
+                    return Ok((src, width, height));
+                }
+
+                let recipe = || {
+                    Ok(vec![
+                        "split".to_string(),
+                        side.to_string(),
+                        src.hash()?,
+                        quality.to_string(),
+                    ])
+                };
+                let half = self.derive(recipe, ext, || crop(src, x, width, ext, quality))?;
+                Ok((half, width, height))
+            })
+            .collect()
+    }
+
+    /// Probes the image of `src`, through the cache if it is a file.
+    fn probe(&self, src: &Resource) -> Result<Probe> {
+        match src {
+            #[cfg(feature = "fs")]
+            Resource::PathBuf(path) => self.cache.lock().unwrap().probe(path),
+            _ => Probe::read_from(src.path(), src.open()?),
+        }
+    }
+
+    /// Returns the image made by `make` from the inputs named by `recipe`, from the image cache if
+    /// it has been made before.
+    #[cfg(feature = "fs")]
+    fn derive(
+        &self,
+        recipe: impl FnOnce() -> Result<Vec<String>>,
+        ext: &str,
+        make: impl FnOnce() -> Result<Resource>,
+    ) -> Result<Resource> {
+        if !self.images.is_enabled() {
+            return make();
+        }
+
+        let recipe = recipe()?;
+        let key = ImageCache::key(&recipe.iter().map(String::as_str).collect::<Vec<_>>());
+        if let Some(path) = self.images.get(&key, ext) {
+            debug!("using cached image {}", path.display());
+            return Ok(path.into());
+        }
+
+        let image = make()?;
+        match self.images.insert(&key, ext, image.path()) {
+            Ok(path) => Ok(path.into()),
+            Err(e) => {
+                warn!("failed to cache an image: {e:#}");
+                Ok(image)
+            }
+        }
+    }
+
+    /// Returns the image made by `make`, as there is no image cache without the file system.
+    #[cfg(not(feature = "fs"))]
+    fn derive(
+        &self,
+        _recipe: impl FnOnce() -> Result<Vec<String>>,
+        _ext: &str,
+        make: impl FnOnce() -> Result<Resource>,
+    ) -> Result<Resource> {
+        make()
+    }
+}
+
+struct Item {
+    media_type: String,
+    href: String,
+    properties: Option<String>,
+    /// Manifest id of the item to use when the reading system cannot render this one.
+    fallback: Option<String>,
+    /// Manifest id of the media overlay that narrates this item.
+    media_overlay: Option<String>,
+    src: Resource,
+}
+
+/// Content of an item, read from a file or held in memory.
+pub(super) enum Resource {
+    #[cfg(feature = "fs")]
+    PathBuf(PathBuf),
+    #[cfg(feature = "fs")]
+    TempPath(TempPath),
+    /// Content given in memory, with the path it stands for.
+    Bytes(PathBuf, Arc<[u8]>),
+}
+
+/// Reader of the content of a resource, in which image decoders seek.
+pub(super) trait Input: BufRead + Seek {}
+
+impl<T: BufRead + Seek> Input for T {}
+
+impl Resource {
+    /// Holds `data` generated for the book with the extension `ext`, in a temporary file.
+    #[cfg(feature = "fs")]
+    fn generated(ext: &str, data: &[u8]) -> Result<Self> {
+        let mut file = tempfile::Builder::new()
+            .suffix(&format!(".{ext}"))
+            .tempfile()?;
+        file.write_all(data)?;
+        Ok(file.into_temp_path().into())
+    }
+
+    /// Holds `data` generated for the book with the extension `ext`, in memory.
+    #[cfg(not(feature = "fs"))]
+    fn generated(ext: &str, data: &[u8]) -> Result<Self> {
+        Ok(Self::Bytes(format!("generated.{ext}").into(), data.into()))
+    }
+
+    /// Returns the path to the content, or the path it stands for if it is held in memory.
+    pub(super) fn path(&self) -> &Path {
+        match self {
+            #[cfg(feature = "fs")]
+            Self::PathBuf(path) => path.as_path(),
+            #[cfg(feature = "fs")]
+            Self::TempPath(path) => path.as_ref(),
+            Self::Bytes(path, _) => path.as_path(),
+        }
+    }
+
+    /// Returns whether the content can be read.
+    fn exists(&self) -> bool {
+        match self {
+            #[cfg(feature = "fs")]
+            Self::PathBuf(_) | Self::TempPath(_) => self.path().is_file(),
+            Self::Bytes(..) => true,
+        }
+    }
+
+    /// Opens the content to read.
+    pub(super) fn open(&self) -> Result<Box<dyn Input + '_>> {
+        match self {
+            #[cfg(feature = "fs")]
+            Self::PathBuf(_) | Self::TempPath(_) => {
+                let path = self.path();
+                let file = File::open(path)
+                    .with_context(|| format!("failed to open `{}`", path.display()))?;
+                Ok(Box::new(BufReader::new(file)))
+            }
+            Self::Bytes(_, data) => Ok(Box::new(Cursor::new(&data[..]))),
+        }
+    }
+
+    /// Returns the SHA-256 hash of the content in hex.
+    fn hash(&self) -> Result<String> {
+        match self {
+            #[cfg(feature = "fs")]
+            Self::PathBuf(_) | Self::TempPath(_) => hash_file(self.path()),
+            Self::Bytes(_, data) => Ok(format!("{:x}", Sha256::digest(data))),
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+impl From<&Path> for Resource {
+    fn from(path: &Path) -> Self {
+        Self::PathBuf(path.to_path_buf())
+    }
+}
+
+#[cfg(feature = "fs")]
+impl From<PathBuf> for Resource {
+    fn from(path: PathBuf) -> Self {
+        Self::PathBuf(path)
+    }
+}
+
+#[cfg(feature = "fs")]
+impl From<TempPath> for Resource {
+    fn from(path: TempPath) -> Self {
+        Self::TempPath(path)
+    }
+}
+
+/// Contents of the source files given in memory by their paths, which are read instead of the
+/// files there.
+#[derive(Default)]
+pub(crate) struct Sources(HashMap<PathBuf, Arc<[u8]>>);
+
+impl Sources {
+    /// Gives `data` as the content of the file at `path`.
+    pub(crate) fn insert(&mut self, path: PathBuf, data: Vec<u8>) {
+        self.0.insert(path, data.into());
+    }
+
+    /// Returns whether the source at `path` is given in memory or exists as a file.
+    fn is_file(&self, path: &Path) -> bool {
+        self.0.contains_key(path) || cfg!(feature = "fs") && path.is_file()
+    }
+
+    /// Returns the source at `path`, from memory if it is given so.
+    fn get(&self, path: PathBuf) -> Result<Resource> {
+        match self.0.get(&path) {
+            Some(data) => Ok(Resource::Bytes(path, Arc::clone(data))),
+            #[cfg(feature = "fs")]
+            None => Ok(Resource::PathBuf(path)),
+            #[cfg(not(feature = "fs"))]
+            None => anyhow::bail!("`{}` is not given", path.display()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ItemRef {
+    id_ref: String,
+    linear: bool,
+    properties: Option<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct Context {
+    book: Arc<Book>,
+    options: Arc<BuildOptions>,
+    title: String,
+    package: Package,
+    warnings: AtomicUsize,
+}
+
+/// Entry of the EPub file.
+pub struct Entry<'a> {
+    /// Path of the entry in the container.
+    pub name: String,
+
+    /// Media type of the content, such as `application/xhtml+xml`.
+    pub media_type: &'a str,
+
+    /// Content of the entry.
+    pub reader: Box<dyn Read + 'a>,
+}
+
+/// Image of a page ready to be added to the package.
+struct PageImage {
+    src: Resource,
+    width: u32,
+    height: u32,
+    /// JPEG rendition for reading systems that cannot render `src`.
+    fallback: Option<Resource>,
+}
+
+/// Items accumulated while building the book.
+#[derive(Default)]
+struct Package {
+    manifest: Map<String, Item>,
+    spine: Vec<ItemRef>,
+    /// Hrefs of the items in the manifest.
+    hrefs: HashSet<String>,
+    /// Manifest ids of the styles linked from the pages of the chapters that select none.
+    styles: Vec<String>,
+    /// Manifest ids of the styles by their hrefs in the project file.
+    style_ids: Map<String, String>,
+    /// Media queries of the styles by their manifest ids.
+    style_media: HashMap<String, String>,
+    /// Styles written into the pages instead of the manifest by their ids.
+    inline_styles: HashMap<String, String>,
+    scripts: Vec<String>,
+    image_index: usize,
+    page_index: usize,
+    cover_index: usize,
+    /// Entries of the table of contents as the manifest ids of the pages, the captions and the
+    /// depths of the chapters.
+    toc: Vec<(String, String, usize)>,
+    /// Captions and depths of the parts without pages waiting for the page that follows them.
+    pending_toc: Vec<(String, usize)>,
+    viewports: Map<String, Viewport>,
+    images: Vec<(String, u32, u32)>,
+    pages: Vec<(String, Vec<String>)>,
+    landmarks: Map<Kind, (String, String)>,
+    /// Manifest ids of the audio files by their paths.
+    audio: HashMap<PathBuf, String>,
+    /// Durations of the media overlays in seconds by their manifest ids.
+    overlays: Map<String, f64>,
+    panel_view: bool,
+    cover: Option<Viewport>,
+    /// Manifest ids of the images of the pages in the order of the spine.
+    page_images: Vec<String>,
+    /// How the images are named in the package.
+    name_by: NameBy,
+    /// Side of the spread the last page in the spine is placed on.
+    placement: Option<Placement>,
+}
+
+impl Package {
+    /// Adds `item` to the manifest as `id`, or as `{id}-{n}` if `id` is taken, and returns the id
+    /// it is added as. Its href is numbered apart the same way so that no file is overwritten.
+    fn insert(&mut self, id: String, mut item: Item) -> String {
+        let id = (1..)
+            .map(|n| numbered(&id, n))
+            .find(|id| !self.manifest.contains_key(id) && !RESERVED_IDS.contains(&id.as_str()))
+            .unwrap();
+
+        let (stem, ext) = match item.href.rfind('.') {
+            Some(i) if !item.href[i..].contains('/') => item.href.split_at(i),
+            _ => (item.href.as_str(), ""),
+        };
+        item.href = (1..)
+            .map(|n| format!("{}{ext}", numbered(stem, n)))
+            .find(|href| !self.hrefs.contains(href) && !RESERVED_HREFS.contains(&href.as_str()))
+            .unwrap();
+
+        self.hrefs.insert(item.href.clone());
+        self.manifest.insert(id.clone(), item);
+        id
+    }
+
+    /// Adds `item` to the manifest as `id`, which is referred to by name and cannot be numbered
+    /// apart from another item.
+    fn insert_named(&mut self, id: &str, item: Item) -> Result<()> {
+        if self.manifest.contains_key(id) || RESERVED_IDS.contains(&id) {
+            anyhow::bail!(tr!(
+                "`{id}` is used by more than one item of the manifest",
+                "`{id}` がマニフェストの複数のアイテムで使われています"
+            ));
+        }
+
+        let added = self.insert(id.to_string(), item);
+        debug_assert_eq!(added, id);
+        Ok(())
+    }
+
+    /// Adds the image of a page, or returns the id of the same image added before when the images
+    /// are named by their hashes.
+    fn add_image(&mut self, src: impl Into<Resource>, cover: bool) -> Result<String> {
+        let src = src.into();
+        let (mime, ext) = image_type(&src);
+
+        // only the first image of the cover is the cover image
+        let (id, properties) = if cover && !self.manifest.contains_key(COVER_ID) {
+            (COVER_ID.to_string(), Some("cover-image".to_string()))
+        } else if self.name_by == NameBy::Hash {
+            let hash = src.hash()?;
+            let id = format!("i-{}", &hash[..IMAGE_HASH_LEN]);
+            if self.manifest.contains_key(&id) {
+                return Ok(id);
+            }
+            (id, None)
+        } else {
+            self.image_index += 1;
+            (format!("i-{:04}", self.image_index), None)
+        };
+
+        let item = Item {
+            media_type: mime.to_string(),
+            href: format!("image/{id}{ext}"),
+            properties,
+            fallback: None,
+            media_overlay: None,
+            src,
+        };
+
+        Ok(self.insert(id, item))
+    }
+
+    /// Adds the high-resolution image of the cover, which is not displayed by itself.
+    fn add_archival_cover(&mut self, src: impl Into<Resource>) -> Result<()> {
+        let src = src.into();
+        let (mime, ext) = image_type(&src);
+
+        let item = Item {
+            media_type: mime.to_string(),
+            href: format!("image/{ARCHIVAL_COVER_ID}{ext}"),
+            properties: None,
+            fallback: None,
+            media_overlay: None,
+            src,
+        };
+
+        self.insert_named(ARCHIVAL_COVER_ID, item)
+    }
+
+    /// Adds a JPEG rendition of the image `id` to fall back on.
+    fn add_fallback(&mut self, id: &str, src: impl Into<Resource>) -> String {
+        let fallback = format!("{id}-fallback");
+
+        let item = Item {
+            media_type: "image/jpeg".to_string(),
+            href: format!("image/{fallback}.jpg"),
+            properties: None,
+            fallback: None,
+            media_overlay: None,
+            src: src.into(),
+        };
+
+        let fallback = self.insert(fallback, item);
+        self.manifest.get_mut(id).unwrap().fallback = Some(fallback.clone());
+
+        fallback
+    }
+
+    /// Adds a page, or the `part`th half of a page split into two, which shares the number of the
+    /// first half so that links to the pages after it keep their targets.
+    fn add_page(&mut self, src: impl Into<Resource>, cover: bool, part: Option<usize>) -> String {
+        let index = if cover {
+            &mut self.cover_index
+        } else {
+            &mut self.page_index
+        };
+        let id = match part {
+            Some(part @ 1..) => format!("{}-{}", page_id(cover, *index), part + 1),
+            _ => {
+                *index += 1;
+                page_id(cover, *index)
+            }
+        };
+
+        let item = Item {
+            media_type: "application/xhtml+xml".to_string(),
+            href: format!("xhtml/{id}.xhtml"),
+            properties: Some(if self.scripts.is_empty() {
+                "svg".to_string()
+            } else {
+                "scripted svg".to_string()
+            }),
+            fallback: None,
+            media_overlay: None,
+            src: src.into(),
+        };
+
+        self.insert(id, item)
+    }
+
+    /// Adds the audio file of `src` unless it has been added already.
+    fn add_audio(&mut self, src: Resource) -> String {
+        let path = src.path().to_path_buf();
+        if let Some(id) = self.audio.get(&path) {
+            return id.clone();
+        }
+
+        let mime = mime_guess::from_path(&path).first_or_octet_stream();
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| format!(".{e}"))
+            .unwrap_or_default();
+        let id = format!("a-{:04}", self.audio.len() + 1);
+
+        let item = Item {
+            media_type: mime.to_string(),
+            href: format!("audio/{id}{ext}"),
+            properties: None,
+            fallback: None,
+            media_overlay: None,
+            src,
+        };
+
+        let id = self.insert(id, item);
+        self.audio.insert(path, id.clone());
+
+        id
+    }
+
+    /// Adds a media overlay of `duration` seconds that narrates the page `id`.
+    fn add_overlay(&mut self, id: &str, src: impl Into<Resource>, duration: f64) -> String {
+        let overlay = format!("{id}-overlay");
+
+        let item = Item {
+            media_type: "application/smil+xml".to_string(),
+            href: format!("smil/{id}.smil"),
+            properties: None,
+            fallback: None,
+            media_overlay: None,
+            src: src.into(),
+        };
+
+        let overlay = self.insert(overlay, item);
+        self.manifest.get_mut(id).unwrap().media_overlay = Some(overlay.clone());
+        self.overlays.insert(overlay.clone(), duration);
+
+        overlay
+    }
+
+    fn add_spine(&mut self, id_ref: String, properties: Option<String>) {
+        self.spine.push(ItemRef {
+            id_ref,
+            linear: true,
+            properties,
+        })
+    }
+}
+
+impl Context {
+    /// Logs a warning about the book, which fails the build in strict mode.
+    fn warn(&self, message: impl std::fmt::Display) {
+        warn!("{message}");
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fails if any warning has been issued in strict mode.
+    fn check_strict(&self) -> Result<()> {
+        let warnings = self.warnings.load(Ordering::Relaxed);
+        if warnings == 0 || !(self.options.strict || self.book.strict) {
+            return Ok(());
+        }
+
+        Err(Failure::new(
+            Status::Strict,
+            tr!(
+                "{warnings} warning(s) are treated as errors in strict mode",
+                "strict モードのため {warnings} 件の警告をエラーとして扱います"
+            ),
+        )
+        .into())
+    }
+
+    #[cfg(feature = "fs")]
+    fn lockfile(&self, project: &Project) -> Result<Lockfile> {
+        let manifest = hash_file(project.path())?;
+
+        let mut source = Vec::new();
+        for item in self.package.manifest.values() {
+            if let Resource::PathBuf(path) = &item.src {
+                source.push(Source {
+                    path: path.strip_prefix(project.root()).unwrap_or(path).into(),
+                    sha256: hash_file(path)?,
+                    entry: format!("item/{}", item.href),
+                });
+            }
+        }
+
+        Ok(Lockfile::new(manifest, source))
+    }
+
+    /// Writes the EPub file into the directory `path` and returns its path.
+    ///
+    /// The entries whose digests in `cache` are unchanged since the file was last written are
+    /// copied from it without being compressed again.
+    #[cfg(feature = "fs")]
+    #[instrument(name = "write", skip_all)]
+    fn write_to(&self, path: impl AsRef<Path>, cache: &mut EntryCache) -> Result<PathBuf> {
+        let file_name = self.file_name();
+        let path = path.as_ref().join(&file_name);
+
+        // write next to the destination and move it into place only once it is complete, so that
+        // a failed build never leaves a truncated file behind
+        let temp = TempPath::from_path(path.with_file_name(format!(".{file_name}.tmp")));
+        let file = File::create(&temp)
+            .with_context(|| format!("failed to create `{}`", temp.display()))?;
+
+        // a file changed since it was written may no longer hold what the digests describe
+        let previous = cache
+            .entries(&path)
+            .and_then(|digests| Some((digests, ZipArchive::new(File::open(&path).ok()?).ok()?)));
+        let (writer, digests) = self.write_entries(
+            BufWriter::with_capacity(BUFFER_SIZE, file),
+            cache.is_enabled(),
+            previous,
+        )?;
+        writer.into_inner()?.flush()?;
+        temp.persist(&path)
+            .with_context(|| format!("failed to write `{}`", path.display()))?;
+        cache.insert(&path, digests);
+
+        Ok(path)
+    }
+
+    /// Packages the book into `writer`, which is returned once the container is complete.
+    pub(crate) fn write<W: Write + Seek>(&self, writer: W) -> Result<W> {
+        Ok(self.write_entries(writer, false, None)?.0)
+    }
+
+    /// Packages the book into `writer`, returning the digests of the entries as well if `digest`
+    /// is set.
+    ///
+    /// The entries whose digests match those in `previous` are copied from its file as they are.
+    fn write_entries<W: Write + Seek>(
+        &self,
+        writer: W,
+        digest: bool,
+        mut previous: Option<(&HashMap<String, String>, ZipArchive<File>)>,
+    ) -> Result<(W, HashMap<String, String>)> {
+        self.validate()?;
+
+        let entries: Box<dyn Iterator<Item = _>> = match self.options.format {
+            Format::Epub | Format::Kindle => Box::new(self.entries()),
+            Format::Cbz => Box::new(self.comic_entries()),
+            Format::Pdf => return Ok((self.write_pdf(writer)?, HashMap::new())),
+        };
+
+        let mut zip = ZipWriter::new(writer);
+        let mut digests = HashMap::new();
+
+        let mut buf = vec![0; BUFFER_SIZE];
+        for entry in entries {
+            let mut entry = entry?;
+            if !is_package_path(&entry.name) {
+                anyhow::bail!("`{}` is not a valid entry name", entry.name);
+            }
+            let options = self.entry_options(&entry);
+            if !digest {
+                zip.start_file(entry.name.as_str(), options)?;
+                copy(&mut entry.reader, &mut zip, &mut buf)?;
+                continue;
+            }
+
+            let mut content = Vec::new();
+            entry.reader.read_to_end(&mut content)?;
+            let value = EntryCache::digest(&content, &format!("{options:?}"));
+
+            let unchanged = previous
+                .as_mut()
+                .filter(|(digests, _)| digests.get(&entry.name) == Some(&value))
+                .and_then(|(_, archive)| archive.by_name(&entry.name).ok());
+            match unchanged {
+                Some(file) => {
+                    debug!("reusing the entry {}", entry.name);
+                    zip.raw_copy_file(file)?;
+                }
+                None => {
+                    zip.start_file(entry.name.as_str(), options)?;
+                    zip.write_all(&content)?;
+                }
+            }
+            digests.insert(entry.name, value);
+        }
+
+        Ok((zip.finish()?, digests))
+    }
+
+    /// Yields the entries of the EPub file in order, without packaging them.
+    ///
+    /// The documents are generated and the items opened as the entries are taken, so that they
+    /// can be stored in a container other than the ZIP file as well.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = Result<Entry<'_>>> {
+        type Render = fn(&Context, &mut Vec<u8>) -> Result<()>;
+
+        let mut documents: Vec<(String, &str, Render)> = vec![
+            ("mimetype".to_string(), "text/plain", |_, out| {
+                out.extend_from_slice(b"application/epub+zip");
+                Ok(())
+            }),
+            (
+                "META-INF/container.xml".to_string(),
+                "application/xml",
+                Self::write_container,
+            ),
+            (
+                "item/standard.opf".to_string(),
+                "application/oebps-package+xml",
+                Self::write_package,
+            ),
+            (
+                "item/navigation-documents.xhtml".to_string(),
+                "application/xhtml+xml",
+                Self::write_navigation,
+            ),
+        ];
+        if self.options.ncx {
+            documents.push((
+                format!("item/{NCX_HREF}"),
+                "application/x-dtbncx+xml",
+                Self::write_ncx,
+            ));
+        }
+
+        let documents = documents.into_iter().map(|(name, media_type, render)| {
+            // the mimetype must be exactly the media type of the container
+            let mut buf = match name.as_str() {
+                "mimetype" => Vec::new(),
+                _ => self.options.bom().to_vec(),
+            };
+            render(self, &mut buf)?;
+            Ok(Entry {
+                name,
+                media_type,
+                reader: Box::new(Cursor::new(buf)),
+            })
+        });
+
+        let items = self.package.manifest.values().map(|item| {
+            let mut file = item.src.open()?;
+            let reader: Box<dyn Read> = if item.media_type == "text/css" {
+                let mut css = String::new();
+                file.read_to_string(&mut css)?;
+                let mut buf = self.options.bom().to_vec();
+                buf.extend_from_slice(self.options.style_text(&css).as_bytes());
+                Box::new(Cursor::new(buf))
+            } else if GENERATED_MEDIA_TYPES.contains(&item.media_type.as_str()) {
+                Box::new(self.options.bom().chain(file))
+            } else {
+                Box::new(file)
+            };
+
+            Ok(Entry {
+                name: format!("item/{}", item.href),
+                media_type: &item.media_type,
+                reader,
+            })
+        });
+
+        documents.chain(items)
+    }
+
+    /// Yields the entries of a CBZ file: the images of the pages in the order of the spine, and
+    /// the metadata for comic readers.
+    pub(super) fn comic_entries(&self) -> impl Iterator<Item = Result<Entry<'_>>> {
+        let pages = self.package.page_images.iter().zip(1..).map(|(id, seq)| {
+            let item = &self.package.manifest[id];
+
+            Ok(Entry {
+                name: comic_page_name(seq, &item.href),
+                media_type: &item.media_type,
+                reader: item.src.open()?,
+            })
+        });
+
+        let info = std::iter::once_with(|| {
+            let mut buf = Vec::new();
+            let mut w = EventWriter::new_with_config(&mut buf, self.options.emitter_config());
+            comic::write_comic_info(
+                &mut w,
+                &self.book.metadata,
+                self.book.rendition.direction,
+                self.package.page_images.len(),
+                self.package.cover.is_some(),
+            )?;
+
+            Ok(Entry {
+                name: COMIC_INFO.to_string(),
+                media_type: "application/xml",
+                reader: Box::new(Cursor::new(buf)),
+            })
+        });
+
+        pages.chain(info)
+    }
+
+    /// Writes the images of the pages into `writer` as a PDF file, with the table of contents as
+    /// its bookmarks.
+    fn write_pdf<W: Write>(&self, mut writer: W) -> Result<W> {
+        let pages = self
+            .package
+            .spine
+            .iter()
+            .zip(&self.package.page_images)
+            .map(|(item_ref, image_id)| {
+                let Viewport { width, height } = self.package.viewports[&item_ref.id_ref];
+                let src = &self.package.manifest[image_id].src;
+                let read = Box::new(move || {
+                    let mut buf = Vec::new();
+                    src.open()?.read_to_end(&mut buf)?;
+                    Ok(buf)
+                });
+                pdf::Page {
+                    src: src.path(),
+                    read,
+                    width,
+                    height,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let toc = &self.package.toc;
+        let bookmarks = toc
+            .iter()
+            .zip(toc_levels(toc))
+            .filter_map(|((id, title, _), level)| {
+                let page = self.package.spine.iter().position(|i| &i.id_ref == id)?;
+                Some(pdf::Bookmark { page, title, level })
+            })
+            .collect::<Vec<_>>();
+
+        let metadata = &self.book.metadata;
+        let author = metadata
+            .creator
+            .iter()
+            .map(|creator| creator.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let info = pdf::Info {
+            title: &self.title,
+            author: &author,
+            language: &metadata.language,
+            direction: self.book.rendition.direction,
+            spread: self.book.rendition.spread != Spread::None,
+        };
+
+        pdf::write_pdf(&mut writer, &info, &pages, &bookmarks)?;
+        Ok(writer)
+    }
+
+    /// Returns the name the output file is named after, before it is made safe as a file name.
+    fn book_name(&self) -> String {
+        // the title of a volume is already numbered
+        match (&self.options.name, &self.options.volume) {
+            (Some(name), Some(volume)) => format!("{name} {}", volume.number),
+            (Some(name), None) => name.clone(),
+            (None, _) => self.title.clone(),
+        }
+    }
+
+    /// Warns if the name of the output file has to differ from that of the book to be safe.
+    fn check_file_name(&self) {
+        let name = self.book_name();
+        let sanitized = sanitize_file_name(&name);
+        if sanitized != name {
+            self.warn(tr!(
+                "writing `{name}` as `{sanitized}`, which is safe as a file name",
+                "`{name}` をファイル名として安全な `{sanitized}` として書き出します"
+            ));
+        }
+    }
+
+    /// Returns the name of the output file, which includes the profile when writing in `out_dir`.
+    fn file_name(&self) -> String {
+        let mut file_name = sanitize_file_name(&self.book_name());
+        if let Some(target) = &self.options.target {
+            file_name = format!("{file_name}.{}", sanitize_file_name(target));
+        }
+        if self.options.out_dir.is_some() {
+            file_name = format!("{file_name}.{}", self.options.profile.as_str());
+        }
+        if self.options.is_preview() {
+            file_name.push_str(".preview");
+        }
+        format!("{file_name}.{}", self.options.format.extension())
+    }
+
+    /// Breaks down the size of the EPub file written at `path` by item, category and page.
+    #[cfg(feature = "fs")]
+    fn report(&self, path: &Path) -> Result<Report> {
+        let pages = self
+            .package
+            .pages
+            .iter()
+            .map(|(page, ids)| {
+                let names = ids
+                    .iter()
+                    .map(|id| format!("item/{}", self.package.manifest[id].href))
+                    .collect();
+                (page.clone(), names)
+            })
+            .collect::<Vec<_>>();
+        let pages = match self.options.format {
+            Format::Epub | Format::Kindle | Format::Pdf => pages,
+            Format::Cbz => pages
+                .into_iter()
+                .zip(&self.package.page_images)
+                .zip(1..)
+                .map(|(((page, _), id), seq)| {
+                    let name = comic_page_name(seq, &self.package.manifest[id].href);
+                    (page, vec![name])
+                })
+                .collect(),
+        };
+
+        Report::read(path, &pages)
+    }
+
+    /// Finds the features of the book the reading system of `profile` is known to mishandle.
+    fn lint(&self, profile: Profile) -> Vec<(Rule, String)> {
+        let quirks = profile.quirks();
+        let name = profile.name();
+        let mut findings = Vec::new();
+
+        match (self.package.cover, quirks.cover_size) {
+            (None, _) if quirks.cover_required => findings.push((
+                Rule::MissingCover,
+                format!("{name} requires a cover; mark a chapter with `cover: true`"),
+            )),
+            (Some(cover), Some((width, height)))
+                if cover.width < width || cover.height < height =>
+            {
+                findings.push((
+                    Rule::SmallCover,
+                    format!(
+                        "the cover is {}x{} but {name} requires at least {width}x{height}",
+                        cover.width, cover.height
+                    ),
+                ))
+            }
+            _ => {}
+        }
+
+        // every page wraps its image in inline SVG, but only the links placed on it break
+        if !quirks.svg_links {
+            let pages = self
+                .book
+                .chapter
+                .iter()
+                .flat_map(|chapter| &chapter.page)
+                .filter(|page| !page.link.is_empty())
+                .count();
+            if 0 < pages {
+                findings.push((
+                    Rule::SvgLink,
+                    format!("{pages} page(s) have links, which {name} does not follow"),
+                ));
+            }
+        }
+
+        for style in &self.book.rendition.style {
+            for decl in quirks.find_unsupported_css(&style.src) {
+                findings.push((
+                    Rule::UnsupportedCss,
+                    format!("`{}` uses `{decl}`, which {name} ignores", style.href),
+                ));
+            }
+        }
+
+        findings
+    }
+
+    /// Warns about the ways the book at `path` exceeds the limits of the selected store.
+    #[cfg(feature = "fs")]
+    fn check_profile(&self, path: &Path) -> Result<()> {
+        let profile = self.options.profile;
+        let file_size = path
+            .metadata()
+            .with_context(|| format!("failed to read `{}`", path.display()))?
+            .len();
+
+        let mut limits = profile.limits();
+        limits.cover_edge = self.options.min_cover_edge.or(limits.cover_edge);
+
+        let mut findings = limits.check(file_size, &self.package.images);
+        if let Some(cover) = self.package.cover {
+            findings.extend(limits.check_cover(cover.width, cover.height));
+        }
+        for finding in findings {
+            self.warn(format!("{finding}, which {} may reject", profile.name()));
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        let missing = self
+            .package
+            .manifest
+            .values()
+            .map(|item| &item.src)
+            .filter(|src| !src.exists())
+            .map(|src| Missing {
+                path: src.path(),
+                context: None,
+            })
+            .collect::<Vec<_>>();
+
+        report_missing(&missing)?;
+        self.check_consistency()
+    }
+
+    /// Checks that the spine and the table of contents agree with each other and with the
+    /// manifest, so that no package is written that reading systems would reject.
+    fn check_consistency(&self) -> Result<()> {
+        let invalid = |message: String| Err(Failure::new(Status::Invalid, message).into());
+
+        let spine = &self.package.spine;
+        if spine.is_empty() {
+            return invalid(tr!(
+                "the spine has no pages",
+                "スパインにページがありません"
+            ));
+        }
+
+        let spine = spine
+            .iter()
+            .map(|item_ref| item_ref.id_ref.as_str())
+            .collect::<HashSet<_>>();
+        let stray = self
+            .package
+            .toc
+            .iter()
+            .find(|(id, ..)| !spine.contains(id.as_str()));
+        if let Some((id, caption, _)) = stray {
+            return invalid(tr!(
+                "`{caption}` of the table of contents refers to `{id}`, which is not in the spine",
+                "目次の `{caption}` が参照する `{id}` がスパインにありません"
+            ));
+        }
+
+        if !self.options.format.is_epub() {
+            return Ok(());
+        }
+        let covers = self.cover_images();
+        if 1 < covers.len() {
+            return invalid(tr!(
+                "`{}` are all marked as the cover image",
+                "`{}` がすべて表紙画像として示されています",
+                covers.join("`, `")
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Warns if no item is marked as the cover image.
+    ///
+    /// A book may go without a cover on purpose, which the reading systems make up for with the
+    /// first page, so it is not rejected.
+    fn check_cover(&self) {
+        if self.options.format.is_epub() && self.cover_images().is_empty() {
+            self.warn(tr!(
+                "no item is marked as the cover image",
+                "表紙画像として示されたアイテムがありません"
+            ));
+        }
+    }
+
+    /// Returns the manifest ids of the items marked as the cover image.
+    fn cover_images(&self) -> Vec<&str> {
+        self.package
+            .manifest
+            .iter()
+            .filter(|(_, item)| {
+                let properties = item.properties.as_deref().unwrap_or_default();
+                has_property(properties, "cover-image")
+            })
+            .map(|(id, _)| id.as_str())
+            .collect()
+    }
+
+    fn file_options(&self) -> SimpleFileOptions {
+        SimpleFileOptions::default().compression_method(self.options.compression.into())
+    }
+
+    fn entry_options(&self, entry: &Entry) -> SimpleFileOptions {
+        // the mimetype must be stored, and already compressed images gain nothing from
+        // deflating them again
+        let options =
+            if entry.name == "mimetype" || COMPRESSED_MEDIA_TYPES.contains(&entry.media_type) {
+                SimpleFileOptions::default().compression_method(CompressionMethod::Stored)
+            } else {
+                self.file_options()
+            };
+
+        // the entries are dated at the start of 1980 unless the book is
+        match self.options.modified.and_then(zip_date_time) {
+            Some(time) => options.last_modified_time(time),
+            None => options,
+        }
+    }
+
+    fn write_container<W: Write>(&self, out: &mut W) -> Result<()> {
+        info!("writing container");
+
+        let mut w = EventWriter::new_with_config(out, self.options.emitter_config());
+
+        w.write(
+            XmlEvent::start_element("container")
+                .default_ns("urn:oasis:names:tc:opendocument:xmlns:container")
+                .attr("version", "1.0"),
+        )?;
+
+        w.write(XmlEvent::start_element("rootfiles"))?;
+
+        w.write(
+            XmlEvent::start_element("rootfile")
+                .attr("full-path", "item/standard.opf")
+                .attr("media-type", "application/oebps-package+xml"),
+        )?;
+
+        w.write(XmlEvent::end_element())?; // rootfile
+        w.write(XmlEvent::end_element())?; // rootfiles
+        w.write(XmlEvent::end_element())?; // container
+
+        Ok(())
+    }
+
+    fn write_package<W: Write>(&self, out: &mut W) -> Result<()> {
+        info!("writing package");
+
+        let mut w = EventWriter::new_with_config(out, self.options.emitter_config());
+
+        w.write(
+            XmlEvent::start_element("package")
+                .default_ns("http://www.idpf.org/2007/opf")
+                .attr("version", "3.0")
+                .attr("xml:lang", &self.book.metadata.language)
+                .attr("unique-identifier", "unique-id")
+                .attr("prefix", &self.prefix()),
+        )?;
+
+        self.write_package_metadata(&mut w)?;
+        self.write_package_manifest(&mut w)?;
+        self.write_package_spine(&mut w)?;
+
+        w.write(XmlEvent::end_element())?;
+
+        Ok(())
+    }
+
+    fn prefix(&self) -> String {
+        let mut prefix = "ebpaj: http://www.ebpaj.jp/".to_string();
+        if !self.book.metadata.content_warning.is_empty() || self.book.archival_cover.is_some() {
+            prefix.push_str(" tsugumi: https://github.com/dacci/tsugumi-rs#");
+        }
+        prefix
+    }
+
+    fn write_package_metadata<W: Write>(&self, w: &mut EventWriter<W>) -> Result<()> {
+        w.write(XmlEvent::start_element("metadata").ns("dc", "http://purl.org/dc/elements/1.1/"))?;
+
+        let titles = &self.book.metadata.title;
+        let seqs = display_seqs(&titles.iter().map(|t| t.display_seq).collect::<Vec<_>>());
+        for ((title, seq), display_seq) in titles.iter().zip(1..).zip(seqs) {
+            let refines = format!("#title{seq}");
+
+            w.write(XmlEvent::start_element("dc:title").attr("id", &refines[1..]))?;
+            w.write(XmlEvent::characters(&title.name))?;
+            w.write(XmlEvent::end_element())?;
+
+            w.write(
+                XmlEvent::start_element("meta")
+                    .attr("refines", &refines)
+                    .attr("property", "title-type"),
+            )?;
+            w.write(XmlEvent::characters(title.title_type.as_ref()))?;
+            w.write(XmlEvent::end_element())?;
+
+            if let Some(value) = &title.alternate_script {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "alternate-script"),
+                )?;
+                w.write(XmlEvent::characters(value))?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            if let Some(value) = &title.file_as {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "file-as"),
+                )?;
+                w.write(XmlEvent::characters(value))?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            if let Some(display_seq) = display_seq {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "display-seq"),
+                )?;
+                w.write(XmlEvent::characters(&display_seq.to_string()))?;
+                w.write(XmlEvent::end_element())?;
+            }
+        }
+
+        let creators = &self.book.metadata.creator;
+        let seqs = display_seqs(&creators.iter().map(|c| c.display_seq).collect::<Vec<_>>());
+        for ((creator, seq), display_seq) in creators.iter().zip(1..).zip(seqs) {
+            let refines = format!("#creator{seq}");
+
+            w.write(XmlEvent::start_element("dc:creator").attr("id", &refines[1..]))?;
+            w.write(XmlEvent::characters(&creator.name))?;
+            w.write(XmlEvent::end_element())?;
+
+            if let Some(value) = &creator.role {
+                let scheme = creator.scheme.as_deref().unwrap_or(MARC_RELATORS);
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "role")
+                        .attr("scheme", scheme),
+                )?;
+                w.write(XmlEvent::characters(value))?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            if let Some(value) = &creator.alternate_script {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "alternate-script"),
+                )?;
+                w.write(XmlEvent::characters(value))?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            if let Some(value) = &creator.file_as {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "file-as"),
+                )?;
+                w.write(XmlEvent::characters(value))?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            if let Some(display_seq) = display_seq {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "display-seq"),
+                )?;
+                w.write(XmlEvent::characters(&display_seq.to_string()))?;
+                w.write(XmlEvent::end_element())?;
+            }
+        }
+
+        let contributors = &self.book.metadata.contributor;
+        let seqs = display_seqs(
+            &contributors
+                .iter()
+                .map(|c| c.display_seq)
+                .collect::<Vec<_>>(),
+        );
+        for ((contributor, seq), display_seq) in contributors.iter().zip(1..).zip(seqs) {
+            let refines = format!("#contributor{seq}");
+
+            w.write(XmlEvent::start_element("dc:contributor").attr("id", &refines[1..]))?;
+            w.write(XmlEvent::characters(&contributor.name))?;
+            w.write(XmlEvent::end_element())?;
+
+            if let Some(value) = &contributor.role {
+                let scheme = contributor.scheme.as_deref().unwrap_or(MARC_RELATORS);
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "role")
+                        .attr("scheme", scheme),
+                )?;
+                w.write(XmlEvent::characters(value))?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            if let Some(value) = &contributor.alternate_script {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "alternate-script"),
+                )?;
+                w.write(XmlEvent::characters(value))?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            if let Some(value) = &contributor.file_as {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "file-as"),
+                )?;
+                w.write(XmlEvent::characters(value))?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            if let Some(display_seq) = display_seq {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "display-seq"),
+                )?;
+                w.write(XmlEvent::characters(&display_seq.to_string()))?;
+                w.write(XmlEvent::end_element())?;
+            }
+        }
+
+        for (collection, seq) in self.book.metadata.collection.iter().zip(1..) {
+            let refines = format!("#collection{seq}");
+
+            w.write(
+                XmlEvent::start_element("meta")
+                    .attr("property", "belongs-to-collection")
+                    .attr("id", &refines[1..]),
+            )?;
+            w.write(XmlEvent::characters(&collection.name))?;
+            w.write(XmlEvent::end_element())?;
+
+            w.write(
+                XmlEvent::start_element("meta")
+                    .attr("refines", &refines)
+                    .attr("property", "collection-type"),
+            )?;
+            w.write(XmlEvent::characters(collection.collection_type.as_ref()))?;
+            w.write(XmlEvent::end_element())?;
+
+            if let Some(value) = &collection.position {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &refines)
+                        .attr("property", "group-position"),
+                )?;
+                w.write(XmlEvent::characters(value.as_str()))?;
+                w.write(XmlEvent::end_element())?;
+            }
+        }
+
+        // calibre reads the series from metadata of its own
+        let series = self
+            .book
+            .metadata
+            .collection
+            .iter()
+            .find(|c| c.collection_type == CollectionType::Series);
+        if let Some(series) = series {
+            let index = series.position.as_ref().map(Position::as_str);
+            for (name, content) in [
+                ("calibre:series", Some(series.name.as_str())),
+                ("calibre:series_index", index),
+            ] {
+                if let Some(content) = content {
+                    w.write(
+                        XmlEvent::start_element("meta")
+                            .attr("name", name)
+                            .attr("content", content),
+                    )?;
+                    w.write(XmlEvent::end_element())?;
+                }
+            }
+        }
+
+        w.write(XmlEvent::start_element("dc:language"))?;
+        w.write(XmlEvent::characters(&self.book.metadata.language))?;
+        w.write(XmlEvent::end_element())?;
+
+        w.write(XmlEvent::start_element("dc:identifier").attr("id", "unique-id"))?;
+        w.write(XmlEvent::characters(&self.book.metadata.identifier))?;
+        w.write(XmlEvent::end_element())?;
+
+        let metadata = &self.book.metadata;
+        let elements = [
+            ("dc:description", metadata.description.as_deref()),
+            ("dc:publisher", metadata.publisher.as_deref()),
+            (
+                "dc:date",
+                metadata.publication_date.as_ref().map(|d| d.as_str()),
+            ),
+            ("dc:rights", metadata.rights.as_deref()),
+        ];
+        for (name, value) in elements {
+            if let Some(value) = value {
+                w.write(XmlEvent::start_element(name))?;
+                w.write(XmlEvent::characters(value))?;
+                w.write(XmlEvent::end_element())?;
+            }
+        }
+
+        for subject in &metadata.subject {
+            w.write(XmlEvent::start_element("dc:subject"))?;
+            w.write(XmlEvent::characters(subject))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        for book_type in &self.book.metadata.book_type {
+            w.write(XmlEvent::start_element("dc:type"))?;
+            w.write(XmlEvent::characters(book_type))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        if let Some(value) = &self.book.metadata.schema_type {
+            w.write(XmlEvent::start_element("meta").attr("property", "schema:additionalType"))?;
+            w.write(XmlEvent::characters(value))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        if let Some(value) = &self.book.metadata.age_rating {
+            w.write(XmlEvent::start_element("meta").attr("property", "schema:contentRating"))?;
+            w.write(XmlEvent::characters(value))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        for value in &self.book.metadata.content_warning {
+            w.write(XmlEvent::start_element("meta").attr("property", "tsugumi:content-warning"))?;
+            w.write(XmlEvent::characters(value))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        if self.book.archival_cover.is_some() {
+            w.write(XmlEvent::start_element("meta").attr("property", "tsugumi:archival-cover"))?;
+            w.write(XmlEvent::characters(ARCHIVAL_COVER_ID))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        w.write(XmlEvent::start_element("meta").attr("property", "dcterms:modified"))?;
+        let modified = self
+            .options
+            .modified
+            .unwrap_or_else(OffsetDateTime::now_utc);
+        w.write(XmlEvent::characters(
+            &modified.format(&Iso8601::DEFAULT).unwrap(),
+        ))?;
+        w.write(XmlEvent::end_element())?;
+
+        w.write(XmlEvent::start_element("meta").attr("property", "rendition:layout"))?;
+        w.write(XmlEvent::characters(self.book.rendition.layout.as_ref()))?;
+        w.write(XmlEvent::end_element())?;
+
+        w.write(XmlEvent::start_element("meta").attr("property", "rendition:orientation"))?;
+        w.write(XmlEvent::characters(
+            self.book.rendition.orientation.as_ref(),
+        ))?;
+        w.write(XmlEvent::end_element())?;
+
+        w.write(XmlEvent::start_element("meta").attr("property", "rendition:spread"))?;
+        w.write(XmlEvent::characters(self.book.rendition.spread.as_ref()))?;
+        w.write(XmlEvent::end_element())?;
+
+        if let Some(viewport) = &self.book.rendition.viewport {
+            w.write(XmlEvent::start_element("meta").attr("property", "rendition:viewport"))?;
+            w.write(XmlEvent::characters(&viewport.to_string()))?;
+            w.write(XmlEvent::end_element())?;
+
+            for (id, page) in &self.package.viewports {
+                if page == viewport {
+                    continue;
+                }
+
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("refines", &format!("#{id}"))
+                        .attr("property", "rendition:viewport"),
+                )?;
+                w.write(XmlEvent::characters(&page.to_string()))?;
+                w.write(XmlEvent::end_element())?;
+            }
+        }
+
+        if !self.package.scripts.is_empty() {
+            // scripts make the book a mix of expository and interactive content
+            w.write(XmlEvent::start_element("meta").attr("property", "schema:interactivityType"))?;
+            w.write(XmlEvent::characters("mixed"))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        let mut names = Vec::new();
+        if self.package.panel_view || self.options.format == Format::Kindle {
+            names.push(("book-type", "comic".to_string()));
+        }
+        if self.package.panel_view {
+            names.push(("region-mag", "true".to_string()));
+        }
+        if self.options.format == Format::Kindle {
+            names.extend(self.kindle_metadata());
+        }
+        for (name, content) in names {
+            w.write(
+                XmlEvent::start_element("meta")
+                    .attr("name", name)
+                    .attr("content", &content),
+            )?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        if !self.package.overlays.is_empty() {
+            for (id, duration) in &self.package.overlays {
+                w.write(
+                    XmlEvent::start_element("meta")
+                        .attr("property", "media:duration")
+                        .attr("refines", &format!("#{id}")),
+                )?;
+                w.write(XmlEvent::characters(&clock_value(*duration)))?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            let total = self.package.overlays.values().sum();
+            w.write(XmlEvent::start_element("meta").attr("property", "media:duration"))?;
+            w.write(XmlEvent::characters(&clock_value(total)))?;
+            w.write(XmlEvent::end_element())?;
+
+            w.write(XmlEvent::start_element("meta").attr("property", "media:active-class"))?;
+            w.write(XmlEvent::characters(MEDIA_ACTIVE_CLASS))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        w.write(XmlEvent::start_element("meta").attr("property", "ebpaj:guide-version"))?;
+        w.write(XmlEvent::characters("1.1.3"))?;
+        w.write(XmlEvent::end_element())?;
+
+        w.write(XmlEvent::end_element())?;
+
+        Ok(())
+    }
+
+    /// Returns the metadata Kindle needs to lay out the pages as they are and in the right order.
+    fn kindle_metadata(&self) -> Vec<(&'static str, String)> {
+        let rendition = &self.book.rendition;
+        let mut metadata = vec![("fixed-layout", "true".to_string())];
+
+        let resolution = rendition
+            .viewport
+            .or(self.package.cover)
+            .or_else(|| self.package.viewports.values().next().copied());
+        if let Some(Viewport { width, height }) = resolution {
+            metadata.push(("original-resolution", format!("{width}x{height}")));
+        }
+
+        let orientation = match rendition.orientation {
+            Orientation::Landscape => "landscape",
+            Orientation::Portrait => "portrait",
+            Orientation::Auto => "none",
+        };
+        metadata.push(("orientation-lock", orientation.to_string()));
+
+        let writing_mode = match rendition.direction {
+            Direction::RightToLeft => "horizontal-rl",
+            Direction::LeftToRight => "horizontal-lr",
+        };
+        metadata.push(("primary-writing-mode", writing_mode.to_string()));
+
+        metadata
+    }
+
+    fn write_package_manifest<W: Write>(&self, w: &mut EventWriter<W>) -> Result<()> {
+        w.write(XmlEvent::start_element("manifest"))?;
+
+        w.write(
+            XmlEvent::start_element("item")
+                .attr("media-type", "application/xhtml+xml")
+                .attr("id", NAV_ID)
+                .attr("href", "navigation-documents.xhtml")
+                .attr("properties", "nav"),
+        )?;
+        w.write(XmlEvent::end_element())?;
+
+        if self.options.ncx {
+            w.write(
+                XmlEvent::start_element("item")
+                    .attr("media-type", "application/x-dtbncx+xml")
+                    .attr("id", NCX_ID)
+                    .attr("href", NCX_HREF),
+            )?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        for (id, item) in &self.package.manifest {
+            let mut event = XmlEvent::start_element("item")
+                .attr("media-type", &item.media_type)
+                .attr("id", id)
+                .attr("href", &item.href);
+            if let Some(properties) = &item.properties {
+                event = event.attr("properties", properties);
+            }
+            if let Some(fallback) = &item.fallback {
+                event = event.attr("fallback", fallback);
+            }
+            if let Some(media_overlay) = &item.media_overlay {
+                event = event.attr("media-overlay", media_overlay);
+            }
+
+            w.write(event)?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        w.write(XmlEvent::end_element())?;
+
+        Ok(())
+    }
+
+    fn write_package_spine<W: Write>(&self, w: &mut EventWriter<W>) -> Result<()> {
+        let mut event = XmlEvent::start_element("spine").attr(
+            "page-progression-direction",
+            self.book.rendition.direction.as_ref(),
+        );
+        if self.options.ncx {
+            event = event.attr("toc", NCX_ID);
+        }
+        w.write(event)?;
+
+        for item_ref in &self.package.spine {
+            let mut event = XmlEvent::start_element("itemref")
+                .attr("linear", if item_ref.linear { "yes" } else { "no" })
+                .attr("idref", &item_ref.id_ref);
+            if let Some(properties) = &item_ref.properties {
+                event = event.attr("properties", properties);
+            }
+
+            w.write(event)?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        w.write(XmlEvent::end_element())?;
+
+        Ok(())
+    }
+
+    fn write_navigation<W: Write>(&self, out: &mut W) -> Result<()> {
+        info!("writing navigation");
+
+        let eol = self.options.line_ending.as_str();
+        write!(out, r#"<?xml version="1.0" encoding="utf-8"?>{eol}"#)?;
+        write!(out, r#"<!DOCTYPE html>{eol}"#)?;
+
+        let mut w = EventWriter::new_with_config(
+            out,
+            self.options
+                .emitter_config()
+                .write_document_declaration(false),
+        );
+
+        w.write(
+            XmlEvent::start_element("html")
+                .default_ns("http://www.w3.org/1999/xhtml")
+                .ns("epub", "http://www.idpf.org/2007/ops")
+                .attr("xml:lang", &self.book.metadata.language),
+        )?;
+
+        w.write(XmlEvent::start_element("head"))?;
+
+        w.write(XmlEvent::start_element("meta").attr("charset", "UTF-8"))?;
+        w.write(XmlEvent::end_element())?; // meta
+
+        w.write(XmlEvent::start_element("title"))?;
+        w.write(XmlEvent::characters("Navigation"))?;
+        w.write(XmlEvent::end_element())?; // title
+
+        w.write(XmlEvent::end_element())?; // head
+
+        w.write(XmlEvent::start_element("body"))?;
+        w.write(
+            XmlEvent::start_element("nav")
+                .attr("epub:type", "toc")
+                .attr("id", "toc"),
+        )?;
+
+        w.write(XmlEvent::start_element("h1"))?;
+        w.write(XmlEvent::characters("Navigation"))?;
+        w.write(XmlEvent::end_element())?; // h1
+
+        w.write(XmlEvent::start_element("ol"))?;
+
+        let levels = toc_levels(&self.package.toc);
+        let mut previous = None;
+        for ((id, title, _), &level) in self.package.toc.iter().zip(&levels) {
+            let item = self.package.manifest.get(id).unwrap();
+
+            // an entry deeper than the previous one opens a list in it
+            match previous {
+                Some(previous) if previous < level => {
+                    w.write(XmlEvent::start_element("ol"))?;
+                }
+                Some(previous) => {
+                    w.write(XmlEvent::end_element())?; // li
+                    for _ in level..previous {
+                        w.write(XmlEvent::end_element())?; // ol
+                        w.write(XmlEvent::end_element())?; // li
+                    }
+                }
+                None => {}
+            }
+            previous = Some(level);
+
+            w.write(XmlEvent::start_element("li"))?;
+            w.write(XmlEvent::start_element("a").attr("href", &item.href))?;
+            w.write(XmlEvent::characters(title))?;
+            w.write(XmlEvent::end_element())?; // a
+        }
+        if let Some(previous) = previous {
+            w.write(XmlEvent::end_element())?; // li
+            for _ in 0..previous {
+                w.write(XmlEvent::end_element())?; // ol
+                w.write(XmlEvent::end_element())?; // li
+            }
+        }
+
+        w.write(XmlEvent::end_element())?; // ol
+        w.write(XmlEvent::end_element())?; // nav
+
+        if !self.package.landmarks.is_empty() {
+            self.write_landmarks(&mut w)?;
+        }
+
+        w.write(XmlEvent::end_element())?; // body
+        w.write(XmlEvent::end_element())?; // html
+
+        Ok(())
+    }
+
+    fn write_landmarks<W: Write>(&self, w: &mut EventWriter<W>) -> Result<()> {
+        w.write(
+            XmlEvent::start_element("nav")
+                .attr("epub:type", "landmarks")
+                .attr("id", "landmarks")
+                .attr("hidden", "hidden"),
+        )?;
+
+        w.write(XmlEvent::start_element("h1"))?;
+        w.write(XmlEvent::characters("Landmarks"))?;
+        w.write(XmlEvent::end_element())?; // h1
+
+        w.write(XmlEvent::start_element("ol"))?;
+
+        w.write(XmlEvent::start_element("li"))?;
+        w.write(
+            XmlEvent::start_element("a")
+                .attr("epub:type", "toc")
+                .attr("href", "#toc"),
+        )?;
+        w.write(XmlEvent::characters("Navigation"))?;
+        w.write(XmlEvent::end_element())?; // a
+        w.write(XmlEvent::end_element())?; // li
+
+        for (kind, (id, label)) in &self.package.landmarks {
+            let item = self.package.manifest.get(id).unwrap();
+
+            w.write(XmlEvent::start_element("li"))?;
+            w.write(
+                XmlEvent::start_element("a")
+                    .attr("epub:type", kind.as_ref())
+                    .attr("href", &item.href),
+            )?;
+            w.write(XmlEvent::characters(label))?;
+            w.write(XmlEvent::end_element())?; // a
+            w.write(XmlEvent::end_element())?; // li
+        }
+
+        w.write(XmlEvent::end_element())?; // ol
+        w.write(XmlEvent::end_element())?; // nav
+
+        Ok(())
+    }
+
+    fn write_ncx<W: Write>(&self, out: &mut W) -> Result<()> {
+        info!("writing ncx");
+
+        let mut w = EventWriter::new_with_config(out, self.options.emitter_config());
+
+        w.write(
+            XmlEvent::start_element("ncx")
+                .default_ns("http://www.daisy.org/z3986/2005/ncx/")
+                .attr("version", "2005-1")
+                .attr("xml:lang", &self.book.metadata.language),
+        )?;
+
+        let levels = toc_levels(&self.package.toc);
+        let depth = levels.iter().max().map_or(1, |level| level + 1).to_string();
+
+        w.write(XmlEvent::start_element("head"))?;
+        for (name, content) in [
+            ("dtb:uid", self.book.metadata.identifier.as_str()),
+            ("dtb:depth", &depth),
+            ("dtb:totalPageCount", "0"),
+            ("dtb:maxPageNumber", "0"),
+        ] {
+            w.write(
+                XmlEvent::start_element("meta")
+                    .attr("name", name)
+                    .attr("content", content),
+            )?;
+            w.write(XmlEvent::end_element())?; // meta
+        }
+        w.write(XmlEvent::end_element())?; // head
+
+        w.write(XmlEvent::start_element("docTitle"))?;
+        w.write(XmlEvent::start_element("text"))?;
+        w.write(XmlEvent::characters(&self.title))?;
+        w.write(XmlEvent::end_element())?; // text
+        w.write(XmlEvent::end_element())?; // docTitle
+
+        w.write(XmlEvent::start_element("navMap"))?;
+
+        // entries that point at the same page are played at the same time
+        let mut play_orders = HashMap::new();
+        let mut previous = None;
+        for (((id, title, _), &level), seq) in self.package.toc.iter().zip(&levels).zip(1..) {
+            let item = self.package.manifest.get(id).unwrap();
+            let next = play_orders.len() + 1;
+            let play_order = *play_orders.entry(id).or_insert(next);
+
+            // an entry deeper than the previous one is nested in it
+            if let Some(previous) = previous.filter(|&previous| level <= previous) {
+                for _ in level..=previous {
+                    w.write(XmlEvent::end_element())?; // navPoint
+                }
+            }
+            previous = Some(level);
+
+            w.write(
+                XmlEvent::start_element("navPoint")
+                    .attr("id", &format!("navPoint-{seq}"))
+                    .attr("playOrder", &play_order.to_string()),
+            )?;
+            w.write(XmlEvent::start_element("navLabel"))?;
+            w.write(XmlEvent::start_element("text"))?;
+            w.write(XmlEvent::characters(title))?;
+            w.write(XmlEvent::end_element())?; // text
+            w.write(XmlEvent::end_element())?; // navLabel
+            w.write(XmlEvent::start_element("content").attr("src", &item.href))?;
+            w.write(XmlEvent::end_element())?; // content
+        }
+        for _ in previous.into_iter().flat_map(|previous| 0..=previous) {
+            w.write(XmlEvent::end_element())?; // navPoint
+        }
+
+        w.write(XmlEvent::end_element())?; // navMap
+        w.write(XmlEvent::end_element())?; // ncx
+
+        Ok(())
+    }
+}
+
+/// Source file that does not exist, with where the book refers to it.
+pub(super) struct Missing<'a> {
+    path: &'a Path,
+    context: Option<String>,
+}
+
+/// Finds every file that `book` refers to, such as a page source, that does not exist under
+/// `root`, unless it is given in `sources`.
+pub(super) fn find_missing<'a>(root: &Path, book: &'a Book, sources: &Sources) -> Vec<Missing<'a>> {
+    let mut missing = Vec::new();
+    let mut check = |path: &'a Path, source: bool, context: &dyn Fn() -> String| {
+        let resolved = if source {
+            root.join(book.source_path(path))
+        } else {
+            root.join(path)
+        };
+        if !sources.is_file(&resolved) {
+            missing.push(Missing {
+                path,
+                context: Some(context()),
+            });
+        }
+    };
+
+    let mut index = 0;
+    for (chapter, seq) in book.chapter.iter().zip(1..) {
+        let chapter_context = || match &chapter.name {
+            Some(name) => tr!("chapter \"{name}\"", "章「{name}」"),
+            None => tr!("chapter {seq}", "第 {seq} 章"),
+        };
+        if let Some(Panel::Sidecar(path)) = &chapter.panel {
+            check(path, false, &|| {
+                tr!("panels of {}", "{}のコマ", chapter_context())
+            });
+        }
+
+        for page in &chapter.page {
+            index += 1;
+
+            let context = || match &chapter.name {
+                Some(name) => tr!(
+                    "page {index} in chapter \"{name}\"",
+                    "章「{name}」の {index} ページ"
+                ),
+                None => tr!(
+                    "page {index} in chapter {seq}",
+                    "第 {seq} 章の {index} ページ"
+                ),
+            };
+            for path in page.sources() {
+                check(path, true, &context);
+            }
+            if let Some(audio) = &page.audio {
+                check(&audio.src, false, &|| {
+                    tr!("audio of {}", "{}の音声", context())
+                });
+            }
+            if let Some(Panel::Sidecar(path)) = &page.panel {
+                check(path, false, &|| tr!("panels of {}", "{}のコマ", context()));
+            }
+        }
+    }
+
+    if let Some(path) = &book.archival_cover {
+        check(path, false, &|| tr!("archival cover", "保存用の表紙"));
+    }
+
+    for path in &book.rendition.script {
+        check(path, false, &|| {
+            tr!("script of the rendition", "レンディションのスクリプト")
+        });
+    }
+
+    missing
+}
+
+/// Inclusive range of pages numbered from 1 in the order of the spine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PageRange {
+    start: usize,
+    end: usize,
+}
+
+impl PageRange {
+    /// Creates the range of the pages from `start` to `end`.
+    pub(crate) fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        self.start <= index && index <= self.end
+    }
+}
+
+impl std::str::FromStr for PageRange {
+    type Err = String;
+
+    /// Parses `N`, `N..M` or `N..`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |s: &str| match s.trim().parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("`{s}` is not a page number")),
+            Ok(n) => Ok(n),
+        };
+
+        let range = match s.split_once("..") {
+            None => {
+                let n = number(s)?;
+                Self { start: n, end: n }
+            }
+            Some((start, "")) => Self {
+                start: number(start)?,
+                end: usize::MAX,
+            },
+            Some((start, end)) => Self {
+                start: number(start)?,
+                end: number(end)?,
+            },
+        };
+
+        if range.end < range.start {
+            return Err(format!("`{s}` is an empty range"));
+        }
+
+        Ok(range)
+    }
+}
+
+/// Returns the numbers `--chapter` selects the chapters of `book` by, counting from 1 without the
+/// cover chapters, which have none.
+fn chapter_numbers(book: &Book) -> Vec<Option<usize>> {
+    let mut number = 0;
+    book.chapter
+        .iter()
+        .map(|chapter| {
+            if chapter.cover {
+                return None;
+            }
+            number += 1;
+            Some(number)
+        })
+        .collect()
+}
+
+/// Leaves only the pages of `book` in the `chapters` or in `pages`, and the cover.
+///
+/// The chapters are counted from 1 without the cover chapters, and keep the captions they are
+/// numbered with in the whole book.
+fn select(mut book: Book, chapters: &[usize], pages: Option<PageRange>) -> Result<Book> {
+    let numbers = chapter_numbers(&book);
+    let count = numbers.iter().flatten().count();
+    if let Some(n) = chapters.iter().find(|&&n| n == 0 || count < n) {
+        anyhow::bail!(tr!(
+            "chapter {} does not exist",
+            "第 {} 章は存在しません",
+            n
+        ));
+    }
+
+    let mut index = 0;
+    let mut selected = 0;
+    let keep = book
+        .chapter
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| {
+            let whole = numbers[i].is_some_and(|n| chapters.contains(&n));
+            (0..chapter.page.len())
+                .map(|j| {
+                    index += 1;
+                    let cover = book.is_cover(i, j);
+                    let keep = whole || pages.is_some_and(|pages| pages.contains(index));
+                    if keep && !cover {
+                        selected += 1;
+                    }
+                    keep || cover
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    if selected == 0 {
+        anyhow::bail!(tr!("no pages are selected", "ページが選択されていません"));
+    }
+
+    let names = book.numbered_names();
+    book.chapter = std::mem::take(&mut book.chapter)
+        .into_iter()
+        .zip(names)
+        .zip(keep)
+        .filter_map(|((mut chapter, name), keep)| {
+            let mut keep = keep.into_iter();
+            chapter.page.retain(|_| keep.next().unwrap_or_default());
+            if chapter.name.is_none() {
+                chapter.name = name;
+            }
+            (!chapter.page.is_empty()).then_some(chapter)
+        })
+        .collect();
+
+    Ok(book)
+}
+
+/// Describes the pages that use the source of an earlier page without being marked for reuse.
+pub(super) fn find_duplicates(book: &Book) -> Vec<String> {
+    let mut first = HashMap::new();
+    let mut findings = Vec::new();
+
+    let pages = book.chapter.iter().flat_map(|chapter| &chapter.page);
+    for (page, index) in pages.zip(1..) {
+        for src in page.sources() {
+            match first.get(src) {
+                Some(earlier) if !page.reuse => findings.push(tr!(
+                    "`{}` of page {index} is also used by page {earlier}; \
+                     set `reuse: true` on the page if this is intended",
+                    "{index} ページの `{}` は {earlier} ページでも使われています。\
+                     意図したものであればページに `reuse: true` を指定してください",
+                    src.display()
+                )),
+                Some(_) => {}
+                None => {
+                    first.insert(src, index);
+                }
+            }
+        }
+    }
+
+    findings
+}
+
+/// Describes the chapters without pages, other than the parts whose sub-chapters have them.
+pub(super) fn find_empty_chapters(book: &Book) -> Vec<String> {
+    let mut findings = Vec::new();
+
+    for (i, chapter) in book.chapter.iter().enumerate() {
+        let has_children = book
+            .chapter
+            .get(i + 1)
+            .is_some_and(|next| chapter.depth < next.depth);
+        if !chapter.page.is_empty() || has_children {
+            continue;
+        }
+
+        let seq = i + 1;
+        findings.push(match &chapter.name {
+            Some(name) => tr!(
+                "chapter \"{name}\" has no pages",
+                "章「{name}」にページがありません"
+            ),
+            None => tr!(
+                "chapter {seq} has no pages",
+                "第 {seq} 章にページがありません"
+            ),
+        });
+    }
+
+    findings
+}
+
+impl std::fmt::Display for Missing<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = self.path.display();
+        match &self.context {
+            Some(context) => f.write_str(&tr!(
+                "`{path}` does not exist ({context})",
+                "`{path}` が存在しません（{context}）"
+            )),
+            None => f.write_str(&tr!("`{path}` does not exist", "`{path}` が存在しません")),
+        }
+    }
+}
+
+pub(super) fn report_missing(missing: &[Missing]) -> Result<()> {
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    for missing in missing {
+        error!("{missing}");
+    }
+
+    Err(missing_failure(missing.len()))
+}
+
+/// Returns the error that fails a task because `count` source files are missing.
+pub(super) fn missing_failure(count: usize) -> anyhow::Error {
+    Failure::new(
+        Status::MissingSources,
+        tr!(
+            "{count} source file(s) are missing",
+            "{count} 個のソースファイルが見つかりません"
+        ),
+    )
+    .into()
+}
+
+/// Converts `time` to the date and time of a ZIP entry, which cannot be before 1980.
+fn zip_date_time(time: OffsetDateTime) -> Option<zip::DateTime> {
+    let time = time.to_offset(UtcOffset::UTC);
+    zip::DateTime::from_date_and_time(
+        u16::try_from(time.year()).ok()?,
+        time.month().into(),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+    )
+    .ok()
+}
+
+/// Returns the levels the entries of `toc` are nested at, which is the number of the entries before
+/// each that are shallower than it and have no entry in between at most as deep, so that every
+/// nested list has an entry to belong to even if a chapter is left out.
+fn toc_levels(toc: &[(String, String, usize)]) -> Vec<usize> {
+    let mut ancestors = Vec::new();
+    toc.iter()
+        .map(|&(_, _, depth)| {
+            while ancestors.last().is_some_and(|&ancestor| depth <= ancestor) {
+                ancestors.pop();
+            }
+            ancestors.push(depth);
+            ancestors.len() - 1
+        })
+        .collect()
+}
+
+/// Returns the manifest id of the cover page at `index`, or the page at `index` among the others.
+fn page_id(cover: bool, index: usize) -> String {
+    match (cover, index) {
+        (true, ..=1) => "p-cover".to_string(),
+        (true, _) => format!("p-cover-{index:04}"),
+        (false, _) => format!("p-{index:04}"),
+    }
+}
+
+/// Returns `name` numbered `n`, which is `name` itself for the first.
+fn numbered(name: &str, n: usize) -> String {
+    if n == 1 {
+        name.to_string()
+    } else {
+        format!("{name}-{n}")
+    }
+}
+
+/// Returns the caption of the landmark of a chapter of `kind` without a name.
+/// Returns the `display-seq` of each of the titles or creators given their `displaySeq`, which is
+/// the position in the list unless given, or none at all for a single one without it.
+fn display_seqs(given: &[Option<u32>]) -> Vec<Option<u32>> {
+    if let [None] = given {
+        return vec![None];
+    }
+
+    // the others take the positions left over, in order
+    let mut free = (1..).filter(|seq| !given.contains(&Some(*seq)));
+    given
+        .iter()
+        .map(|seq| seq.or_else(|| free.next()))
+        .collect()
+}
+
+fn landmark_label(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Cover => "Cover",
+        Kind::Frontmatter => "Front Matter",
+        Kind::Bodymatter => "Start of Content",
+        Kind::Backmatter => "Back Matter",
+    }
+}
+
+/// Replaces the characters of `name` that cannot be in a file name on this platform with `_`.
+pub(super) fn sanitize_file_name(name: &str) -> String {
+    const RESERVED: &[char] = if cfg!(windows) {
+        &['<', '>', ':', '"', '/', '\\', '|', '?', '*']
+    } else if cfg!(target_os = "macos") {
+        // Finder shows `:` as `/`
+        &['/', ':']
+    } else {
+        &['/']
+    };
+
+    let mut sanitized = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || RESERVED.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+
+    if cfg!(windows) {
+        // Windows drops trailing dots and spaces, and reserves the names of devices
+        let len = sanitized.trim_end_matches(['.', ' ']).len();
+        sanitized.replace_range(len.., &"_".repeat(sanitized.len() - len));
+
+        let stem = sanitized.split('.').next().unwrap_or_default();
+        let device = ["CON", "PRN", "AUX", "NUL"].contains(&stem.to_ascii_uppercase().as_str())
+            || (stem.len() == 4
+                && stem
+                    .get(..3)
+                    .is_some_and(|p| ["COM", "LPT"].iter().any(|d| p.eq_ignore_ascii_case(d)))
+                && stem.as_bytes()[3].is_ascii_digit());
+        if device {
+            sanitized.insert(0, '_');
+        }
+    }
+
+    if sanitized.is_empty() || sanitized.chars().all(|c| c == '.') {
+        sanitized = "_".repeat(sanitized.len().max(1));
+    }
+
+    sanitized
+}
+
+/// Formats `seconds` as a SMIL clock value, such as `0:01:02.500`.
+fn clock_value(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Converts `path` written in the project file into a path within the package, using `/` as
+/// the separator whichever platform the project file was written on.
+fn href_path(path: &str) -> Result<String> {
+    let href = path.replace('\\', "/");
+    if is_package_path(&href) {
+        Ok(href)
+    } else {
+        anyhow::bail!("`{path}` is not a relative path within the package")
+    }
+}
+
+/// Whether `name` is a relative path that stays within the package, such as `item/a.png`.
+///
+/// Backslashes, drive letters and `..` are not allowed, as readers on some platforms would take
+/// them for directories out of the package.
+fn is_package_path(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains('\\')
+        && name
+            .split('/')
+            .all(|segment| !matches!(segment, "" | "." | "..") && !segment.contains(':'))
+}
+
+/// Returns the name of the image of the page at `seq` in a CBZ file, which comic readers sort by.
+fn comic_page_name(seq: usize, href: &str) -> String {
+    match Path::new(href).extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{seq:04}.{ext}"),
+        None => format!("{seq:04}"),
+    }
+}
+
+/// Stitches the images of `sources` side by side, from left to right, into an image in the format
+/// of `ext`, encoded at `quality` if it is JPEG.
+pub(super) fn stitch(sources: &[Resource], ext: &str, quality: u8) -> Result<Resource> {
+    let images = sources.iter().map(decode).collect::<Result<Vec<_>>>()?;
+
+    let width = images.iter().map(|image| image.width()).sum();
+    let height = images.iter().map(|image| image.height()).max().unwrap_or(0);
+
+    let mut spread = image::RgbImage::new(width, height);
+    let mut x = 0;
+    for image in &images {
+        let y = (height - image.height()) / 2;
+        image::imageops::replace(&mut spread, &image.to_rgb8(), x.into(), y.into());
+        x += image.width();
+    }
+
+    let format = image::ImageFormat::from_extension(ext).unwrap_or(image::ImageFormat::Png);
+    encode(&spread.into(), ext, format, quality)
+}
+
+/// Crops the columns of the image of `src` from `x` for `width` pixels into an image in the
+/// format of `ext`, encoded at `quality` if it is JPEG.
+fn crop(src: &Resource, x: u32, width: u32, ext: &str, quality: u8) -> Result<Resource> {
+    let image = decode(src)?;
+    let half = image.crop_imm(x, 0, width, image.height());
+
+    let format = image::ImageFormat::from_extension(ext).unwrap_or(image::ImageFormat::Png);
+    encode(&half, ext, format, quality)
+}
+
+/// Decodes the image of `src`, in the format of its content, or else of its name.
+fn decode(src: &Resource) -> Result<image::DynamicImage> {
+    let path = src.path();
+    let mut reader = image::ImageReader::new(src.open()?)
+        .with_guessed_format()
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    if reader.format().is_none() {
+        if let Ok(format) = image::ImageFormat::from_path(path) {
+            reader.set_format(format);
+        }
+    }
+    reader
+        .decode()
+        .with_context(|| format!("failed to read `{}`", path.display()))
+}
+
+/// Encodes `image` in `format` into a resource with the extension `ext`, at `quality` if it is
+/// JPEG.
+fn encode(
+    image: &image::DynamicImage,
+    ext: &str,
+    format: image::ImageFormat,
+    quality: u8,
+) -> Result<Resource> {
+    let mut buf = Cursor::new(Vec::new());
+    if format == image::ImageFormat::Jpeg {
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, quality);
+        image.to_rgb8().write_with_encoder(encoder)?;
+    } else {
+        image.write_to(&mut buf, format)?;
+    }
+
+    Resource::generated(ext, buf.get_ref())
+}
+
+/// Returns the size of an image of `width` by `height` scaled down, keeping its aspect ratio, to
+/// fit in `max_width` by `max_height`.
+pub(super) fn fit(
+    width: u32,
+    height: u32,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+) -> (u32, u32) {
+    let scale = [
+        max_width.map(|max| f64::from(max) / f64::from(width)),
+        max_height.map(|max| f64::from(max) / f64::from(height)),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(1.0, f64::min);
+    if scale < 1.0 {
+        let scaled = |n: u32| ((f64::from(n) * scale).round() as u32).max(1);
+        (scaled(width), scaled(height))
+    } else {
+        (width, height)
+    }
+}
+
+/// Returns the format of the image of `src`, if it is of a known one, and the format and the
+/// extension it is encoded with as `format`, or in its own format if `format` is not set.
+pub(super) fn target_format(
+    src: &Resource,
+    format: Option<PageFormat>,
+) -> (Option<image::ImageFormat>, image::ImageFormat, &'static str) {
+    let ext = src
+        .path()
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let source_format = sniff_format(src).or_else(|| image::ImageFormat::from_extension(&ext));
+    let (format, ext) = match format {
+        Some(PageFormat::Jpeg) => (image::ImageFormat::Jpeg, "jpg"),
+        Some(PageFormat::Png) => (image::ImageFormat::Png, "png"),
+        None => match source_format {
+            Some(format) => (format, format.extensions_str()[0]),
+            None => (image::ImageFormat::Png, "png"),
+        },
+    };
+    (source_format, format, ext)
+}
+
+/// Resizes the image of `src` to `size` and encodes it in `format`, with `quality` if it is JPEG.
+pub(super) fn scale(
+    src: &Resource,
+    size: (u32, u32),
+    format: image::ImageFormat,
+    quality: u8,
+) -> Result<Resource> {
+    let mut image = decode(src)?;
+    if (image.width(), image.height()) != size {
+        image = image.resize_exact(size.0, size.1, image::imageops::FilterType::Lanczos3);
+    }
+
+    let ext = format.extensions_str().first().copied().unwrap_or("png");
+    encode(&image, ext, format, quality)
+}
+
+/// Returns the format of the image of `src` by its content alone, if it is of a known one.
+fn sniff_format(src: &Resource) -> Option<image::ImageFormat> {
+    image::ImageReader::new(src.open().ok()?)
+        .with_guessed_format()
+        .ok()?
+        .format()
+}
+
+/// Returns the media type of the image of `src` and the extension it is packaged with, by its
+/// content rather than its name, which may be wrong.
+fn image_type(src: &Resource) -> (String, String) {
+    let path = src.path();
+    let named = mime_guess::from_path(path).first_or_octet_stream();
+    match sniff_format(src) {
+        Some(format) if format.to_mime_type() != named.essence_str() => (
+            format.to_mime_type().to_string(),
+            format!(".{}", format.extensions_str()[0]),
+        ),
+        _ => {
+            let ext = path.extension().and_then(|e| e.to_str());
+            let ext = ext.map(|e| format!(".{e}")).unwrap_or_default();
+            (named.to_string(), ext)
+        }
+    }
+}
+
+/// Re-encodes the image of `src` as JPEG, for reading systems that cannot render its format.
+fn encode_jpeg(src: &Resource) -> Result<Resource> {
+    let image = decode(src)?;
+
+    let mut buf = Cursor::new(Vec::new());
+    image
+        .to_rgb8()
+        .write_to(&mut buf, image::ImageFormat::Jpeg)?;
+
+    Resource::generated("jpg", buf.get_ref())
+}
+
+/// Makes a blank PNG image of `width`×`height`, which compresses to a few kilobytes.
+fn placeholder(width: u32, height: u32) -> Result<Resource> {
+    let image = image::GrayImage::from_pixel(width, height, image::Luma([0xe0]));
+
+    let mut buf = Cursor::new(Vec::new());
+    image.write_to(&mut buf, image::ImageFormat::Png)?;
+
+    Resource::generated("png", buf.get_ref())
+}
+
+/// Removes comments and insignificant whitespace from `css`.
+/// Returns whether the space-separated `properties` of an item include `property`.
+pub(crate) fn has_property(properties: &str, property: &str) -> bool {
+    properties.split_whitespace().any(|p| p == property)
+}
+
+fn minify_css(css: &str) -> String {
+    /// Characters that need no whitespace after them.
+    const TIGHT_AFTER: &[char] = &['{', '}', ';', ',', '>', ':'];
+    /// Characters that need no whitespace before them.
+    const TIGHT_BEFORE: &[char] = &['{', '}', ';', ',', '>'];
+
+    let mut out = String::with_capacity(css.len());
+    let mut space = false;
+    let mut chars = css.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            let mut prev = '\0';
+            for c in chars.by_ref() {
+                if prev == '*' && c == '/' {
+                    break;
+                }
+                prev = c;
+            }
+            space = true;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+
+        if space
+            && !TIGHT_BEFORE.contains(&c)
+            && out
+                .chars()
+                .next_back()
+                .is_some_and(|c| !TIGHT_AFTER.contains(&c))
+        {
+            out.push(' ');
+        }
+        space = false;
+
+        if c == '}' && out.ends_with(';') {
+            out.pop();
+        }
+        out.push(c);
+
+        if c == '"' || c == '\'' {
+            while let Some(s) = chars.next() {
+                out.push(s);
+                if s == '\\' {
+                    out.extend(chars.next());
+                } else if s == c {
+                    break;
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn copy<R: Read, W: Write>(reader: &mut R, writer: &mut W, buf: &mut [u8]) -> std::io::Result<u64> {
+    let mut written = 0;
+    loop {
+        let len = match reader.read(buf) {
+            Ok(0) => break Ok(written),
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        };
+        writer.write_all(&buf[..len])?;
+        written += len as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BuildTarget, Creator, Images, Link, Metadata, Rendition};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Builder>();
+        assert_send_sync::<Context>();
+    }
+
+    #[test]
+    fn test_copy() {
+        let src = (0..=255).cycle().take(1000).collect::<Vec<u8>>();
+        let mut dst = Vec::new();
+        let mut buf = [0; 7];
+
+        assert_eq!(copy(&mut src.as_slice(), &mut dst, &mut buf).unwrap(), 1000);
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn test_clock_value() {
+        assert_eq!(clock_value(0.0), "0:00:00.000");
+        assert_eq!(clock_value(62.5), "0:01:02.500");
+        assert_eq!(clock_value(3723.0004), "1:02:03.000");
+    }
+
+    #[test]
+    fn test_encode_jpeg() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.webp");
+        image::RgbaImage::new(4, 3).save(&src).unwrap();
+
+        let jpeg = encode_jpeg(&src.as_path().into()).unwrap();
+        let reader = image::ImageReader::open(jpeg.path())
+            .unwrap()
+            .with_guessed_format()
+            .unwrap();
+        assert_eq!(reader.format(), Some(image::ImageFormat::Jpeg));
+        assert_eq!(reader.into_dimensions().unwrap(), (4, 3));
+    }
+
+    #[test]
+    fn test_emitter_config() {
+        let options = BuildOptions {
+            empty_elements: EmptyElements::Compact,
+            line_ending: LineEnding::Crlf,
+            ..Default::default()
+        };
+        let config = options.emitter_config().write_document_declaration(false);
+        let mut w = EventWriter::new_with_config(Vec::new(), config);
+        w.write(XmlEvent::start_element("a")).unwrap();
+        w.write(XmlEvent::start_element("b")).unwrap();
+        w.write(XmlEvent::end_element()).unwrap();
+        w.write(XmlEvent::end_element()).unwrap();
+        assert_eq!(w.into_inner(), b"<a>\r\n  <b/>\r\n</a>");
+    }
+
+    #[test]
+    fn test_zip_date_time() {
+        let time = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let date_time = zip_date_time(time).unwrap();
+        assert_eq!(
+            (date_time.year(), date_time.month(), date_time.day()),
+            (2023, 11, 14)
+        );
+        assert_eq!(
+            (date_time.hour(), date_time.minute(), date_time.second()),
+            (22, 13, 20)
+        );
+        assert_eq!(zip_date_time(OffsetDateTime::UNIX_EPOCH), None);
+    }
+
+    #[test]
+    fn test_file_name() {
+        let mut cx = Context {
+            title: "Book".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(cx.file_name(), "Book.epub");
+
+        cx.options = Arc::new(BuildOptions {
+            out_dir: Some("out".into()),
+            profile: Profile::Kobo,
+            ..Default::default()
+        });
+        assert_eq!(cx.file_name(), "Book.kobo.epub");
+
+        cx.options = Arc::new(BuildOptions {
+            name: Some("a/b".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(cx.file_name(), "a_b.epub");
+
+        cx.options = Arc::new(BuildOptions {
+            chapter: vec![2],
+            ..Default::default()
+        });
+        assert_eq!(cx.file_name(), "Book.preview.epub");
+
+        cx.options = Arc::new(BuildOptions {
+            target: Some("store".to_string()),
+            format: Format::Cbz,
+            ..Default::default()
+        });
+        assert_eq!(cx.file_name(), "Book.store.cbz");
+    }
+
+    #[test]
+    fn test_with_book_target() {
+        let book = || Book {
+            images: Images {
+                max_width: Some(1600),
+                ..Images::default()
+            },
+            targets: vec![BuildTarget {
+                name: "store".to_string(),
+                format: Some(Format::Kindle),
+                profile: Some(Profile::Kobo),
+                images: Images {
+                    quality: Some(80),
+                    ..Images::default()
+                },
+            }],
+            ..Book::default()
+        };
+        let build = |target: &str| {
+            let options = BuildOptions {
+                target: Some(target.to_string()),
+                ..Default::default()
+            };
+            Builder::with_book(
+                Path::new("."),
+                book(),
+                options,
+                Sources::default(),
+                ProbeCache::disabled(),
+                ImageCache::disabled(),
+            )
+        };
+
+        let builder = build("store").unwrap();
+        assert_eq!(builder.options.format, Format::Kindle);
+        assert_eq!(builder.options.profile, Profile::Kobo);
+        assert_eq!(builder.book.images.max_width, Some(1600));
+        assert_eq!(builder.book.images.quality, Some(80));
+
+        assert!(build("archive").is_err());
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit(1200, 1800, None, None), (1200, 1800));
+        assert_eq!(fit(1200, 1800, Some(600), None), (600, 900));
+        assert_eq!(fit(1200, 1800, Some(1000), Some(600)), (400, 600));
+        assert_eq!(fit(1200, 1800, Some(2000), Some(2000)), (1200, 1800));
+        assert_eq!(fit(1000, 1, Some(10), None), (10, 1));
+    }
+
+    #[test]
+    fn test_scale() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.png");
+        image::RgbaImage::new(8, 6).save(&src).unwrap();
+
+        let jpeg = scale(&src.as_path().into(), (4, 3), image::ImageFormat::Jpeg, 80).unwrap();
+        let reader = image::ImageReader::open(jpeg.path())
+            .unwrap()
+            .with_guessed_format()
+            .unwrap();
+        assert_eq!(reader.format(), Some(image::ImageFormat::Jpeg));
+        assert_eq!(reader.into_dimensions().unwrap(), (4, 3));
+    }
+
+    #[test]
+    fn test_crop() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.png");
+        image::RgbImage::from_fn(5, 2, |x, _| image::Rgb([x as u8, 0, 0]))
+            .save(&src)
+            .unwrap();
+
+        let half = crop(&src.as_path().into(), 2, 3, "png", JPEG_QUALITY).unwrap();
+        let half = image::open(half.path()).unwrap();
+        assert_eq!((half.width(), half.height()), (3, 2));
+        assert_eq!(half.to_rgb8().get_pixel(0, 0), &image::Rgb([2, 0, 0]));
+    }
+
+    #[test]
+    fn test_stitch_quality() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = ["a.jpg", "b.jpg"].map(|name| dir.path().join(name));
+        for path in &paths {
+            image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * y) as u8, x as u8, y as u8]))
+                .save(path)
+                .unwrap();
+        }
+
+        let size = |quality| {
+            let sources = paths.each_ref().map(|path| path.as_path().into());
+            let spread = stitch(&sources, "jpg", quality).unwrap();
+            let reader = image::ImageReader::open(spread.path())
+                .unwrap()
+                .with_guessed_format()
+                .unwrap();
+            assert_eq!(reader.format(), Some(image::ImageFormat::Jpeg));
+            spread.path().metadata().unwrap().len()
+        };
+        assert!(size(20) < size(95));
+    }
+
+    #[test]
+    fn test_add_page_split() {
+        let mut package = Package::default();
+        let path = || PathBuf::from("a.xhtml");
+        assert_eq!(package.add_page(path(), true, None), "p-cover");
+        assert_eq!(package.add_page(path(), false, Some(0)), "p-0001");
+        assert_eq!(package.add_page(path(), false, Some(1)), "p-0001-2");
+        assert_eq!(package.add_page(path(), false, None), "p-0002");
+    }
+
+    #[test]
+    fn test_add_page_covers() {
+        let mut package = Package::default();
+        let path = || PathBuf::from("a.xhtml");
+        assert_eq!(package.add_page(path(), true, None), "p-cover");
+        assert_eq!(package.add_page(path(), true, Some(0)), "p-cover-0002");
+        assert_eq!(package.add_page(path(), true, Some(1)), "p-cover-0002-2");
+        assert_eq!(package.add_page(path(), false, None), "p-0001");
+        assert_eq!(
+            package.manifest["p-cover-0002"].href,
+            "xhtml/p-cover-0002.xhtml"
+        );
+    }
+
+    #[test]
+    fn test_insert() {
+        let item = |href: &str| Item {
+            media_type: "text/css".to_string(),
+            href: href.to_string(),
+            properties: None,
+            fallback: None,
+            media_overlay: None,
+            src: PathBuf::from("a.css").into(),
+        };
+
+        let mut package = Package::default();
+        assert_eq!(package.insert("s".to_string(), item("style/a.css")), "s");
+        assert_eq!(package.insert("s".to_string(), item("style/a.css")), "s-2");
+        assert_eq!(package.insert("t".to_string(), item("style/a.css")), "t");
+        assert_eq!(package.insert(NAV_ID.to_string(), item("a.d/b")), "toc-2");
+        assert_eq!(package.manifest["s"].href, "style/a.css");
+        assert_eq!(package.manifest["s-2"].href, "style/a-2.css");
+        assert_eq!(package.manifest["t"].href, "style/a-3.css");
+        assert_eq!(package.manifest["toc-2"].href, "a.d/b");
+
+        package.insert_named("u", item("u.css")).unwrap();
+        assert!(package.insert_named("u", item("u.css")).is_err());
+        assert!(package.insert_named(NCX_ID, item("v.css")).is_err());
+    }
+
+    #[test]
+    fn test_add_image_by_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.png");
+        let b = dir.path().join("b.png");
+        std::fs::write(&a, "image").unwrap();
+        std::fs::write(&b, "image").unwrap();
+
+        let mut package = Package {
+            name_by: NameBy::Hash,
+            ..Default::default()
+        };
+        let id = package.add_image(a.as_path(), false).unwrap();
+        assert_eq!(id, "i-6105d6cc76af4003");
+        assert_eq!(package.add_image(b.as_path(), false).unwrap(), id);
+        assert_eq!(package.manifest.len(), 1);
+        assert_eq!(package.manifest[&id].href, format!("image/{id}.png"));
+    }
+
+    #[test]
+    fn test_image_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let png = dir.path().join("a.jpg");
+        image::RgbImage::new(2, 2)
+            .save_with_format(&png, image::ImageFormat::Png)
+            .unwrap();
+        assert_eq!(
+            image_type(&png.as_path().into()),
+            ("image/png".to_string(), ".png".to_string())
+        );
+
+        let unknown = dir.path().join("b.jpeg");
+        std::fs::write(&unknown, "image").unwrap();
+        assert_eq!(
+            image_type(&unknown.as_path().into()),
+            ("image/jpeg".to_string(), ".jpeg".to_string())
+        );
+
+        let mut package = Package::default();
+        let id = package.add_image(png.as_path(), false).unwrap();
+        assert_eq!(package.manifest[&id].media_type, "image/png");
+        assert_eq!(package.manifest[&id].href, "image/i-0001.png");
+    }
+
+    #[test]
+    fn test_href_path() {
+        assert_eq!(href_path("a.css").unwrap(), "a.css");
+        assert_eq!(href_path("dir\\a.css").unwrap(), "dir/a.css");
+        assert!(href_path("C:\\dir\\a.css").is_err());
+        assert!(href_path("\\\\server\\share\\a.css").is_err());
+        assert!(href_path("/a.css").is_err());
+        assert!(href_path("..\\a.css").is_err());
+        assert!(href_path("dir//a.css").is_err());
+    }
+
+    #[test]
+    fn test_is_package_path() {
+        assert!(is_package_path("mimetype"));
+        assert!(is_package_path("item/image/i-0001.png"));
+        assert!(!is_package_path(""));
+        assert!(!is_package_path("item\\image\\i-0001.png"));
+        assert!(!is_package_path("C:/item/a.png"));
+        assert!(!is_package_path("item/../../a.png"));
+    }
+
+    #[test]
+    fn test_windows_sources() {
+        // the href is made of the manifest id and the extension alone
+        let mut package = Package::default();
+        let id = package
+            .add_image(PathBuf::from("pages\\sub dir\\a.png"), false)
+            .unwrap();
+        assert_eq!(package.manifest[&id].href, "image/i-0001.png");
+
+        #[cfg(windows)]
+        {
+            let id = package
+                .add_image(PathBuf::from(r"C:\pages\b.jpg"), false)
+                .unwrap();
+            assert_eq!(package.manifest[&id].href, "image/i-0002.jpg");
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_paths() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("pages")).unwrap();
+        for src in ["a.png", "b.png", "c.png"] {
+            image::RgbImage::new(6, 9)
+                .save(root.path().join("pages").join(src))
+                .unwrap();
         }
 
-        for (collection, seq) in self.book.metadata.collection.iter().zip(1..) {
-            let refines = format!("#collection{seq}");
+        // a relative path with `\`, a path with a drive letter and a verbatim UNC path
+        let sources = [
+            PathBuf::from(r"pages\a.png"),
+            root.path().join(r"pages\b.png"),
+            root.path().canonicalize().unwrap().join(r"pages\c.png"),
+        ];
+        assert!(sources[2].to_string_lossy().starts_with(r"\\?\"));
+        let book = Book {
+            chapter: sources
+                .into_iter()
+                .map(|src| Chapter {
+                    page: vec![Page {
+                        src,
+                        ..Page::default()
+                    }],
+                    ..Chapter::default()
+                })
+                .collect(),
+            ..Book::default()
+        };
+        let options = BuildOptions {
+            no_cache: true,
+            ..Default::default()
+        };
+        let mut cx = package(root.path(), book, options, Sources::default()).unwrap();
+        for item in cx.package.manifest.values() {
+            assert!(is_package_path(&item.href), "{}", item.href);
+        }
 
-            w.write(
-                XmlEvent::start_element("meta")
-                    .attr("property", "belongs-to-collection")
-                    .attr("id", &refines[1..]),
-            )?;
-            w.write(XmlEvent::characters(&collection.name))?;
-            w.write(XmlEvent::end_element())?;
+        // the output directory keeps its separators, while the file name loses them
+        let dir = root.path().join(r"out\sub");
+        std::fs::create_dir_all(&dir).unwrap();
+        cx.title = r"a\b:c".to_string();
+        let path = cx.write_to(&dir, &mut EntryCache::disabled()).unwrap();
+        assert_eq!(path, dir.join("a_b_c.epub"));
 
-            w.write(
-                XmlEvent::start_element("meta")
-                    .attr("refines", &refines)
-                    .attr("property", "collection-type"),
-            )?;
-            w.write(XmlEvent::characters(collection.collection_type.as_ref()))?;
-            w.write(XmlEvent::end_element())?;
+        let zip = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        assert!(zip.file_names().all(is_package_path));
+    }
 
-            if let Some(value) = collection.position {
-                w.write(
-                    XmlEvent::start_element("meta")
-                        .attr("refines", &refines)
-                        .attr("property", "group-position"),
-                )?;
-                w.write(XmlEvent::characters(&value.to_string()))?;
-                w.write(XmlEvent::end_element())?;
-            }
+    #[test]
+    fn test_placeholder() {
+        let image = placeholder(300, 400).unwrap();
+        assert_eq!(image::image_dimensions(image.path()).unwrap(), (300, 400));
+        assert!(std::fs::metadata(image.path()).unwrap().len() < 4096);
+    }
+
+    #[test]
+    fn test_page_range() {
+        let range = |start, end| Ok(PageRange { start, end });
+        assert_eq!("3".parse(), range(3, 3));
+        assert_eq!("10..50".parse(), range(10, 50));
+        assert_eq!("10..".parse(), range(10, usize::MAX));
+        assert!("0..2".parse::<PageRange>().is_err());
+        assert!("5..2".parse::<PageRange>().is_err());
+        assert!("..2".parse::<PageRange>().is_err());
+    }
+
+    #[test]
+    fn test_plan_volumes() {
+        let root = tempfile::tempdir().unwrap();
+        for (name, size) in [
+            ("cover.png", 10),
+            ("a.png", 50),
+            ("b.png", 30),
+            ("c.png", 80),
+        ] {
+            std::fs::write(root.path().join(name), vec![0; size]).unwrap();
         }
 
-        w.write(XmlEvent::start_element("dc:language"))?;
-        w.write(XmlEvent::characters(&self.book.metadata.language))?;
-        w.write(XmlEvent::end_element())?;
+        let chapter = |src: &str, cover| Chapter {
+            page: vec![Page {
+                src: src.into(),
+                ..Page::default()
+            }],
+            cover,
+            ..Chapter::default()
+        };
+        let book = || Book {
+            chapter: vec![
+                chapter("cover.png", true),
+                chapter("a.png", false),
+                chapter("b.png", false),
+                chapter("c.png", false),
+            ],
+            ..Book::default()
+        };
 
-        w.write(XmlEvent::start_element("dc:identifier").attr("id", "unique-id"))?;
-        w.write(XmlEvent::characters(&self.book.metadata.identifier))?;
-        w.write(XmlEvent::end_element())?;
+        // the volumes are checked by the pages they are built with
+        let sources = |max_size| {
+            plan_volumes(root.path(), &book(), max_size)
+                .into_iter()
+                .zip(1..)
+                .map(|((chapters, findings), number)| {
+                    let volume = Volume {
+                        number,
+                        count: 0,
+                        chapters,
+                        findings,
+                    };
+                    let book = volume.apply(book()).unwrap();
+                    book.chapter
+                        .iter()
+                        .flat_map(|chapter| &chapter.page)
+                        .map(|page| page.src.to_string_lossy().into_owned())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sources(100),
+            [
+                vec!["cover.png", "a.png", "b.png"],
+                vec!["cover.png", "c.png"]
+            ]
+        );
+        assert_eq!(
+            sources(60),
+            [
+                vec!["cover.png", "a.png"],
+                vec!["cover.png", "b.png"],
+                vec!["cover.png", "c.png"],
+            ]
+        );
+        assert_eq!(
+            sources(1000),
+            [vec!["cover.png", "a.png", "b.png", "c.png"]]
+        );
 
-        w.write(XmlEvent::start_element("meta").attr("property", "dcterms:modified"))?;
-        w.write(XmlEvent::characters(
-            &OffsetDateTime::now_utc().format(&Iso8601::DEFAULT).unwrap(),
-        ))?;
-        w.write(XmlEvent::end_element())?;
+        // only the chapter that does not fit on its own is reported
+        let findings = plan_volumes(root.path(), &book(), 60)
+            .into_iter()
+            .map(|(_, findings)| findings.len())
+            .collect::<Vec<_>>();
+        assert_eq!(findings, [0, 0, 1]);
+    }
 
-        w.write(XmlEvent::start_element("meta").attr("property", "rendition:layout"))?;
-        w.write(XmlEvent::characters(self.book.rendition.layout.as_ref()))?;
-        w.write(XmlEvent::end_element())?;
+    #[test]
+    fn test_run_split() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("tsugumi.yaml"),
+            "metadata: {title: T, language: ja, identifier: id}\n\
+             chapter:\n\
+             - {cover: true, page: [cover.png]}\n\
+             - {name: A, page: [a.png]}\n\
+             - {name: B, page: [b.png]}\n\
+             - {name: C, page: [c.png]}\n",
+        )
+        .unwrap();
+        let mut sizes = Vec::new();
+        for src in ["cover.png", "a.png", "b.png", "c.png"] {
+            let path = dir.path().join(src);
+            image::RgbImage::new(6, 9).save(&path).unwrap();
+            sizes.push(path.metadata().unwrap().len());
+        }
+        let project = Project::open(dir.path()).unwrap();
 
-        w.write(XmlEvent::start_element("meta").attr("property", "rendition:orientation"))?;
-        w.write(XmlEvent::characters(
-            self.book.rendition.orientation.as_ref(),
-        ))?;
-        w.write(XmlEvent::end_element())?;
+        // the cover and two chapters fit in a volume
+        let options = BuildOptions {
+            no_cache: true,
+            ..Default::default()
+        };
+        let paths = run_split(&project, options, sizes[..3].iter().sum(), |_| {}).unwrap();
+        let pages = paths
+            .iter()
+            .map(|path| {
+                let zip = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+                zip.file_names()
+                    .filter(|name| name.starts_with("item/xhtml/"))
+                    .count()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pages, [3, 2]);
+    }
 
-        w.write(XmlEvent::start_element("meta").attr("property", "rendition:spread"))?;
-        w.write(XmlEvent::characters(self.book.rendition.spread.as_ref()))?;
-        w.write(XmlEvent::end_element())?;
+    #[test]
+    fn test_volume_identifier() {
+        let id = "urn:uuid:c4370978-757c-42f0-b3d4-c8dfdae821a1";
+        assert_ne!(volume_identifier(id, 1), volume_identifier(id, 2));
+        assert_eq!(volume_identifier(id, 1), volume_identifier(id, 1));
+        assert!(uuid::Uuid::parse_str(&volume_identifier(id, 1)).is_ok());
+        assert_eq!(volume_identifier("isbn", 2), "isbn-2");
+    }
 
-        w.write(XmlEvent::start_element("meta").attr("property", "ebpaj:guide-version"))?;
-        w.write(XmlEvent::characters("1.1.3"))?;
-        w.write(XmlEvent::end_element())?;
+    #[test]
+    fn test_select() {
+        let page = |src: &str| Page {
+            src: src.into(),
+            ..Page::default()
+        };
+        let book = || Book {
+            chapter: vec![
+                Chapter {
+                    page: vec![page("cover.png")],
+                    cover: true,
+                    ..Chapter::default()
+                },
+                Chapter {
+                    page: vec![page("a.png"), page("b.png")],
+                    ..Chapter::default()
+                },
+                Chapter {
+                    page: vec![page("c.png"), page("d.png")],
+                    ..Chapter::default()
+                },
+            ],
+            ..Book::default()
+        };
+        let sources = |book: &Book| {
+            book.chapter
+                .iter()
+                .map(|chapter| {
+                    let pages = chapter.page.iter().map(|page| page.src.clone());
+                    (chapter.name.clone(), pages.collect::<Vec<_>>())
+                })
+                .collect::<Vec<_>>()
+        };
 
-        w.write(XmlEvent::end_element())?;
+        // the cover chapter is not counted
+        let selected = select(book(), &[2], None).unwrap();
+        assert_eq!(
+            sources(&selected),
+            [
+                (None, vec![PathBuf::from("cover.png")]),
+                (
+                    Some("Chapter 2".to_string()),
+                    vec!["c.png".into(), "d.png".into()]
+                ),
+            ]
+        );
 
-        Ok(())
+        let selected = select(book(), &[], "3..4".parse().ok()).unwrap();
+        assert_eq!(
+            sources(&selected),
+            [
+                (None, vec![PathBuf::from("cover.png")]),
+                (Some("Chapter 1".to_string()), vec!["b.png".into()]),
+                (Some("Chapter 2".to_string()), vec!["c.png".into()]),
+            ]
+        );
+
+        assert!(select(book(), &[3], None).is_err());
+        assert!(select(book(), &[], "1".parse().ok()).is_err());
     }
 
-    fn write_package_manifest<W: Write>(&self, w: &mut EventWriter<W>) -> Result<()> {
-        w.write(XmlEvent::start_element("manifest"))?;
+    #[test]
+    fn test_write_package_metadata() {
+        let creator = |name: &str| Creator {
+            name: name.to_string(),
+            ..Creator::default()
+        };
+        let cx = Context {
+            book: Arc::new(Book {
+                metadata: Metadata {
+                    creator: vec![creator("A")],
+                    contributor: vec![creator("B"), creator("C")],
+                    collection: vec![Collection {
+                        name: "Series".to_string(),
+                        collection_type: CollectionType::Series,
+                        position: "3.5".parse().ok(),
+                    }],
+                    publisher: Some("P".to_string()),
+                    publication_date: "2024-05-31".parse().ok(),
+                    subject: vec!["S".to_string(), "T".to_string()],
+                    ..Metadata::default()
+                },
+                ..Book::default()
+            }),
+            ..Default::default()
+        };
 
-        w.write(
-            XmlEvent::start_element("item")
-                .attr("media-type", "application/xhtml+xml")
-                .attr("id", "toc")
-                .attr("href", "navigation-documents.xhtml")
-                .attr("properties", "nav"),
-        )?;
-        w.write(XmlEvent::end_element())?;
+        let mut w = EventWriter::new_with_config(
+            Vec::new(),
+            EmitterConfig::new().write_document_declaration(false),
+        );
+        cx.write_package_metadata(&mut w).unwrap();
+        let xml = String::from_utf8(w.into_inner()).unwrap();
+
+        assert!(xml.contains(r#"<dc:creator id="creator1">A</dc:creator>"#));
+        assert!(xml.contains(r#"<dc:contributor id="contributor1">B</dc:contributor>"#));
+        assert!(xml.contains(r#"<dc:contributor id="contributor2">C</dc:contributor>"#));
+        assert!(xml.contains(r##"<meta refines="#contributor2" property="display-seq">2</meta>"##));
+        assert_eq!(xml.matches(r#"id="creator1""#).count(), 1);
+        assert!(
+            xml.contains(r##"<meta refines="#collection1" property="group-position">3.5</meta>"##)
+        );
+        assert!(xml.contains(r#"<meta name="calibre:series_index" content="3.5""#));
+        assert!(xml.contains("<dc:publisher>P</dc:publisher><dc:date>2024-05-31</dc:date>"));
+        assert!(xml.contains("<dc:subject>S</dc:subject><dc:subject>T</dc:subject>"));
+        assert!(!xml.contains("dc:rights"));
+    }
 
-        for (id, item) in &self.manifest {
-            let mut event = XmlEvent::start_element("item")
-                .attr("media-type", &item.media_type)
-                .attr("id", id)
-                .attr("href", &item.href);
-            if let Some(properties) = &item.properties {
-                event = event.attr("properties", properties);
-            }
+    #[test]
+    fn test_kindle_metadata() {
+        let cx = Context {
+            book: Arc::new(Book {
+                rendition: Rendition {
+                    orientation: Orientation::Portrait,
+                    viewport: Some(Viewport {
+                        width: 1600,
+                        height: 2560,
+                    }),
+                    ..Rendition::default()
+                },
+                ..Book::default()
+            }),
+            options: Arc::new(BuildOptions {
+                format: Format::Kindle,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
 
-            w.write(event)?;
-            w.write(XmlEvent::end_element())?;
+        let mut w = EventWriter::new_with_config(
+            Vec::new(),
+            EmitterConfig::new().write_document_declaration(false),
+        );
+        cx.write_package_metadata(&mut w).unwrap();
+        let xml = String::from_utf8(w.into_inner()).unwrap();
+
+        for (name, content) in [
+            ("book-type", "comic"),
+            ("fixed-layout", "true"),
+            ("original-resolution", "1600x2560"),
+            ("orientation-lock", "portrait"),
+            ("primary-writing-mode", "horizontal-rl"),
+        ] {
+            let meta = format!(r#"<meta name="{name}" content="{content}" />"#);
+            assert!(xml.contains(&meta), "{meta} in {xml}");
         }
+        assert!(!xml.contains("region-mag"));
+    }
 
-        w.write(XmlEvent::end_element())?;
+    #[test]
+    fn test_write_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cx = Context {
+            title: "Book".to_string(),
+            ..Default::default()
+        };
+        cx.package.add_spine("p-0001".to_string(), None);
+
+        let path = cx
+            .write_to(dir.path(), &mut EntryCache::disabled())
+            .unwrap();
+        assert_eq!(path, dir.path().join("Book.epub"));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert!(zip::ZipArchive::new(File::open(&path).unwrap()).is_ok());
+    }
 
-        Ok(())
+    #[test]
+    fn test_write_to_reusing_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cx = Context {
+            title: "Book".to_string(),
+            ..Default::default()
+        };
+        cx.package.add_spine("p-0001".to_string(), None);
+
+        let mut cache = EntryCache::open(dir.path().join(ENTRY_CACHE));
+        let path = cx.write_to(dir.path(), &mut cache).unwrap();
+        let digests = cache.entries(&path).unwrap().clone();
+        assert!(digests.contains_key("mimetype"));
+
+        // an entry whose digest is unchanged is copied from the last file, whatever it holds
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("META-INF/container.xml", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"stale").unwrap();
+        zip.finish().unwrap();
+        cache.insert(&path, digests);
+
+        let path = cx.write_to(dir.path(), &mut cache).unwrap();
+        let mut zip = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut content = String::new();
+        zip.by_name("META-INF/container.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "stale");
+        content.clear();
+        zip.by_name("mimetype")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "application/epub+zip");
     }
 
-    fn write_package_spine<W: Write>(&self, w: &mut EventWriter<W>) -> Result<()> {
-        w.write(XmlEvent::start_element("spine").attr(
-            "page-progression-direction",
-            self.book.rendition.direction.as_ref(),
-        ))?;
+    #[test]
+    fn test_check_cover() {
+        let mut cx = Context {
+            options: Arc::new(BuildOptions {
+                strict: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        cx.check_cover();
+        assert_eq!(Status::of(&cx.check_strict().unwrap_err()), Status::Strict);
+
+        cx.warnings = AtomicUsize::new(0);
+        cx.package.manifest.insert(
+            COVER_ID.to_string(),
+            Item {
+                media_type: "image/png".to_string(),
+                href: "image/cover.png".to_string(),
+                properties: Some("cover-image".to_string()),
+                fallback: None,
+                media_overlay: None,
+                src: PathBuf::from("cover.png").into(),
+            },
+        );
+        cx.check_cover();
+        assert!(cx.check_strict().is_ok());
+    }
 
-        for item_ref in &self.spine {
-            let mut event = XmlEvent::start_element("itemref")
-                .attr("linear", if item_ref.linear { "yes" } else { "no" })
-                .attr("idref", &item_ref.id_ref);
-            if let Some(properties) = &item_ref.properties {
-                event = event.attr("properties", properties);
-            }
+    #[test]
+    fn test_lint() {
+        let page = |link| Page {
+            src: "a.png".into(),
+            link,
+            ..Default::default()
+        };
+        let cx = Context {
+            book: Arc::new(Book {
+                chapter: vec![Chapter {
+                    page: vec![
+                        page(Vec::new()),
+                        page(vec![Link {
+                            x: 0,
+                            y: 0,
+                            width: 1,
+                            height: 1,
+                            target: Target::Page(1),
+                        }]),
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
 
-            w.write(event)?;
-            w.write(XmlEvent::end_element())?;
+        let rules = |profile| {
+            cx.lint(profile)
+                .into_iter()
+                .map(|(rule, _)| rule)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rules(Profile::Generic), []);
+        assert_eq!(rules(Profile::Kindle), [Rule::MissingCover, Rule::SvgLink]);
+        assert_eq!(
+            cx.lint(Profile::Kindle)[1].1,
+            "1 page(s) have links, which Kindle does not follow"
+        );
+    }
+
+    #[test]
+    fn test_check_file_name() {
+        let cx = Context {
+            title: "Book 1/2".to_string(),
+            ..Default::default()
+        };
+        cx.check_file_name();
+        assert_eq!(cx.warnings.load(Ordering::Relaxed), 1);
+
+        let cx = Context {
+            title: "Book".to_string(),
+            ..Default::default()
+        };
+        cx.check_file_name();
+        assert_eq!(cx.warnings.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_check_consistency() {
+        let cover = |id: &str| Item {
+            media_type: "image/png".to_string(),
+            href: format!("image/{id}.png"),
+            properties: Some("cover-image".to_string()),
+            fallback: None,
+            media_overlay: None,
+            src: PathBuf::from("cover.png").into(),
+        };
+        let mut cx = Context::default();
+        let status = |cx: &Context| Status::of(&cx.check_consistency().unwrap_err());
+        assert_eq!(status(&cx), Status::Invalid);
+
+        cx.package.add_spine("p-cover".to_string(), None);
+        cx.package
+            .manifest
+            .insert("cover".to_string(), cover("cover"));
+        cx.package
+            .toc
+            .push(("p-cover".to_string(), "Cover".to_string(), 0));
+        assert!(cx.check_consistency().is_ok());
+
+        cx.package
+            .toc
+            .push(("p-0001".to_string(), "Body".to_string(), 0));
+        assert_eq!(status(&cx), Status::Invalid);
+        cx.package.toc.pop();
+
+        cx.package
+            .manifest
+            .insert("i-0001".to_string(), cover("i-0001"));
+        assert_eq!(status(&cx), Status::Invalid);
+    }
+
+    #[test]
+    fn test_build_script() {
+        let root = tempfile::tempdir().unwrap();
+        for src in ["a.png", "b.png"] {
+            image::RgbImage::new(6, 9)
+                .save(root.path().join(src))
+                .unwrap();
         }
+        std::fs::write(root.path().join("main.js"), "").unwrap();
+        let page = |src: &str| Chapter {
+            page: vec![Page {
+                src: src.into(),
+                ..Page::default()
+            }],
+            ..Chapter::default()
+        };
+        let build = |scripted, strict| {
+            let book = Book {
+                rendition: Rendition {
+                    script: vec!["main.js".into()],
+                    ..Rendition::default()
+                },
+                chapter: vec![
+                    Chapter {
+                        cover: true,
+                        ..page("a.png")
+                    },
+                    page("b.png"),
+                ],
+                ..Book::default()
+            };
+            let options = BuildOptions {
+                no_cache: true,
+                scripted,
+                strict,
+                ..Default::default()
+            };
+            package(root.path(), book, options, Sources::default())
+        };
 
-        w.write(XmlEvent::end_element())?;
+        let cx = build(true, true).unwrap();
+        let script = &cx.package.manifest["j-0001"];
+        assert_eq!(script.href, "script/main.js");
+        assert_eq!(script.media_type, "application/javascript");
+        let page = &cx.package.manifest["p-0001"];
+        assert_eq!(page.properties.as_deref(), Some("scripted svg"));
+
+        // the scripts are left out with a warning unless they are allowed
+        let cx = build(false, false).unwrap();
+        assert!(!cx.package.manifest.contains_key("j-0001"));
+        let page = &cx.package.manifest["p-0001"];
+        assert_eq!(page.properties.as_deref(), Some("svg"));
+        assert_eq!(cx.warnings.load(Ordering::Relaxed), 1);
+        let e = build(false, true).err().unwrap();
+        assert_eq!(Status::of(&e), Status::Strict);
+    }
 
-        Ok(())
+    #[test]
+    fn test_reproducible() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("tsugumi.yaml"),
+            "metadata: {title: T, language: ja, identifier: id}\n\
+             chapter:\n\
+             - {name: One, page: [a.png, b.png]}\n",
+        )
+        .unwrap();
+        for src in ["a.png", "b.png"] {
+            image::RgbImage::new(6, 9)
+                .save(dir.path().join(src))
+                .unwrap();
+        }
+        let project = Project::open(dir.path()).unwrap();
+
+        let build = || {
+            let options = BuildOptions {
+                modified: source_date(true).unwrap(),
+                ..Default::default()
+            };
+            std::fs::read(run(&project, options, |_| {}).unwrap()).unwrap()
+        };
+        let first = build();
+
+        // neither the time of the build nor that of the project file makes a difference
+        std::thread::sleep(Duration::from_millis(1100));
+        File::options()
+            .append(true)
+            .open(project.path())
+            .unwrap()
+            .set_modified(SystemTime::now())
+            .unwrap();
+        assert!(first == build());
     }
 
-    fn write_navigation(&self, zip: &mut ZipWriter<File>) -> Result<()> {
-        info!("writing navigation");
+    #[test]
+    fn test_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("tsugumi.yaml"),
+            "metadata: {title: T, language: ja, identifier: id}\n\
+             chapter:\n\
+             - {name: One, page: [a.png]}\n",
+        )
+        .unwrap();
+        image::RgbImage::new(6, 9)
+            .save(dir.path().join("a.png"))
+            .unwrap();
+        let project = Project::open(dir.path()).unwrap();
+        let build = |lock, locked| {
+            let options = BuildOptions {
+                lock,
+                locked,
+                no_cache: true,
+                ..Default::default()
+            };
+            run(&project, options, |_| {})
+        };
 
-        zip.start_file(
-            "item/navigation-documents.xhtml",
-            SimpleFileOptions::default(),
-        )?;
+        // the sources are recorded only on request
+        build(false, false).unwrap();
+        assert!(!project.lock_path().exists());
+        build(true, false).unwrap();
+        assert_eq!(project.lock_path(), dir.path().join("tsugumi.lock"));
+        assert!(!project.lock_path().starts_with(project.dir()));
+        assert!(project.lock_path().exists());
+        build(false, true).unwrap();
+
+        image::RgbImage::new(9, 6)
+            .save(dir.path().join("a.png"))
+            .unwrap();
+        let e = build(false, true).err().unwrap();
+        assert_eq!(Status::of(&e), Status::Locked);
+    }
 
-        writeln!(zip, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
-        writeln!(zip, r#"<!DOCTYPE html>"#)?;
+    #[test]
+    fn test_write_nested_toc() {
+        let root = tempfile::tempdir().unwrap();
+        let chapter = |name: &str, src: &str, children| Chapter {
+            name: Some(name.to_string()),
+            page: vec![Page {
+                src: src.into(),
+                ..Page::default()
+            }],
+            children,
+            ..Chapter::default()
+        };
+        for src in ["a.png", "b.png", "c.png"] {
+            image::RgbImage::new(6, 9)
+                .save(root.path().join(src))
+                .unwrap();
+        }
 
-        let mut w = EventWriter::new_with_config(
-            zip,
-            EmitterConfig::new()
-                .perform_indent(true)
-                .write_document_declaration(false),
+        let part = Chapter {
+            page: Vec::new(),
+            ..chapter(
+                "Part",
+                "",
+                vec![
+                    chapter("One", "a.png", vec![]),
+                    chapter("Two", "b.png", vec![]),
+                ],
+            )
+        };
+        let book = Book {
+            chapter: vec![part, chapter("Three", "c.png", vec![])],
+            ..Book::default()
+        };
+        let options = BuildOptions {
+            no_cache: true,
+            minify: true,
+            ncx: true,
+            ..Default::default()
+        };
+        let buf = package(root.path(), book, options, Sources::default())
+            .and_then(|cx| cx.write(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut zip = zip::ZipArchive::new(buf).unwrap();
+        let mut read = |name: &str| {
+            let mut xml = String::new();
+            zip.by_name(name).unwrap().read_to_string(&mut xml).unwrap();
+            xml
+        };
+
+        let entry = |href: &str, title: &str| format!(r#"<a href="{href}">{title}</a>"#);
+        let nav = read("item/navigation-documents.xhtml");
+        assert!(
+            nav.contains(&format!(
+                "<ol><li>{}<ol><li>{}</li><li>{}</li></ol></li><li>{}</li></ol>",
+                entry("xhtml/p-0001.xhtml", "Part"),
+                entry("xhtml/p-0001.xhtml", "One"),
+                entry("xhtml/p-0002.xhtml", "Two"),
+                entry("xhtml/p-0003.xhtml", "Three"),
+            )),
+            "{nav}"
         );
 
-        w.write(
-            XmlEvent::start_element("html")
-                .default_ns("http://www.w3.org/1999/xhtml")
-                .ns("epub", "http://www.idpf.org/2007/ops")
-                .attr("xml:lang", &self.book.metadata.language),
-        )?;
+        let ncx = read("item/toc.ncx");
+        assert!(
+            ncx.contains(r#"<meta name="dtb:depth" content="2" />"#),
+            "{ncx}"
+        );
+        let points = ncx
+            .split("<navPoint")
+            .skip(1)
+            .map(|point| point.matches("</navPoint>").count())
+            .collect::<Vec<_>>();
+        assert_eq!(points, [0, 1, 2, 1]);
+        let play_orders = ncx
+            .split(r#"playOrder=""#)
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .collect::<Vec<_>>();
+        assert_eq!(play_orders, ["1", "1", "2", "3"]);
+
+        let opf = read("item/standard.opf");
+        let spine = opf.split("<spine").nth(1).unwrap();
+        assert!(
+            spine[..spine.find('>').unwrap()].contains(r#"toc="ncx""#),
+            "{opf}"
+        );
+        assert!(
+            opf.contains(
+                r#"<item media-type="application/x-dtbncx+xml" id="ncx" href="toc.ncx" />"#
+            ),
+            "{opf}"
+        );
+    }
 
-        w.write(XmlEvent::start_element("head"))?;
+    #[test]
+    fn test_toc_levels() {
+        let entry = |depth| (String::new(), String::new(), depth);
+        let toc = [entry(1), entry(2), entry(2), entry(0), entry(3), entry(1)];
+        assert_eq!(toc_levels(&toc), [0, 1, 1, 0, 1, 1]);
+    }
 
-        w.write(XmlEvent::start_element("meta").attr("charset", "UTF-8"))?;
-        w.write(XmlEvent::end_element())?; // meta
+    #[test]
+    fn test_entries() {
+        let cx = Context::default();
+        let mut entries = cx.entries().map(Result::unwrap);
+
+        let mut mimetype = entries.next().unwrap();
+        let mut content = String::new();
+        mimetype.reader.read_to_string(&mut content).unwrap();
+        assert_eq!(mimetype.name, "mimetype");
+        assert_eq!(content, "application/epub+zip");
+
+        assert_eq!(
+            entries.map(|entry| entry.name).collect::<Vec<_>>(),
+            [
+                "META-INF/container.xml",
+                "item/standard.opf",
+                "item/navigation-documents.xhtml"
+            ]
+        );
+    }
 
-        w.write(XmlEvent::start_element("title"))?;
-        w.write(XmlEvent::characters("Navigation"))?;
-        w.write(XmlEvent::end_element())?; // title
+    #[test]
+    fn test_entries_bom() {
+        let read = |cx: &Context| {
+            cx.entries()
+                .map(Result::unwrap)
+                .take(2)
+                .map(|mut entry| {
+                    let mut buf = Vec::new();
+                    entry.reader.read_to_end(&mut buf).unwrap();
+                    buf
+                })
+                .collect::<Vec<_>>()
+        };
 
-        w.write(XmlEvent::end_element())?; // head
+        let entries = read(&Context::default());
+        assert_eq!(entries[0], b"application/epub+zip");
+        assert!(entries[1].starts_with(b"<?xml"));
+        assert!(!entries[1].contains(&b'\r'));
+
+        let cx = Context {
+            options: Arc::new(BuildOptions {
+                bom: true,
+                line_ending: LineEnding::Crlf,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let entries = read(&cx);
+        assert_eq!(entries[0], b"application/epub+zip");
+        assert!(entries[1].starts_with(b"\xEF\xBB\xBF<?xml"));
+        assert!(entries[1].windows(3).any(|w| w == b">\r\n"));
+    }
 
-        w.write(XmlEvent::start_element("body"))?;
-        w.write(
-            XmlEvent::start_element("nav")
-                .attr("epub:type", "toc")
-                .attr("id", "toc"),
-        )?;
+    #[test]
+    fn test_style_text() {
+        let css = "\u{feff}a {\r\n  b: c;\r\n}\n";
+        assert_eq!(BuildOptions::default().style_text(css), "a {\n  b: c;\n}\n");
 
-        w.write(XmlEvent::start_element("h1"))?;
-        w.write(XmlEvent::characters("Navigation"))?;
-        w.write(XmlEvent::end_element())?; // h1
+        let options = BuildOptions {
+            line_ending: LineEnding::Crlf,
+            ..Default::default()
+        };
+        assert_eq!(options.style_text(css), "a {\r\n  b: c;\r\n}\r\n");
 
-        w.write(XmlEvent::start_element("ol"))?;
+        let options = BuildOptions {
+            minify: true,
+            ..Default::default()
+        };
+        assert_eq!(options.style_text(css), "a{b:c}");
+    }
 
-        for (id, title) in &self.toc {
-            let item = self.manifest.get(id).unwrap();
+    #[test]
+    fn test_display_seqs() {
+        assert_eq!(display_seqs(&[None]), [None]);
+        assert_eq!(display_seqs(&[Some(1)]), [Some(1)]);
+        assert_eq!(display_seqs(&[None, None]), [Some(1), Some(2)]);
+        assert_eq!(
+            display_seqs(&[None, Some(1), None]),
+            [Some(2), Some(1), Some(3)]
+        );
+    }
 
-            w.write(XmlEvent::start_element("li"))?;
-            w.write(XmlEvent::start_element("a").attr("href", &item.href))?;
-            w.write(XmlEvent::characters(title))?;
-            w.write(XmlEvent::end_element())?; // a
-            w.write(XmlEvent::end_element())?; // li
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("タイトル 1"), "タイトル 1");
+        assert_eq!(sanitize_file_name("A/B\tC"), "A_B_C");
+        assert_eq!(sanitize_file_name(""), "_");
+        assert_eq!(sanitize_file_name(".."), "__");
+        if cfg!(windows) {
+            assert_eq!(sanitize_file_name("Re: Zero?"), "Re_ Zero_");
+            assert_eq!(sanitize_file_name("Fin."), "Fin_");
+            assert_eq!(sanitize_file_name("con"), "_con");
+            assert_eq!(sanitize_file_name("com1.x"), "_com1.x");
         }
+    }
 
-        w.write(XmlEvent::end_element())?; // ol
-        w.write(XmlEvent::end_element())?; // nav
-        w.write(XmlEvent::end_element())?; // body
-        w.write(XmlEvent::end_element())?; // html
+    #[test]
+    fn test_minify_css() {
+        let css = r#"/* page */
+body {
+  margin: 0;
+  font-family: "A  B", serif;
+}
 
-        Ok(())
+div > img:first-child, .main  svg { width: 100%; }
+"#;
+        assert_eq!(
+            minify_css(css),
+            r#"body{margin:0;font-family:"A  B",serif}div>img:first-child,.main svg{width:100%}"#
+        );
+    }
+
+    #[test]
+    fn test_find_missing() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("a.png"), []).unwrap();
+
+        let page = |src: &str| Page {
+            src: src.into(),
+            ..Page::default()
+        };
+        let book = Book {
+            chapter: vec![
+                Chapter {
+                    page: vec![page("a.png"), page("b.png")],
+                    ..Chapter::default()
+                },
+                Chapter {
+                    name: Some("Name".to_string()),
+                    page: vec![Page {
+                        join: vec!["a.png".into(), "c.png".into()],
+                        audio: Some(Audio {
+                            src: "e.mp3".into(),
+                            begin: 0.0,
+                            end: 1.0,
+                        }),
+                        ..Page::default()
+                    }],
+                    panel: Some(Panel::Sidecar("f.json".into())),
+                    ..Chapter::default()
+                },
+            ],
+            archival_cover: Some("d.png".into()),
+            rendition: Rendition {
+                script: vec!["g.js".into()],
+                ..Rendition::default()
+            },
+            ..Book::default()
+        };
+
+        let missing = find_missing(root.path(), &book, &Sources::default())
+            .into_iter()
+            .map(|m| (m.path, m.context.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            missing,
+            [
+                (Path::new("b.png"), "page 2 in chapter 1".to_string()),
+                (
+                    Path::new("f.json"),
+                    "panels of chapter \"Name\"".to_string()
+                ),
+                (Path::new("c.png"), "page 3 in chapter \"Name\"".to_string()),
+                (
+                    Path::new("e.mp3"),
+                    "audio of page 3 in chapter \"Name\"".to_string()
+                ),
+                (Path::new("d.png"), "archival cover".to_string()),
+                (Path::new("g.js"), "script of the rendition".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_empty_chapters() {
+        let chapter = |name: &str, depth, pages: usize| Chapter {
+            name: Some(name.to_string()),
+            page: vec![Page::default(); pages],
+            depth,
+            ..Chapter::default()
+        };
+        let book = Book {
+            chapter: vec![
+                chapter("Part", 0, 0),
+                chapter("One", 1, 2),
+                chapter("Empty", 1, 0),
+                chapter("Two", 0, 1),
+                chapter("Last", 0, 0),
+            ],
+            ..Book::default()
+        };
+
+        assert_eq!(
+            find_empty_chapters(&book),
+            [
+                "chapter \"Empty\" has no pages",
+                "chapter \"Last\" has no pages"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let page = |src: &str, reuse| Page {
+            src: src.into(),
+            reuse,
+            ..Page::default()
+        };
+        let book = Book {
+            chapter: vec![Chapter {
+                page: vec![
+                    page("a.png", false),
+                    page("b.png", false),
+                    page("a.png", false),
+                    page("b.png", true),
+                ],
+                ..Chapter::default()
+            }],
+            ..Book::default()
+        };
+
+        assert_eq!(
+            find_duplicates(&book),
+            ["`a.png` of page 3 is also used by page 1; \
+              set `reuse: true` on the page if this is intended"]
+        );
     }
 }
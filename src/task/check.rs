@@ -0,0 +1,626 @@
+use super::build::{
+    find_duplicates, find_empty_chapters, find_missing, missing_failure, Sources, JPEG_QUALITY,
+    PROBE_CACHE,
+};
+use crate::cache::ProbeCache;
+use crate::edit::{scalar, Segment};
+use crate::i18n::tr;
+use crate::message::{MessageFormat, Reporter, Rule};
+use crate::model::{Book, Page};
+use crate::profile::Profile;
+use crate::project::{expand_book, Project};
+use crate::status::{Failure, Status};
+use anyhow::{Context as _, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat};
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{error, info};
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    /// Fully decode every image instead of reading only its header.
+    #[arg(long)]
+    decode: bool,
+
+    /// Do not use the cache of probed images.
+    #[arg(long, env = "TSUGUMI_NO_CACHE")]
+    no_cache: bool,
+
+    /// Number of images to decode at once [default: number of CPUs]
+    #[arg(short, long, value_name = "N", env = "TSUGUMI_JOBS")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Check the dimensions of the images against the limits of the store.
+    #[arg(
+        long,
+        value_name = "STORE",
+        default_value = "generic",
+        env = "TSUGUMI_PROFILE"
+    )]
+    profile: Profile,
+
+    /// Remedy the findings that can be fixed, writing the fixed images under `.tsugumi/fixed` and
+    /// filling in the project file.
+    #[arg(long)]
+    fix: bool,
+
+    /// Overwrite the source images with the fixed ones instead of writing copies.
+    #[arg(long, requires = "fix")]
+    in_place: bool,
+
+    /// Format of the findings: log messages, a SARIF log or GitHub Actions annotations, the
+    /// latter two on the standard output.
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    message_format: MessageFormat,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::find()?;
+    let mut book = project.load()?;
+    expand_book(project.root(), &mut book);
+
+    let mut reporter = Reporter::new(args.message_format, project.root());
+    let result = check(&args, &project, &mut book, &mut reporter);
+    reporter.finish()?;
+    result
+}
+
+fn check(args: &Args, project: &Project, book: &mut Book, reporter: &mut Reporter) -> Result<()> {
+    // findings about the book itself are located in the project file
+    let file_name = Path::new(project.path().file_name().unwrap_or_default());
+
+    let mut sources = book
+        .chapter
+        .iter()
+        .flat_map(|chapter| &chapter.page)
+        .flat_map(Page::sources)
+        .map(|src| book.source_path(src))
+        .collect::<Vec<_>>();
+    sources.sort();
+    sources.dedup();
+
+    for finding in find_duplicates(book) {
+        reporter.report(Rule::DuplicateSource, finding, file_name);
+    }
+    for finding in find_empty_chapters(book) {
+        reporter.report(Rule::EmptyChapter, finding, file_name);
+    }
+
+    let missing = find_missing(project.root(), book, &Sources::default());
+    for missing in &missing {
+        reporter.report(Rule::MissingSource, missing.to_string(), file_name);
+    }
+    // go on with the sources that exist, so that every problem is reported at once
+    let missing_count = missing.len();
+    sources.retain(|src| project.root().join(src).is_file());
+
+    let findings = find_fixable(project.root(), book, &sources, args.profile);
+    if args.fix {
+        fix_all(project, book, &findings, args.in_place, reporter)?;
+    } else {
+        for finding in &findings {
+            reporter.report(
+                finding.rule(),
+                format!(
+                    "{finding}{}",
+                    tr!(", which --fix can remedy", "。--fix で修正できます")
+                ),
+                finding.path().unwrap_or(file_name),
+            );
+        }
+    }
+
+    let failures = if args.decode {
+        info!("decoding {} image(s)", sources.len());
+        let jobs = args
+            .jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get);
+        decode_all(project.root(), &sources, jobs)
+    } else {
+        let mut cache = if args.no_cache {
+            ProbeCache::disabled()
+        } else {
+            project.create_dir()?;
+            ProbeCache::open(project.cache_dir().join(PROBE_CACHE))
+        };
+
+        let failures = sources
+            .iter()
+            .filter_map(|src| {
+                let e = cache.probe(&project.root().join(src)).err()?;
+                Some((src.clone(), e))
+            })
+            .collect();
+
+        if let Err(e) = cache.save() {
+            error!("failed to save cache: {e:?}");
+        }
+
+        failures
+    };
+
+    if failures.is_empty() {
+        info!(
+            "{}",
+            tr!(
+                "{} image(s) are fine",
+                "{} 個の画像に問題はありません",
+                sources.len()
+            )
+        );
+        return match missing_count {
+            0 => Ok(()),
+            count => Err(missing_failure(count)),
+        };
+    }
+
+    for (src, e) in &failures {
+        reporter.report(
+            Rule::UnreadableImage,
+            tr!(
+                "`{}` is unreadable: {e:#}",
+                "`{}` を読み込めません: {e:#}",
+                src.display()
+            ),
+            src,
+        );
+    }
+
+    let unreadable = tr!(
+        "{} image(s) are unreadable",
+        "{} 個の画像を読み込めません",
+        failures.len()
+    );
+    if 0 < missing_count {
+        // missing sources take precedence in the exit status
+        let missing = missing_failure(missing_count);
+        return Err(
+            Failure::new(Status::MissingSources, format!("{missing}, {unreadable}")).into(),
+        );
+    }
+
+    Err(Failure::new(Status::Image, unreadable).into())
+}
+
+/// Problem with the book that `--fix` knows how to remedy.
+#[derive(Debug)]
+enum Finding {
+    /// Image rotated by its EXIF orientation, which reading systems may ignore.
+    Rotated(PathBuf, Orientation),
+
+    /// JPEG image in the CMYK color space, which few reading systems render correctly.
+    Cmyk(PathBuf),
+
+    /// Image with more pixels than the store accepts.
+    Oversized {
+        src: PathBuf,
+        width: u32,
+        height: u32,
+        pixels: u64,
+    },
+
+    /// Creator at the index without the name to sort by.
+    FileAs(usize, String),
+}
+
+impl Finding {
+    fn rule(&self) -> Rule {
+        match self {
+            Self::Rotated(..) => Rule::ExifRotation,
+            Self::Cmyk(_) => Rule::CmykImage,
+            Self::Oversized { .. } => Rule::OversizedImage,
+            Self::FileAs(..) => Rule::MissingFileAs,
+        }
+    }
+
+    /// Returns the image the finding is about, if any.
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Rotated(src, _) | Self::Cmyk(src) | Self::Oversized { src, .. } => Some(src),
+            Self::FileAs(..) => None,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rotated(src, _) => f.write_str(&tr!(
+                "`{}` is rotated by its EXIF orientation",
+                "`{}` は EXIF の向きで回転されています",
+                src.display()
+            )),
+            Self::Cmyk(src) => f.write_str(&tr!(
+                "`{}` is in the CMYK color space",
+                "`{}` は CMYK 色空間です",
+                src.display()
+            )),
+            Self::Oversized {
+                src,
+                width,
+                height,
+                pixels,
+            } => f.write_str(&tr!(
+                "`{}` is {width}x{height}, more than {pixels} pixels",
+                "`{}` は {width}x{height} で {pixels} ピクセルを超えています",
+                src.display()
+            )),
+            Self::FileAs(_, name) => f.write_str(&tr!(
+                "creator \"{name}\" has no `fileAs`",
+                "作者「{name}」に `fileAs` がありません"
+            )),
+        }
+    }
+}
+
+/// Finds the problems of `sources` and the metadata of `book` that can be fixed.
+fn find_fixable(root: &Path, book: &Book, sources: &[PathBuf], profile: Profile) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for src in sources {
+        let path = root.join(src);
+        // unreadable images are reported by the checks that follow
+        let Ok(mut decoder) = image::ImageReader::open(&path)
+            .and_then(|r| r.with_guessed_format())
+            .map_err(anyhow::Error::from)
+            .and_then(|r| r.into_decoder().map_err(anyhow::Error::from))
+        else {
+            continue;
+        };
+
+        match decoder.orientation() {
+            Ok(Orientation::NoTransforms) | Err(_) => {}
+            Ok(orientation) => findings.push(Finding::Rotated(src.clone(), orientation)),
+        }
+
+        let (width, height) = decoder.dimensions();
+        if let Some(pixels) = profile.limits().image_pixels {
+            if pixels < u64::from(width) * u64::from(height) {
+                findings.push(Finding::Oversized {
+                    src: src.clone(),
+                    width,
+                    height,
+                    pixels,
+                });
+            }
+        }
+
+        if ImageFormat::from_path(&path).ok() == Some(ImageFormat::Jpeg)
+            && std::fs::read(&path).is_ok_and(|data| jpeg_components(&data) == Some(4))
+        {
+            findings.push(Finding::Cmyk(src.clone()));
+        }
+    }
+
+    for (i, creator) in book.metadata.creator.iter().enumerate() {
+        if creator.file_as.is_none() {
+            findings.push(Finding::FileAs(i, creator.name.clone()));
+        }
+    }
+
+    findings
+}
+
+/// Applies the remedies for `findings`, and reports what has been changed.
+///
+/// The fixed images are written under `.tsugumi/fixed` at the paths of their sources, or over the
+/// sources if `in_place` is set.
+fn fix_all(
+    project: &Project,
+    book: &mut Book,
+    findings: &[Finding],
+    in_place: bool,
+    reporter: &mut Reporter,
+) -> Result<()> {
+    let file_name = Path::new(project.path().file_name().unwrap_or_default());
+    let quality = book.images.quality.unwrap_or(JPEG_QUALITY);
+
+    // every finding about an image is remedied at once, so that it is encoded only once
+    let mut images = Vec::<(&Path, Vec<&Finding>)>::new();
+    for finding in findings {
+        let Some(src) = finding.path() else {
+            continue;
+        };
+        match images.iter_mut().find(|(path, _)| *path == src) {
+            Some((_, group)) => group.push(finding),
+            None => images.push((src, vec![finding])),
+        }
+    }
+
+    if !images.is_empty() && !in_place {
+        project.create_dir()?;
+    }
+    for (src, group) in &images {
+        let dest = if in_place {
+            project.root().join(src)
+        } else {
+            project.dir().join(FIXED_DIR).join(fixed_path(src))
+        };
+        fix_image(&project.root().join(src), &dest, group, quality)?;
+    }
+    if !images.is_empty() && !in_place {
+        info!(
+            "{}",
+            tr!(
+                "wrote the fixed images in `{}`, which --in-place writes over the sources instead",
+                "修正した画像を `{}` に書き出しました。--in-place で元の画像を上書きします",
+                project.dir().join(FIXED_DIR).display()
+            )
+        );
+    }
+
+    let mut filled = Vec::new();
+
+    for finding in findings {
+        let fixed = match finding {
+            Finding::Rotated(..) | Finding::Cmyk(_) | Finding::Oversized { .. } => true,
+            Finding::FileAs(i, name) => match file_as(name) {
+                Some(file_as) => {
+                    book.metadata.creator[*i].file_as = Some(file_as);
+                    filled.push(*i);
+                    true
+                }
+                None => false,
+            },
+        };
+
+        if fixed {
+            info!("{}", tr!("fixed: {finding}", "修正しました: {finding}"));
+        } else {
+            reporter.report(
+                finding.rule(),
+                tr!(
+                    "{finding}, which must be fixed by hand",
+                    "{finding}。手動で修正してください"
+                ),
+                finding.path().unwrap_or(file_name),
+            );
+        }
+    }
+
+    if !filled.is_empty() {
+        // the pages are written back as they are given rather than expanded
+        let mut saved = project.load()?;
+        saved.metadata = std::mem::take(&mut book.metadata);
+        project.edit(&saved, |document| {
+            for &i in &filled {
+                let file_as = saved.metadata.creator[i]
+                    .file_as
+                    .as_deref()
+                    .unwrap_or_default();
+                let path = [
+                    Segment::Key("metadata"),
+                    Segment::Key("creator"),
+                    Segment::Index(i),
+                ];
+                document.expand(&path, "name")?;
+                document.set(&path, "fileAs", Some(&scalar(file_as)?))?;
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Directory in the project data directory that the fixed images are written in.
+const FIXED_DIR: &str = "fixed";
+
+/// Returns the path under [`FIXED_DIR`] the fixed copy of the image at `src` is written at, which
+/// stays in the directory even if `src` is absolute or goes up from the project.
+fn fixed_path(src: &Path) -> PathBuf {
+    src.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+/// Decodes the image at `src`, applies the remedies for `findings` to it, and writes it at `dest`
+/// in the same format, with `quality` if it is JPEG.
+fn fix_image(src: &Path, dest: &Path, findings: &[&Finding], quality: u8) -> Result<()> {
+    let decoder = image::ImageReader::open(src)
+        .and_then(|r| r.with_guessed_format())
+        .with_context(|| format!("failed to read `{}`", src.display()))?
+        .into_decoder()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+
+    // rotate first, so that the image is resized by its dimensions as displayed
+    for finding in findings {
+        if let Finding::Rotated(_, orientation) = finding {
+            image.apply_orientation(*orientation);
+        }
+    }
+    for finding in findings {
+        if let Finding::Oversized { pixels, .. } = finding {
+            let (width, height) = fit_pixels(image.width(), image.height(), *pixels);
+            image = image.resize(width, height, image::imageops::FilterType::Lanczos3);
+        }
+    }
+    // the decoder converts CMYK to RGB, which is kept by encoding the image again
+
+    let dir = dest.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create `{}`", dir.display()))?;
+    // the source is replaced only once the image is complete
+    let file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("failed to create a file in `{}`", dir.display()))?;
+    let format = ImageFormat::from_path(src)?;
+    if format == ImageFormat::Jpeg {
+        let encoder =
+            JpegEncoder::new_with_quality(std::io::BufWriter::new(file.as_file()), quality);
+        image.to_rgb8().write_with_encoder(encoder)
+    } else {
+        image.write_to(&mut std::io::BufWriter::new(file.as_file()), format)
+    }
+    .with_context(|| format!("failed to write `{}`", dest.display()))?;
+    file.persist(dest)
+        .with_context(|| format!("failed to write `{}`", dest.display()))?;
+
+    Ok(())
+}
+
+/// Returns the largest dimensions with the aspect ratio of `width`×`height` within `pixels`.
+fn fit_pixels(width: u32, height: u32, pixels: u64) -> (u32, u32) {
+    let scale = (pixels as f64 / (f64::from(width) * f64::from(height))).sqrt();
+    let width = (f64::from(width) * scale).floor() as u32;
+    let height = (f64::from(height) * scale).floor() as u32;
+    (width.max(1), height.max(1))
+}
+
+/// Returns the name of the creator as sorted by the family name, like "Doe, John".
+///
+/// Only Latin names are converted, because the reading of other scripts cannot be derived.
+fn file_as(name: &str) -> Option<String> {
+    let words = name.split_whitespace().collect::<Vec<_>>();
+    if words.len() < 2 || name.contains(',') || !name.is_ascii() {
+        return None;
+    }
+
+    let (family, given) = words.split_last()?;
+    Some(format!("{family}, {}", given.join(" ")))
+}
+
+/// Returns the number of color components in the frame header of the JPEG image in `data`.
+fn jpeg_components(data: &[u8]) -> Option<u8> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        if data[i] != 0xFF {
+            return None;
+        }
+
+        let marker = data[i + 1];
+        if marker == 0xFF {
+            // fill byte
+            i += 1;
+            continue;
+        }
+
+        // SOF0 to SOF15, except DHT, JPG and DAC
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            return data.get(i + 9).copied();
+        }
+
+        i += 2 + usize::from(u16::from_be_bytes([data[i + 2], data[i + 3]]));
+    }
+
+    None
+}
+
+/// Decodes `sources` on `jobs` threads and returns the ones that failed.
+fn decode_all(root: &Path, sources: &[PathBuf], jobs: usize) -> Vec<(PathBuf, anyhow::Error)> {
+    let chunk_size = sources.len().div_ceil(jobs).max(1);
+
+    let failures = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for chunk in sources.chunks(chunk_size) {
+            let failures = &failures;
+            scope.spawn(move || {
+                for src in chunk {
+                    let path = root.join(src);
+                    let result = image::ImageReader::open(&path)
+                        .and_then(|r| r.with_guessed_format())
+                        .map_err(anyhow::Error::from)
+                        .and_then(|r| r.decode().map_err(anyhow::Error::from));
+                    if let Err(e) = result {
+                        failures.lock().unwrap().push((src.to_path_buf(), e));
+                    }
+                }
+            });
+        }
+    });
+
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort_by(|a, b| a.0.cmp(&b.0));
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_all() {
+        let dir = tempfile::tempdir().unwrap();
+        image::RgbImage::new(2, 2)
+            .save(dir.path().join("good.png"))
+            .unwrap();
+
+        let mut png = std::fs::read(dir.path().join("good.png")).unwrap();
+        png.truncate(png.len() / 2);
+        std::fs::write(dir.path().join("truncated.png"), png).unwrap();
+
+        let failures = decode_all(dir.path(), &["good.png".into(), "truncated.png".into()], 2);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, Path::new("truncated.png"));
+    }
+
+    #[test]
+    fn test_jpeg_components() {
+        let mut jpeg = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(2, 2)
+            .write_to(&mut jpeg, ImageFormat::Jpeg)
+            .unwrap();
+        assert_eq!(jpeg_components(jpeg.get_ref()), Some(3));
+
+        // SOI, an empty APP0 and a baseline frame header with 4 components
+        let cmyk = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x02, 0xFF, 0xC0, 0x00, 0x14, 0x08, 0x00, 0x02, 0x00,
+            0x02, 0x04,
+        ];
+        assert_eq!(jpeg_components(&cmyk), Some(4));
+        assert_eq!(jpeg_components(b"\x89PNG"), None);
+    }
+
+    #[test]
+    fn test_fix_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("page.jpg");
+        image::RgbImage::new(40, 20).save(&src).unwrap();
+        let original = std::fs::read(&src).unwrap();
+
+        let findings = [
+            Finding::Rotated(src.clone(), Orientation::Rotate90),
+            Finding::Oversized {
+                src: src.clone(),
+                width: 40,
+                height: 20,
+                pixels: 200,
+            },
+        ];
+        let dest = dir.path().join("fixed/page.jpg");
+        fix_image(&src, &dest, &findings.iter().collect::<Vec<_>>(), 95).unwrap();
+
+        // resized after the rotation, and the source is left alone
+        let fixed = image::open(&dest).unwrap();
+        assert_eq!((fixed.width(), fixed.height()), (10, 20));
+        assert_eq!(std::fs::read(&src).unwrap(), original);
+
+        assert_eq!(
+            fixed_path(Path::new("scans/a.jpg")),
+            Path::new("scans/a.jpg")
+        );
+        assert_eq!(
+            fixed_path(Path::new("../scans/a.jpg")),
+            Path::new("scans/a.jpg")
+        );
+    }
+
+    #[test]
+    fn test_fix_helpers() {
+        assert_eq!(fit_pixels(4000, 2000, 2_000_000), (2000, 1000));
+        assert_eq!(
+            file_as("John Ronald Doe"),
+            Some("Doe, John Ronald".to_string())
+        );
+        assert_eq!(file_as("山田 太郎"), None);
+        assert_eq!(file_as("Doe, John"), None);
+    }
+}
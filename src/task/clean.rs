@@ -0,0 +1,32 @@
+use crate::project::{dir_size, format_size, Project};
+use anyhow::{Context as _, Result};
+use tracing::info;
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    /// Remove the build cache.
+    #[arg(long)]
+    cache: bool,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::find()?;
+
+    let dir = if args.cache {
+        project.cache_dir()
+    } else {
+        project.dir()
+    };
+
+    if !dir.exists() {
+        info!("nothing to clean");
+        return Ok(());
+    }
+
+    let size = dir_size(&dir).unwrap_or_default();
+    std::fs::remove_dir_all(&dir)
+        .with_context(|| format!("failed to remove `{}`", dir.display()))?;
+    info!("removed `{}` ({})", dir.display(), format_size(size));
+
+    Ok(())
+}
@@ -0,0 +1,364 @@
+use super::build::{
+    self, find_duplicates, find_empty_chapters, find_missing, BuildOptions, Sources, PROBE_CACHE,
+};
+use super::watch::{changed_paths, stamps};
+use crate::cache::ProbeCache;
+use crate::message::Rule;
+use crate::profile::Profile;
+use crate::project::{expand_book, Project};
+use anyhow::Result;
+use clap::ValueEnum as _;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// JSON-RPC error code of a message that is not JSON.
+const PARSE_ERROR: i64 = -32700;
+
+/// JSON-RPC error code of a message that is not a request.
+const INVALID_REQUEST: i64 = -32600;
+
+/// JSON-RPC error code of an unknown method.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// JSON-RPC error code of malformed parameters.
+const INVALID_PARAMS: i64 = -32602;
+
+/// JSON-RPC error code of a request that tsugumi failed to carry out.
+const REQUEST_FAILED: i64 = -32000;
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    /// Project directory or project file to serve instead of finding it from the current directory.
+    #[arg(value_name = "PROJECT", value_hint = clap::ValueHint::AnyPath)]
+    project: Option<PathBuf>,
+
+    /// Milliseconds between checks for changed files while watching.
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    poll_interval: u64,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::open_or_find(args.project.as_deref())?;
+    project.create_dir()?;
+
+    info!(
+        "serving `{}` over JSON-RPC on the standard input and output",
+        project.path().display()
+    );
+
+    let mut server = Server::new(project, Duration::from_millis(args.poll_interval));
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(response) = server.handle(&line) {
+            server.send(&response)?;
+        }
+        if server.shutdown {
+            break;
+        }
+    }
+
+    if let Err(e) = server.cache.save() {
+        warn!("failed to save cache: {e:#}");
+    }
+
+    Ok(())
+}
+
+/// State kept between the requests of a client.
+struct Server {
+    project: Arc<Project>,
+    cache: ProbeCache,
+    output: Arc<Mutex<std::io::Stdout>>,
+    poll_interval: Duration,
+    watching: bool,
+    shutdown: bool,
+}
+
+struct Error {
+    code: i64,
+    message: String,
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Self {
+            code: REQUEST_FAILED,
+            message: format!("{e:#}"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct ProbePageParams {
+    /// Path to the image as written as the source of a page.
+    src: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct BuildParams {
+    output: Option<PathBuf>,
+    profile: Option<String>,
+}
+
+impl Server {
+    fn new(project: Project, poll_interval: Duration) -> Self {
+        let cache = ProbeCache::open(project.cache_dir().join(PROBE_CACHE));
+
+        Self {
+            project: Arc::new(project),
+            cache,
+            output: Arc::new(Mutex::new(std::io::stdout())),
+            poll_interval,
+            watching: false,
+            shutdown: false,
+        }
+    }
+
+    /// Writes `message` as a line to the client.
+    fn send(&self, message: &Value) -> Result<()> {
+        send(&self.output, message)
+    }
+
+    /// Handles a line of the client, returning the response unless it is a notification.
+    fn handle(&mut self, line: &str) -> Option<Value> {
+        let request = match serde_json::from_str::<Value>(line) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, e.to_string())),
+        };
+
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            let id = id.unwrap_or(Value::Null);
+            return Some(error_response(id, INVALID_REQUEST, "missing method"));
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = self.call(method, params);
+
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => error_response(id, e.code, e.message),
+        })
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, Error> {
+        match method {
+            "validateManifest" => self.validate_manifest(),
+            "probePage" => self.probe_page(parse_params(params)?),
+            "build" => self.build(parse_params(params)?),
+            "watch" => Ok(self.watch()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            method => Err(Error {
+                code: METHOD_NOT_FOUND,
+                message: format!("unknown method `{method}`"),
+            }),
+        }
+    }
+
+    /// Reads the project file and lists the problems of the book.
+    fn validate_manifest(&self) -> Result<Value, Error> {
+        let mut book = match self.project.load() {
+            Ok(book) => book,
+            Err(e) => {
+                return Ok(json!({
+                    "findings": [{ "level": "error", "message": format!("{e:#}") }],
+                }))
+            }
+        };
+        expand_book(self.project.root(), &mut book);
+
+        let finding = |rule: Rule, message: String| json!({ "ruleId": rule.id(), "level": rule.level(), "message": message });
+        let findings = find_duplicates(&book)
+            .into_iter()
+            .map(|message| finding(Rule::DuplicateSource, message))
+            .chain(
+                find_empty_chapters(&book)
+                    .into_iter()
+                    .map(|message| finding(Rule::EmptyChapter, message)),
+            )
+            .chain(
+                find_missing(self.project.root(), &book, &Sources::default())
+                    .iter()
+                    .map(|missing| finding(Rule::MissingSource, missing.to_string())),
+            )
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "findings": findings }))
+    }
+
+    /// Reads the dimensions and the format of an image.
+    fn probe_page(&mut self, params: ProbePageParams) -> Result<Value, Error> {
+        let book = self.project.load()?;
+        let path = self.project.root().join(book.source_path(&params.src));
+        let probe = self.cache.probe(&path)?;
+
+        Ok(json!({
+            "width": probe.width,
+            "height": probe.height,
+            "mediaType": probe.media_type,
+        }))
+    }
+
+    /// Builds the book and returns the path to the EPub file.
+    fn build(&mut self, params: BuildParams) -> Result<Value, Error> {
+        let profile = match &params.profile {
+            Some(profile) => Profile::from_str(profile, true).map_err(|e| Error {
+                code: INVALID_PARAMS,
+                message: e,
+            })?,
+            None => Profile::default(),
+        };
+
+        // the builder reads the probes the daemon has cached so far
+        if let Err(e) = self.cache.save() {
+            warn!("failed to save cache: {e:#}");
+        }
+
+        let options = BuildOptions {
+            output: params.output,
+            profile,
+            ..Default::default()
+        };
+        let path = build::run(&self.project, options, |_| {})?;
+        self.cache = ProbeCache::open(self.project.cache_dir().join(PROBE_CACHE));
+
+        Ok(json!({ "path": path }))
+    }
+
+    /// Starts sending `didChange` notifications when the project file or a page source changes.
+    fn watch(&mut self) -> Value {
+        if self.watching {
+            return json!(false);
+        }
+        self.watching = true;
+
+        let project = Arc::clone(&self.project);
+        let output = Arc::clone(&self.output);
+        let interval = self.poll_interval;
+        std::thread::spawn(move || {
+            let mut before = stamps(&project);
+            loop {
+                std::thread::sleep(interval);
+
+                let after = stamps(&project);
+                let changed = changed_paths(&before, &after);
+                before = after;
+
+                if changed.is_empty() {
+                    continue;
+                }
+
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "didChange",
+                    "params": { "paths": changed },
+                });
+                if send(&output, &notification).is_err() {
+                    break;
+                }
+            }
+        });
+
+        json!(true)
+    }
+}
+
+fn send(output: &Mutex<std::io::Stdout>, message: &Value) -> Result<()> {
+    let mut output = output.lock().unwrap();
+    writeln!(output, "{message}")?;
+    output.flush()?;
+    Ok(())
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, Error> {
+    // omitted parameters are the same as no parameters
+    let params = if params.is_null() { json!({}) } else { params };
+
+    serde_json::from_value(params).map_err(|e| Error {
+        code: INVALID_PARAMS,
+        message: e.to_string(),
+    })
+}
+
+fn error_response(id: Value, code: i64, message: impl Into<String>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message.into() },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("tsugumi.yaml"),
+            "metadata: {title: T, language: ja, identifier: id}\nchapter: {page: [a.png, b.png]}\n",
+        )
+        .unwrap();
+        image::RgbImage::new(3, 2)
+            .save(dir.path().join("a.png"))
+            .unwrap();
+
+        let project = Project::open(dir.path()).unwrap();
+        let mut server = Server::new(project, Duration::from_millis(10));
+
+        let response = server
+            .handle(r#"{"jsonrpc":"2.0","id":1,"method":"validateManifest"}"#)
+            .unwrap();
+        let findings = response["result"]["findings"].as_array().unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0]["ruleId"], "TSG001");
+
+        let response = server
+            .handle(r#"{"jsonrpc":"2.0","id":2,"method":"probePage","params":{"src":"a.png"}}"#)
+            .unwrap();
+        assert_eq!(
+            response["result"],
+            json!({ "width": 3, "height": 2, "mediaType": "image/png" })
+        );
+
+        let response = server
+            .handle(r#"{"jsonrpc":"2.0","id":3,"method":"probePage","params":{}}"#)
+            .unwrap();
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+
+        let response = server
+            .handle(r#"{"jsonrpc":"2.0","id":4,"method":"nothing"}"#)
+            .unwrap();
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        assert_eq!(
+            server.handle("{"),
+            Some(error_response(
+                Value::Null,
+                PARSE_ERROR,
+                "EOF while parsing an object at line 1 column 1"
+            ))
+        );
+        assert_eq!(
+            server.handle(r#"{"jsonrpc":"2.0","method":"shutdown"}"#),
+            None
+        );
+        assert!(server.shutdown);
+    }
+}
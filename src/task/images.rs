@@ -0,0 +1,292 @@
+use super::build::{fit, scale, target_format, Resource, JPEG_QUALITY};
+use crate::cache::Probe;
+use crate::i18n::tr;
+use crate::model::PageFormat;
+use crate::project::{is_image, natural_cmp};
+use anyhow::{Context as _, Result};
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn, Span};
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Scale down and convert images as a build does, without a book.
+    Convert(ConvertArgs),
+}
+
+#[derive(clap::Args)]
+struct ConvertArgs {
+    /// Image file, or directory of images, to convert.
+    #[arg(value_hint = clap::ValueHint::AnyPath)]
+    src: PathBuf,
+
+    /// Directory to write the converted images in, which is created if it does not exist.
+    #[arg(value_hint = clap::ValueHint::DirPath)]
+    dest: PathBuf,
+
+    /// Width in pixels to scale the images down to fit in.
+    #[arg(long, value_name = "PX")]
+    max_width: Option<u32>,
+
+    /// Height in pixels to scale the images down to fit in.
+    #[arg(long, value_name = "PX")]
+    max_height: Option<u32>,
+
+    /// Quality from 1 to 100 of the JPEG images encoded [default: 90]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+
+    /// Format to convert the images to [default: the format of each]
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<PageFormat>,
+
+    /// Number of images to convert at once [default: number of CPUs]
+    #[arg(short, long, value_name = "N", env = "TSUGUMI_JOBS")]
+    jobs: Option<NonZeroUsize>,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    match args.command {
+        Command::Convert(args) => convert(args),
+    }
+}
+
+fn convert(args: ConvertArgs) -> Result<()> {
+    let sources = sources(&args.src)?;
+    let sources = destinations(&args, sources);
+    std::fs::create_dir_all(&args.dest)
+        .with_context(|| format!("failed to create `{}`", args.dest.display()))?;
+
+    let jobs = args
+        .jobs
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let chunk_size = sources.len().div_ceil(jobs).max(1);
+
+    info!(
+        "{}",
+        tr!(
+            "converting {} image(s) on {jobs} thread(s)",
+            "{} 枚の画像を {jobs} スレッドで変換しています",
+            sources.len()
+        )
+    );
+
+    let span = Span::current();
+    std::thread::scope(|scope| {
+        let handles = sources
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let span = span.clone();
+                let args = &args;
+                scope.spawn(move || {
+                    let _enter = span.enter();
+                    chunk
+                        .iter()
+                        .try_for_each(|(src, dest)| convert_image(args, src, dest))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap())
+    })?;
+
+    info!(
+        "{}",
+        tr!(
+            "converted {} image(s) into `{}`",
+            "{} 枚の画像を `{}` に変換しました",
+            sources.len(),
+            args.dest.display()
+        )
+    );
+    Ok(())
+}
+
+/// Returns the images `src` stands for, which is an image itself or a directory of them.
+fn sources(src: &Path) -> Result<Vec<PathBuf>> {
+    if src.is_file() {
+        return Ok(vec![src.to_path_buf()]);
+    }
+
+    let entries =
+        std::fs::read_dir(src).with_context(|| format!("failed to open `{}`", src.display()))?;
+    let mut sources = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && is_image(path))
+        .filter(|path| {
+            // images such as SVG and PSD are named as images, but cannot be decoded
+            let readable = image::ImageFormat::from_path(path).is_ok_and(|f| f.reading_enabled());
+            if !readable {
+                warn!(
+                    "{}",
+                    tr!(
+                        "skipping `{}`, which is not in a format that can be converted",
+                        "`{}` は変換できない形式のため飛ばします",
+                        path.display()
+                    )
+                );
+            }
+            readable
+        })
+        .collect::<Vec<_>>();
+    if sources.is_empty() {
+        anyhow::bail!(tr!(
+            "`{}` contains no images",
+            "`{}` に画像がありません",
+            src.display()
+        ));
+    }
+
+    sources.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    Ok(sources)
+}
+
+/// Pairs each of `sources` with the path in the destination it is converted to, numbering apart
+/// the ones that would have the same name, such as `a.png` and `a.jpg` converted to JPEG, which
+/// would be written over each other.
+fn destinations(args: &ConvertArgs, sources: Vec<PathBuf>) -> Vec<(PathBuf, PathBuf)> {
+    // names that differ only in case are the same file on some file systems
+    let mut taken = HashSet::new();
+    sources
+        .into_iter()
+        .map(|src| {
+            let (_, _, ext) = target_format(&Resource::from(src.as_path()), args.format);
+            let stem = src.file_stem().unwrap_or_default().to_string_lossy();
+            let mut name = format!("{stem}.{ext}");
+            let mut n = 1;
+            while !taken.insert(name.to_lowercase()) {
+                n += 1;
+                name = format!("{stem}-{n}.{ext}");
+            }
+
+            let dest = args.dest.join(&name);
+            if 1 < n {
+                warn!(
+                    "{}",
+                    tr!(
+                        "converting `{}` to `{name}`, as another image has its name",
+                        "別の画像と名前が重なるため `{}` を `{name}` に変換します",
+                        src.display()
+                    )
+                );
+            }
+            (src, dest)
+        })
+        .collect()
+}
+
+/// Scales down and converts the image at `src` into `dest` as `args` asks. An image that needs
+/// neither is copied as is, unless it is JPEG and the quality is given to encode it with.
+fn convert_image(args: &ConvertArgs, src: &Path, dest: &Path) -> Result<()> {
+    let Probe { width, height, .. } = Probe::read(src)?;
+    let scaled = fit(width, height, args.max_width, args.max_height);
+    let source = Resource::from(src);
+    let (source_format, format, ext) = target_format(&source, args.format);
+
+    if dest.canonicalize().ok() == src.canonicalize().ok() {
+        anyhow::bail!(tr!(
+            "`{}` would be overwritten by its own conversion",
+            "`{}` が変換結果で上書きされてしまいます",
+            src.display()
+        ));
+    }
+
+    let recompress = args.quality.is_some() && format == image::ImageFormat::Jpeg;
+    if (width, height) == scaled && source_format == Some(format) && !recompress {
+        debug!("copying {}", src.display());
+        std::fs::copy(src, dest)
+            .with_context(|| format!("failed to write `{}`", dest.display()))?;
+        return Ok(());
+    }
+
+    debug!(
+        "scaling {} to {}x{} as {ext}",
+        src.display(),
+        scaled.0,
+        scaled.1
+    );
+    let quality = args.quality.unwrap_or(JPEG_QUALITY);
+    let image = scale(&source, scaled, format, quality)?;
+    std::fs::copy(image.path(), dest)
+        .with_context(|| format!("failed to write `{}`", dest.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.png");
+        image::RgbImage::new(400, 600).save(&src).unwrap();
+        let args = |max_width, format| ConvertArgs {
+            src: src.clone(),
+            dest: dir.path().join("out"),
+            max_width,
+            max_height: None,
+            quality: None,
+            format,
+            jobs: None,
+        };
+        std::fs::create_dir(dir.path().join("out")).unwrap();
+
+        let dest = dir.path().join("out/a.jpg");
+        convert_image(&args(Some(200), Some(PageFormat::Jpeg)), &src, &dest).unwrap();
+        let probe = Probe::read(&dest).unwrap();
+        assert_eq!((probe.width, probe.height), (200, 300));
+        assert_eq!(probe.media_type, "image/jpeg");
+
+        let dest = dir.path().join("out/a.png");
+        convert_image(&args(Some(800), None), &src, &dest).unwrap();
+        assert_eq!(std::fs::read(dest).unwrap(), std::fs::read(&src).unwrap());
+
+        assert!(convert_image(&args(None, None), &src, &src).is_err());
+    }
+
+    #[test]
+    fn test_destinations() {
+        let dir = tempfile::tempdir().unwrap();
+        image::RgbImage::new(2, 2)
+            .save(dir.path().join("a.png"))
+            .unwrap();
+        image::RgbImage::new(2, 2)
+            .save(dir.path().join("a.jpg"))
+            .unwrap();
+        image::RgbImage::new(2, 2)
+            .save(dir.path().join("b.png"))
+            .unwrap();
+        std::fs::write(dir.path().join("c.svg"), "<svg/>").unwrap();
+
+        // the SVG image is skipped rather than failing the conversion
+        let sources = sources(dir.path()).unwrap();
+        let args = ConvertArgs {
+            src: dir.path().to_path_buf(),
+            dest: PathBuf::from("out"),
+            max_width: None,
+            max_height: None,
+            quality: None,
+            format: Some(PageFormat::Jpeg),
+            jobs: None,
+        };
+        let names = destinations(&args, sources)
+            .into_iter()
+            .map(|(_, dest)| dest)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["out/a.jpg", "out/a-2.jpg", "out/b.jpg"].map(PathBuf::from)
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use super::build::PROBE_CACHE;
+use crate::cache::ProbeCache;
+use crate::model::{Book, Page};
+use crate::project::{expand_book, Project};
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    /// Format of the listing.
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    format: Format,
+
+    /// Do not use the cache of probed images.
+    #[arg(long, env = "TSUGUMI_NO_CACHE")]
+    no_cache: bool,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Serialize)]
+struct ChapterEntry {
+    name: Option<String>,
+    cover: bool,
+    page: Vec<PageEntry>,
+}
+
+#[derive(Serialize)]
+struct PageEntry {
+    /// Position of the page in the spine, starting at 1.
+    index: usize,
+    src: Vec<PathBuf>,
+    exists: bool,
+    width: Option<u32>,
+    height: Option<u32>,
+    spread: Option<&'static str>,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::find()?;
+    let mut book = project.load()?;
+    expand_book(project.root(), &mut book);
+
+    let mut cache = if args.no_cache {
+        ProbeCache::disabled()
+    } else {
+        ProbeCache::open(project.cache_dir().join(PROBE_CACHE))
+    };
+
+    let mut index = 0;
+    let chapters = book
+        .chapter
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| ChapterEntry {
+            name: chapter.name.clone(),
+            cover: chapter.cover,
+            page: chapter
+                .page
+                .iter()
+                .enumerate()
+                .map(|(j, page)| {
+                    index += 1;
+                    let cover = book.is_cover(i, j);
+                    list_page(project.root(), &book, &mut cache, index, page, cover)
+                })
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+
+    if let Err(e) = cache.save() {
+        warn!("failed to save cache: {e:?}");
+    }
+
+    match args.format {
+        Format::Text => print_text(&chapters),
+        Format::Json => {
+            serde_json::to_writer_pretty(std::io::stdout().lock(), &chapters)?;
+            println!();
+        }
+    }
+
+    Ok(())
+}
+
+fn list_page(
+    root: &Path,
+    book: &Book,
+    cache: &mut ProbeCache,
+    index: usize,
+    page: &Page,
+    cover: bool,
+) -> PageEntry {
+    let sources = page.sources();
+    let exists = sources
+        .iter()
+        .all(|src| root.join(book.source_path(src)).is_file());
+
+    // joined pages are laid out side by side
+    let (width, height) = if exists {
+        sources
+            .iter()
+            .map(|src| cache.probe(&root.join(book.source_path(src))).ok())
+            .try_fold((0, 0), |(width, height), probe| {
+                probe.map(|p| (width + p.width, height.max(p.height)))
+            })
+            .unzip()
+    } else {
+        (None, None)
+    };
+
+    let spread = if cover || !page.join.is_empty() {
+        Some("center")
+    } else {
+        None
+    };
+
+    PageEntry {
+        index,
+        src: sources.into_iter().map(Path::to_path_buf).collect(),
+        exists,
+        width,
+        height,
+        spread,
+    }
+}
+
+fn print_text(chapters: &[ChapterEntry]) {
+    for chapter in chapters {
+        let name = chapter.name.as_deref().unwrap_or("(untitled)");
+        if chapter.cover {
+            println!("{name} (cover)");
+        } else {
+            println!("{name}");
+        }
+
+        for page in &chapter.page {
+            let src = page
+                .src
+                .iter()
+                .map(|src| src.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" + ");
+
+            let mut status = match (page.exists, page.width, page.height) {
+                (false, _, _) => "missing".to_string(),
+                (true, Some(width), Some(height)) => format!("{width}x{height}"),
+                (true, _, _) => "unreadable".to_string(),
+            };
+            if let Some(spread) = page.spread {
+                status.push_str(&format!(", spread {spread}"));
+            }
+
+            println!("  {:4}  {src}  ({status})", page.index);
+        }
+    }
+}
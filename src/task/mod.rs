@@ -1,11 +1,49 @@
-mod build;
+#[cfg(feature = "fs")]
+mod add;
+pub(crate) mod build;
+#[cfg(feature = "fs")]
+mod check;
+#[cfg(feature = "fs")]
+mod clean;
+#[cfg(feature = "fs")]
+mod daemon;
+#[cfg(feature = "fs")]
+mod images;
+#[cfg(feature = "fs")]
+mod list;
+#[cfg(feature = "fs")]
 mod new;
+#[cfg(feature = "fs")]
+mod publish;
+#[cfg(feature = "fs")]
+mod serve;
+#[cfg(feature = "fs")]
+mod stats;
+#[cfg(feature = "fs")]
+mod thumbnail;
+#[cfg(feature = "fs")]
+mod toc;
+#[cfg(feature = "fs")]
+mod validate;
+#[cfg(feature = "fs")]
+mod verify;
+#[cfg(feature = "fs")]
+mod watch;
 
-use anyhow::Result;
+#[cfg(feature = "fs")]
+use crate::i18n::{self, tr, Lang};
+#[cfg(feature = "fs")]
+use crate::status::Status;
+#[cfg(feature = "fs")]
+use anyhow::{Context as _, Result};
+#[cfg(feature = "fs")]
 use clap::{CommandFactory, Parser};
+#[cfg(feature = "fs")]
+use std::process::ExitCode;
 
+#[cfg(feature = "fs")]
 #[derive(clap::Parser)]
-#[command(about, version)]
+#[command(about, version, after_help = Status::HELP)]
 struct Args {
     #[clap(subcommand)]
     task: Option<Task>,
@@ -13,24 +51,118 @@ struct Args {
     /// Generate shell completions.
     #[arg(long, value_name = "SHELL", exclusive = true)]
     generate_completion: Option<clap_complete::aot::Shell>,
+
+    /// Set the format of log messages.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        global = true,
+        default_value = "text",
+        env = "TSUGUMI_LOG_FORMAT"
+    )]
+    log_format: LogFormat,
+
+    /// Set the language of messages [default: from the locale]
+    #[arg(long, value_name = "LANG", global = true, env = "TSUGUMI_LANG")]
+    lang: Option<Lang>,
+}
+
+#[cfg(feature = "fs")]
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
 }
 
+#[cfg(feature = "fs")]
 #[derive(clap::Subcommand)]
 enum Task {
     /// Create a new book.
+    #[command(visible_alias = "init")]
     New(new::Args),
 
+    /// Add image files as pages to the current book.
+    Add(add::Args),
+
     /// Build the current book.
     Build(build::Args),
+
+    /// Build the current book again whenever its files change.
+    Watch(watch::Args),
+
+    /// Serve a preview of the current book to a web browser.
+    Serve(serve::Args),
+
+    /// Build the current book and upload it to a library or a server.
+    Publish(publish::Args),
+
+    /// Check the sources of the current book without building it.
+    Check(check::Args),
+
+    /// Verify an EPub file against the lockfile of the current book.
+    Verify(verify::Args),
+
+    /// Check the structure of an EPub file.
+    Validate(validate::Args),
+
+    /// List the chapters and pages of the current book in spine order.
+    List(list::Args),
+
+    /// Edit the table of contents of the current book.
+    Toc(toc::Args),
+
+    /// Show statistics of the current book.
+    Stats(stats::Args),
+
+    /// Process images the way a build does, outside of a book.
+    Images(images::Args),
+
+    /// Make store-ready thumbnails of the cover of the current book.
+    Thumbnail(thumbnail::Args),
+
+    /// Remove data generated for the current book.
+    Clean(clean::Args),
+
+    /// Serve JSON-RPC requests of an editor on the standard input and output.
+    Daemon(daemon::Args),
 }
 
-pub fn main() -> Result<()> {
+#[cfg(feature = "fs")]
+pub fn main() -> ExitCode {
     let args = Args::parse();
+    i18n::init(args.lang);
+
+    match run(args) {
+        Ok(()) => Status::Success.into(),
+        Err(e) => {
+            eprintln!("{} {e:?}", tr!("Error:", "エラー:"));
+            Status::of(&e).into()
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+fn run(args: Args) -> Result<()> {
+    let _guard = init_tracing(args.log_format)?;
 
     if let Some(task) = args.task {
         return match task {
             Task::New(args) => new::main(args),
+            Task::Add(args) => add::main(args),
             Task::Build(args) => build::main(args),
+            Task::Watch(args) => watch::main(args),
+            Task::Serve(args) => serve::main(args),
+            Task::Publish(args) => publish::main(args),
+            Task::Check(args) => check::main(args),
+            Task::Verify(args) => verify::main(args),
+            Task::Validate(args) => validate::main(args),
+            Task::List(args) => list::main(args),
+            Task::Toc(args) => toc::main(args),
+            Task::Stats(args) => stats::main(args),
+            Task::Images(args) => images::main(args),
+            Task::Thumbnail(args) => thumbnail::main(args),
+            Task::Clean(args) => clean::main(args),
+            Task::Daemon(args) => daemon::main(args),
         };
     }
 
@@ -40,7 +172,7 @@ pub fn main() -> Result<()> {
         clap_complete::generate(
             shell,
             &mut cmd,
-            env!("CARGO_BIN_NAME"),
+            env!("CARGO_PKG_NAME"),
             &mut std::io::stdout(),
         );
         return Ok(());
@@ -49,3 +181,100 @@ pub fn main() -> Result<()> {
     cmd.print_help()?;
     Ok(())
 }
+
+#[cfg(feature = "fs")]
+fn init_tracing(format: LogFormat) -> Result<TracingGuard> {
+    use tracing_subscriber::fmt::format::FmtSpan;
+    use tracing_subscriber::prelude::*;
+
+    let fmt = match format {
+        // keep stdout for the output of tasks such as `list`
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .json()
+            .with_span_events(FmtSpan::CLOSE)
+            .boxed(),
+    };
+
+    let filter = tracing_subscriber::filter::EnvFilter::builder()
+        .with_default_directive(tracing_subscriber::filter::LevelFilter::INFO.into())
+        .from_env()
+        .context("failed to initialize tracing")?;
+
+    let registry = tracing_subscriber::registry().with(fmt).with(filter);
+
+    #[cfg(feature = "otlp")]
+    {
+        let provider = otlp::provider()?;
+        let layer = provider
+            .as_ref()
+            .map(|p| tracing_opentelemetry::layer().with_tracer(otlp::tracer(p)));
+        registry.with(layer).init();
+
+        Ok(TracingGuard { provider })
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        registry.init();
+
+        Ok(TracingGuard {})
+    }
+}
+
+/// Flushes pending telemetry when dropped.
+#[cfg(feature = "fs")]
+struct TracingGuard {
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+#[cfg(all(feature = "fs", feature = "otlp"))]
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("failed to export traces: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "fs", feature = "otlp"))]
+mod otlp {
+    use anyhow::Result;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+
+    pub(super) fn provider() -> Result<Option<SdkTracerProvider>> {
+        if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+            return Ok(None);
+        }
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()?;
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter)
+            .build();
+
+        Ok(Some(provider))
+    }
+
+    pub(super) fn tracer(provider: &SdkTracerProvider) -> SdkTracer {
+        provider.tracer(env!("CARGO_PKG_NAME"))
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+    }
+}
@@ -1,9 +1,15 @@
+use crate::i18n::tr;
+use crate::import::import;
 use crate::model::{
-    Book, Chapter, Creator, Metadata, Orientation, Page, Rendition, Title, TitleType,
+    Book, Chapter, Collection, CollectionType, Creator, Metadata, Orientation, Page, Rendition,
+    Title, TitleType, VERSION,
 };
+use crate::project::FILE_NAME;
 use anyhow::Result;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use tracing::warn;
 
 #[derive(clap::Args)]
 pub(super) struct Args {
@@ -19,34 +25,61 @@ pub(super) struct Args {
     #[arg(short, long, value_name = "URN", value_hint = clap::ValueHint::Other)]
     identifier: Option<String>,
 
+    /// Set the series and the position of the book from a directory name like "Series v03".
+    #[arg(long)]
+    infer_series: bool,
+
+    /// Reconstruct the book from an existing fixed-layout EPub file.
+    #[arg(long, value_name = "EPUB", value_hint = clap::ValueHint::FilePath, conflicts_with = "files")]
+    from: Option<PathBuf>,
+
+    /// Read the files to create pages from, one per line, from the standard input.
+    #[arg(long, conflicts_with_all = ["files", "from"])]
+    stdin: bool,
+
+    /// Overwrite the project file if it already exists.
+    #[arg(short, long)]
+    force: bool,
+
     /// Create pages from files and set the first page as the cover page.
     #[arg(value_hint = clap::ValueHint::FilePath)]
     files: Vec<PathBuf>,
 }
 
 pub(super) fn main(args: Args) -> Result<()> {
+    let dir_name = std::env::current_dir()
+        .unwrap_or_default()
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    if !args.force && Path::new(FILE_NAME).exists() {
+        anyhow::bail!(tr!(
+            "`{FILE_NAME}` already exists; pass --force to overwrite it",
+            "`{FILE_NAME}` は既に存在します。上書きするには --force を指定してください"
+        ));
+    }
+
+    if let Some(path) = &args.from {
+        return main_from(&args, path, &dir_name);
+    }
+
+    let collection = infer_series(&args, &dir_name);
+
+    let files = if args.stdin {
+        read_files(std::io::stdin().lock())?
+    } else {
+        args.files.clone()
+    };
+
     let metadata = Metadata {
         title: vec![Title {
-            name: args.title.as_ref().cloned().unwrap_or_else(|| {
-                std::env::current_dir()
-                    .unwrap_or_default()
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_string()
-            }),
+            name: args.title.as_ref().cloned().unwrap_or(dir_name),
             title_type: TitleType::Main,
             ..Default::default()
         }],
-        creator: args
-            .author
-            .map(|name| Creator {
-                name,
-                role: Some("aut".to_string()),
-                ..Default::default()
-            })
-            .map(|c| vec![c])
-            .unwrap_or_default(),
+        creator: args.author.clone().map(author).into_iter().collect(),
         language: std::env::var("LANG")
             .ok()
             .as_deref()
@@ -56,6 +89,7 @@ pub(super) fn main(args: Args) -> Result<()> {
         identifier: args
             .identifier
             .unwrap_or_else(|| format!("urn:uuid:{}", uuid::Uuid::new_v4())),
+        collection,
         ..Default::default()
     };
 
@@ -65,23 +99,102 @@ pub(super) fn main(args: Args) -> Result<()> {
     };
 
     let book = Book {
+        version: Some(VERSION),
         metadata,
         rendition,
-        chapter: create_chapter(args.title.as_deref(), &args.files),
+        chapter: create_chapter(args.title.as_deref(), &files),
+        ..Default::default()
     };
 
-    let file = File::create("tsugumi.yaml")?;
+    let file = File::create(FILE_NAME)?;
     serde_yaml::to_writer(file, &book)?;
 
     Ok(())
 }
 
+/// Creates the project file from the EPub file at `path`, preferring the metadata given in `args`.
+fn main_from(args: &Args, path: &Path, dir_name: &str) -> Result<()> {
+    let import = import(path, Path::new("."))?;
+    for finding in &import.findings {
+        warn!("{finding}");
+    }
+
+    let mut book = import.book;
+    let metadata = &mut book.metadata;
+
+    if let Some(name) = &args.title {
+        metadata.title = vec![Title {
+            name: name.clone(),
+            title_type: TitleType::Main,
+            ..Default::default()
+        }];
+    }
+    if let Some(name) = &args.author {
+        metadata.creator = vec![author(name.clone())];
+    }
+    if let Some(identifier) = &args.identifier {
+        metadata.identifier = identifier.clone();
+    }
+    // keep the collections of the book unless asked to infer them
+    if args.infer_series {
+        metadata.collection = infer_series(args, dir_name);
+    }
+
+    let file = File::create(FILE_NAME)?;
+    serde_yaml::to_writer(file, &book)?;
+
+    Ok(())
+}
+
+fn author(name: String) -> Creator {
+    Creator {
+        name,
+        role: Some("aut".to_string()),
+        ..Default::default()
+    }
+}
+
+fn infer_series(args: &Args, dir_name: &str) -> Vec<Collection> {
+    if args.infer_series {
+        parse_series(dir_name)
+            .map(|(name, position)| Collection {
+                name,
+                collection_type: CollectionType::Series,
+                position: Some(position.into()),
+            })
+            .into_iter()
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Reads paths from `reader`, one per line, skipping blank lines and leading `./` like `find` prints.
+fn read_files<R: BufRead>(reader: R) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        files.push(PathBuf::from(line.strip_prefix("./").unwrap_or(line)));
+    }
+
+    Ok(files)
+}
+
 fn create_chapter(title: Option<&str>, files: &[PathBuf]) -> Vec<Chapter> {
-    let mut iter = files.iter().map(|src| Page { src: src.clone() });
+    let mut iter = files.iter().map(|src| Page {
+        src: src.clone(),
+        ..Default::default()
+    });
     let cover = iter.next().map(|page| Chapter {
         name: Some("表紙".to_string()),
         page: vec![page],
         cover: true,
+        ..Default::default()
     });
     let pages = Chapter {
         name: title.map(|s| s.to_string()),
@@ -92,6 +205,42 @@ fn create_chapter(title: Option<&str>, files: &[PathBuf]) -> Vec<Chapter> {
     cover.into_iter().chain(Some(pages)).collect()
 }
 
+/// Splits a name like "Series v03" into the name and the position of the series.
+fn parse_series(name: &str) -> Option<(String, u32)> {
+    const SEPARATORS: [char; 4] = [' ', '_', '-', '.'];
+
+    let name = name.trim_end();
+    let name = name.strip_suffix('巻').unwrap_or(name);
+    let name = name.strip_suffix(')').unwrap_or(name);
+
+    let rest = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let position = name[rest.len()..].parse().ok()?;
+
+    let trimmed = rest.trim_end_matches(SEPARATORS);
+
+    let marker = ["vol", "v", "#", "第", "("].into_iter().find(|marker| {
+        trimmed
+            .get(trimmed.len().saturating_sub(marker.len())..)
+            .is_some_and(|s| s.eq_ignore_ascii_case(marker))
+    });
+
+    // the number must be set apart from the name, so that "Area51" is not a series
+    let (series, apart) = match marker {
+        Some(marker) => {
+            let series = &trimmed[..trimmed.len() - marker.len()];
+            let apart = series.ends_with(SEPARATORS) || matches!(marker, "#" | "第" | "(");
+            (series.trim_end_matches(SEPARATORS), apart)
+        }
+        None => (trimmed, trimmed.len() < rest.len()),
+    };
+
+    if !apart || series.is_empty() {
+        return None;
+    }
+
+    Some((series.to_string(), position))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -108,9 +257,11 @@ mod tests {
             Some(Chapter {
                 name: Some("表紙".to_string()),
                 page: vec![Page {
-                    src: "cover".into()
+                    src: "cover".into(),
+                    ..Default::default()
                 }],
                 cover: true,
+                ..Default::default()
             })
         );
         assert_eq!(
@@ -119,10 +270,12 @@ mod tests {
                 name: Some("title".to_string()),
                 page: vec![
                     Page {
-                        src: "page1".into()
+                        src: "page1".into(),
+                        ..Default::default()
                     },
                     Page {
-                        src: "page2".into()
+                        src: "page2".into(),
+                        ..Default::default()
                     }
                 ],
                 ..Default::default()
@@ -139,9 +292,11 @@ mod tests {
             Some(Chapter {
                 name: Some("表紙".to_string()),
                 page: vec![Page {
-                    src: "cover".into()
+                    src: "cover".into(),
+                    ..Default::default()
                 }],
                 cover: true,
+                ..Default::default()
             })
         );
         assert_eq!(iter.next(), Some(Default::default()));
@@ -154,4 +309,35 @@ mod tests {
         assert_eq!(iter.next(), Some(Default::default()));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_read_files() {
+        let input = "./cover.jpg\r\n\npage 1.jpg\n./dir/page2.jpg\n";
+        assert_eq!(
+            read_files(input.as_bytes()).unwrap(),
+            [
+                PathBuf::from("cover.jpg"),
+                PathBuf::from("page 1.jpg"),
+                PathBuf::from("dir/page2.jpg")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_series() {
+        let series = |name: &str, position| Some((name.to_string(), position));
+
+        assert_eq!(parse_series("Series Name v03"), series("Series Name", 3));
+        assert_eq!(
+            parse_series("Series Name Vol. 12"),
+            series("Series Name", 12)
+        );
+        assert_eq!(parse_series("Series_Name_vol_1"), series("Series_Name", 1));
+        assert_eq!(parse_series("Series Name - 007"), series("Series Name", 7));
+        assert_eq!(parse_series("Series Name (2)"), series("Series Name", 2));
+        assert_eq!(parse_series("シリーズ第3巻"), series("シリーズ", 3));
+        assert_eq!(parse_series("Area51"), None);
+        assert_eq!(parse_series("Series Name"), None);
+        assert_eq!(parse_series("v3"), None);
+    }
 }
@@ -0,0 +1,688 @@
+use super::build::{self, BuildOptions};
+use super::watch::{changed_paths, stamps, Interval, Stamps, Watcher};
+use crate::import::{read_spine, read_viewport, SpineItem};
+use crate::model::{Direction, Format};
+use crate::project::Project;
+use crate::url::percent_decode;
+use anyhow::{Context as _, Result};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use sha1::{Digest as _, Sha1};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// Height in pixels the pages are scaled to in the reader.
+const PAGE_HEIGHT: u32 = 720;
+
+/// Path of the WebSocket that tells the reader to reload when the book is rebuilt.
+const LIVE_RELOAD_PATH: &str = "/livereload";
+
+/// Script of the reader that reloads the page when told to, or when the server comes back after
+/// the connection is lost.
+const LIVE_RELOAD_SCRIPT: &str = "<script>(function connect(){\
+    const ws=new WebSocket(`ws://${location.host}/livereload`);\
+    ws.onmessage=()=>location.reload();\
+    ws.onclose=()=>setTimeout(connect,1000);\
+    })()</script>";
+
+/// GUID appended to the key of a WebSocket handshake, as defined in RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Time a connection is given to send its request before it is closed.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(clap::Args)]
+#[group(skip)]
+pub(super) struct Args {
+    #[command(flatten)]
+    build: build::Args,
+
+    /// Address to serve the preview on.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Rebuild the book as its files change, and reload the open reader after each build.
+    #[arg(long)]
+    watch: bool,
+
+    #[command(flatten)]
+    interval: Interval,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::open_or_find(args.build.project.as_deref())?;
+    let mut targets = args.build.targets(&project)?;
+    let mut options = BuildOptions::from(args.build);
+    options.target = targets.pop();
+    let format = match &options.target {
+        Some(name) => project
+            .load()?
+            .target(name)
+            .and_then(|target| target.format),
+        None => None,
+    };
+    if options.format != Format::Epub
+        || format.is_some_and(|format| format != Format::Epub)
+        || options.split_size.is_some()
+        || !targets.is_empty()
+    {
+        anyhow::bail!("serve previews a single EPub file");
+    }
+
+    // keep the EPub file out of the project unless told where to write it
+    let dir = tempfile::tempdir()?;
+    if options.output.is_none() && options.out_dir.is_none() {
+        options.output = Some(dir.path().to_path_buf());
+    }
+
+    let listener = TcpListener::bind(args.listen)
+        .with_context(|| format!("failed to listen on {}", args.listen))?;
+
+    let mut watcher = args.watch.then(|| Watcher::new(&project, args.interval));
+    let server = Server {
+        stamps: stamps(&project),
+        path: build::run(&project, options.clone(), |_| {})?,
+        project: Arc::new(project),
+        options,
+        error: None,
+        clients: watcher.as_ref().map(|_| Vec::new()),
+    };
+
+    info!(
+        "serving `{}` on http://{}/",
+        server.path.display(),
+        listener.local_addr()?
+    );
+
+    let project = Arc::clone(&server.project);
+    let server = Arc::new(Mutex::new(server));
+    if let Some(mut watcher) = watcher.take() {
+        info!("watching `{}` for changes", project.path().display());
+
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || loop {
+            let changed = watcher.wait(&project);
+            server.lock().unwrap().rebuild(changed.len());
+            notify(&server);
+        });
+    }
+
+    serve(&listener, &server);
+    Ok(())
+}
+
+/// Accepts the connections of `listener`, each handled on its own thread so that one that sends
+/// nothing, such as a connection a browser opens in advance, does not hold up the others.
+fn serve(listener: &TcpListener, server: &Arc<Mutex<Server>>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("failed to accept a connection: {e}");
+                continue;
+            }
+        };
+
+        let server = Arc::clone(server);
+        std::thread::spawn(move || {
+            if let Err(e) = handle(&server, &stream) {
+                warn!("failed to respond: {e:#}");
+            }
+        });
+    }
+}
+
+/// Reads the request on `stream` and responds to it, keeping `server` locked only while the
+/// response is made.
+fn handle(server: &Mutex<Server>, stream: &TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    match reader.read_line(&mut request) {
+        // the browser has closed a connection it did not use
+        Ok(0) => return Ok(()),
+        Ok(_) => {}
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            debug!("closing an idle connection");
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    // only the key of a WebSocket handshake matters, but all have to be read before responding
+    let mut key = None;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next(), parts.next());
+    if method == Some("GET") && target == Some(LIVE_RELOAD_PATH) {
+        return upgrade(server, stream, key.as_deref());
+    }
+
+    let response = match (method, target) {
+        (Some("GET" | "HEAD"), Some(target)) => server.lock().unwrap().route(target),
+        (Some(_), Some(_)) => Response::text(405, "only GET and HEAD are allowed"),
+        _ => Response::text(400, "malformed request"),
+    };
+
+    response.write(stream, method == Some("HEAD"))
+}
+
+/// State kept between the requests of the browser.
+struct Server {
+    project: Arc<Project>,
+    options: BuildOptions,
+
+    /// EPub file being served.
+    path: PathBuf,
+
+    /// Modification times of the files of the book as of the last build.
+    stamps: Stamps,
+
+    /// Error of the last build, shown instead of the book until a build succeeds.
+    error: Option<String>,
+
+    /// WebSocket connections of the open readers, which are told to reload after each build when
+    /// the files are watched.
+    clients: Option<Vec<TcpStream>>,
+}
+
+struct Response {
+    status: u16,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type: content_type.to_string(),
+            body: body.into(),
+        }
+    }
+
+    fn text(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body.into())
+    }
+
+    fn write(&self, mut stream: &TcpStream, head: bool) -> Result<()> {
+        let reason = match self.status {
+            101 => "Switching Protocols",
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {} {reason}\r\n\
+             Content-Type: {}\r\n\
+             Content-Length: {}\r\n\
+             Cache-Control: no-store\r\n\
+             Connection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        )?;
+        if !head {
+            stream.write_all(&self.body)?;
+        }
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+impl Server {
+    fn route(&mut self, target: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let path = percent_decode(path);
+
+        let response = match path.as_str() {
+            "/" => {
+                self.refresh();
+                match &self.error {
+                    Some(e) if self.clients.is_some() => Ok(Response::new(
+                        500,
+                        "text/html; charset=utf-8",
+                        format!(
+                            "<!DOCTYPE html>\n\
+                             <html><head><meta charset=\"utf-8\"><title>build failed</title>\
+                             </head><body><pre>{}</pre>{LIVE_RELOAD_SCRIPT}</body></html>\n",
+                            escape(e)
+                        ),
+                    )),
+                    Some(e) => Ok(Response::text(500, e.clone())),
+                    None => self.reader(query),
+                }
+            }
+            "/book.epub" => std::fs::read(&self.path)
+                .map(|body| Response::new(200, "application/epub+zip", body))
+                .map_err(anyhow::Error::from),
+            path => match path.strip_prefix("/epub/") {
+                Some(name) => self.entry(name),
+                None => Ok(Response::text(404, "not found")),
+            },
+        };
+
+        response.unwrap_or_else(|e| Response::text(500, format!("{e:#}")))
+    }
+
+    /// Builds the book again if any of its files has changed since the last build, unless the
+    /// files are watched to build it as soon as they change.
+    fn refresh(&mut self) {
+        if self.clients.is_some() {
+            return;
+        }
+
+        let current = stamps(&self.project);
+        let changed = changed_paths(&self.stamps, &current);
+        self.stamps = current;
+        if !changed.is_empty() {
+            self.rebuild(changed.len());
+        }
+    }
+
+    /// Builds the book again, as `changed` files have changed.
+    fn rebuild(&mut self, changed: usize) {
+        info!("rebuilding, as {changed} file(s) changed");
+        match build::run(&self.project, self.options.clone(), |_| {}) {
+            Ok(path) => {
+                self.path = path;
+                self.error = None;
+            }
+            Err(e) => {
+                error!("build failed: {e:#}");
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+    }
+
+    fn open(&self) -> Result<ZipArchive<BufReader<File>>> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("failed to open `{}`", self.path.display()))?;
+        ZipArchive::new(BufReader::new(file))
+            .with_context(|| format!("failed to read `{}`", self.path.display()))
+    }
+
+    /// Returns the entry `name` of the EPub file.
+    fn entry(&self, name: &str) -> Result<Response> {
+        let mut zip = self.open()?;
+        let mut entry = match zip.by_name(name) {
+            Ok(entry) => entry,
+            Err(ZipError::FileNotFound) => return Ok(Response::text(404, "not found")),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut body = Vec::new();
+        std::io::copy(&mut entry, &mut body)?;
+
+        let content_type = mime_guess::from_path(name).first_or_octet_stream();
+
+        Ok(Response::new(200, content_type.essence_str(), body))
+    }
+
+    /// Renders the reader, which lists the pages and shows the spread numbered in `query`.
+    fn reader(&self, query: &str) -> Result<Response> {
+        let mut zip = self.open()?;
+        let (spine, direction) = read_spine(&mut zip)?;
+        let spreads = spreads(&spine);
+
+        let current = query
+            .split('&')
+            .find_map(|param| param.strip_prefix("spread="))
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(1)
+            .clamp(1, spreads.len().max(1));
+        let flow = match direction {
+            Direction::RightToLeft => "row-reverse",
+            _ => "row",
+        };
+        let title = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+
+        let mut html = String::new();
+        write!(
+            html,
+            "<!DOCTYPE html>\n\
+             <html><head><meta charset=\"utf-8\"><title>{}</title><style>\
+             body{{margin:0;display:flex;height:100vh;font-family:sans-serif}}\
+             nav{{width:16em;overflow-y:auto;padding:0 1em;border-right:1px solid #ccc}}\
+             nav .current{{font-weight:bold}}\
+             main{{flex:1;display:flex;flex-direction:column;align-items:center;\
+             justify-content:center;background:#444;color:#fff}}\
+             .spread,.controls{{display:flex;flex-direction:{flow};gap:1em}}\
+             .page{{overflow:hidden;background:#fff}}\
+             .page iframe{{border:0;transform-origin:0 0}}\
+             a{{color:inherit}}\
+             </style></head><body><nav><p><a href=\"/book.epub\">{}.epub</a></p><ol>",
+            escape(&title),
+            escape(&title),
+        )?;
+
+        for (number, pages) in spreads.iter().enumerate().map(|(i, p)| (i + 1, p)) {
+            for &page in pages {
+                let name = spine[page].href.rsplit('/').next().unwrap_or_default();
+                let class = if number == current {
+                    " class=\"current\""
+                } else {
+                    ""
+                };
+                write!(
+                    html,
+                    "<li value=\"{}\"><a href=\"/?spread={number}\"{class}>{}</a></li>",
+                    page + 1,
+                    escape(name)
+                )?;
+            }
+        }
+        html.push_str("</ol></nav><main><div class=\"spread\">");
+
+        for &page in spreads.get(current - 1).into_iter().flatten() {
+            let href = &spine[page].href;
+            let (width, height) = match read_viewport(&mut zip, href)? {
+                Some(viewport) => (viewport.width, viewport.height),
+                None => (PAGE_HEIGHT * 2 / 3, PAGE_HEIGHT),
+            };
+            let scale = f64::from(PAGE_HEIGHT) / f64::from(height.max(1));
+            write!(
+                html,
+                "<div class=\"page\" style=\"width:{:.0}px;height:{PAGE_HEIGHT}px\">\
+                 <iframe src=\"/epub/{}\" width=\"{width}\" height=\"{height}\" \
+                 style=\"transform:scale({scale:.4})\"></iframe></div>",
+                f64::from(width) * scale,
+                escape(href)
+            )?;
+        }
+
+        write!(
+            html,
+            "</div><p class=\"controls\">\
+             <a href=\"/?spread={}\">previous</a><span>{current} / {}</span>\
+             <a href=\"/?spread={}\">next</a></p></main>",
+            current.saturating_sub(1).max(1),
+            spreads.len(),
+            (current + 1).min(spreads.len().max(1)),
+        )?;
+        if self.clients.is_some() {
+            html.push_str(LIVE_RELOAD_SCRIPT);
+        }
+        html.push_str("</body></html>\n");
+
+        Ok(Response::new(200, "text/html; charset=utf-8", html))
+    }
+}
+
+/// Tells the readers connected to `server` to reload, and forgets those that have gone, without
+/// keeping `server` locked while writing to them.
+fn notify(server: &Mutex<Server>) {
+    let Some(mut clients) = server.lock().unwrap().clients.as_mut().map(std::mem::take) else {
+        return;
+    };
+
+    // an unmasked text frame, as a server sends
+    let mut frame = vec![0x81, b"reload".len() as u8];
+    frame.extend_from_slice(b"reload");
+    clients.retain_mut(|stream| {
+        stream
+            .write_all(&frame)
+            .and_then(|_| stream.flush())
+            .is_ok()
+    });
+    debug!("told {} reader(s) to reload", clients.len());
+
+    // readers may have connected in the meantime
+    if let Some(current) = &mut server.lock().unwrap().clients {
+        current.append(&mut clients);
+    }
+}
+
+/// Completes the WebSocket handshake of a reader that asks to be told of rebuilds with `key`,
+/// and keeps the connection in `server` to notify it.
+fn upgrade(server: &Mutex<Server>, stream: &TcpStream, key: Option<&str>) -> Result<()> {
+    if server.lock().unwrap().clients.is_none() {
+        return Response::text(404, "not found").write(stream, false);
+    }
+    let Some(key) = key else {
+        return Response::text(400, "expected a WebSocket handshake").write(stream, false);
+    };
+
+    // a reader that stops reading must not hold up the others
+    let mut stream = stream.try_clone()?;
+    stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )?;
+    stream.flush()?;
+
+    if let Some(clients) = &mut server.lock().unwrap().clients {
+        clients.push(stream);
+    }
+    Ok(())
+}
+
+/// Groups the pages of `spine` into spreads: a page that takes a whole spread stands alone, and
+/// the others face each other in pairs counted from the last one that stands alone.
+fn spreads(spine: &[SpineItem]) -> Vec<Vec<usize>> {
+    let mut spreads = Vec::new();
+    let mut i = 0;
+    while i < spine.len() {
+        if !spine[i].center && spine.get(i + 1).is_some_and(|next| !next.center) {
+            spreads.push(vec![i, i + 1]);
+            i += 2;
+        } else {
+            spreads.push(vec![i]);
+            i += 1;
+        }
+    }
+    spreads
+}
+
+/// Returns the value of `Sec-WebSocket-Accept` that answers the handshake with `key`.
+fn accept_key(key: &str) -> String {
+    let digest = Sha1::new()
+        .chain_update(key)
+        .chain_update(WEBSOCKET_GUID)
+        .finalize();
+    BASE64_STANDARD.encode(digest)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read as _;
+    use std::path::Path;
+
+    /// Builds a book of three pages in `dir`, and returns the server of its preview.
+    fn server(dir: &Path) -> Server {
+        std::fs::write(
+            dir.join("tsugumi.yaml"),
+            "metadata: {title: T, language: ja, identifier: id}\n\
+             rendition: {direction: rtl}\n\
+             chapter: {page: [a.png, b.png, c.png]}\n",
+        )
+        .unwrap();
+        for name in ["a.png", "b.png", "c.png"] {
+            image::RgbImage::new(3, 4).save(dir.join(name)).unwrap();
+        }
+
+        let project = Project::open(dir).unwrap();
+        let options = BuildOptions {
+            output: Some(dir.join("out")),
+            no_cache: true,
+            ..Default::default()
+        };
+        std::fs::create_dir(dir.join("out")).unwrap();
+        Server {
+            stamps: stamps(&project),
+            path: build::run(&project, options.clone(), |_| {}).unwrap(),
+            project: Arc::new(project),
+            options,
+            error: None,
+            clients: None,
+        }
+    }
+
+    /// Starts serving `server` on a port of its own, and returns the address.
+    fn listen(server: Server) -> (SocketAddr, Arc<Mutex<Server>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(Mutex::new(server));
+        let shared = Arc::clone(&server);
+        std::thread::spawn(move || serve(&listener, &shared));
+        (addr, server)
+    }
+
+    /// Sends `request` on a new connection to `addr`, and returns it with the first `len` bytes of
+    /// the response.
+    fn request(addr: SocketAddr, request: &str, len: usize) -> (TcpStream, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = vec![0; len];
+        stream.read_exact(&mut response).unwrap();
+        (stream, response)
+    }
+
+    #[test]
+    fn test_route() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = server(dir.path());
+
+        let response = server.route("/?spread=2");
+        assert_eq!(response.status, 200);
+        let html = String::from_utf8(response.body).unwrap();
+        assert!(html.contains("flex-direction:row-reverse"));
+        assert_eq!(html.matches("<iframe").count(), 1);
+        assert!(html.contains("<span>2 / 2</span>"));
+        assert!(!html.contains("WebSocket"));
+
+        let response = server.route("/epub/META-INF/container%2Exml");
+        assert_eq!(response.status, 200);
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .contains("rootfile"));
+
+        assert_eq!(server.route("/epub/missing").status, 404);
+        assert_eq!(server.route("/missing").status, 404);
+
+        server.clients = Some(Vec::new());
+        let html = String::from_utf8(server.route("/").body).unwrap();
+        assert!(html.contains(LIVE_RELOAD_SCRIPT));
+    }
+
+    #[test]
+    fn test_idle_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let (addr, _) = listen(server(dir.path()));
+
+        // a connection that sends nothing must not keep the next one waiting
+        let _idle = TcpStream::connect(addr).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"HEAD /book.epub HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_live_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let (addr, server) = listen(Server {
+            clients: Some(Vec::new()),
+            ..server(dir.path())
+        });
+
+        // two readers stay connected, and neither holds up the requests of the other
+        let handshake = format!(
+            "GET {LIVE_RELOAD_PATH} HTTP/1.1\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+        );
+        let switching = b"HTTP/1.1 101 Switching Protocols\r\n";
+        let (mut first, response) = request(addr, &handshake, switching.len());
+        assert_eq!(response, switching);
+        let (mut second, response) = request(addr, &handshake, switching.len());
+        assert_eq!(response, switching);
+
+        let ok = b"HTTP/1.1 200 OK\r\n";
+        let (_, response) = request(addr, "HEAD / HTTP/1.1\r\n\r\n", ok.len());
+        assert_eq!(response, ok);
+
+        // wait for the handshake of the second reader to complete
+        while server.lock().unwrap().clients.as_ref().unwrap().len() < 2 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        notify(&server);
+        for stream in [&mut first, &mut second] {
+            let mut response = Vec::new();
+            let mut buf = [0; 1];
+            while !response.ends_with(b"reload") {
+                stream.read_exact(&mut buf).unwrap();
+                response.push(buf[0]);
+            }
+            assert!(response.ends_with(b"\r\n\r\n\x81\x06reload"));
+        }
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // the example of RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_spreads() {
+        let item = |center| SpineItem {
+            href: String::new(),
+            center,
+        };
+        let spine = [true, false, false, false, true, false].map(item);
+        assert_eq!(
+            spreads(&spine),
+            [vec![0], vec![1, 2], vec![3], vec![4], vec![5]]
+        );
+        assert!(spreads(&[]).is_empty());
+    }
+}
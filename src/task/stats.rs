@@ -0,0 +1,31 @@
+use crate::project::{dir_size, expand_book, format_size, Project};
+use anyhow::Result;
+
+#[derive(clap::Args)]
+pub(super) struct Args {}
+
+pub(super) fn main(_args: Args) -> Result<()> {
+    let project = Project::find()?;
+    let mut book = project.load()?;
+    expand_book(project.root(), &mut book);
+
+    let pages = book
+        .chapter
+        .iter()
+        .flat_map(|c| &c.page)
+        .collect::<Vec<_>>();
+    let sources = pages
+        .iter()
+        .flat_map(|page| page.sources())
+        .filter_map(|src| project.root().join(book.source_path(src)).metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum::<u64>();
+    let cache = dir_size(&project.cache_dir())?;
+
+    println!("Chapters: {}", book.chapter.len());
+    println!("Pages:    {}", pages.len());
+    println!("Sources:  {}", format_size(sources));
+    println!("Cache:    {}", format_size(cache));
+
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use super::build::{fit, sanitize_file_name, scale, stitch, Resource, JPEG_QUALITY};
+use crate::cache::Probe;
+use crate::i18n::tr;
+use crate::model::{Book, Direction, PageFormat};
+use crate::project::{expand_book, Project};
+use anyhow::{Context as _, Result};
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    /// Project directory or project file instead of finding it from the current directory.
+    #[arg(value_name = "PROJECT", value_hint = clap::ValueHint::AnyPath)]
+    project: Option<PathBuf>,
+
+    /// Sizes in pixels, such as `350x494`, the cover is scaled down to fit in, a thumbnail each.
+    #[arg(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        value_delimiter = ',',
+        value_parser = parse_dimensions,
+        default_value = "350x494,800x1131"
+    )]
+    size: Vec<(u32, u32)>,
+
+    /// Directory to write the thumbnails in [default: directory of the project file]
+    #[arg(short, long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    output: Option<PathBuf>,
+
+    /// Name of the thumbnails before their sizes [default: main title of the book]
+    #[arg(long, value_name = "NAME", value_hint = clap::ValueHint::Other)]
+    name: Option<String>,
+
+    /// Format of the thumbnails.
+    #[arg(long, value_name = "FORMAT", default_value = "jpeg")]
+    format: PageFormat,
+
+    /// Quality from 1 to 100 of JPEG thumbnails [default: 90]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::open_or_find(args.project.as_deref())?;
+    let mut book = project.load()?;
+    expand_book(project.root(), &mut book);
+
+    let sources = cover_sources(&book)?
+        .into_iter()
+        .map(|src| project.root().join(src))
+        .collect::<Vec<_>>();
+    // a cover joined from several images is made into a spread as the build does
+    let joined;
+    let src = match &sources[..] {
+        [src] => src.as_path(),
+        _ => {
+            let sources = sources.iter().map(|src| src.as_path().into());
+            joined = stitch(&sources.collect::<Vec<_>>(), "png", JPEG_QUALITY)?;
+            joined.path()
+        }
+    };
+    let name = args
+        .name
+        .as_deref()
+        .or_else(|| book.metadata.main_title())
+        .unwrap_or("cover");
+    let dir = args.output.as_deref().unwrap_or(project.root());
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create `{}`", dir.display()))?;
+
+    for &size in &args.size {
+        let dest = dir.join(format!(
+            "{}.{}x{}.{}",
+            sanitize_file_name(name),
+            size.0,
+            size.1,
+            args.format.extension()
+        ));
+        write_thumbnail(src, &dest, size, args.format, args.quality)?;
+        info!(
+            "{}",
+            tr!(
+                "wrote the thumbnail `{}`",
+                "サムネイル `{}` を書き出しました",
+                dest.display()
+            )
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the paths to the images the thumbnails are made from, relative to the project file:
+/// the archival cover, which has the highest resolution, or else those the cover page is joined
+/// from, from left to right.
+fn cover_sources(book: &Book) -> Result<Vec<PathBuf>> {
+    if let Some(path) = &book.archival_cover {
+        return Ok(vec![path.clone()]);
+    }
+
+    let page = book
+        .chapter
+        .iter()
+        .enumerate()
+        .flat_map(|(i, chapter)| chapter.page.iter().enumerate().map(move |(j, p)| (i, j, p)))
+        .find(|&(i, j, _)| book.is_cover(i, j))
+        .map(|(_, _, page)| page)
+        .with_context(|| tr!("the book has no cover", "本に表紙がありません"))?;
+
+    let mut sources = page
+        .sources()
+        .into_iter()
+        .map(|src| book.source_path(src))
+        .collect::<Vec<_>>();
+    if sources.is_empty() {
+        anyhow::bail!(tr!("the cover has no image", "表紙に画像がありません"));
+    }
+    if book.rendition.direction == Direction::RightToLeft {
+        sources.reverse();
+    }
+    Ok(sources)
+}
+
+/// Scales the image at `src` down to fit in `size`, and writes it at `dest` in `format`.
+fn write_thumbnail(
+    src: &Path,
+    dest: &Path,
+    size: (u32, u32),
+    format: PageFormat,
+    quality: Option<u8>,
+) -> Result<()> {
+    let Probe { width, height, .. } = Probe::read(src)?;
+    let scaled = fit(width, height, Some(size.0), Some(size.1));
+    if scaled == (width, height) {
+        warn!(
+            "{}",
+            tr!(
+                "the cover is {width}x{height}, which is not larger than the thumbnail of {}x{}",
+                "表紙は {width}x{height} で、サムネイルの {}x{} より大きくありません",
+                size.0,
+                size.1
+            )
+        );
+    }
+
+    let format = match format {
+        PageFormat::Jpeg => ImageFormat::Jpeg,
+        PageFormat::Png => ImageFormat::Png,
+    };
+    let quality = quality.unwrap_or(JPEG_QUALITY);
+    let image = scale(&Resource::from(src), scaled, format, quality)?;
+    std::fs::copy(image.path(), dest)
+        .with_context(|| format!("failed to write `{}`", dest.display()))?;
+    Ok(())
+}
+
+/// Parses dimensions such as `350x494`.
+fn parse_dimensions(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("`{s}` is not of the form WIDTHxHEIGHT"))?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|&v| 0 < v)
+            .ok_or_else(|| format!("`{v}` is not a positive size in pixels"))
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cover_sources() {
+        let book: Book = serde_yaml::from_str(
+            "metadata: {title: T, language: ja, identifier: id}\n\
+             assetsDir: pages\n\
+             rendition: {direction: rtl}\n\
+             chapter:\n\
+             - {cover: true, page: {join: [a.png, b.png]}}\n\
+             - {page: c.png}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            cover_sources(&book).unwrap(),
+            [Path::new("pages/b.png"), Path::new("pages/a.png")]
+        );
+
+        let book = Book {
+            archival_cover: Some("cover.png".into()),
+            ..book
+        };
+        assert_eq!(cover_sources(&book).unwrap(), [Path::new("cover.png")]);
+    }
+
+    #[test]
+    fn test_write_thumbnail() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("cover.png");
+        image::RgbImage::new(700, 1000).save(&src).unwrap();
+
+        let dest = dir.path().join("thumb.jpg");
+        write_thumbnail(&src, &dest, (350, 494), PageFormat::Jpeg, None).unwrap();
+        let probe = Probe::read(&dest).unwrap();
+        assert_eq!((probe.width, probe.height), (346, 494));
+        assert_eq!(probe.media_type, "image/jpeg");
+    }
+
+    #[test]
+    fn test_parse_dimensions() {
+        assert_eq!(parse_dimensions("350x494"), Ok((350, 494)));
+        assert_eq!(parse_dimensions("800X1131"), Ok((800, 1131)));
+        assert!(parse_dimensions("350").is_err());
+        assert!(parse_dimensions("0x494").is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::edit::{scalar, Segment};
+use crate::model::Book;
+use crate::project::Project;
+use anyhow::Result;
+use tracing::info;
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// List the entries of the table of contents.
+    List,
+
+    /// Add an entry for a page or change its caption.
+    Set {
+        /// Position of the page in the spine, starting at 1.
+        page: usize,
+
+        /// Caption of the entry.
+        caption: String,
+    },
+
+    /// Remove the entry for a page.
+    Remove {
+        /// Position of the page in the spine, starting at 1.
+        page: usize,
+    },
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::find()?;
+    let mut book = project.load()?;
+
+    match args.command {
+        Command::List => {
+            let mut index = 0;
+            let names = book.numbered_names();
+            for (chapter, name) in book.chapter.iter().zip(&names) {
+                for i in 0..chapter.page.len() {
+                    index += 1;
+                    if let Some(caption) = chapter.caption(i, name.as_deref()) {
+                        println!("{index:4}  {caption}");
+                    }
+                }
+            }
+
+            return Ok(());
+        }
+        Command::Set { page, caption } => {
+            let (c, i) = locate(&book, page)?;
+            let value = scalar(&caption)?;
+            book.chapter[c].page[i].toc = Some(caption);
+            project.edit(&book, |document| {
+                let path = page_path(c, i);
+                document.expand(&path, "src")?;
+                document.set(&path, "toc", Some(&value))
+            })?;
+            info!("set the entry for page {page}");
+        }
+        Command::Remove { page } => {
+            let (c, i) = locate(&book, page)?;
+            let chapter = &mut book.chapter[c];
+            chapter.page[i].toc = None;
+            // the chapter may be named or numbered
+            if i == 0 {
+                chapter.toc = false;
+            }
+            project.edit(&book, |document| {
+                document.set(&page_path(c, i), "toc", None)?;
+                if i == 0 {
+                    document.set(&page_path(c, i)[..2], "toc", Some("false"))?;
+                }
+                Ok(())
+            })?;
+            info!("removed the entry for page {page}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the chapter that contains the page at `index` in the spine, and returns the positions of
+/// the chapter in the book and of the page in the chapter.
+fn locate(book: &Book, index: usize) -> Result<(usize, usize)> {
+    let mut rest = index.checked_sub(1);
+    for (c, chapter) in book.chapter.iter().enumerate() {
+        match rest {
+            Some(i) if i < chapter.page.len() => return Ok((c, i)),
+            Some(i) => rest = Some(i - chapter.page.len()),
+            None => break,
+        }
+    }
+
+    anyhow::bail!("page {index} does not exist")
+}
+
+/// Returns the path to the `i`th page of the `c`th chapter in the project file.
+fn page_path(c: usize, i: usize) -> [Segment<'static>; 4] {
+    [
+        Segment::Key("chapter"),
+        Segment::Index(c),
+        Segment::Key("page"),
+        Segment::Index(i),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Chapter, Page};
+
+    #[test]
+    fn test_locate() {
+        let chapter = |pages: usize| Chapter {
+            page: (0..pages).map(|_| Page::default()).collect(),
+            ..Chapter::default()
+        };
+        let book = Book {
+            chapter: vec![chapter(1), chapter(3)],
+            ..Book::default()
+        };
+
+        assert_eq!(locate(&book, 1).unwrap().1, 0);
+        assert_eq!(locate(&book, 3).unwrap().1, 1);
+        assert!(locate(&book, 0).is_err());
+        assert!(locate(&book, 5).is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use crate::i18n::tr;
+use crate::status::{Failure, Status};
+use crate::validate::validate;
+use anyhow::{Context as _, Result};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+use zip::ZipArchive;
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    /// EPub file to validate.
+    #[arg(value_name = "EPUB", value_hint = clap::ValueHint::FilePath)]
+    epub: PathBuf,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    validate_file(&args.epub)
+}
+
+/// Checks the structure of the EPub file at `path`, and fails if it has any problem.
+pub(super) fn validate_file(path: &Path) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("failed to open `{}`", path.display()))?;
+    let mut zip = ZipArchive::new(BufReader::new(file))
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+
+    let findings = validate(&mut zip)?;
+    if findings.is_empty() {
+        info!(
+            "{}",
+            tr!("`{}` is valid", "`{}` は妥当です", path.display())
+        );
+        return Ok(());
+    }
+
+    for finding in &findings {
+        error!("{finding}");
+    }
+
+    Err(Failure::new(
+        Status::Invalid,
+        tr!(
+            "`{}` has {} problem(s)",
+            "`{}` に {} 件の問題があります",
+            path.display(),
+            findings.len()
+        ),
+    )
+    .into())
+}
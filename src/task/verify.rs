@@ -0,0 +1,101 @@
+use crate::i18n::tr;
+use crate::lock::{hash, hash_file, Lockfile};
+use crate::project::{Project, LOCK_FILE_NAME};
+use crate::status::{Failure, Status};
+use anyhow::{Context as _, Result};
+use std::fs::File;
+use std::path::PathBuf;
+use tracing::{error, info, warn};
+use zip::ZipArchive;
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    /// EPub file to verify.
+    #[arg(value_name = "EPUB", value_hint = clap::ValueHint::FilePath)]
+    epub: PathBuf,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::find()?;
+    let lock_path = project.lock_path();
+    let lock = Lockfile::read(&lock_path)?.ok_or_else(|| {
+        Failure::new(
+            Status::Locked,
+            tr!(
+                "`{}` does not exist",
+                "`{}` が存在しません",
+                lock_path.display()
+            ),
+        )
+    })?;
+
+    let file = File::open(&args.epub)
+        .with_context(|| format!("failed to open `{}`", args.epub.display()))?;
+    let mut zip = ZipArchive::new(file)
+        .with_context(|| format!("failed to read `{}`", args.epub.display()))?;
+
+    let mut mismatches = 0;
+    for source in &lock.source {
+        let sha256 = match zip.by_name(&source.entry) {
+            Ok(entry) => hash(entry)?,
+            Err(zip::result::ZipError::FileNotFound) => {
+                error!(
+                    "{}",
+                    tr!("`{}` is missing", "`{}` がありません", source.entry)
+                );
+                mismatches += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if sha256 != source.sha256 {
+            error!(
+                "{}",
+                tr!(
+                    "`{}` does not match `{}`",
+                    "`{}` が `{}` と一致しません",
+                    source.entry,
+                    source.path.display()
+                )
+            );
+            mismatches += 1;
+        }
+
+        match hash_file(&project.root().join(&source.path)) {
+            Ok(sha256) if sha256 == source.sha256 => {}
+            _ => warn!(
+                "{}",
+                tr!(
+                    "`{}` has changed since it was locked",
+                    "`{}` はロック後に変更されています",
+                    source.path.display()
+                )
+            ),
+        }
+    }
+
+    if 0 < mismatches {
+        return Err(Failure::new(
+            Status::Locked,
+            tr!(
+                "{mismatches} item(s) of `{}` differ from `{LOCK_FILE_NAME}`",
+                "`{}` の {mismatches} 個のアイテムが `{LOCK_FILE_NAME}` と異なります",
+                args.epub.display()
+            ),
+        )
+        .into());
+    }
+
+    info!(
+        "{}",
+        tr!(
+            "`{}` matches `{LOCK_FILE_NAME}` ({} source(s))",
+            "`{}` は `{LOCK_FILE_NAME}` と一致します（ソース {} 個）",
+            args.epub.display(),
+            lock.source.len()
+        )
+    );
+
+    Ok(())
+}
@@ -0,0 +1,405 @@
+use super::build::{self, BuildOptions};
+use crate::model::Panel;
+use crate::project::{expand_book, format_size, Project, IGNORE_FILE_NAME};
+use anyhow::Result;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info, warn};
+
+/// Modification times of the watched files, relative to the project directory.
+pub(super) type Stamps = HashMap<PathBuf, Option<SystemTime>>;
+
+#[derive(clap::Args)]
+#[group(skip)]
+pub(super) struct Args {
+    #[command(flatten)]
+    build: build::Args,
+
+    #[command(flatten)]
+    interval: Interval,
+}
+
+/// Intervals of checking the files for changes.
+#[derive(clap::Args, Clone, Copy)]
+pub(super) struct Interval {
+    /// Milliseconds between checks for changed files where the file system cannot notify of them.
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    poll_interval: u64,
+
+    /// Milliseconds the files have to stay unchanged before the book is rebuilt.
+    #[arg(long, value_name = "MS", default_value_t = 300)]
+    debounce: u64,
+}
+
+pub(super) fn main(args: Args) -> Result<()> {
+    let project = Project::open_or_find(args.build.project.as_deref())?;
+    let targets = args.build.targets(&project)?;
+    let options = BuildOptions::from(args.build);
+
+    let mut watcher = Watcher::new(&project, args.interval);
+    rebuild(&project, &options, &targets);
+
+    info!("watching `{}` for changes", project.path().display());
+
+    loop {
+        for path in watcher.wait(&project) {
+            info!("`{}` changed", path.display());
+        }
+        rebuild(&project, &options, &targets);
+    }
+}
+
+/// Watches the files of a project for changes.
+///
+/// The file system notifies of the changes where it can, and the files are polled otherwise.
+pub(super) struct Watcher {
+    poll_interval: Duration,
+    debounce: Duration,
+
+    /// Modification times of the files as of the last change seen.
+    before: Stamps,
+
+    /// Notifier of the changes in the watched directories, unless the files are polled.
+    notifier: Option<Notifier>,
+}
+
+struct Notifier {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+
+    /// Directories outside the project directory being watched.
+    outside: HashSet<PathBuf>,
+}
+
+impl Watcher {
+    pub(super) fn new(project: &Project, interval: Interval) -> Self {
+        let notifier = Notifier::new(project)
+            .inspect_err(|e| warn!("polling the files, which cannot be watched: {e}"))
+            .ok();
+
+        Self {
+            poll_interval: Duration::from_millis(interval.poll_interval),
+            debounce: Duration::from_millis(interval.debounce),
+            before: stamps(project),
+            notifier,
+        }
+    }
+
+    /// Blocks until some of the files of `project` change and then stay unchanged for a while,
+    /// and returns the paths of those changed.
+    pub(super) fn wait(&mut self, project: &Project) -> Vec<PathBuf> {
+        loop {
+            match &mut self.notifier {
+                Some(notifier) => {
+                    if !notifier.wait(None) {
+                        warn!("polling the files, which are no longer watched");
+                        self.notifier = None;
+                    }
+                }
+                None => std::thread::sleep(self.poll_interval),
+            }
+
+            let mut after = stamps(project);
+            let mut changed = changed_paths(&self.before, &after);
+            if changed.is_empty() {
+                self.before = after;
+                continue;
+            }
+
+            // wait for the files to settle, so that saving several of them causes a single build
+            loop {
+                match &mut self.notifier {
+                    Some(notifier) => {
+                        // no more events in the meantime means nothing changed
+                        if !notifier.wait(Some(self.debounce)) {
+                            break;
+                        }
+                    }
+                    None => std::thread::sleep(self.debounce),
+                }
+
+                let current = stamps(project);
+                let more = changed_paths(&after, &current);
+                after = current;
+                if more.is_empty() {
+                    break;
+                }
+                changed.extend(more);
+            }
+            changed.sort();
+            changed.dedup();
+
+            // the files the book refers to may have moved to other directories
+            if let Some(notifier) = &mut self.notifier {
+                notifier.watch_outside(project);
+            }
+
+            self.before = after;
+            return changed;
+        }
+    }
+}
+
+impl Notifier {
+    fn new(project: &Project) -> notify::Result<Self> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher.watch(project.root(), RecursiveMode::Recursive)?;
+
+        let mut notifier = Self {
+            watcher,
+            events,
+            outside: HashSet::new(),
+        };
+        notifier.watch_outside(project);
+        Ok(notifier)
+    }
+
+    /// Blocks until the file system notifies of a change, for up to `timeout` if set, and returns
+    /// whether it did.
+    ///
+    /// The events that follow at once are taken as well, as they are part of the same change.
+    fn wait(&mut self, timeout: Option<Duration>) -> bool {
+        let event = match timeout {
+            Some(timeout) => match self.events.recv_timeout(timeout) {
+                Ok(event) => event,
+                Err(_) => return false,
+            },
+            None => match self.events.recv() {
+                Ok(event) => event,
+                Err(_) => return false,
+            },
+        };
+        if let Err(e) = event {
+            debug!("failed to watch the files: {e}");
+        }
+
+        while self.events.try_recv().is_ok() {}
+        true
+    }
+
+    /// Watches the directories of the watched files that are outside the project directory, which
+    /// is watched as a whole.
+    fn watch_outside(&mut self, project: &Project) {
+        let dirs = watched_paths(project)
+            .into_iter()
+            .filter(|path| {
+                path.is_absolute() || path.components().any(|c| c == Component::ParentDir)
+            })
+            .filter_map(|path| {
+                let path = project.root().join(path);
+                path.parent().map(Path::to_path_buf)
+            })
+            .collect::<HashSet<_>>();
+
+        for dir in self.outside.difference(&dirs) {
+            let _ = self.watcher.unwatch(dir);
+        }
+        self.outside.retain(|dir| dirs.contains(dir));
+        for dir in dirs {
+            if self.outside.contains(&dir) {
+                continue;
+            }
+            match self.watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    self.outside.insert(dir);
+                }
+                Err(e) => debug!("failed to watch {}: {e}", dir.display()),
+            }
+        }
+    }
+}
+
+/// Builds the book for each of `targets`, or once if there are none, and logs a line that tells
+/// how each build went.
+fn rebuild(project: &Project, options: &BuildOptions, targets: &[String]) {
+    if targets.is_empty() {
+        return rebuild_target(project, options);
+    }
+
+    for target in targets {
+        let options = BuildOptions {
+            target: Some(target.clone()),
+            ..options.clone()
+        };
+        rebuild_target(project, &options);
+    }
+}
+
+fn rebuild_target(project: &Project, options: &BuildOptions) {
+    let start = Instant::now();
+    let result = match options.split_size {
+        Some(max_size) => build::run_split(project, options.clone(), max_size, |_| {}),
+        None => build::run(project, options.clone(), |_| {}).map(|path| vec![path]),
+    };
+    let elapsed = start.elapsed().as_secs_f64();
+
+    match result {
+        Ok(paths) => {
+            let size = paths
+                .iter()
+                .filter_map(|path| path.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum();
+            let names = paths
+                .iter()
+                .map(|path| format!("`{}`", path.display()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            info!("built {names} ({}) in {elapsed:.2}s", format_size(size));
+        }
+        Err(e) => error!("build failed in {elapsed:.2}s: {e:#}"),
+    }
+}
+
+/// Returns the project file and the files of the book it refers to, relative to the project
+/// directory.
+pub(super) fn watched_paths(project: &Project) -> Vec<PathBuf> {
+    let file_name = PathBuf::from(project.path().file_name().unwrap_or_default());
+
+    // the ignore file decides the pages of directories and patterns as well, even if it is missing
+    let mut paths = vec![file_name, PathBuf::from(IGNORE_FILE_NAME)];
+    let Ok(mut book) = project.load() else {
+        return paths;
+    };
+    // the pages a directory or a pattern stands for change as files are added to it
+    expand_book(project.root(), &mut book);
+
+    for chapter in &book.chapter {
+        if let Some(Panel::Sidecar(path)) = &chapter.panel {
+            paths.push(path.clone());
+        }
+
+        for page in &chapter.page {
+            paths.extend(page.sources().into_iter().map(|src| book.source_path(src)));
+            if let Some(audio) = &page.audio {
+                paths.push(audio.src.clone());
+            }
+            if let Some(Panel::Sidecar(path)) = &page.panel {
+                paths.push(path.clone());
+            }
+        }
+    }
+    paths.extend(book.archival_cover.iter().cloned());
+    paths.extend(book.rendition.script.iter().cloned());
+
+    paths
+}
+
+/// Returns the modification times of the watched files of `project`.
+pub(super) fn stamps(project: &Project) -> Stamps {
+    watched_paths(project)
+        .into_iter()
+        .map(|path| {
+            let mtime = project
+                .root()
+                .join(&path)
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok();
+            (path, mtime)
+        })
+        .collect()
+}
+
+/// Returns the paths watched in both `before` and `after` whose modification time differs.
+pub(super) fn changed_paths(before: &Stamps, after: &Stamps) -> Vec<PathBuf> {
+    let mut changed = after
+        .iter()
+        .filter(|(path, mtime)| before.get(*path).is_some_and(|before| before != *mtime))
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    changed.sort();
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watched_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("tsugumi.yaml"),
+            "metadata: {title: T, language: ja, identifier: id}\n\
+             assetsDir: pages\n\
+             rendition: {script: main.js}\n\
+             chapter:\n\
+             - panel: panels.json\n  \
+               page:\n  \
+               - a.png\n  \
+               - {join: [b.png, c.png], audio: {src: b.mp3, end: 1}}\n",
+        )
+        .unwrap();
+
+        let project = Project::open(dir.path()).unwrap();
+        assert_eq!(
+            watched_paths(&project),
+            [
+                "tsugumi.yaml",
+                ".tsugumiignore",
+                "panels.json",
+                "pages/a.png",
+                "pages/b.png",
+                "pages/c.png",
+                "b.mp3",
+                "main.js"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_watcher() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("tsugumi.yaml"),
+            "metadata: {title: T, language: ja, identifier: id}\n\
+             chapter:\n\
+             - page: a.png\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("a.png"), b"a").unwrap();
+
+        let project = Project::open(dir.path()).unwrap();
+        let interval = Interval {
+            poll_interval: 50,
+            debounce: 50,
+        };
+        let mut watcher = Watcher::new(&project, interval);
+
+        let path = dir.path().join("a.png");
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            // files other than those of the book are not reported
+            std::fs::write(path.with_file_name("b.png"), b"b").unwrap();
+            std::fs::write(&path, b"changed").unwrap();
+        });
+        assert_eq!(watcher.wait(&project), [PathBuf::from("a.png")]);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        let now = SystemTime::now();
+        let before = HashMap::from([
+            (PathBuf::from("a"), Some(now)),
+            (PathBuf::from("b"), Some(now)),
+            (PathBuf::from("c"), None),
+        ]);
+        let after = HashMap::from([
+            (PathBuf::from("a"), Some(now)),
+            (PathBuf::from("b"), Some(now + Duration::from_secs(1))),
+            (PathBuf::from("c"), Some(now)),
+            (PathBuf::from("d"), Some(now)),
+        ]);
+        assert_eq!(
+            changed_paths(&before, &after),
+            [PathBuf::from("b"), PathBuf::from("c")]
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use crate::i18n::tr;
+use crate::import::{read_xml, resolve};
+use crate::task::build::has_property;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+use zip::{CompressionMethod, ZipArchive};
+
+/// Media type the `mimetype` entry must consist of.
+const MIMETYPE: &str = "application/epub+zip";
+
+/// Checks the structure of the EPub file in `zip` much like a validator would, and returns the
+/// problems found, if any.
+///
+/// Only a broken container is an error; the problems of its contents are returned.
+pub fn validate<R: Read + Seek>(zip: &mut ZipArchive<R>) -> Result<Vec<String>> {
+    let mut findings = Vec::new();
+
+    match zip.by_index(0) {
+        Ok(mut entry) if entry.name() == "mimetype" => {
+            if entry.compression() != CompressionMethod::Stored {
+                findings.push(tr!(
+                    "`mimetype` is compressed",
+                    "`mimetype` が圧縮されています"
+                ));
+            }
+            let mut content = String::new();
+            if entry.read_to_string(&mut content).is_err() || content != MIMETYPE {
+                findings.push(tr!(
+                    "`mimetype` is not `{MIMETYPE}`",
+                    "`mimetype` が `{MIMETYPE}` ではありません"
+                ));
+            }
+        }
+        _ => findings.push(tr!(
+            "`mimetype` is not the first entry",
+            "`mimetype` が最初のエントリではありません"
+        )),
+    }
+
+    let container = match read_xml(zip, "META-INF/container.xml") {
+        Ok(container) => container,
+        Err(e) => {
+            findings.push(format!("{e:#}"));
+            return Ok(findings);
+        }
+    };
+    let Some(opf_path) = container
+        .iter()
+        .find(|e| e.name == "rootfile")
+        .and_then(|e| e.attr("full-path"))
+    else {
+        findings.push(tr!(
+            "the container names no package document",
+            "コンテナーにパッケージ文書がありません"
+        ));
+        return Ok(findings);
+    };
+    let package = match read_xml(zip, opf_path) {
+        Ok(package) => package,
+        Err(e) => {
+            findings.push(format!("{e:#}"));
+            return Ok(findings);
+        }
+    };
+
+    let text = |name: &str| {
+        package
+            .iter()
+            .filter(|e| e.name == name)
+            .any(|e| !e.text.trim().is_empty())
+    };
+    for name in ["title", "language"] {
+        if !text(name) {
+            findings.push(tr!(
+                "the package has no `dc:{name}`",
+                "パッケージに `dc:{name}` がありません"
+            ));
+        }
+    }
+
+    let unique_id = package
+        .iter()
+        .find(|e| e.name == "package")
+        .and_then(|e| e.attr("unique-identifier"));
+    let identified = package
+        .iter()
+        .filter(|e| e.name == "identifier" && !e.text.trim().is_empty())
+        .any(|e| unique_id.is_some() && e.attr("id") == unique_id);
+    if !identified {
+        findings.push(tr!(
+            "the package has no `dc:identifier` named by `unique-identifier`",
+            "パッケージに `unique-identifier` が示す `dc:identifier` がありません"
+        ));
+    }
+
+    let modified = package
+        .iter()
+        .any(|e| e.name == "meta" && e.attr("property") == Some("dcterms:modified"));
+    if !modified {
+        findings.push(tr!(
+            "the package has no `dcterms:modified`",
+            "パッケージに `dcterms:modified` がありません"
+        ));
+    }
+
+    let mut ids = HashSet::new();
+    let mut documents = Vec::new();
+    let mut navs = 0;
+    for item in package.iter().filter(|e| e.name == "item") {
+        let (Some(id), Some(href)) = (item.attr("id"), item.attr("href")) else {
+            findings.push(tr!(
+                "an item of the manifest has no `id` or `href`",
+                "マニフェストに `id` か `href` のないアイテムがあります"
+            ));
+            continue;
+        };
+        if !ids.insert(id) {
+            findings.push(tr!(
+                "`{id}` is used by more than one item",
+                "`{id}` が複数のアイテムで使われています"
+            ));
+        }
+
+        let name = resolve(opf_path, href);
+        let media_type = item.attr("media-type").unwrap_or_default();
+        match zip.by_name(&name) {
+            Err(_) => findings.push(tr!(
+                "`{name}` of the manifest is not in the package",
+                "マニフェストの `{name}` がパッケージにありません"
+            )),
+            Ok(_) if media_type == "application/xhtml+xml" => documents.push(name),
+            Ok(mut entry) if media_type.starts_with("image/") => {
+                let mut magic = Vec::new();
+                entry.by_ref().take(64).read_to_end(&mut magic)?;
+                let actual = image::guess_format(&magic).ok().map(|f| f.to_mime_type());
+                if let Some(actual) = actual.filter(|actual| *actual != media_type) {
+                    findings.push(tr!(
+                        "`{name}` is `{actual}` but declared as `{media_type}`",
+                        "`{name}` は `{actual}` ですが `{media_type}` と宣言されています"
+                    ));
+                }
+            }
+            Ok(_) => {}
+        }
+
+        if has_property(item.attr("properties").unwrap_or_default(), "nav") {
+            navs += 1;
+        }
+    }
+    if navs != 1 {
+        findings.push(tr!(
+            "the manifest has {navs} navigation document(s) instead of one",
+            "マニフェストにナビゲーション文書が1つではなく {navs} 個あります"
+        ));
+    }
+
+    for itemref in package.iter().filter(|e| e.name == "itemref") {
+        let idref = itemref.attr("idref").unwrap_or_default();
+        if !ids.contains(idref) {
+            findings.push(tr!(
+                "`{idref}` of the spine is not in the manifest",
+                "スパインの `{idref}` がマニフェストにありません"
+            ));
+        }
+    }
+
+    for name in documents {
+        if let Err(e) = read_xml(zip, &name) {
+            findings.push(format!("{e:#}"));
+        }
+    }
+
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::EpubBuilder;
+    use crate::model::{Book, Chapter, Metadata, Page, Title};
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    #[test]
+    fn test_validate() {
+        let root = tempfile::tempdir().unwrap();
+        image::RgbImage::new(6, 9)
+            .save(root.path().join("a.png"))
+            .unwrap();
+        let book = Book {
+            metadata: Metadata {
+                title: vec![Title {
+                    name: "Title".to_string(),
+                    ..Title::default()
+                }],
+                language: "ja".to_string(),
+                identifier: "id".to_string(),
+                ..Metadata::default()
+            },
+            chapter: vec![Chapter {
+                page: vec![Page {
+                    src: "a.png".into(),
+                    ..Page::default()
+                }],
+                ..Chapter::default()
+            }],
+            ..Book::default()
+        };
+
+        let buf = EpubBuilder::new(book, root.path())
+            .write(Cursor::new(Vec::new()))
+            .unwrap();
+        let mut zip = ZipArchive::new(buf).unwrap();
+        assert_eq!(validate(&mut zip).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_validate_broken() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        zip.start_file("META-INF/container.xml", options).unwrap();
+        zip.write_all(
+            br#"<container><rootfiles><rootfile full-path="a.opf"/></rootfiles></container>"#,
+        )
+        .unwrap();
+        zip.start_file("a.opf", options).unwrap();
+        zip.write_all(
+            br#"<package unique-identifier="id"><metadata><title>T</title></metadata>
+<manifest><item id="p" href="p.xhtml" media-type="application/xhtml+xml"/>
+<item id="q" href="q.xhtml" media-type="application/xhtml+xml"/>
+<item id="i" href="i.jpg" media-type="image/jpeg"/>
+<item id="s" href="a%20b.png" media-type="image/png"/></manifest>
+<spine><itemref idref="p"/><itemref idref="r"/></spine></package>"#,
+        )
+        .unwrap();
+        zip.start_file("p.xhtml", options).unwrap();
+        zip.write_all(b"<html><body></html>").unwrap();
+        zip.start_file("i.jpg", options).unwrap();
+        zip.write_all(b"\x89PNG\r\n\x1a\n").unwrap();
+        zip.start_file("a b.png", options).unwrap();
+        zip.write_all(b"\x89PNG\r\n\x1a\n").unwrap();
+
+        let mut zip = ZipArchive::new(zip.finish().unwrap()).unwrap();
+        let findings = validate(&mut zip).unwrap();
+        assert_eq!(findings.len(), 9, "{findings:#?}");
+        assert_eq!(findings[0], "`mimetype` is not the first entry");
+        assert!(findings.contains(&"`q.xhtml` of the manifest is not in the package".to_string()));
+        assert!(findings.contains(&"`r` of the spine is not in the manifest".to_string()));
+        assert!(
+            findings.contains(&"`i.jpg` is `image/png` but declared as `image/jpeg`".to_string())
+        );
+        assert!(findings[8].starts_with("failed to read `p.xhtml`"));
+        assert!(!findings.iter().any(|finding| finding.contains("a%20b.png")));
+    }
+}